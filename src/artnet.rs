@@ -0,0 +1,131 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{device::HostBuffer, Device};
+
+pub const ARTNET_PORT: u16 = 6454;
+const DMX_CHANNELS: usize = 512;
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+
+/// Art-Net output fed by the `pc.resources.dmx` buffer, one float in `[0, 1]` per channel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DmxSettings {
+    /// Node address, a broadcast address reaches every node on the LAN.
+    pub target: String,
+    /// Port address of the first universe.
+    pub universe: u16,
+    /// Consecutive universes of 512 channels sent every update.
+    pub universes: u16,
+    /// Packets per second, DMX itself refreshes at about 44 Hz.
+    pub rate: f32,
+}
+
+impl Default for DmxSettings {
+    fn default() -> Self {
+        Self {
+            target: "255.255.255.255".into(),
+            universe: 0,
+            universes: 1,
+            rate: 44.,
+        }
+    }
+}
+
+pub struct DmxOutput {
+    settings: DmxSettings,
+    socket: UdpSocket,
+    target: SocketAddr,
+    buffer: HostBuffer,
+    sequence: u8,
+    last_send: Instant,
+}
+
+impl DmxOutput {
+    pub fn new(device: &Arc<Device>, settings: DmxSettings) -> Result<Self> {
+        if settings.universes == 0 || settings.universe as u32 + settings.universes as u32 > 1 << 15
+        {
+            bail!("DMX universes must fit into the 15-bit Art-Net port address");
+        }
+        let target = match settings.target.parse::<SocketAddr>() {
+            Ok(addr) => addr,
+            Err(_) => SocketAddr::new(
+                settings
+                    .target
+                    .parse()
+                    .context("Invalid DMX target address")?,
+                ARTNET_PORT,
+            ),
+        };
+        let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        let mut buffer = device.create_host_buffer(
+            (settings.universes as usize * DMX_CHANNELS * size_of::<f32>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::DOWNLOAD,
+        )?;
+        device.name_object(buffer.buffer, "DMX Channels");
+        buffer.fill(0);
+
+        Ok(Self {
+            settings,
+            socket,
+            target,
+            buffer,
+            sequence: 0,
+            last_send: Instant::now(),
+        })
+    }
+
+    /// Address for `Resources::dmx`.
+    pub fn address(&self) -> u64 {
+        self.buffer.address
+    }
+
+    /// Sends the channels once per update period. They are read without
+    /// waiting on the GPU, so lights trail the picture by the frames in flight.
+    pub fn update(&mut self) {
+        let period = Duration::from_secs_f32(1. / self.settings.rate.max(1.));
+        if self.last_send.elapsed() < period {
+            return;
+        }
+        self.last_send = Instant::now();
+        // Zero is reserved for nodes that don't track sequence numbers
+        self.sequence = self.sequence.wrapping_add(1).max(1);
+
+        let universe_bytes = DMX_CHANNELS * size_of::<f32>();
+        for (i, channels) in self.buffer.chunks_exact(universe_bytes).enumerate() {
+            let universe = self.settings.universe + i as u16;
+            let packet = art_dmx(self.sequence, universe, channels);
+            if let Err(err) = self.socket.send_to(&packet, self.target) {
+                log::warn!("Failed to send Art-Net universe {universe}: {err}");
+            }
+        }
+    }
+}
+
+fn art_dmx(sequence: u8, universe: u16, channels: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(18 + DMX_CHANNELS);
+    packet.extend_from_slice(b"Art-Net\0");
+    packet.extend_from_slice(&OP_DMX.to_le_bytes());
+    packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+    packet.push(sequence);
+    packet.push(0); // physical input port
+    packet.extend_from_slice(&universe.to_le_bytes());
+    packet.extend_from_slice(&(DMX_CHANNELS as u16).to_be_bytes());
+    packet.extend(channels.chunks_exact(size_of::<f32>()).map(|bytes| {
+        let value = f32::from_ne_bytes(bytes.try_into().unwrap());
+        (value.clamp(0., 1.) * 255.).round() as u8
+    }));
+    packet
+}
@@ -0,0 +1,226 @@
+use std::{collections::VecDeque, path::Path, sync::Arc};
+
+use anyhow::{Context, Result};
+use ash::vk;
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    FromSample, SizedSample,
+};
+use either::Either;
+use parking_lot::Mutex;
+
+use crate::{
+    device::HostBuffer, dispatch_optimal, ComputeHandle, Device, PipelineArena, TextureArena,
+};
+
+/// Samples generated by one dispatch of the sound shader.
+const BLOCK_SIZE: u64 = 8192;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct SoundPushConstant {
+    pub samples: u64,
+    pub offset: u32,
+    pub count: u32,
+    pub sample_rate: f32,
+    pub _pad: u32,
+}
+
+type SampleQueue = Arc<Mutex<VecDeque<[f32; 2]>>>;
+
+/// Runs `sound.comp` into a host visible buffer and feeds the result to
+/// the default output device.
+pub struct SoundPass {
+    pub pipeline: ComputeHandle,
+    pub sample_rate: u32,
+    offset: u64,
+    latency: usize,
+    queue: SampleQueue,
+    buffer: HostBuffer,
+    _stream: cpal::Stream,
+}
+
+impl SoundPass {
+    pub fn new(
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        shader_path: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let host = cpal::default_host();
+        let output = host
+            .default_output_device()
+            .context("Failed to find audio output device")?;
+        let config = output.default_output_config()?;
+        let sample_rate = config.sample_rate().0;
+
+        let queue = SampleQueue::default();
+        let stream = match config.sample_format() {
+            cpal::SampleFormat::F32 => build_stream::<f32>(&output, &config.into(), &queue),
+            cpal::SampleFormat::I16 => build_stream::<i16>(&output, &config.into(), &queue),
+            cpal::SampleFormat::U16 => build_stream::<u16>(&output, &config.into(), &queue),
+            format => anyhow::bail!("Unsupported audio sample format: {format}"),
+        }?;
+        stream.play()?;
+
+        let pipeline = create_pipeline(pipeline_arena, texture_arena, shader_path)?;
+
+        let buffer = device.create_host_buffer(
+            BLOCK_SIZE * size_of::<[f32; 2]>() as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::DOWNLOAD,
+        )?;
+        device.name_object(buffer.buffer, "Sound Samples Buffer");
+
+        Ok(Self {
+            pipeline,
+            sample_rate,
+            offset: 0,
+            latency: sample_rate as usize / 10,
+            queue,
+            buffer,
+            _stream: stream,
+        })
+    }
+
+    /// Swaps in a freshly compiled pipeline, playback carries on from where
+    /// it was.
+    pub fn rebuild(
+        &mut self,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        shader_path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let pipeline = create_pipeline(pipeline_arena, texture_arena, shader_path)?;
+        let old = std::mem::replace(&mut self.pipeline, pipeline);
+        pipeline_arena.remove_pipeline(Either::Right(old));
+        Ok(())
+    }
+
+    /// Starts sound playback from the beginning.
+    pub fn restart(&mut self) {
+        self.offset = 0;
+        self.queue.lock().clear();
+    }
+
+    /// Tops up the playback queue, nothing is generated while paused.
+    pub fn update(
+        &mut self,
+        device: &Device,
+        queue: &vk::Queue,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        playing: bool,
+    ) -> Result<()> {
+        if !playing {
+            return Ok(());
+        }
+        let queued = self.queue.lock().len();
+        let count = self.latency.saturating_sub(queued).min(BLOCK_SIZE as usize) as u32;
+        if count == 0 {
+            return Ok(());
+        }
+
+        let pipeline = pipeline_arena.get_pipeline(self.pipeline);
+        let descriptor_sets = pipeline.descriptor_sets(texture_arena.images_set);
+        let push_constant = SoundPushConstant {
+            samples: self.buffer.address,
+            offset: self.offset as u32,
+            count,
+            sample_rate: self.sample_rate as f32,
+            _pad: 0,
+        };
+        device.one_time_submit(queue, |device, cbuff| unsafe {
+            device.cmd_bind_descriptor_sets(
+                cbuff,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                0,
+                &descriptor_sets,
+                &[],
+            );
+            device.cmd_bind_pipeline(cbuff, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+            device.cmd_push_constants(
+                cbuff,
+                pipeline.layout,
+                vk::ShaderStageFlags::COMPUTE,
+                0,
+                core::slice::from_raw_parts(
+                    core::ptr::from_ref(&push_constant).cast(),
+                    size_of::<SoundPushConstant>(),
+                ),
+            );
+            device.cmd_dispatch(cbuff, dispatch_optimal(count, pipeline.local_size[0]), 1, 1);
+
+            let memory_barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_STORAGE_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+                .dst_access_mask(vk::AccessFlags2::HOST_READ);
+            let dependency_info = vk::DependencyInfo::default()
+                .memory_barriers(std::slice::from_ref(&memory_barrier));
+            device.cmd_pipeline_barrier2(cbuff, &dependency_info);
+        })?;
+        self.buffer.invalidate()?;
+
+        let samples = self.buffer[..count as usize * size_of::<[f32; 2]>()]
+            .chunks_exact(size_of::<[f32; 2]>())
+            .map(|bytes| {
+                let l = f32::from_ne_bytes(bytes[0..4].try_into().unwrap());
+                let r = f32::from_ne_bytes(bytes[4..8].try_into().unwrap());
+                [l.clamp(-1., 1.), r.clamp(-1., 1.)]
+            });
+        self.queue.lock().extend(samples);
+        self.offset += count as u64;
+
+        Ok(())
+    }
+}
+
+fn create_pipeline(
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    shader_path: impl AsRef<Path>,
+) -> Result<ComputeHandle> {
+    let push_constant_range = vk::PushConstantRange::default()
+        .size(size_of::<SoundPushConstant>() as _)
+        .stage_flags(vk::ShaderStageFlags::COMPUTE);
+    pipeline_arena.create_compute_pipeline(
+        shader_path,
+        &[push_constant_range],
+        &[texture_arena.images_set_layout],
+    )
+}
+
+fn build_stream<T>(
+    output: &cpal::Device,
+    config: &cpal::StreamConfig,
+    queue: &SampleQueue,
+) -> Result<cpal::Stream>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as usize;
+    let queue = queue.clone();
+    let stream = output.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut queue = queue.lock();
+            for frame in data.chunks_mut(channels) {
+                let [l, r] = queue.pop_front().unwrap_or_default();
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    let value = match (channels, i) {
+                        (1, _) => (l + r) * 0.5,
+                        (_, 0) => l,
+                        (_, 1) => r,
+                        _ => 0.,
+                    };
+                    *sample = T::from_sample(value);
+                }
+            }
+        },
+        |err| log::error!("Audio stream error: {err}"),
+        None,
+    )?;
+    Ok(stream)
+}
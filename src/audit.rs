@@ -0,0 +1,216 @@
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use ahash::AHashMap;
+use ash::vk;
+use parking_lot::Mutex;
+
+/// Share of a heap pilka may fill before it warns.
+const HEAP_WARNING: f64 = 0.9;
+
+/// What a device memory allocation is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryKind {
+    /// Images of the texture arena: feedback, targets, loaded textures.
+    Textures,
+    /// Depth and multisampled scene attachments and check targets.
+    Attachments,
+    /// Buffers the shaders read or write.
+    Buffers,
+    /// Upload and copy buffers.
+    Staging,
+    /// Linear images that screenshots and recordings are read back through.
+    Readback,
+}
+
+impl MemoryKind {
+    const ALL: [MemoryKind; 5] = [
+        MemoryKind::Textures,
+        MemoryKind::Attachments,
+        MemoryKind::Buffers,
+        MemoryKind::Staging,
+        MemoryKind::Readback,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            MemoryKind::Textures => "textures",
+            MemoryKind::Attachments => "attachments",
+            MemoryKind::Buffers => "buffers",
+            MemoryKind::Staging => "staging",
+            MemoryKind::Readback => "readback",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Pipeline,
+    PipelineLayout,
+    DescriptorSetLayout,
+    DescriptorPool,
+    Semaphore,
+    Fence,
+    CommandBuffer,
+    Image,
+    ImageView,
+    Sampler,
+    Buffer,
+    QueryPool,
+    Memory,
+}
+
+impl ObjectKind {
+    const ALL: [ObjectKind; 13] = [
+        ObjectKind::Pipeline,
+        ObjectKind::PipelineLayout,
+        ObjectKind::DescriptorSetLayout,
+        ObjectKind::DescriptorPool,
+        ObjectKind::Semaphore,
+        ObjectKind::Fence,
+        ObjectKind::CommandBuffer,
+        ObjectKind::Image,
+        ObjectKind::ImageView,
+        ObjectKind::Sampler,
+        ObjectKind::Buffer,
+        ObjectKind::QueryPool,
+        ObjectKind::Memory,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            ObjectKind::Pipeline => "pipelines",
+            ObjectKind::PipelineLayout => "pipeline layouts",
+            ObjectKind::DescriptorSetLayout => "set layouts",
+            ObjectKind::DescriptorPool => "descriptor pools",
+            ObjectKind::Semaphore => "semaphores",
+            ObjectKind::Fence => "fences",
+            ObjectKind::CommandBuffer => "command buffers",
+            ObjectKind::Image => "images",
+            ObjectKind::ImageView => "image views",
+            ObjectKind::Sampler => "samplers",
+            ObjectKind::Buffer => "buffers",
+            ObjectKind::QueryPool => "query pools",
+            ObjectKind::Memory => "allocations",
+        }
+    }
+}
+
+/// Created and destroyed counts of every Vulkan object made through
+/// [`crate::Device`], a steadily growing difference is a leak.
+#[derive(Debug, Default)]
+pub struct ObjectAudit {
+    created: [AtomicU64; ObjectKind::ALL.len()],
+    destroyed: [AtomicU64; ObjectKind::ALL.len()],
+    allocated_bytes: AtomicU64,
+    freed_bytes: AtomicU64,
+    kind_bytes: [AtomicU64; MemoryKind::ALL.len()],
+    heap_bytes: [AtomicU64; vk::MAX_MEMORY_HEAPS],
+    /// Bit per heap that was reported as nearly full.
+    warned_heaps: AtomicU32,
+    /// Kind and heap of every live block by memory and offset, for the frees.
+    blocks: Mutex<AHashMap<(vk::DeviceMemory, u64), (MemoryKind, usize)>>,
+}
+
+impl ObjectAudit {
+    pub fn created(&self, kind: ObjectKind, count: usize) {
+        self.created[kind as usize].fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    pub fn destroyed(&self, kind: ObjectKind, count: usize) {
+        self.destroyed[kind as usize].fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    /// Counts a block of `bytes` at `offset` into `memory`, returns the
+    /// bytes now in use on its heap.
+    pub fn allocated(
+        &self,
+        kind: MemoryKind,
+        heap: usize,
+        memory: vk::DeviceMemory,
+        offset: u64,
+        bytes: u64,
+    ) -> u64 {
+        self.allocated_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.kind_bytes[kind as usize].fetch_add(bytes, Ordering::Relaxed);
+        self.blocks.lock().insert((memory, offset), (kind, heap));
+        self.heap_bytes[heap].fetch_add(bytes, Ordering::Relaxed) + bytes
+    }
+
+    pub fn freed(&self, memory: vk::DeviceMemory, offset: u64, bytes: u64) {
+        self.freed_bytes.fetch_add(bytes, Ordering::Relaxed);
+        if let Some((kind, heap)) = self.blocks.lock().remove(&(memory, offset)) {
+            self.kind_bytes[kind as usize].fetch_sub(bytes, Ordering::Relaxed);
+            self.heap_bytes[heap].fetch_sub(bytes, Ordering::Relaxed);
+        }
+    }
+
+    /// Whether `heap` of `size` bytes holding `used` should be reported,
+    /// once per heap.
+    pub fn heap_nearly_full(&self, heap: usize, used: u64, size: u64) -> bool {
+        let bit = 1 << heap;
+        used as f64 > size as f64 * HEAP_WARNING
+            && self.warned_heaps.fetch_or(bit, Ordering::Relaxed) & bit == 0
+    }
+
+    /// Device memory in use by kind and by heap, `memory_heaps` are the
+    /// heaps of the device.
+    pub fn memory_report(&self, memory_heaps: &[vk::MemoryHeap]) -> String {
+        let mib = |bytes: u64| bytes as f64 / (1024. * 1024.);
+        let mut report = format!("device memory:\t{:.1} MiB\n", mib(self.memory_in_use()));
+        for kind in MemoryKind::ALL {
+            let bytes = self.kind_bytes[kind as usize].load(Ordering::Relaxed);
+            if bytes > 0 {
+                report += &format!("  {:<14}{:>9.1} MiB\n", kind.name(), mib(bytes));
+            }
+        }
+        for (i, heap) in memory_heaps.iter().enumerate() {
+            let bytes = self.heap_bytes[i].load(Ordering::Relaxed);
+            if bytes == 0 {
+                continue;
+            }
+            let local = match heap.flags.contains(vk::MemoryHeapFlags::DEVICE_LOCAL) {
+                true => "device local",
+                false => "host",
+            };
+            report += &format!(
+                "  heap {i} {local:<7}{:>9.1} MiB of {:.0} MiB\n",
+                mib(bytes),
+                mib(heap.size)
+            );
+        }
+        report
+    }
+
+    /// Bytes of device memory currently handed out by the allocator.
+    pub fn memory_in_use(&self) -> u64 {
+        let allocated = self.allocated_bytes.load(Ordering::Relaxed);
+        allocated.saturating_sub(self.freed_bytes.load(Ordering::Relaxed))
+    }
+
+    pub fn alive(&self, kind: ObjectKind) -> i64 {
+        let created = self.created[kind as usize].load(Ordering::Relaxed);
+        let destroyed = self.destroyed[kind as usize].load(Ordering::Relaxed);
+        created as i64 - destroyed as i64
+    }
+}
+
+impl std::fmt::Display for ObjectAudit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<18}{:>8}{:>10}{:>10}",
+            "object", "alive", "created", "freed"
+        )?;
+        for kind in ObjectKind::ALL {
+            writeln!(
+                f,
+                "{:<18}{:>8}{:>10}{:>10}",
+                kind.name(),
+                self.alive(kind),
+                self.created[kind as usize].load(Ordering::Relaxed),
+                self.destroyed[kind as usize].load(Ordering::Relaxed),
+            )?;
+        }
+        Ok(())
+    }
+}
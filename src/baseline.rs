@@ -0,0 +1,108 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::{Deserialize, Serialize};
+
+pub const BASELINE_FILE: &str = "pilka-baseline.toml";
+/// Frames the smoothed GPU timings get to settle after a reload.
+const SETTLE_FRAMES: u32 = 60;
+/// Relative change reported after a reload.
+const THRESHOLD: f32 = 0.15;
+/// Changes below this many milliseconds are noise.
+const MIN_CHANGE_MS: f32 = 0.1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Baseline {
+    /// Render resolution the time was measured at.
+    resolution: [u32; 2],
+    /// GPU time of a whole frame in milliseconds.
+    gpu_ms: f32,
+}
+
+/// GPU frame time of a project saved next to its shaders, compared against
+/// every successful reload. Times at other resolutions are scaled by the
+/// pixel count before comparing.
+pub struct PerfBaseline {
+    path: PathBuf,
+    saved: Option<Baseline>,
+    /// Frames until the pending comparison.
+    check_in: Option<u32>,
+}
+
+impl PerfBaseline {
+    pub fn load(shader_dir: &Path) -> Self {
+        let path = shader_dir.join(BASELINE_FILE);
+        let saved = match std::fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|err| log::warn!("Ignoring {}: {err}", path.display()))
+                .ok(),
+            Err(_) => None,
+        };
+        Self {
+            path,
+            saved,
+            check_in: None,
+        }
+    }
+
+    /// Stores `gpu_ms` measured at `extent` as the new baseline.
+    pub fn save(&mut self, gpu_ms: Option<f32>, extent: vk::Extent2D) -> Result<()> {
+        let Some(gpu_ms) = gpu_ms else {
+            bail!("No GPU timings to save as a baseline yet");
+        };
+        let baseline = Baseline {
+            resolution: [extent.width, extent.height],
+            gpu_ms,
+        };
+        let text = toml::to_string(&baseline)?;
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        println!(
+            "Saved baseline of {gpu_ms:.3} ms at {}x{}",
+            extent.width, extent.height
+        );
+        self.saved = Some(baseline);
+        Ok(())
+    }
+
+    /// Schedules a comparison once the reloaded shaders had time to settle.
+    pub fn reloaded(&mut self) {
+        if self.saved.is_some() {
+            self.check_in = Some(SETTLE_FRAMES);
+        }
+    }
+
+    /// Counts down a pending comparison, reporting the change when it is due.
+    pub fn frame(&mut self, gpu_ms: Option<f32>, extent: vk::Extent2D) {
+        let Some(frames) = self.check_in else {
+            return;
+        };
+        if frames > 0 {
+            self.check_in = Some(frames - 1);
+            return;
+        }
+        self.check_in = None;
+        let (Some(saved), Some(gpu_ms)) = (self.saved, gpu_ms) else {
+            return;
+        };
+        let [width, height] = saved.resolution;
+        let scale = (width * height) as f32 / (extent.width * extent.height).max(1) as f32;
+        let scaled = gpu_ms * scale;
+        let change = scaled / saved.gpu_ms - 1.;
+        if change.abs() < THRESHOLD || (scaled - saved.gpu_ms).abs() < MIN_CHANGE_MS {
+            return;
+        }
+        let percent = change.abs() * 100.;
+        let message = format!(
+            "{scaled:.3} ms against the baseline of {:.3} ms at {width}x{height}",
+            saved.gpu_ms
+        );
+        if change > 0. {
+            log::warn!("Reload is {percent:.0}% slower: {message}");
+        } else {
+            println!("Reload is {percent:.0}% faster: {message}");
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{
+    device::HostBuffer,
+    reflect::{self, DescriptorBinding},
+    Device, TextureArena, Textures, LINEAR_SAMPLER_IDX,
+};
+
+/// Set of the bindings pipelines build from their shaders, set 0 is the
+/// texture arena.
+pub const USER_SET: u32 = 1;
+
+/// Descriptors set 1 may declare, one per binding.
+const USER_TYPES: [vk::DescriptorType; 4] = [
+    vk::DescriptorType::STORAGE_BUFFER,
+    vk::DescriptorType::UNIFORM_BUFFER,
+    vk::DescriptorType::SAMPLED_IMAGE,
+    vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+];
+
+/// A zeroed buffer bound to every `buffer` or `uniform` block of set 1 with
+/// the same block name.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BufferDesc {
+    pub name: String,
+    /// In bytes.
+    pub size: u64,
+}
+
+/// Checks a binding of a compiled shader against what the pipelines can
+/// provide, the message tells how to declare it instead.
+pub fn check_binding(binding: &DescriptorBinding) -> Result<(), String> {
+    let (set, index, name) = (binding.set, binding.binding, &binding.name);
+    let supported = binding.count == 1 && binding.ty.is_some_and(|ty| USER_TYPES.contains(&ty));
+    match set {
+        0 if index < 2 => Ok(()),
+        0 => Err(format!(
+            "Set 0 binding {index} doesn't exist, set 0 only holds the samplers at \
+             binding 0 and the images at binding 1. Declare your own in set {USER_SET}"
+        )),
+        USER_SET if supported => Ok(()),
+        USER_SET => Err(format!(
+            "Set {USER_SET} binding {index} `{name}` has to be a single storage buffer, \
+             uniform buffer, sampled image or combined image sampler"
+        )),
+        _ => Err(format!(
+            "Set {set} doesn't exist, declare resources in set {USER_SET}"
+        )),
+    }
+}
+
+/// Set 1 bindings of the modules of one pipeline, sorted by binding. Every
+/// stage declaring a binding has to agree on it.
+pub fn user_bindings(modules: &[&[u32]]) -> Result<Vec<DescriptorBinding>> {
+    let mut bindings: Vec<DescriptorBinding> = vec![];
+    let declared = modules
+        .iter()
+        .flat_map(|words| reflect::descriptor_bindings(words))
+        .filter(|binding| binding.set == USER_SET);
+    for binding in declared {
+        match bindings.iter().find(|b| b.binding == binding.binding) {
+            Some(other) if *other != binding => bail!(
+                "Set {USER_SET} binding {} is `{}` in one stage and `{}` in another",
+                binding.binding,
+                other.name,
+                binding.name
+            ),
+            Some(_) => {}
+            None => bindings.push(binding),
+        }
+    }
+    bindings.sort_by_key(|binding| binding.binding);
+    Ok(bindings)
+}
+
+struct UserBuffer {
+    desc: BufferDesc,
+    buffer: HostBuffer,
+}
+
+/// The buffers and textures set 1 bindings are looked up in by name.
+#[derive(Default)]
+pub struct UserResources {
+    buffers: Vec<UserBuffer>,
+    textures: Vec<(String, vk::ImageView)>,
+    sampler: vk::Sampler,
+    /// Buffers the sets of older pipelines may still point at.
+    retired: Vec<UserBuffer>,
+}
+
+impl UserResources {
+    /// Replaces the buffers with the ones of `descs`, keeping the contents of
+    /// those with the same name and size, and picks up the loaded textures.
+    /// Pipelines created before keep the old descriptors, the buffers they
+    /// point at are retired until [`Self::release_retired`].
+    pub fn configure(
+        &mut self,
+        descs: &[BufferDesc],
+        textures: &Textures,
+        texture_arena: &TextureArena,
+        device: &Arc<Device>,
+    ) -> Result<()> {
+        self.retired.append(&mut self.buffers);
+        for desc in descs {
+            if self
+                .buffers
+                .iter()
+                .any(|buffer| buffer.desc.name == desc.name)
+            {
+                bail!("Buffer `{}` is declared twice", desc.name);
+            }
+            if desc.size == 0 || desc.size % 4 != 0 {
+                bail!(
+                    "Buffer `{}` has to be a positive multiple of 4 bytes",
+                    desc.name
+                );
+            }
+            let kept = self.retired.iter().position(|buffer| buffer.desc == *desc);
+            let buffer = match kept {
+                Some(i) => self.retired.swap_remove(i).buffer,
+                None => {
+                    let mut buffer = device.create_host_buffer(
+                        desc.size,
+                        vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::UNIFORM_BUFFER,
+                        gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+                    )?;
+                    buffer.fill(0);
+                    device.name_object(buffer.buffer, &format!("Buffer: {}", desc.name));
+                    buffer
+                }
+            };
+            self.buffers.push(UserBuffer {
+                desc: desc.clone(),
+                buffer,
+            });
+        }
+        self.textures = textures
+            .slots()
+            .map(|(name, slot)| (name.to_owned(), texture_arena.views[slot]))
+            .collect();
+        self.sampler = texture_arena.samplers[LINEAR_SAMPLER_IDX];
+        Ok(())
+    }
+
+    /// Frees the buffers replaced since the last call, once no pipeline
+    /// built before is left.
+    pub fn release_retired(&mut self) {
+        self.retired.clear();
+    }
+
+    fn buffer(&self, name: &str) -> Option<&HostBuffer> {
+        self.buffers
+            .iter()
+            .find(|buffer| buffer.desc.name == name)
+            .map(|buffer| &buffer.buffer)
+    }
+
+    fn texture(&self, name: &str) -> Option<vk::ImageView> {
+        self.textures
+            .iter()
+            .find(|(texture, _)| texture == name)
+            .map(|&(_, view)| view)
+    }
+}
+
+/// Set 1 of a pipeline, laid out after what its shaders declare there.
+pub struct UserSet {
+    pub layout: vk::DescriptorSetLayout,
+    pub set: vk::DescriptorSet,
+    pool: vk::DescriptorPool,
+    bindings: Vec<DescriptorBinding>,
+    device: Arc<Device>,
+}
+
+impl Drop for UserSet {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
+
+impl UserSet {
+    /// Builds and fills the set of `bindings`, `None` without any.
+    pub fn new(
+        device: &Arc<Device>,
+        bindings: Vec<DescriptorBinding>,
+        resources: &UserResources,
+    ) -> Result<Option<Self>> {
+        if bindings.is_empty() {
+            return Ok(None);
+        }
+        let layout_bindings: Vec<_> = bindings
+            .iter()
+            .map(|binding| {
+                vk::DescriptorSetLayoutBinding::default()
+                    .binding(binding.binding)
+                    .descriptor_type(binding.ty.unwrap_or_default())
+                    .descriptor_count(1)
+                    .stage_flags(vk::ShaderStageFlags::ALL_GRAPHICS | vk::ShaderStageFlags::COMPUTE)
+            })
+            .collect();
+        let pool_sizes: Vec<_> = USER_TYPES
+            .into_iter()
+            .filter_map(|ty| {
+                let count = bindings.iter().filter(|b| b.ty == Some(ty)).count();
+                (count > 0).then(|| {
+                    vk::DescriptorPoolSize::default()
+                        .ty(ty)
+                        .descriptor_count(count as u32)
+                })
+            })
+            .collect();
+        let layout = unsafe {
+            device.create_descriptor_set_layout(
+                &vk::DescriptorSetLayoutCreateInfo::default().bindings(&layout_bindings),
+                None,
+            )?
+        };
+        let pool = unsafe {
+            device.create_descriptor_pool(
+                &vk::DescriptorPoolCreateInfo::default()
+                    .pool_sizes(&pool_sizes)
+                    .max_sets(1),
+                None,
+            )
+        };
+        let pool = match pool {
+            Ok(pool) => pool,
+            Err(err) => {
+                unsafe { device.destroy_descriptor_set_layout(layout, None) };
+                return Err(err.into());
+            }
+        };
+        // Dropped on a failed allocation or write, which destroys both
+        let mut user_set = Self {
+            layout,
+            set: vk::DescriptorSet::null(),
+            pool,
+            bindings,
+            device: device.clone(),
+        };
+        user_set.set = unsafe {
+            device.allocate_descriptor_sets(
+                &vk::DescriptorSetAllocateInfo::default()
+                    .descriptor_pool(pool)
+                    .set_layouts(&[layout]),
+            )?[0]
+        };
+        user_set.write(resources)?;
+        Ok(Some(user_set))
+    }
+
+    pub fn bindings(&self) -> &[DescriptorBinding] {
+        &self.bindings
+    }
+
+    /// Points every binding at the resource of its name.
+    fn write(&self, resources: &UserResources) -> Result<()> {
+        use vk::DescriptorType as D;
+        for binding in &self.bindings {
+            let name = &binding.name;
+            let write = vk::WriteDescriptorSet::default()
+                .dst_set(self.set)
+                .dst_binding(binding.binding)
+                .descriptor_type(binding.ty.unwrap_or_default());
+            match binding.ty {
+                Some(D::STORAGE_BUFFER | D::UNIFORM_BUFFER) => {
+                    let buffer = resources.buffer(name).with_context(|| {
+                        format!(
+                            "Block `{name}` at set {USER_SET} binding {} has no [[buffer]] \
+                             of the same name in the manifest",
+                            binding.binding
+                        )
+                    })?;
+                    let info = vk::DescriptorBufferInfo::default()
+                        .buffer(buffer.buffer)
+                        .range(vk::WHOLE_SIZE);
+                    let write = write.buffer_info(std::slice::from_ref(&info));
+                    unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+                }
+                _ => {
+                    let view = resources.texture(name).with_context(|| {
+                        format!(
+                            "Image `{name}` at set {USER_SET} binding {} has no [[texture]] \
+                             of the same name in the manifest",
+                            binding.binding
+                        )
+                    })?;
+                    let info = vk::DescriptorImageInfo::default()
+                        .image_view(view)
+                        .sampler(resources.sampler)
+                        .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+                    let write = write.image_info(std::slice::from_ref(&info));
+                    unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+
+use crate::{ManagedImage, PushConstant};
+
+/// Which read back of the frame a capture is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureKind {
+    /// The 8-bit output copy.
+    Output,
+    /// The float output of the post chain.
+    Float,
+}
+
+/// Frames read back while paused, handed out again until a frame is rendered
+/// with different push constants, so repeated exports of a paused frame skip
+/// the blocking copy.
+///
+/// Only `frame` and `time_delta` are ignored in the comparison, hot reloads
+/// bump `reload_count` and seeking moves `time`.
+#[derive(Default)]
+pub struct CaptureCache {
+    key: Option<PushConstant>,
+    images: Vec<(CaptureKind, Arc<ManagedImage>)>,
+}
+
+impl CaptureCache {
+    /// Called with the push constants of every rendered frame.
+    pub fn update(&mut self, paused: bool, push_constant: &PushConstant) {
+        let key = paused.then_some(PushConstant {
+            frame: 0,
+            time_delta: 0.,
+            ..*push_constant
+        });
+        if key.is_none() || key != self.key {
+            self.images.clear();
+        }
+        self.key = key;
+    }
+
+    /// Drops the captures after changes the push constants don't show.
+    pub fn invalidate(&mut self) {
+        self.images.clear();
+        self.key = None;
+    }
+
+    pub fn get(&self, kind: CaptureKind) -> Option<Arc<ManagedImage>> {
+        self.images
+            .iter()
+            .find(|(k, _)| *k == kind)
+            .map(|(_, image)| image.clone())
+    }
+
+    /// Maps a fresh read back and keeps it while the frame stays paused.
+    pub fn insert(
+        &mut self,
+        kind: CaptureKind,
+        mut image: ManagedImage,
+    ) -> Result<Arc<ManagedImage>> {
+        image
+            .map_memory()
+            .context("Failed to map the captured frame")?;
+        let image = Arc::new(image);
+        if self.key.is_some() {
+            self.images.retain(|(k, _)| *k != kind);
+            self.images.push((kind, image.clone()));
+        }
+        Ok(image)
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use parking_lot::Mutex;
+use serde::Deserialize;
+
+use crate::{device::HostBuffer, Device};
+
+/// `cmd_update_buffer` accepts at most this many bytes per call.
+const UPDATE_CHUNK: usize = 65536;
+
+/// A named array of floats published from the CPU, read in shaders through
+/// `pc.resources.data.channels[i]` in manifest order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataChannelDesc {
+    pub name: String,
+    /// Maximum number of floats, longer arrays are truncated.
+    #[serde(default = "default_capacity")]
+    pub capacity: u32,
+    /// Program started in the shader folder that prints one array per line
+    /// to stdout. Without a source the channel is fed through a [`DataPublisher`].
+    pub command: Option<String>,
+    /// UDP port receiving one array per datagram.
+    pub udp: Option<u16>,
+    /// Datagrams hold little-endian `f32`s instead of text.
+    #[serde(default)]
+    pub binary: bool,
+    /// Serial device sending one array per line.
+    pub serial: Option<PathBuf>,
+    pub baud: Option<u32>,
+}
+
+fn default_capacity() -> u32 {
+    4096
+}
+
+/// Hands the latest array to the uploader, older unread arrays are dropped.
+#[derive(Debug, Clone, Default)]
+pub struct DataPublisher {
+    pending: Arc<Mutex<Option<Vec<f32>>>>,
+}
+
+impl DataPublisher {
+    pub fn publish(&self, values: Vec<f32>) {
+        *self.pending.lock() = Some(values);
+    }
+
+    fn take(&self) -> Option<Vec<f32>> {
+        self.pending.lock().take()
+    }
+}
+
+struct DataChannel {
+    name: String,
+    capacity: u32,
+    publisher: DataPublisher,
+    buffer: HostBuffer,
+    feed: Option<Feed>,
+}
+
+/// Storage buffers for CPU data, each laid out as `uint len; float values[]`.
+/// Updates are recorded into the frame command buffer, so frames in flight
+/// keep reading the values they were submitted with.
+#[derive(Default)]
+pub struct DataBuffers {
+    channels: Vec<DataChannel>,
+    table: Option<HostBuffer>,
+}
+
+impl DataBuffers {
+    pub fn new(device: &Arc<Device>, descs: &[DataChannelDesc], shader_dir: &Path) -> Result<Self> {
+        let mut channels = vec![];
+        for desc in descs {
+            if channels.iter().any(|c: &DataChannel| c.name == desc.name) {
+                bail!("Duplicate data channel `{}`", desc.name);
+            }
+            let buffer = device.create_host_buffer(
+                4 + desc.capacity.max(1) as u64 * 4,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            )?;
+            device.name_object(buffer.buffer, &format!("Data Channel: {}", desc.name));
+            let publisher = DataPublisher::default();
+            let feed = Feed::new(desc, shader_dir, publisher.clone())
+                .with_context(|| format!("Failed to start data channel `{}`", desc.name))?;
+            channels.push(DataChannel {
+                name: desc.name.clone(),
+                capacity: desc.capacity,
+                publisher,
+                buffer,
+                feed,
+            });
+        }
+
+        let table = match channels.is_empty() {
+            true => None,
+            false => {
+                let mut table = device.create_host_buffer(
+                    channels.len() as u64 * 8,
+                    vk::BufferUsageFlags::STORAGE_BUFFER,
+                    gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+                )?;
+                device.name_object(table.buffer, "Data Channel Table");
+                for (dst, channel) in table.chunks_exact_mut(8).zip(&channels) {
+                    dst.copy_from_slice(&channel.buffer.address.to_ne_bytes());
+                }
+                Some(table)
+            }
+        };
+
+        Ok(Self { channels, table })
+    }
+
+    /// Address for `Resources::data`, `0` when no channel is declared.
+    pub fn address(&self) -> u64 {
+        self.table.as_ref().map_or(0, |table| table.address)
+    }
+
+    /// Entry point for plugins that compute their arrays in-process.
+    pub fn publisher(&self, name: &str) -> Option<DataPublisher> {
+        self.channels
+            .iter()
+            .find(|channel| channel.name == name)
+            .map(|channel| channel.publisher.clone())
+    }
+
+    /// Records uploads of every channel that received a new array since the
+    /// last frame.
+    pub fn upload(&mut self, device: &Device, cbuff: vk::CommandBuffer) {
+        let updates: Vec<_> = self
+            .channels
+            .iter_mut()
+            .filter_map(|channel| {
+                if let Some(feed) = channel.feed.take_if(|feed| !feed.alive()) {
+                    log::warn!("Data channel `{}` {} closed", channel.name, feed.kind);
+                }
+                let mut values = channel.publisher.take()?;
+                values.truncate(channel.capacity as usize);
+                Some((channel.buffer.buffer, values))
+            })
+            .collect();
+        if updates.is_empty() {
+            return;
+        }
+
+        let barrier = |src_stage, src_access, dst_stage, dst_access| {
+            let memory_barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access);
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .memory_barriers(std::slice::from_ref(&memory_barrier)),
+                )
+            };
+        };
+
+        barrier(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::SHADER_READ,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+        for (buffer, values) in updates {
+            let mut bytes = Vec::with_capacity(4 + values.len() * 4);
+            bytes.extend_from_slice(&(values.len() as u32).to_ne_bytes());
+            bytes.extend(values.iter().flat_map(|v| v.to_ne_bytes()));
+            for (i, chunk) in bytes.chunks(UPDATE_CHUNK).enumerate() {
+                let offset = (i * UPDATE_CHUNK) as u64;
+                unsafe { device.cmd_update_buffer(cbuff, buffer, offset, chunk) };
+            }
+        }
+        barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::SHADER_READ,
+        );
+    }
+}
+
+/// Background reader publishing into a channel.
+struct Feed {
+    /// Shown when the source closes.
+    kind: &'static str,
+    child: Option<Child>,
+    stop: Arc<AtomicBool>,
+    thread_handle: Option<JoinHandle<()>>,
+}
+
+impl Feed {
+    fn new(desc: &DataChannelDesc, dir: &Path, publisher: DataPublisher) -> Result<Option<Self>> {
+        let feed = match (&desc.command, desc.udp, &desc.serial) {
+            (None, None, None) => return Ok(None),
+            (Some(command), None, None) => Self::command(command, dir, publisher)?,
+            (None, Some(port), None) => Self::udp(port, desc.binary, publisher)?,
+            (None, None, Some(path)) => Self::serial(path, desc.baud, publisher)?,
+            _ => bail!("Only one of `command`, `udp` and `serial` can feed a channel"),
+        };
+        Ok(Some(feed))
+    }
+
+    /// External program printing arrays to its stdout.
+    fn command(command: &str, dir: &Path, publisher: DataPublisher) -> Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().context("Empty data command")?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .current_dir(dir)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run `{command}`"))?;
+        let stdout = child.stdout.take().unwrap();
+        let thread_handle = std::thread::spawn(move || read_lines(stdout, publisher));
+        Ok(Self {
+            kind: "command",
+            child: Some(child),
+            stop: Arc::default(),
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// One array per datagram, as text or as raw little-endian `f32`s.
+    fn udp(port: u16, binary: bool, publisher: DataPublisher) -> Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("Failed to bind data socket on port {port}"))?;
+        // Lets the thread notice `stop` and release the port on folder switch
+        socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread_stop = stop.clone();
+        let thread_handle = std::thread::spawn(move || {
+            let mut buf = vec![0u8; 65536];
+            while !thread_stop.load(Ordering::Relaxed) {
+                let len = match socket.recv(&mut buf) {
+                    Ok(len) => len,
+                    Err(err)
+                        if matches!(
+                            err.kind(),
+                            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                        ) =>
+                    {
+                        continue
+                    }
+                    Err(err) => {
+                        log::error!("Data socket error: {err}");
+                        return;
+                    }
+                };
+                let packet = &buf[..len];
+                let values = match binary {
+                    true => Ok(packet
+                        .chunks_exact(4)
+                        .map(|b| f32::from_le_bytes(b.try_into().unwrap()))
+                        .collect()),
+                    false => std::str::from_utf8(packet)
+                        .map_err(anyhow::Error::from)
+                        .and_then(parse_values),
+                };
+                match values {
+                    Ok(values) => publisher.publish(values),
+                    Err(err) => log::warn!("Malformed data packet: {err}"),
+                }
+            }
+        });
+        Ok(Self {
+            kind: "socket",
+            child: None,
+            stop,
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    /// Text lines from a serial device, the port is configured with `stty`.
+    fn serial(path: &Path, baud: Option<u32>, publisher: DataPublisher) -> Result<Self> {
+        if let Some(baud) = baud {
+            let flag = if cfg!(target_os = "macos") {
+                "-f"
+            } else {
+                "-F"
+            };
+            let status = Command::new("stty")
+                .arg(flag)
+                .arg(path)
+                .args([&baud.to_string(), "raw", "-echo"])
+                .status()
+                .context("Failed to run `stty` to configure the serial port")?;
+            if !status.success() {
+                bail!("`stty` failed to set {baud} baud on {}", path.display());
+            }
+        }
+        let port = File::open(path)
+            .with_context(|| format!("Failed to open serial port {}", path.display()))?;
+        let thread_handle = std::thread::spawn(move || read_lines(port, publisher));
+        Ok(Self {
+            kind: "serial port",
+            child: None,
+            stop: Arc::default(),
+            thread_handle: Some(thread_handle),
+        })
+    }
+
+    fn alive(&mut self) -> bool {
+        self.thread_handle
+            .as_ref()
+            .is_some_and(|handle| !handle.is_finished())
+    }
+}
+
+impl Drop for Feed {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+        // A serial read can block indefinitely, so that thread is left to
+        // exit on its next line
+        if self.kind != "serial port" {
+            if let Some(handle) = self.thread_handle.take() {
+                let _ = handle.join();
+            }
+        }
+    }
+}
+
+fn read_lines(source: impl Read, publisher: DataPublisher) {
+    for line in BufReader::new(source).lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_values(&line) {
+            Ok(values) => publisher.publish(values),
+            Err(err) => log::warn!("{err}"),
+        }
+    }
+}
+
+/// Numbers separated by whitespace or commas.
+pub fn parse_values(line: &str) -> Result<Vec<f32>> {
+    line.split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse()
+                .with_context(|| format!("Invalid number `{s}` in data line"))
+        })
+        .collect()
+}
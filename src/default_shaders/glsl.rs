@@ -1,4 +1,4 @@
-pub const FRAG_SHADER: &str = "#version 460
+pub const FRAG_HEADER: &str = "#version 460
 #extension GL_EXT_buffer_reference : require
 #extension GL_EXT_nonuniform_qualifier : require
 
@@ -20,6 +20,55 @@ vec4 Tex(uint id, vec2 uv) {
         nonuniformEXT(sampler2D(gtextures[id], gsamplers[LINER_SAMPL])), uv);
 }
 
+layout(buffer_reference, std430) readonly buffer Data {
+    uint len;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer DataTable {
+    Data channels[];
+};
+layout(buffer_reference, std430) buffer Dmx {
+    float channels[];
+};
+layout(buffer_reference, std430) readonly buffer Table {
+    uint rows;
+    uint columns;
+    uint texture;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer Tables {
+    Table tables[];
+};
+layout(buffer_reference, std430) buffer StreamFeedback {
+    uint requested[];
+};
+layout(buffer_reference, std430) readonly buffer Stream {
+    StreamFeedback feedback;
+    uvec2 size;
+    uint tile_size;
+    uint tiles_x;
+    uint atlas;
+    uint atlas_columns;
+    uint overview;
+    uint pages[];
+};
+layout(buffer_reference, std430) readonly buffer Streams {
+    Stream streams[];
+};
+layout(buffer_reference, std430) buffer Particles {
+    vec4 particles[];
+};
+layout(buffer_reference, std430) readonly buffer Resources {
+    DataTable data;
+    Dmx dmx;
+    Tables tables;
+    Streams streams;
+    Particles particles;
+    uint particle_count;
+    uint particle_vertices;
+    uvec2 scene;
+};
+
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
     float time;
@@ -29,9 +78,45 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    vec4 picked_color;
+    Resources resources;
+    uint reload_count;
+    uint mouse_buttons;
+    vec2 scroll;
 }
 pc;
 
+// Samples streamed image `id`, asking for the tile under `uv`. Until it is
+// resident the low resolution overview is drawn instead.
+vec4 StreamTex(uint id, vec2 uv) {
+    Stream s = pc.resources.streams.streams[id];
+    if (s.overview == 0xFFFFFFFFu) {
+        return vec4(0.);
+    }
+    vec4 coarse = Tex(s.overview, uv);
+    vec2 texel = clamp(uv, 0., 1.) * vec2(s.size);
+    vec2 footprint = fwidth(texel);
+    vec2 overview_scale = vec2(s.size) / vec2(textureSize(gtextures[nonuniformEXT(s.overview)], 0));
+    if (max(footprint.x, footprint.y) > max(overview_scale.x, overview_scale.y)) {
+        return coarse;
+    }
+    uvec2 tile = min(uvec2(texel), s.size - 1u) / s.tile_size;
+    uint idx = tile.y * s.tiles_x + tile.x;
+    s.feedback.requested[idx] = 1u;
+    uint page = s.pages[idx];
+    if (page == 0u) {
+        return coarse;
+    }
+    uint slot = page - 1u;
+    vec2 origin = vec2(slot % s.atlas_columns, slot / s.atlas_columns) * float(s.tile_size);
+    vec2 local = clamp(texel - vec2(tile * s.tile_size), vec2(0.5), vec2(s.tile_size) - 0.5);
+    vec2 atlas_size = vec2(textureSize(gtextures[nonuniformEXT(s.atlas)], 0));
+    return textureLod(nonuniformEXT(sampler2D(gtextures[s.atlas], gsamplers[LINER_SAMPL])),
+                      (origin + local) / atlas_size, 0.);
+}
+";
+
+pub const FRAG_MAIN: &str = "
 void main() {
     vec2 uv = (in_uv + -0.5) * vec2(pc.resolution.x / pc.resolution.y, 1);
 
@@ -39,6 +124,95 @@ void main() {
     out_color = vec4(col, 1.0);
 }";
 
+pub const PLASMA_FRAG_MAIN: &str = "
+void main() {
+    vec2 uv = (in_uv + -0.5) * vec2(pc.resolution.x / pc.resolution.y, 1) * 4.;
+    float t = pc.time * 0.5;
+
+    float v = sin(uv.x + t);
+    v += sin((uv.y + t) * 0.5);
+    v += sin((uv.x + uv.y + t) * 0.5);
+    vec2 c = uv + vec2(sin(t / 3.), cos(t / 2.)) * 2.;
+    v += sin(sqrt(dot(c, c) + 1.) + t);
+
+    vec3 col = 0.5 + 0.5 * cos(PI * v + vec3(0., 2., 4.));
+    out_color = vec4(col, 1.0);
+}";
+
+pub const RAYMARCH_FRAG_MAIN: &str = "
+float sdf(vec3 p) {
+    vec3 q = abs(p) - vec3(0.6);
+    float box = length(max(q, 0.)) + min(max(q.x, max(q.y, q.z)), 0.) - 0.1;
+    float sphere = length(p) - 0.8;
+    return max(box, -sphere);
+}
+
+vec3 normal(vec3 p) {
+    vec2 e = vec2(1e-3, 0.);
+    return normalize(vec3(sdf(p + e.xyy) - sdf(p - e.xyy),
+                          sdf(p + e.yxy) - sdf(p - e.yxy),
+                          sdf(p + e.yyx) - sdf(p - e.yyx)));
+}
+
+mat2 rot(float a) {
+    float c = cos(a), s = sin(a);
+    return mat2(c, -s, s, c);
+}
+
+void main() {
+    vec2 uv = (in_uv + -0.5) * vec2(pc.resolution.x / pc.resolution.y, 1);
+
+    // Arrows move the camera through `pc.pos`
+    vec3 ro = vec3(0., 0., -3.) + pc.pos;
+    vec3 rd = normalize(vec3(uv, 1.5));
+    mat2 spin = rot(pc.time * 0.4);
+
+    float t = 0.;
+    vec3 col = vec3(0.02, 0.03, 0.05);
+    for (int i = 0; i < 128; i++) {
+        vec3 p = ro + rd * t;
+        p.xz *= spin;
+        float d = sdf(p);
+        if (d < 1e-3) {
+            vec3 n = normal(p);
+            vec3 light = normalize(vec3(0.6, 0.8, -0.4));
+            float diffuse = max(dot(n, light), 0.);
+            col = vec3(0.9, 0.6, 0.3) * (diffuse * 0.9 + 0.1);
+            break;
+        }
+        t += d;
+        if (t > 20.) {
+            break;
+        }
+    }
+    out_color = vec4(col, 1.0);
+}";
+
+pub const AUDIO_FRAG_MAIN: &str = "
+// Bars of the spectrum sent to the `spectrum` data channel, a fake one
+// while nothing arrives.
+float band(uint i, uint count) {
+    Data spectrum = pc.resources.data.channels[0];
+    if (spectrum.len > 0) {
+        return spectrum.values[min(i * spectrum.len / count, spectrum.len - 1)];
+    }
+    float x = float(i) / float(count);
+    return (0.5 + 0.5 * sin(pc.time * 3. + x * 17.)) * (1. - x) * 0.8;
+}
+
+void main() {
+    const uint BANDS = 64;
+    vec2 uv = vec2(in_uv.x, 1. - in_uv.y);
+    uint i = min(uint(uv.x * BANDS), BANDS - 1);
+    float level = clamp(band(i, BANDS), 0., 1.);
+
+    float cell = fract(uv.x * BANDS);
+    float bar = step(0.1, cell) * step(cell, 0.9) * step(uv.y, level);
+    vec3 col = mix(vec3(0.1, 0.8, 0.5), vec3(1., 0.2, 0.3), uv.y) * bar;
+    col += vec3(0.02, 0.02, 0.04) * (1. - bar);
+    out_color = vec4(col, 1.0);
+}";
+
 pub const VERT_SHADER: &str = "#version 460
 #extension GL_EXT_buffer_reference : require
 #extension GL_EXT_nonuniform_qualifier : require
@@ -46,6 +220,55 @@ pub const VERT_SHADER: &str = "#version 460
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
 
+layout(buffer_reference, std430) readonly buffer Data {
+    uint len;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer DataTable {
+    Data channels[];
+};
+layout(buffer_reference, std430) buffer Dmx {
+    float channels[];
+};
+layout(buffer_reference, std430) readonly buffer Table {
+    uint rows;
+    uint columns;
+    uint texture;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer Tables {
+    Table tables[];
+};
+layout(buffer_reference, std430) buffer StreamFeedback {
+    uint requested[];
+};
+layout(buffer_reference, std430) readonly buffer Stream {
+    StreamFeedback feedback;
+    uvec2 size;
+    uint tile_size;
+    uint tiles_x;
+    uint atlas;
+    uint atlas_columns;
+    uint overview;
+    uint pages[];
+};
+layout(buffer_reference, std430) readonly buffer Streams {
+    Stream streams[];
+};
+layout(buffer_reference, std430) buffer Particles {
+    vec4 particles[];
+};
+layout(buffer_reference, std430) readonly buffer Resources {
+    DataTable data;
+    Dmx dmx;
+    Tables tables;
+    Streams streams;
+    Particles particles;
+    uint particle_count;
+    uint particle_vertices;
+    uvec2 scene;
+};
+
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
     float time;
@@ -55,6 +278,11 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    vec4 picked_color;
+    Resources resources;
+    uint reload_count;
+    uint mouse_buttons;
+    vec2 scroll;
 }
 pc;
 
@@ -65,13 +293,62 @@ void main() {
     gl_Position = vec4(out_uv * 2.0f + -1.0f, 0.0, 1.0);
 }";
 
-pub const COMP_SHADER: &str = "#version 460
+pub const COMP_HEADER: &str = "#version 460
 #extension GL_EXT_nonuniform_qualifier : require
 #extension GL_EXT_buffer_reference : require
 
 layout(set = 0, binding = 0) uniform sampler gsamplers[];
 layout(set = 0, binding = 1) uniform texture2D gtextures[];
 
+layout(buffer_reference, std430) readonly buffer Data {
+    uint len;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer DataTable {
+    Data channels[];
+};
+layout(buffer_reference, std430) buffer Dmx {
+    float channels[];
+};
+layout(buffer_reference, std430) readonly buffer Table {
+    uint rows;
+    uint columns;
+    uint texture;
+    float values[];
+};
+layout(buffer_reference, std430) readonly buffer Tables {
+    Table tables[];
+};
+layout(buffer_reference, std430) buffer StreamFeedback {
+    uint requested[];
+};
+layout(buffer_reference, std430) readonly buffer Stream {
+    StreamFeedback feedback;
+    uvec2 size;
+    uint tile_size;
+    uint tiles_x;
+    uint atlas;
+    uint atlas_columns;
+    uint overview;
+    uint pages[];
+};
+layout(buffer_reference, std430) readonly buffer Streams {
+    Stream streams[];
+};
+layout(buffer_reference, std430) buffer Particles {
+    vec4 particles[];
+};
+layout(buffer_reference, std430) readonly buffer Resources {
+    DataTable data;
+    Dmx dmx;
+    Tables tables;
+    Streams streams;
+    Particles particles;
+    uint particle_count;
+    uint particle_vertices;
+    uvec2 scene;
+};
+
 layout(std430, push_constant) uniform PushConstant {
     vec3 pos;
     float time;
@@ -81,11 +358,18 @@ layout(std430, push_constant) uniform PushConstant {
     uint frame;
     float time_delta;
     float record_time;
+    vec4 picked_color;
+    Resources resources;
+    uint reload_count;
+    uint mouse_buttons;
+    vec2 scroll;
 }
 pc;
 
 layout (local_size_x = 16, local_size_y = 16, local_size_z = 1) in;
+";
 
+pub const COMP_MAIN: &str = "
 void main() {
     if (gl_GlobalInvocationID.x >= pc.resolution.x ||
         gl_GlobalInvocationID.y >= pc.resolution.y) {
@@ -93,18 +377,54 @@ void main() {
     }
 }";
 
+pub const PARTICLES_COMP_MAIN: &str = "
+float hash(uint n) {
+    n = (n << 13u) ^ n;
+    n = n * (n * n * 15731u + 789221u) + 1376312589u;
+    return float(n & 0x7fffffffu) / float(0x7fffffff);
+}
+
+// Particles drift along a curl-like flow field and respawn when they leave
+// the screen. `w` is their age, 0 until they are first seeded.
+void main() {
+    uint id = gl_GlobalInvocationID.y * gl_NumWorkGroups.x * gl_WorkGroupSize.x
+            + gl_GlobalInvocationID.x;
+    if (id >= pc.resources.particle_count) {
+        return;
+    }
+    float aspect = pc.resolution.x / pc.resolution.y;
+    vec4 p = pc.resources.particles.particles[id];
+    if (p.w == 0. || abs(p.x) > aspect * 0.5 || abs(p.y) > 0.5) {
+        uint seed = id * 2u + pc.frame * 7919u;
+        p = vec4((hash(seed) - 0.5) * aspect, hash(seed + 1u) - 0.5, 0., 1.);
+    }
+    vec2 q = p.xy * 3.;
+    float t = pc.time * 0.2;
+    vec2 flow = vec2(sin(q.y * 1.7 + t) + cos(q.x * 0.9 - t),
+                     cos(q.x * 1.3 - t) - sin(q.y * 0.7 + t));
+    p.xy += flow * pc.time_delta * 0.05;
+    p.w += pc.time_delta;
+    pc.resources.particles.particles[id] = p;
+}";
+
 pub const PRELUDE: &str = "const float PI = acos(-1.);
 const float TAU = 2. * PI;
 
-const uint PREV_TEX = 0;
-const uint GENERIC_TEX1 = 1;
-const uint GENERIC_TEX2 = 2;
-const uint DITHER_TEX = 3;
-const uint NOISE_TEX = 4;
-const uint BLUE_TEX = 5;
+const uint PREV_TEX = 0u;
+const uint GENERIC_TEX1 = 1u;
+const uint GENERIC_TEX2 = 2u;
+const uint DITHER_TEX = 3u;
+const uint NOISE_TEX = 4u;
+const uint BLUE_TEX = 5u;
+const uint HISTORY_TEX = 6u;
+
+const uint LINER_SAMPL = 0u;
+const uint NEAREST_SAMPL = 1u;
 
-const uint LINER_SAMPL = 0;
-const uint NEAREST_SAMPL = 1;
+// `pc.mouse` in the space of `(uv - 0.5) * vec2(aspect, 1)` and in pixels
+// from the bottom left, macros since `pc` is declared after the prelude
+#define mouse_uv() (pc.mouse * 0.5 * vec2(pc.resolution.x / pc.resolution.y, 1.))
+#define mouse_px() ((pc.mouse * 0.5 + 0.5) * pc.resolution)
 
 vec4 ASSERT_COL = vec4(0.);
 void assert(bool cond, int v) {
@@ -167,3 +487,572 @@ vec3 wnormal(in vec3 p) {
                         worldsdf(p + EPS.xyx) - worldsdf(p - EPS.xyx),
                         worldsdf(p + EPS.xxy) - worldsdf(p - EPS.xxy)));
 }";
+
+pub const POST_VERT_SHADER: &str = "#version 460
+
+layout(location = 0) out vec2 out_uv;
+
+void main() {
+    vec2 uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(uv * 2.0f + -1.0f, 0.0, 1.0);
+    // The viewport is flipped, keep texel rows and screen rows in the same order
+    out_uv = vec2(uv.x, 1.0 - uv.y);
+}";
+
+pub const POST_PRELUDE: &str = "#extension GL_EXT_nonuniform_qualifier : require
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+
+layout(std430, push_constant) uniform PostPushConstant {
+    vec2 resolution;
+    vec2 direction;
+    uint src;
+    uint aux;
+    uint mode;
+    float strength;
+}
+pc;
+
+vec4 Tex(uint id, vec2 uv) {
+    return texture(nonuniformEXT(sampler2D(gtextures[id], gsamplers[0])), uv);
+}";
+
+pub const POST_BLUR_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+// 9-tap Gaussian folded into 5 bilinear fetches, run once per axis.
+const float OFFSETS[3] = float[](0.0, 1.3846153846, 3.2307692308);
+const float WEIGHTS[3] = float[](0.2270270270, 0.3162162162, 0.0702702703);
+
+void main() {
+    vec2 texel = pc.direction / pc.resolution;
+    vec4 color = Tex(pc.src, in_uv) * WEIGHTS[0];
+    for (int i = 1; i < 3; i++) {
+        color += Tex(pc.src, in_uv + texel * OFFSETS[i]) * WEIGHTS[i];
+        color += Tex(pc.src, in_uv - texel * OFFSETS[i]) * WEIGHTS[i];
+    }
+    out_color = color;
+}";
+
+pub const POST_BLOOM_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+// Dual filter kernels from \"Bandwidth-Efficient Rendering\" by Marius Bjorge.
+vec4 downsample(vec2 uv, vec2 hp) {
+    vec4 sum = Tex(pc.src, uv) * 4.0;
+    sum += Tex(pc.src, uv - hp);
+    sum += Tex(pc.src, uv + hp);
+    sum += Tex(pc.src, uv + vec2(hp.x, -hp.y));
+    sum += Tex(pc.src, uv - vec2(hp.x, -hp.y));
+    return sum / 8.0;
+}
+
+vec4 upsample(vec2 uv, vec2 hp) {
+    vec4 sum = Tex(pc.src, uv + vec2(-hp.x * 2.0, 0.0));
+    sum += Tex(pc.src, uv + vec2(-hp.x, hp.y)) * 2.0;
+    sum += Tex(pc.src, uv + vec2(0.0, hp.y * 2.0));
+    sum += Tex(pc.src, uv + vec2(hp.x, hp.y)) * 2.0;
+    sum += Tex(pc.src, uv + vec2(hp.x * 2.0, 0.0));
+    sum += Tex(pc.src, uv + vec2(hp.x, -hp.y)) * 2.0;
+    sum += Tex(pc.src, uv + vec2(0.0, -hp.y * 2.0));
+    sum += Tex(pc.src, uv + vec2(-hp.x, -hp.y)) * 2.0;
+    return sum / 12.0;
+}
+
+void main() {
+    vec2 hp = pc.direction / pc.resolution;
+    switch (pc.mode) {
+    case 0: {
+        vec4 color = downsample(in_uv, hp);
+        float brightness = max(color.r, max(color.g, color.b));
+        out_color = color * max(brightness - pc.strength, 0.0) / max(brightness, 1e-4);
+        break;
+    }
+    case 1:
+        out_color = downsample(in_uv, hp);
+        break;
+    case 2:
+        out_color = upsample(in_uv, hp);
+        break;
+    default:
+        out_color = Tex(pc.aux, in_uv) + upsample(in_uv, hp) * pc.strength;
+        break;
+    }
+}";
+
+pub const POST_FXAA_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+const float FXAA_REDUCE_MIN = 1.0 / 128.0;
+const float FXAA_REDUCE_MUL = 1.0 / 8.0;
+const float FXAA_SPAN_MAX = 8.0;
+
+float luma(vec3 color) {
+    return dot(color, vec3(0.299, 0.587, 0.114));
+}
+
+void main() {
+    vec2 texel = 1.0 / pc.resolution;
+    vec4 center = Tex(pc.src, in_uv);
+    float luma_nw = luma(Tex(pc.src, in_uv + vec2(-1.0, -1.0) * texel).rgb);
+    float luma_ne = luma(Tex(pc.src, in_uv + vec2(1.0, -1.0) * texel).rgb);
+    float luma_sw = luma(Tex(pc.src, in_uv + vec2(-1.0, 1.0) * texel).rgb);
+    float luma_se = luma(Tex(pc.src, in_uv + vec2(1.0, 1.0) * texel).rgb);
+    float luma_m = luma(center.rgb);
+    float luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    float luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    vec2 dir = vec2(-((luma_nw + luma_ne) - (luma_sw + luma_se)),
+                    ((luma_nw + luma_sw) - (luma_ne + luma_se)));
+    float dir_reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * FXAA_REDUCE_MUL,
+                           FXAA_REDUCE_MIN);
+    float rcp_dir_min = 1.0 / (min(abs(dir.x), abs(dir.y)) + dir_reduce);
+    dir = clamp(dir * rcp_dir_min, -FXAA_SPAN_MAX, FXAA_SPAN_MAX) * texel;
+
+    vec3 rgb_a = 0.5 * (Tex(pc.src, in_uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+                        Tex(pc.src, in_uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    vec3 rgb_b = rgb_a * 0.5 + 0.25 * (Tex(pc.src, in_uv + dir * -0.5).rgb +
+                                       Tex(pc.src, in_uv + dir * 0.5).rgb);
+    float luma_b = luma(rgb_b);
+    vec3 color = (luma_b < luma_min || luma_b > luma_max) ? rgb_a : rgb_b;
+    out_color = vec4(color, center.a);
+}";
+
+pub const POST_PRESENT_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+void main() {
+    out_color = Tex(pc.src, in_uv);
+}";
+
+pub const POST_OVERLAY_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+void main() {
+    out_color = vec4(Tex(pc.src, in_uv).rgb, pc.strength);
+}";
+
+pub const POST_LAYER_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+// Matches `LayerBlend`, the blend state of each pipeline does the mixing
+const uint NORMAL = 0;
+const uint MULTIPLY = 2;
+
+void main() {
+    vec4 layer = Tex(pc.src, in_uv);
+    float k = layer.a * pc.strength;
+    if (pc.mode == NORMAL) {
+        out_color = vec4(layer.rgb, k);
+    } else if (pc.mode == MULTIPLY) {
+        out_color = vec4(mix(vec3(1.0), layer.rgb, k), 1.0);
+    } else {
+        out_color = vec4(layer.rgb * k, 1.0);
+    }
+}";
+
+pub const POST_GUIDES_SHADER: &str = "#version 460
+
+#include \"post.glsl\"
+
+// `pc.strength` is the aspect ratio of the framed area, 0 uses the window
+float line(float dist_px) {
+    return 1.0 - smoothstep(0.5, 1.5, abs(dist_px));
+}
+
+float rect(vec2 p, vec2 size, float margin) {
+    vec2 q = (abs(p - 0.5) - (0.5 - margin)) * size;
+    return line(max(q.x, q.y));
+}
+
+void main() {
+    vec2 px = in_uv * pc.resolution;
+    float aspect = pc.strength > 0.0 ? pc.strength : pc.resolution.x / pc.resolution.y;
+    vec2 size = pc.resolution;
+    if (aspect > size.x / size.y) {
+        size.y = size.x / aspect;
+    } else {
+        size.x = size.y * aspect;
+    }
+    vec2 p = (px - (pc.resolution - size) * 0.5) / size;
+
+    if (any(lessThan(p, vec2(0.0))) || any(greaterThan(p, vec2(1.0)))) {
+        out_color = vec4(0.0, 0.0, 0.0, 0.6);
+        return;
+    }
+
+    float thirds = 0.0;
+    for (int i = 1; i < 3; i++) {
+        thirds = max(thirds, line((p.x - float(i) / 3.0) * size.x));
+        thirds = max(thirds, line((p.y - float(i) / 3.0) * size.y));
+    }
+    vec2 center = abs(p - 0.5) * size;
+    float cross = max(line(center.x) * step(center.y, 12.0),
+                      line(center.y) * step(center.x, 12.0));
+    float action_safe = rect(p, size, 0.05);
+    float title_safe = rect(p, size, 0.1);
+
+    vec4 color = vec4(0.0);
+    color = mix(color, vec4(1.0, 1.0, 1.0, 0.4), thirds);
+    color = mix(color, vec4(0.3, 0.8, 1.0, 0.6), action_safe);
+    color = mix(color, vec4(1.0, 0.8, 0.3, 0.6), title_safe);
+    color = mix(color, vec4(1.0, 1.0, 1.0, 0.8), cross);
+    out_color = color;
+}";
+
+pub const POST_SLICE_VERT_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+
+layout(buffer_reference, std430) readonly buffer Mesh {
+    vec2 points[];
+};
+
+layout(std430, push_constant) uniform SlicePushConstant {
+    vec4 source;
+    vec4 output_rect;
+    vec4 blend;
+    Mesh mesh;
+    uint src;
+    uint columns;
+    uint rows;
+    float gamma;
+}
+pc;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec2 out_local;
+
+// Two triangles per mesh cell, corners in cell space
+const uvec2 CORNERS[6] = uvec2[](uvec2(0, 0), uvec2(1, 0), uvec2(1, 1),
+                                 uvec2(0, 0), uvec2(1, 1), uvec2(0, 1));
+
+void main() {
+    uint cell = gl_VertexIndex / 6;
+    uvec2 grid = uvec2(cell % (pc.columns - 1), cell / (pc.columns - 1)) +
+                 CORNERS[gl_VertexIndex % 6];
+    vec2 local = vec2(grid) / vec2(pc.columns - 1, pc.rows - 1);
+    vec2 point = pc.mesh.points[grid.y * pc.columns + grid.x];
+
+    // Window space with y down, the viewport flip turns it back
+    vec2 window = pc.output_rect.xy + point * pc.output_rect.zw;
+    gl_Position = vec4(window.x * 2.0 - 1.0, 1.0 - window.y * 2.0, 0.0, 1.0);
+    out_uv = pc.source.xy + local * pc.source.zw;
+    out_local = local;
+}";
+
+pub const POST_SLICE_FRAG_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_nonuniform_qualifier : require
+
+layout(buffer_reference, std430) readonly buffer Mesh {
+    vec2 points[];
+};
+
+layout(std430, push_constant) uniform SlicePushConstant {
+    vec4 source;
+    vec4 output_rect;
+    vec4 blend;
+    Mesh mesh;
+    uint src;
+    uint columns;
+    uint rows;
+    float gamma;
+}
+pc;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 1) in vec2 in_local;
+layout(location = 0) out vec4 out_color;
+
+layout(set = 0, binding = 0) uniform sampler gsamplers[];
+layout(set = 0, binding = 1) uniform texture2D gtextures[];
+
+float ramp(float dist, float width) {
+    return width > 0.0 ? clamp(dist / width, 0.0, 1.0) : 1.0;
+}
+
+void main() {
+    vec4 color = texture(nonuniformEXT(sampler2D(gtextures[pc.src], gsamplers[0])), in_uv);
+    // Edge blend widths are left, right, top, bottom in slice space, the ramp
+    // is linear in light once the projector applies its gamma
+    float weight = ramp(in_local.x, pc.blend.x) * ramp(1.0 - in_local.x, pc.blend.y) *
+                   ramp(in_local.y, pc.blend.z) * ramp(1.0 - in_local.y, pc.blend.w);
+    out_color = vec4(color.rgb * pow(weight, 1.0 / pc.gamma), 1.0);
+}";
+
+pub const POST_STATS_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+
+layout(buffer_reference, std430) readonly buffer Samples {
+    float ms[];
+};
+
+layout(std430, push_constant) uniform GraphPushConstant {
+    vec2 resolution;
+    float budget_ms;
+    uint count;
+    Samples samples;
+}
+pc;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+const vec2 MARGIN = vec2(16.0);
+const vec2 SIZE = vec2(480.0, 120.0);
+
+void main() {
+    // Pixels with y up from the bottom left corner of the graph
+    vec2 px = in_uv * pc.resolution - MARGIN;
+    px.y = SIZE.y - px.y;
+    if (any(lessThan(px, vec2(0.0))) || any(greaterThan(px, SIZE))) {
+        discard;
+    }
+
+    uint idx = min(uint(px.x / SIZE.x * float(pc.count)), pc.count - 1);
+    float ms = pc.samples.ms[idx];
+    // The budget sits at half height, anything past twice the budget is clipped
+    float height = ms / (2.0 * pc.budget_ms) * SIZE.y;
+
+    vec4 color = vec4(0.0, 0.0, 0.0, 0.5);
+    if (px.y < height) {
+        vec3 bar = ms <= pc.budget_ms * 1.05 ? vec3(0.3, 0.9, 0.4)
+                 : ms <= pc.budget_ms * 2.0  ? vec3(1.0, 0.8, 0.2)
+                                             : vec3(1.0, 0.3, 0.3);
+        color = vec4(bar, 0.9);
+    }
+    if (abs(px.y - SIZE.y * 0.5) < 0.5) {
+        color = vec4(1.0, 1.0, 1.0, 0.6);
+    }
+    out_color = color;
+}";
+
+pub const POST_HUD_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+
+layout(buffer_reference, std430) readonly buffer Text {
+    uint chars[];
+};
+
+layout(std430, push_constant) uniform HudPushConstant {
+    vec2 resolution;
+    float scale;
+    uint columns;
+    uint lines;
+    Text text;
+}
+pc;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+const vec2 MARGIN = vec2(16.0);
+// Font pixels of a glyph and of a cell with its spacing
+const ivec2 GLYPH = ivec2(3, 5);
+const vec2 CELL = vec2(4.0, 7.0);
+const float PADDING = 2.0;
+
+// 3x5 glyphs of ASCII 32 to 95, the top left pixel in bit 14
+const uint FONT[64] = uint[](
+    0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x52a5u, 0x0000u, 0x0000u,
+    0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x01c0u, 0x0002u, 0x12a4u,
+    0x7b6fu, 0x2c97u, 0x73e7u, 0x72cfu, 0x5bc9u, 0x79cfu, 0x79efu, 0x7292u,
+    0x7befu, 0x7bcfu, 0x0410u, 0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x0000u,
+    0x0000u, 0x2bedu, 0x6baeu, 0x3923u, 0x6b6eu, 0x79a7u, 0x79a4u, 0x396bu,
+    0x5bedu, 0x7497u, 0x126au, 0x5badu, 0x4927u, 0x5fedu, 0x5ffdu, 0x2b6au,
+    0x6ba4u, 0x2b73u, 0x6badu, 0x388eu, 0x7492u, 0x5b6bu, 0x5b52u, 0x5bfdu,
+    0x5aadu, 0x5a92u, 0x72a7u, 0x0000u, 0x0000u, 0x0000u, 0x0000u, 0x0000u
+);
+
+void main() {
+    vec2 size = (vec2(pc.columns, pc.lines) * CELL + 2.0 * PADDING) * pc.scale;
+    // Font pixels with y down from the top left corner of the box
+    vec2 px = in_uv * pc.resolution - vec2(pc.resolution.x - MARGIN.x - size.x, MARGIN.y);
+    if (any(lessThan(px, vec2(0.0))) || any(greaterThanEqual(px, size))) {
+        discard;
+    }
+    vec2 p = px / pc.scale - PADDING;
+
+    out_color = vec4(0.0, 0.0, 0.0, 0.5);
+    ivec2 cell = ivec2(floor(p / CELL));
+    if (any(lessThan(p, vec2(0.0))) || cell.x >= int(pc.columns) || cell.y >= int(pc.lines)) {
+        return;
+    }
+    ivec2 glyph = ivec2(floor(p - vec2(cell) * CELL));
+    if (any(greaterThanEqual(glyph, GLYPH))) {
+        return;
+    }
+    uint c = pc.text.chars[cell.y * int(pc.columns) + cell.x];
+    uint bits = c >= 32u && c < 96u ? FONT[c - 32u] : 0u;
+    if (((bits >> (14 - glyph.y * 3 - glyph.x)) & 1u) != 0u) {
+        out_color = vec4(1.0, 1.0, 1.0, 0.9);
+    }
+}";
+
+pub const POST_HEATMAP_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+
+layout(buffer_reference, std430) readonly buffer Costs {
+    float ms[];
+};
+
+layout(std430, push_constant) uniform HeatmapPushConstant {
+    uvec2 grid;
+    float max_ms;
+    float opacity;
+    Costs costs;
+}
+pc;
+
+layout(location = 0) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+// Blue for cheap tiles through green to red for the slowest one
+vec3 heat(float t) {
+    return clamp(vec3(2.0 * t - 0.5, 2.0 - abs(4.0 * t - 2.0), 1.5 - 2.0 * t), 0.0, 1.0);
+}
+
+void main() {
+    vec2 cell = in_uv * vec2(pc.grid);
+    uvec2 tile = min(uvec2(cell), pc.grid - 1u);
+    float ms = pc.costs.ms[tile.y * pc.grid.x + tile.x];
+    float t = pc.max_ms > 0.0 ? ms / pc.max_ms : 0.0;
+    // Thin lines between the tiles
+    bool border = any(lessThan(fract(cell), fwidth(cell)));
+    out_color = vec4(heat(t), border ? 0.9 : pc.opacity);
+}";
+
+pub const PARTICLES_VERT_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Positions come from `pc.resources.particles`, written by `shader.comp` or `init.comp`
+// in the space of `(uv - 0.5) * vec2(aspect, 1)`.
+
+layout(buffer_reference, std430) buffer Particles {
+    vec4 particles[];
+};
+layout(buffer_reference, std430) readonly buffer Resources {
+    uint64_t data;
+    uint64_t dmx;
+    uint64_t tables;
+    uint64_t streams;
+    Particles particles;
+    uint particle_count;
+    uint particle_vertices;
+    uvec2 scene;
+};
+
+layout(std430, push_constant) uniform PushConstant {
+    vec3 pos;
+    float time;
+    vec2 resolution;
+    vec2 mouse;
+    bool mouse_pressed;
+    uint frame;
+    float time_delta;
+    float record_time;
+    vec4 picked_color;
+    Resources resources;
+    uint reload_count;
+    uint mouse_buttons;
+    vec2 scroll;
+}
+pc;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    uint id = gl_VertexIndex / max(pc.resources.particle_vertices, 1u);
+    vec4 particle = pc.resources.particles.particles[id];
+    float aspect = pc.resolution.x / pc.resolution.y;
+    gl_Position = vec4(particle.xy * 2. / vec2(aspect, 1.), 0., 1.);
+    gl_PointSize = 1.;
+    out_color = vec4(vec3(0.05), 1.);
+}";
+
+pub const PARTICLES_FRAG_SHADER: &str = "#version 460
+
+layout(location = 0) in vec4 in_color;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    out_color = in_color;
+}";
+
+pub const MESH_VERT_SHADER: &str = "#version 460
+#extension GL_EXT_buffer_reference : require
+#extension GL_EXT_shader_explicit_arithmetic_types_int64 : require
+
+// Inputs follow `attributes` of the `[mesh]` table, the default layout is
+// position, normal and uv. With `fit` the mesh fills the -1 to 1 cube.
+
+layout(location = 0) in vec3 in_position;
+layout(location = 1) in vec3 in_normal;
+layout(location = 2) in vec2 in_uv;
+
+layout(std430, push_constant) uniform PushConstant {
+    vec3 pos;
+    float time;
+    vec2 resolution;
+    vec2 mouse;
+    bool mouse_pressed;
+    uint frame;
+    float time_delta;
+    float record_time;
+    vec4 picked_color;
+    uint64_t resources;
+    uint reload_count;
+    uint mouse_buttons;
+    vec2 scroll;
+}
+pc;
+
+layout(location = 0) out vec3 out_normal;
+layout(location = 1) out vec2 out_uv;
+
+// Right handed view space to Vulkan clip space with depth from 0 to 1.
+mat4 perspective(float fov, float aspect, float near, float far) {
+    float f = 1. / tan(fov / 2.);
+    return mat4(
+        f / aspect, 0., 0., 0.,
+        0., f, 0., 0.,
+        0., 0., far / (near - far), -1.,
+        0., 0., near * far / (near - far), 0.);
+}
+
+void main() {
+    float angle = pc.time * 0.5;
+    mat3 orbit = mat3(
+        cos(angle), 0., -sin(angle),
+        0., 1., 0.,
+        sin(angle), 0., cos(angle));
+    vec3 camera = vec3(0., 0., 4.) + pc.pos;
+    vec3 view = orbit * in_position - camera;
+    float aspect = pc.resolution.x / pc.resolution.y;
+    gl_Position = perspective(radians(45.), aspect, 0.1, 100.) * vec4(view, 1.);
+    out_normal = orbit * in_normal;
+    out_uv = in_uv;
+}";
+
+pub const MESH_FRAG_SHADER: &str = "#version 460
+
+layout(location = 0) in vec3 in_normal;
+layout(location = 1) in vec2 in_uv;
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    vec3 light = normalize(vec3(0.5, 1., 0.8));
+    float diffuse = max(dot(normalize(in_normal), light), 0.);
+    out_color = vec4(vec3(0.1 + 0.9 * diffuse), 1.);
+}";
@@ -1,13 +1,102 @@
 use std::fs::File;
 use std::io::Write;
 use std::path::Path;
+use std::str::FromStr;
 
-use crate::create_folder;
+use anyhow::bail;
+
+use crate::{
+    create_folder, MANIFEST_FILE, SCREENSHOT_FOLDER, SHADER_DUMP_FOLDER, VALIDATION_LOG_FILE,
+    VIDEO_FOLDER, WINDOW_STATE_FILE,
+};
 
 mod glsl;
 
-pub fn create_default_shaders<P: AsRef<Path>>(name: P) -> std::io::Result<()> {
-    create_folder(&name)?;
+/// Manifest of a new project whose template doesn't need one, every key is
+/// optional.
+const STARTER_MANIFEST: &str = "# See the Manifest section of the pilka README for every table
+
+# [window]
+# size = [1280, 720]
+
+# keep_state = true                 # keep feedback and particles across reloads
+# samples = 4                       # antialiasing of the scene
+
+# [record]
+# codec = \"x265\"
+";
+
+const AUDIO_MANIFEST: &str =
+    "# Spectrum bins in 0..1, e.g. from an FFT script, one datagram per update
+[[data]]
+name = \"spectrum\"
+udp = 9000
+";
+
+const PARTICLES_MANIFEST: &str = "[particles]
+count = 200000
+blend = \"add\"
+";
+
+/// Starting point of a new project.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    /// The uv gradient.
+    #[default]
+    Default,
+    Plasma,
+    Raymarch,
+    /// Bars of a spectrum received over UDP.
+    Audio,
+    /// Particles moved by `shader.comp` through a flow field.
+    Particles,
+}
+
+impl Template {
+    fn frag_main(self) -> &'static str {
+        match self {
+            Template::Default | Template::Particles => glsl::FRAG_MAIN,
+            Template::Plasma => glsl::PLASMA_FRAG_MAIN,
+            Template::Raymarch => glsl::RAYMARCH_FRAG_MAIN,
+            Template::Audio => glsl::AUDIO_FRAG_MAIN,
+        }
+    }
+
+    fn comp_main(self) -> &'static str {
+        match self {
+            Template::Particles => glsl::PARTICLES_COMP_MAIN,
+            _ => glsl::COMP_MAIN,
+        }
+    }
+
+    fn manifest(self) -> Option<&'static str> {
+        match self {
+            Template::Audio => Some(AUDIO_MANIFEST),
+            Template::Particles => Some(PARTICLES_MANIFEST),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "default" => Template::Default,
+            "plasma" => Template::Plasma,
+            "raymarch" => Template::Raymarch,
+            "audio" => Template::Audio,
+            "particles" => Template::Particles,
+            _ => bail!(
+                "Unknown template `{s}`, expected default, plasma, raymarch, audio or particles"
+            ),
+        })
+    }
+}
+
+pub fn create_default_shaders<P: AsRef<Path>>(name: P, template: Template) -> std::io::Result<()> {
+    std::fs::create_dir_all(&name)?;
 
     let create_file = |filename: &str, content: &str| -> std::io::Result<()> {
         let path = name.as_ref().join(filename);
@@ -16,9 +105,98 @@ pub fn create_default_shaders<P: AsRef<Path>>(name: P) -> std::io::Result<()> {
     };
 
     create_file("prelude.glsl", glsl::PRELUDE)?;
-    create_file("shader.frag", glsl::FRAG_SHADER)?;
+    create_file(
+        "shader.frag",
+        &[glsl::FRAG_HEADER, template.frag_main()].concat(),
+    )?;
     create_file("shader.vert", glsl::VERT_SHADER)?;
-    create_file("shader.comp", glsl::COMP_SHADER)?;
+    create_file(
+        "shader.comp",
+        &[glsl::COMP_HEADER, template.comp_main()].concat(),
+    )?;
+    if let Some(manifest) = template.manifest() {
+        create_file(MANIFEST_FILE, manifest)?;
+    }
+
+    Ok(())
+}
 
+/// `pilka new`: a project folder with the shaders of `template`, a manifest
+/// and a `.gitignore` for what running it writes.
+pub fn create_project<P: AsRef<Path>>(name: P, template: Template) -> std::io::Result<()> {
+    let dir = name.as_ref();
+    create_default_shaders(dir, template)?;
+    if template.manifest().is_none() {
+        std::fs::write(dir.join(MANIFEST_FILE), STARTER_MANIFEST)?;
+    }
+    let ignored = [
+        WINDOW_STATE_FILE,
+        VIDEO_FOLDER,
+        SCREENSHOT_FOLDER,
+        SHADER_DUMP_FOLDER,
+        VALIDATION_LOG_FILE,
+    ]
+    .map(|name| format!("/{name}\n"))
+    .concat();
+    std::fs::write(dir.join(".gitignore"), ignored)?;
+    Ok(())
+}
+
+/// Writes the built-in post passes, leaving files the user already edited alone.
+pub fn create_post_shaders<P: AsRef<Path>>(name: P) -> std::io::Result<()> {
+    create_folder(&name)?;
+
+    let create_file = |filename: &str, content: &str| -> std::io::Result<()> {
+        let path = name.as_ref().join(filename);
+        if path.exists() {
+            return Ok(());
+        }
+        let mut file = File::create(path)?;
+        file.write_all(content.as_bytes())
+    };
+
+    create_file("post.glsl", glsl::POST_PRELUDE)?;
+    create_file("post.vert", glsl::POST_VERT_SHADER)?;
+    create_file("blur.frag", glsl::POST_BLUR_SHADER)?;
+    create_file("bloom.frag", glsl::POST_BLOOM_SHADER)?;
+    create_file("fxaa.frag", glsl::POST_FXAA_SHADER)?;
+    create_file("present.frag", glsl::POST_PRESENT_SHADER)?;
+    create_file("overlay.frag", glsl::POST_OVERLAY_SHADER)?;
+    create_file("guides.frag", glsl::POST_GUIDES_SHADER)?;
+    create_file("slice.vert", glsl::POST_SLICE_VERT_SHADER)?;
+    create_file("slice.frag", glsl::POST_SLICE_FRAG_SHADER)?;
+    create_file("stats.frag", glsl::POST_STATS_SHADER)?;
+    create_file("heatmap.frag", glsl::POST_HEATMAP_SHADER)?;
+    create_file("hud.frag", glsl::POST_HUD_SHADER)?;
+    create_file("layer.frag", glsl::POST_LAYER_SHADER)?;
+
+    Ok(())
+}
+
+/// Writes the particle shaders into the project, leaving existing ones alone.
+pub fn create_particle_shaders<P: AsRef<Path>>(name: P) -> std::io::Result<()> {
+    for (filename, content) in [
+        ("particles.vert", glsl::PARTICLES_VERT_SHADER),
+        ("particles.frag", glsl::PARTICLES_FRAG_SHADER),
+    ] {
+        let path = name.as_ref().join(filename);
+        if !path.exists() {
+            std::fs::write(path, content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Writes the mesh shaders into the project, leaving existing ones alone.
+pub fn create_mesh_shaders<P: AsRef<Path>>(name: P) -> std::io::Result<()> {
+    for (filename, content) in [
+        ("mesh.vert", glsl::MESH_VERT_SHADER),
+        ("mesh.frag", glsl::MESH_FRAG_SHADER),
+    ] {
+        let path = name.as_ref().join(filename);
+        if !path.exists() {
+            std::fs::write(path, content)?;
+        }
+    }
     Ok(())
 }
@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{swapchain::FrameGuard, DepthDesc, Device, ManagedImage};
+
+const DEPTH_FORMAT: vk::Format = vk::Format::D32_SFLOAT;
+
+const DEPTH_SUBRESOURCE_RANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
+    aspect_mask: vk::ImageAspectFlags::DEPTH,
+    base_mip_level: 0,
+    level_count: 1,
+    base_array_layer: 0,
+    layer_count: 1,
+};
+
+/// How a pipeline uses the scene depth buffer.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepthMode {
+    #[default]
+    Off,
+    Test,
+    Write,
+    TestWrite,
+}
+
+impl DepthMode {
+    fn desc(self) -> DepthDesc {
+        DepthDesc {
+            format: DEPTH_FORMAT,
+            test: matches!(self, DepthMode::Test | DepthMode::TestWrite),
+            write: matches!(self, DepthMode::Write | DepthMode::TestWrite),
+        }
+    }
+}
+
+/// The `[depth]` table of the manifest, the depth use of every pass drawn
+/// into the scene.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DepthSettings {
+    /// Writes need `gl_FragDepth`, the fullscreen triangle is at depth 0.
+    pub image: DepthMode,
+    pub passes: DepthMode,
+    pub particles: DepthMode,
+}
+
+struct DepthTarget {
+    image: ManagedImage,
+    view: vk::ImageView,
+    device: Arc<Device>,
+}
+
+impl DepthTarget {
+    fn new(
+        device: &Arc<Device>,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(DEPTH_FORMAT)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT);
+        let image = ManagedImage::new(device, &info, gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS)?;
+        device.name_object(image.image, "Scene Depth");
+        let view = unsafe {
+            device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .image(image.image)
+                    .format(DEPTH_FORMAT)
+                    .subresource_range(DEPTH_SUBRESOURCE_RANGE),
+                None,
+            )?
+        };
+        Ok(Self {
+            image,
+            view,
+            device: device.clone(),
+        })
+    }
+}
+
+impl Drop for DepthTarget {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_image_view(self.view, None) };
+    }
+}
+
+/// Depth buffer at render resolution shared by the passes drawn into the
+/// scene, cleared to 1 every frame. Only allocated when a pass uses it.
+pub struct SceneDepth {
+    settings: DepthSettings,
+    samples: vk::SampleCountFlags,
+    target: Option<DepthTarget>,
+}
+
+impl Default for SceneDepth {
+    fn default() -> Self {
+        Self {
+            settings: DepthSettings::default(),
+            samples: vk::SampleCountFlags::TYPE_1,
+            target: None,
+        }
+    }
+}
+
+impl SceneDepth {
+    /// `mesh` keeps the buffer even without any pass opting in, `samples`
+    /// has to match the scene color target.
+    pub fn configure(
+        &mut self,
+        settings: DepthSettings,
+        mesh: bool,
+        samples: vk::SampleCountFlags,
+        device: &Arc<Device>,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        self.settings = settings;
+        self.samples = samples;
+        let enabled = mesh || settings != DepthSettings::default();
+        self.target = match enabled {
+            true => Some(DepthTarget::new(device, samples, extent)?),
+            false => None,
+        };
+        Ok(())
+    }
+
+    pub fn resize(&mut self, device: &Arc<Device>, extent: vk::Extent2D) -> Result<()> {
+        if self.target.is_some() {
+            self.target = Some(DepthTarget::new(device, self.samples, extent)?);
+        }
+        Ok(())
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.target.is_some()
+    }
+
+    fn desc(&self, mode: DepthMode) -> Option<DepthDesc> {
+        self.target.as_ref().map(|_| mode.desc())
+    }
+
+    pub fn image(&self) -> Option<DepthDesc> {
+        self.desc(self.settings.image)
+    }
+
+    pub fn passes(&self) -> Option<DepthDesc> {
+        self.desc(self.settings.passes)
+    }
+
+    pub fn particles(&self) -> Option<DepthDesc> {
+        self.desc(self.settings.particles)
+    }
+
+    pub fn mesh(&self) -> Option<DepthDesc> {
+        self.desc(DepthMode::TestWrite)
+    }
+
+    /// Readies the buffer for the scene rendering and returns its view.
+    pub fn begin(&self, frame: &FrameGuard, device: &Device) -> Option<vk::ImageView> {
+        let target = self.target.as_ref()?;
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(DEPTH_SUBRESOURCE_RANGE)
+            .src_stage_mask(vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS)
+            .src_access_mask(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS
+                    | vk::PipelineStageFlags2::LATE_FRAGMENT_TESTS,
+            )
+            .dst_access_mask(
+                vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_READ
+                    | vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            )
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+            .image(target.image.image);
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                *frame.command_buffer(),
+                &vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&barrier)),
+            )
+        };
+        Some(target.view)
+    }
+}
@@ -1,5 +1,5 @@
 use anyhow::Result;
-use gpu_alloc::{GpuAllocator, MemoryBlock, Request, UsageFlags};
+use gpu_alloc::{GpuAllocator, MemoryBlock, MemoryPropertyFlags, Request, UsageFlags};
 use gpu_alloc_ash::AshMemoryDevice;
 use parking_lot::Mutex;
 use std::{
@@ -14,20 +14,35 @@ use ash::{
     vk::{self, DeviceMemory, Handle},
 };
 
-use crate::{align_to, ManagedImage, COLOR_SUBRESOURCE_MASK};
+use crate::{
+    align_to,
+    audit::{MemoryKind, ObjectAudit, ObjectKind},
+    ManagedImage, COLOR_SUBRESOURCE_MASK,
+};
 
 pub struct Device {
+    pub(crate) instance: ash::Instance,
     pub physical_device: vk::PhysicalDevice,
     pub memory_properties: vk::PhysicalDeviceMemoryProperties,
     pub device_properties: vk::PhysicalDeviceProperties,
+    /// PCI address of the GPU, `None` without `VK_EXT_pci_bus_info`.
+    pub pci_bus_info: Option<vk::PhysicalDevicePCIBusInfoPropertiesEXT<'static>>,
+    /// Optional core features turned on at creation.
+    pub enabled_features: vk::PhysicalDeviceFeatures,
     pub descriptor_indexing_props: vk::PhysicalDeviceDescriptorIndexingProperties<'static>,
+    /// `VK_EXT_graphics_pipeline_library` is enabled, render pipelines are
+    /// linked from per-stage libraries instead of built whole.
+    pub pipeline_libraries: bool,
     pub command_pool: vk::CommandPool,
     pub main_queue_family_idx: u32,
     pub transfer_queue_family_idx: u32,
     pub allocator: Arc<Mutex<GpuAllocator<DeviceMemory>>>,
     pub device: ash::Device,
     pub dynamic_rendering: khr::dynamic_rendering::Device,
+    /// Present when the device supports ray queries.
+    pub acceleration_structure: Option<khr::acceleration_structure::Device>,
     pub(crate) dbg_utils: ext::debug_utils::Device,
+    pub audit: ObjectAudit,
 }
 
 impl std::ops::Deref for Device {
@@ -38,6 +53,122 @@ impl std::ops::Deref for Device {
     }
 }
 
+/// Shadows the `ash::Device` constructors and destructors reached through
+/// `Deref`, so every object made through [`Device`] is counted in its audit.
+macro_rules! audited {
+    ($($kind:ident: $create:ident($info:ty) -> $handle:ty, $destroy:ident;)*) => {
+        #[allow(clippy::missing_safety_doc)]
+        impl Device {
+            $(
+                pub unsafe fn $create(
+                    &self,
+                    create_info: &$info,
+                    allocation_callbacks: Option<&vk::AllocationCallbacks<'_>>,
+                ) -> VkResult<$handle> {
+                    let handle = self.device.$create(create_info, allocation_callbacks)?;
+                    self.audit.created(ObjectKind::$kind, 1);
+                    Ok(handle)
+                }
+
+                pub unsafe fn $destroy(
+                    &self,
+                    handle: $handle,
+                    allocation_callbacks: Option<&vk::AllocationCallbacks<'_>>,
+                ) {
+                    if handle != <$handle>::null() {
+                        self.audit.destroyed(ObjectKind::$kind, 1);
+                    }
+                    self.device.$destroy(handle, allocation_callbacks)
+                }
+            )*
+        }
+    };
+}
+
+audited! {
+    PipelineLayout: create_pipeline_layout(vk::PipelineLayoutCreateInfo<'_>) -> vk::PipelineLayout, destroy_pipeline_layout;
+    DescriptorSetLayout: create_descriptor_set_layout(vk::DescriptorSetLayoutCreateInfo<'_>) -> vk::DescriptorSetLayout, destroy_descriptor_set_layout;
+    DescriptorPool: create_descriptor_pool(vk::DescriptorPoolCreateInfo<'_>) -> vk::DescriptorPool, destroy_descriptor_pool;
+    Semaphore: create_semaphore(vk::SemaphoreCreateInfo<'_>) -> vk::Semaphore, destroy_semaphore;
+    Fence: create_fence(vk::FenceCreateInfo<'_>) -> vk::Fence, destroy_fence;
+    Image: create_image(vk::ImageCreateInfo<'_>) -> vk::Image, destroy_image;
+    ImageView: create_image_view(vk::ImageViewCreateInfo<'_>) -> vk::ImageView, destroy_image_view;
+    Sampler: create_sampler(vk::SamplerCreateInfo<'_>) -> vk::Sampler, destroy_sampler;
+    Buffer: create_buffer(vk::BufferCreateInfo<'_>) -> vk::Buffer, destroy_buffer;
+    QueryPool: create_query_pool(vk::QueryPoolCreateInfo<'_>) -> vk::QueryPool, destroy_query_pool;
+}
+
+#[allow(clippy::missing_safety_doc)]
+impl Device {
+    pub unsafe fn create_graphics_pipelines(
+        &self,
+        pipeline_cache: vk::PipelineCache,
+        create_infos: &[vk::GraphicsPipelineCreateInfo<'_>],
+        allocation_callbacks: Option<&vk::AllocationCallbacks<'_>>,
+    ) -> Result<Vec<vk::Pipeline>, (Vec<vk::Pipeline>, vk::Result)> {
+        let result = self.device.create_graphics_pipelines(
+            pipeline_cache,
+            create_infos,
+            allocation_callbacks,
+        );
+        self.count_pipelines(&result);
+        result
+    }
+
+    pub unsafe fn create_compute_pipelines(
+        &self,
+        pipeline_cache: vk::PipelineCache,
+        create_infos: &[vk::ComputePipelineCreateInfo<'_>],
+        allocation_callbacks: Option<&vk::AllocationCallbacks<'_>>,
+    ) -> Result<Vec<vk::Pipeline>, (Vec<vk::Pipeline>, vk::Result)> {
+        let result = self.device.create_compute_pipelines(
+            pipeline_cache,
+            create_infos,
+            allocation_callbacks,
+        );
+        self.count_pipelines(&result);
+        result
+    }
+
+    fn count_pipelines(&self, result: &Result<Vec<vk::Pipeline>, (Vec<vk::Pipeline>, vk::Result)>) {
+        let (Ok(pipelines) | Err((pipelines, _))) = result;
+        let created = pipelines.iter().filter(|p| **p != vk::Pipeline::null());
+        self.audit.created(ObjectKind::Pipeline, created.count());
+    }
+
+    pub unsafe fn destroy_pipeline(
+        &self,
+        pipeline: vk::Pipeline,
+        allocation_callbacks: Option<&vk::AllocationCallbacks<'_>>,
+    ) {
+        if pipeline != vk::Pipeline::null() {
+            self.audit.destroyed(ObjectKind::Pipeline, 1);
+        }
+        self.device.destroy_pipeline(pipeline, allocation_callbacks)
+    }
+
+    pub unsafe fn allocate_command_buffers(
+        &self,
+        allocate_info: &vk::CommandBufferAllocateInfo<'_>,
+    ) -> VkResult<Vec<vk::CommandBuffer>> {
+        let command_buffers = self.device.allocate_command_buffers(allocate_info)?;
+        self.audit
+            .created(ObjectKind::CommandBuffer, command_buffers.len());
+        Ok(command_buffers)
+    }
+
+    pub unsafe fn free_command_buffers(
+        &self,
+        command_pool: vk::CommandPool,
+        command_buffers: &[vk::CommandBuffer],
+    ) {
+        self.audit
+            .destroyed(ObjectKind::CommandBuffer, command_buffers.len());
+        self.device
+            .free_command_buffers(command_pool, command_buffers)
+    }
+}
+
 impl Device {
     pub fn name_object(&self, handle: impl Handle, name: &str) {
         let name = CString::new(name).unwrap();
@@ -71,10 +202,49 @@ impl Device {
         Ok(view)
     }
 
+    pub fn create_2d_array_view(
+        &self,
+        image: &vk::Image,
+        format: vk::Format,
+        layers: u32,
+    ) -> VkResult<vk::ImageView> {
+        let view = unsafe {
+            self.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+                    .image(*image)
+                    .format(format)
+                    .subresource_range(
+                        vk::ImageSubresourceRange::default()
+                            .aspect_mask(vk::ImageAspectFlags::COLOR)
+                            .base_mip_level(0)
+                            .level_count(1)
+                            .base_array_layer(0)
+                            .layer_count(layers),
+                    ),
+                None,
+            )?
+        };
+        Ok(view)
+    }
+
     pub fn one_time_submit(
         &self,
         queue: &vk::Queue,
         callbk: impl FnOnce(&Self, vk::CommandBuffer),
+    ) -> VkResult<()> {
+        self.one_time_submit_timeout(queue, u64::MAX, callbk)
+    }
+
+    /// Like [`Self::one_time_submit`], giving up with `TIMEOUT` after
+    /// `timeout` nanoseconds. The command buffer and fence are leaked then,
+    /// the GPU may still be using them, so the device has to be treated as
+    /// lost.
+    pub fn one_time_submit_timeout(
+        &self,
+        queue: &vk::Queue,
+        timeout: u64,
+        callbk: impl FnOnce(&Self, vk::CommandBuffer),
     ) -> VkResult<()> {
         let fence = unsafe { self.create_fence(&vk::FenceCreateInfo::default(), None)? };
         let command_buffer = unsafe {
@@ -83,37 +253,48 @@ impl Device {
                     .command_pool(self.command_pool)
                     .command_buffer_count(1)
                     .level(vk::CommandBufferLevel::PRIMARY),
-            )?[0]
+            )
+        };
+        let command_buffer = match command_buffer {
+            Ok(command_buffers) => command_buffers[0],
+            Err(err) => {
+                unsafe { self.destroy_fence(fence, None) };
+                return Err(err);
+            }
         };
 
-        unsafe {
+        let result = unsafe {
             self.begin_command_buffer(
                 command_buffer,
                 &vk::CommandBufferBeginInfo::default()
                     .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-            )?;
-
-            callbk(self, command_buffer);
-
-            self.end_command_buffer(command_buffer)?;
-
-            let submit_info =
-                vk::SubmitInfo::default().command_buffers(std::slice::from_ref(&command_buffer));
-
-            self.queue_submit(*queue, &[submit_info], fence)?;
-            self.wait_for_fences(&[fence], true, u64::MAX)?;
+            )
+            .and_then(|()| {
+                callbk(self, command_buffer);
+                self.end_command_buffer(command_buffer)
+            })
+            .and_then(|()| {
+                let submit_info = vk::SubmitInfo::default()
+                    .command_buffers(std::slice::from_ref(&command_buffer));
+                self.queue_submit(*queue, &[submit_info], fence)
+            })
+            .and_then(|()| self.wait_for_fences(&[fence], true, timeout))
+        };
 
-            self.destroy_fence(fence, None);
-            self.free_command_buffers(self.command_pool, &[command_buffer]);
+        if result != Err(vk::Result::TIMEOUT) {
+            unsafe {
+                self.destroy_fence(fence, None);
+                self.free_command_buffers(self.command_pool, &[command_buffer]);
+            }
         }
-
-        Ok(())
+        result
     }
 
     pub fn alloc_memory(
         &self,
         memory_reqs: vk::MemoryRequirements,
         usage: UsageFlags,
+        kind: MemoryKind,
     ) -> Result<gpu_alloc::MemoryBlock<DeviceMemory>, gpu_alloc::AllocationError> {
         let mut allocator = self.allocator.lock();
         let memory_block = unsafe {
@@ -127,14 +308,63 @@ impl Device {
                 },
             )
         };
+        if let Ok(block) = &memory_block {
+            self.audit.created(ObjectKind::Memory, 1);
+            let heap_idx = self.memory_properties.memory_types[block.memory_type() as usize]
+                .heap_index as usize;
+            let used = self.audit.allocated(
+                kind,
+                heap_idx,
+                *block.memory(),
+                block.offset(),
+                block.size(),
+            );
+            let heap = self.memory_properties.memory_heaps[heap_idx];
+            if self.audit.heap_nearly_full(heap_idx, used, heap.size) {
+                log::warn!(
+                    "pilka uses {} of {} MiB of memory heap {heap_idx}, \
+                     smaller windows or fewer targets leave room for the rest",
+                    used >> 20,
+                    heap.size >> 20
+                );
+            }
+        }
         memory_block
     }
 
     pub fn dealloc_memory(&self, block: MemoryBlock<DeviceMemory>) {
+        self.audit.destroyed(ObjectKind::Memory, 1);
+        self.audit
+            .freed(*block.memory(), block.offset(), block.size());
         let mut allocator = self.allocator.lock();
         unsafe { allocator.dealloc(AshMemoryDevice::wrap(self), block) };
     }
 
+    pub fn format_features(
+        &self,
+        format: vk::Format,
+        tiling: vk::ImageTiling,
+    ) -> vk::FormatFeatureFlags {
+        let properties = unsafe {
+            self.instance
+                .get_physical_device_format_properties(self.physical_device, format)
+        };
+        match tiling {
+            vk::ImageTiling::LINEAR => properties.linear_tiling_features,
+            _ => properties.optimal_tiling_features,
+        }
+    }
+
+    /// Whether `src_format` images can be blitted straight into a linear
+    /// RGBA readback image.
+    pub fn supports_capture_blit(&self, src_format: vk::Format) -> bool {
+        self.format_features(src_format, vk::ImageTiling::OPTIMAL)
+            .contains(vk::FormatFeatureFlags::BLIT_SRC)
+            && self
+                .format_features(capture_format(src_format), vk::ImageTiling::LINEAR)
+                .contains(vk::FormatFeatureFlags::BLIT_DST)
+    }
+
     pub fn blit_image(
         &self,
         command_buffer: &vk::CommandBuffer,
@@ -219,41 +449,210 @@ impl Device {
         unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
     }
 
+    /// Copies `src_image` into a single layer of `dst_image` and leaves that
+    /// layer ready to be sampled.
+    pub fn blit_image_to_layer(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        src_image: &vk::Image,
+        src_layout: vk::ImageLayout,
+        dst_image: &vk::Image,
+        extent: vk::Extent2D,
+        layer: u32,
+    ) {
+        let layer_range = vk::ImageSubresourceRange {
+            base_array_layer: layer,
+            layer_count: 1,
+            ..COLOR_SUBRESOURCE_MASK
+        };
+        let src_barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .image(*src_image)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(src_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        let dst_barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(layer_range)
+            .image(*dst_image)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let image_memory_barriers = &[src_barrier, dst_barrier];
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(image_memory_barriers);
+        unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+
+        let offsets = [
+            vk::Offset3D { x: 0, y: 0, z: 0 },
+            vk::Offset3D {
+                x: extent.width as _,
+                y: extent.height as _,
+                z: 1,
+            },
+        ];
+        let subresource_layer = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_array_layer: 0,
+            layer_count: 1,
+            mip_level: 0,
+        };
+        let regions = [vk::ImageBlit2::default()
+            .src_offsets(offsets)
+            .dst_offsets(offsets)
+            .src_subresource(subresource_layer)
+            .dst_subresource(vk::ImageSubresourceLayers {
+                base_array_layer: layer,
+                ..subresource_layer
+            })];
+        let blit_info = vk::BlitImageInfo2::default()
+            .src_image(*src_image)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_image(*dst_image)
+            .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .regions(&regions)
+            .filter(vk::Filter::NEAREST);
+        unsafe { self.cmd_blit_image2(*command_buffer, &blit_info) };
+
+        let src_barrier = src_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ | vk::AccessFlags2::MEMORY_WRITE)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(src_layout);
+        let dst_barrier = dst_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::BLIT)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(
+                vk::PipelineStageFlags2::FRAGMENT_SHADER | vk::PipelineStageFlags2::COMPUTE_SHADER,
+            )
+            .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let image_memory_barriers = &[src_barrier, dst_barrier];
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(image_memory_barriers);
+        unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+    }
+
+    /// Raw copy without format conversion, `dst_image` must have the same
+    /// format as `src_image`.
+    pub fn copy_image(
+        &self,
+        command_buffer: &vk::CommandBuffer,
+        src_image: &vk::Image,
+        src_orig_layout: vk::ImageLayout,
+        dst_image: &vk::Image,
+        extent: vk::Extent2D,
+    ) {
+        let src_barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .image(*src_image)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .old_layout(src_orig_layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL);
+        let dst_barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .image(*dst_image)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags2::COPY)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL);
+        let image_memory_barriers = &[src_barrier, dst_barrier];
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(image_memory_barriers);
+        unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+
+        let subresource_layer = vk::ImageSubresourceLayers {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_array_layer: 0,
+            layer_count: 1,
+            mip_level: 0,
+        };
+        let regions = [vk::ImageCopy2::default()
+            .src_subresource(subresource_layer)
+            .dst_subresource(subresource_layer)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })];
+        let copy_info = vk::CopyImageInfo2::default()
+            .src_image(*src_image)
+            .src_image_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .dst_image(*dst_image)
+            .dst_image_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .regions(&regions);
+        unsafe { self.cmd_copy_image2(*command_buffer, &copy_info) };
+
+        let src_barrier = src_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(src_orig_layout);
+        let dst_barrier = dst_barrier
+            .src_stage_mask(vk::PipelineStageFlags2::COPY)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::HOST)
+            .dst_access_mask(vk::AccessFlags2::HOST_READ)
+            .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+            .new_layout(vk::ImageLayout::GENERAL);
+        let image_memory_barriers = &[src_barrier, dst_barrier];
+        let dependency_info =
+            vk::DependencyInfo::default().image_memory_barriers(image_memory_barriers);
+        unsafe { self.cmd_pipeline_barrier2(*command_buffer, &dependency_info) };
+    }
+
+    /// Reads `src_image` back into host memory. Formats that can't be
+    /// blitted are copied as is and converted to RGBA on the CPU side.
     pub fn capture_image_data(
         self: &Arc<Self>,
         queue: &vk::Queue,
         src_image: &vk::Image,
+        src_layout: vk::ImageLayout,
+        src_format: vk::Format,
         extent: vk::Extent2D,
         callback: impl FnOnce(ManagedImage),
     ) -> Result<()> {
-        let dst_image = ManagedImage::new(
-            self,
-            &vk::ImageCreateInfo::default()
-                .extent(vk::Extent3D {
-                    width: align_to(extent.width, 2),
-                    height: align_to(extent.height, 2),
-                    depth: 1,
-                })
-                .image_type(vk::ImageType::TYPE_2D)
-                .format(vk::Format::R8G8B8A8_SRGB)
-                .usage(vk::ImageUsageFlags::TRANSFER_DST)
-                .samples(vk::SampleCountFlags::TYPE_1)
-                .mip_levels(1)
-                .array_layers(1)
-                .tiling(vk::ImageTiling::LINEAR),
-            UsageFlags::DOWNLOAD,
-        )?;
+        let blit = self.supports_capture_blit(src_format);
+        let dst_format = match blit {
+            true => capture_format(src_format),
+            false => src_format,
+        };
+        let dst_extent = vk::Extent2D {
+            width: align_to(extent.width, 2),
+            height: align_to(extent.height, 2),
+        };
+        let dst_image = self.create_readback_image(dst_format, dst_extent)?;
 
-        self.one_time_submit(queue, |device, command_buffer| {
-            device.blit_image(
+        self.one_time_submit(queue, |device, command_buffer| match blit {
+            true => device.blit_image(
                 &command_buffer,
                 src_image,
                 extent,
-                vk::ImageLayout::PRESENT_SRC_KHR,
+                src_layout,
                 &dst_image.image,
                 extent,
                 vk::ImageLayout::UNDEFINED,
-            );
+            ),
+            false => device.copy_image(
+                &command_buffer,
+                src_image,
+                src_layout,
+                &dst_image.image,
+                extent,
+            ),
         })?;
 
         callback(dst_image);
@@ -261,6 +660,58 @@ impl Device {
         Ok(())
     }
 
+    /// Reads `src_image` back without any conversion, e.g. to keep float
+    /// data intact.
+    pub fn capture_image_raw(
+        self: &Arc<Self>,
+        queue: &vk::Queue,
+        src_image: &vk::Image,
+        src_layout: vk::ImageLayout,
+        src_format: vk::Format,
+        extent: vk::Extent2D,
+        callback: impl FnOnce(ManagedImage),
+    ) -> Result<()> {
+        let dst_image = self.create_readback_image(src_format, extent)?;
+
+        self.one_time_submit(queue, |device, command_buffer| {
+            device.copy_image(
+                &command_buffer,
+                src_image,
+                src_layout,
+                &dst_image.image,
+                extent,
+            )
+        })?;
+
+        callback(dst_image);
+
+        Ok(())
+    }
+
+    fn create_readback_image(
+        self: &Arc<Self>,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<ManagedImage> {
+        ManagedImage::new(
+            self,
+            &vk::ImageCreateInfo::default()
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(format)
+                .usage(vk::ImageUsageFlags::TRANSFER_DST)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .mip_levels(1)
+                .array_layers(1)
+                .tiling(vk::ImageTiling::LINEAR),
+            UsageFlags::DOWNLOAD,
+        )
+    }
+
     pub fn create_host_buffer(
         self: &Arc<Self>,
         size: u64,
@@ -277,8 +728,11 @@ impl Device {
         };
         let mem_requirements = unsafe { self.get_buffer_memory_requirements(buffer) };
 
-        let mut memory =
-            self.alloc_memory(mem_requirements, memory_usage | UsageFlags::HOST_ACCESS)?;
+        let mut memory = self.alloc_memory(
+            mem_requirements,
+            memory_usage | UsageFlags::HOST_ACCESS,
+            buffer_kind(usage),
+        )?;
         unsafe { self.bind_buffer_memory(buffer, *memory.memory(), memory.offset()) }?;
 
         let address = unsafe {
@@ -311,7 +765,7 @@ impl Device {
     ) -> Result<HostBufferTyped<T>> {
         let byte_size = (size_of::<T>()) as vk::DeviceSize;
         let buffer = unsafe {
-            self.device.create_buffer(
+            self.create_buffer(
                 &vk::BufferCreateInfo::default()
                     .size(byte_size)
                     .usage(usage | vk::BufferUsageFlags::SHADER_DEVICE_ADDRESS),
@@ -320,8 +774,11 @@ impl Device {
         };
         let mem_requirements = unsafe { self.get_buffer_memory_requirements(buffer) };
 
-        let mut memory =
-            self.alloc_memory(mem_requirements, memory_usage | UsageFlags::HOST_ACCESS)?;
+        let mut memory = self.alloc_memory(
+            mem_requirements,
+            memory_usage | UsageFlags::HOST_ACCESS,
+            buffer_kind(usage),
+        )?;
         unsafe { self.bind_buffer_memory(buffer, *memory.memory(), memory.offset()) }?;
 
         let address = unsafe {
@@ -382,6 +839,18 @@ impl Device {
     }
 }
 
+/// 8-bit format for copies of `src_format` that keep the values as shown,
+/// UNORM sources hold display values already.
+pub fn capture_format(src_format: vk::Format) -> vk::Format {
+    match src_format {
+        vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_UNORM
+        | vk::Format::A2B10G10R10_UNORM_PACK32
+        | vk::Format::A2R10G10B10_UNORM_PACK32 => vk::Format::R8G8B8A8_UNORM,
+        _ => vk::Format::R8G8B8A8_SRGB,
+    }
+}
+
 impl Drop for Device {
     fn drop(&mut self) {
         unsafe {
@@ -404,6 +873,26 @@ pub struct HostBuffer {
     device: Arc<Device>,
 }
 
+impl HostBuffer {
+    /// Makes device writes visible through the mapping, which non coherent
+    /// memory needs before every read.
+    pub fn invalidate(&self) -> VkResult<()> {
+        if self
+            .memory
+            .props()
+            .contains(MemoryPropertyFlags::HOST_COHERENT)
+        {
+            return Ok(());
+        }
+        // The allocator keeps non coherent blocks aligned to the atom size
+        let range = vk::MappedMemoryRange::default()
+            .memory(*self.memory.memory())
+            .offset(self.memory.offset())
+            .size(self.memory.size());
+        unsafe { self.device.invalidate_mapped_memory_ranges(&[range]) }
+    }
+}
+
 impl std::ops::Deref for HostBuffer {
     type Target = [u8];
     fn deref(&self) -> &Self::Target {
@@ -473,3 +962,14 @@ impl std::fmt::Display for RendererInfo {
         Ok(())
     }
 }
+
+/// Buffers no shader or acceleration structure reads only carry copies.
+fn buffer_kind(usage: vk::BufferUsageFlags) -> MemoryKind {
+    let read = vk::BufferUsageFlags::STORAGE_BUFFER
+        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR
+        | vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+    match usage.intersects(read) {
+        true => MemoryKind::Buffers,
+        false => MemoryKind::Staging,
+    }
+}
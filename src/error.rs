@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use ash::vk;
+
+/// Failures the renderer hands back to its caller instead of aborting.
+///
+/// The rest of the API returns [`anyhow::Error`], these can be recovered
+/// from it with `downcast_ref`.
+#[derive(Debug)]
+pub enum Error {
+    /// A Vulkan call failed, `context` names the operation.
+    Vulkan {
+        context: &'static str,
+        result: vk::Result,
+    },
+    /// A shader failed to read or compile.
+    Shader { path: PathBuf, message: String },
+    /// A pass couldn't be set up.
+    Pass { name: String, source: anyhow::Error },
+    /// A reloaded shader declares other set 1 bindings than its pipeline
+    /// was built with, only rebuilding the pipelines picks them up.
+    Bindings { path: PathBuf },
+}
+
+impl Error {
+    pub fn vulkan(context: &'static str) -> impl FnOnce(vk::Result) -> Self {
+        move |result| Error::Vulkan { context, result }
+    }
+
+    pub fn pass(name: impl Into<String>) -> impl FnOnce(anyhow::Error) -> Self {
+        move |source| Error::Pass {
+            name: name.into(),
+            source,
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Vulkan { context, result } => write!(f, "{context}: {result}"),
+            // shaderc already names the file in its messages
+            Error::Shader { message, .. } => write!(f, "{message}"),
+            Error::Pass { name, source } => write!(f, "Pass `{name}`: {source}"),
+            Error::Bindings { path } => {
+                write!(f, "{}: the set 1 bindings changed", path.display())
+            }
+        }
+    }
+}
+
+// The messages already include the causes, so there is no `source`
+impl std::error::Error for Error {}
@@ -0,0 +1,115 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::VIDEO_FOLDER;
+
+pub const EXPORT_CHECKPOINT_FILE: &str = "pilka-export.toml";
+
+/// Where an interrupted `--record` export stopped, written into the video
+/// folder every time a segment is finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportCheckpoint {
+    /// Finished segments in order, joined into one video at the end.
+    pub parts: Vec<PathBuf>,
+    /// Video frames in the finished segments.
+    pub frames: u64,
+    /// `Time` in seconds and `pc.frame` of the first frame not exported yet.
+    pub time: f64,
+    pub frame: u32,
+    /// Length of the whole export in seconds.
+    pub duration: f32,
+}
+
+impl ExportCheckpoint {
+    fn path() -> PathBuf {
+        Path::new(VIDEO_FOLDER).join(EXPORT_CHECKPOINT_FILE)
+    }
+
+    pub fn load() -> Option<Self> {
+        let path = Self::path();
+        let text = std::fs::read_to_string(&path).ok()?;
+        toml::from_str(&text)
+            .map_err(|err| log::warn!("Ignoring {}: {err}", path.display()))
+            .ok()
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let text = toml::to_string(self)?;
+        // Renamed into place, a crash while writing keeps the last checkpoint
+        let path = Self::path();
+        let temp = path.with_extension("toml.tmp");
+        std::fs::write(&temp, text)?;
+        std::fs::rename(temp, path)?;
+        Ok(())
+    }
+
+    pub fn remove() {
+        let _ = std::fs::remove_file(Self::path());
+    }
+}
+
+/// Progress of an offline `--record` export, printed about once a second,
+/// and the frames where it checkpoints.
+pub struct ExportProgress {
+    total_frames: u64,
+    /// Frames done before this run, by the run a checkpoint is from.
+    resumed_frames: u64,
+    checkpoint_frames: Option<u64>,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl ExportProgress {
+    /// `rate` is the frames per second of the exported video.
+    pub fn new(
+        duration: Duration,
+        checkpoint: Option<Duration>,
+        rate: u64,
+        resumed_frames: u64,
+    ) -> Self {
+        let to_frames = |time: Duration| (time.as_secs_f64() * rate as f64) as u64;
+        Self {
+            total_frames: to_frames(duration),
+            resumed_frames,
+            checkpoint_frames: checkpoint.map(to_frames).filter(|&frames| frames > 0),
+            started: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Prints frames done, encode rate and the time left after `frames`
+    /// video frames were sent to the encoder.
+    pub fn report(&mut self, frames: u64) {
+        if self.last_report.elapsed() < Duration::from_secs(1) && frames < self.total_frames {
+            return;
+        }
+        self.last_report = Instant::now();
+        let elapsed = self.started.elapsed().as_secs_f64();
+        let fps = frames.saturating_sub(self.resumed_frames) as f64 / elapsed.max(1e-3);
+        let left = self.total_frames.saturating_sub(frames);
+        let eta = match fps > 0. {
+            true => (left as f64 / fps) as u64,
+            false => 0,
+        };
+        let percent = frames as f64 / self.total_frames.max(1) as f64 * 100.;
+        println!(
+            "Exported {frames}/{} frames ({percent:.1}%), {fps:.1} fps, ETA {}:{:02}:{:02}",
+            self.total_frames,
+            eta / 3600,
+            eta / 60 % 60,
+            eta % 60
+        );
+    }
+
+    /// Whether going from `before` to `after` frames passed a checkpoint.
+    pub fn checkpoint_due(&self, before: u64, after: u64) -> bool {
+        self.checkpoint_frames
+            .is_some_and(|every| before / every != after / every && after < self.total_frames)
+    }
+}
@@ -0,0 +1,224 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use ash::vk;
+use winit::window::Window;
+
+use crate::{
+    create_init_pipeline, create_pipelines, dispatch_optimal, rgba_rows, surface_defines, Instance,
+    Manifest, PipelineArena, PushConstant, Resources, SceneRaster, TextureArena, Textures,
+    ValidationSettings, Watcher, COLOR_SUBRESOURCE_MASK,
+};
+
+const FORMAT: vk::Format = vk::Format::R8G8B8A8_SRGB;
+/// What the shaders see in `COLOR_SPACE`, there is no surface to ask.
+const SURFACE_FORMAT: vk::SurfaceFormatKHR = vk::SurfaceFormatKHR {
+    format: FORMAT,
+    color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+};
+
+/// Renders a single frame of the project in `shader_dir` at `time` seconds,
+/// without a window or an event loop, and returns its sRGB RGBA pixels row by
+/// row from the top.
+///
+/// `overrides` supplies the push constants, `time`, `time_delta` and the
+/// resolution are set from the arguments. `init.comp` and `shader.comp` run
+/// once before the image pass. The manifest textures, buffers, sampler and
+/// compile settings apply; data channels, tables, streams, extra passes, particles,
+/// meshes, layers and the post chain don't.
+pub fn render_frame_to_buffer(
+    shader_dir: &Path,
+    time: f32,
+    width: u32,
+    height: u32,
+    overrides: PushConstant,
+) -> Result<Vec<u8>> {
+    if width == 0 || height == 0 {
+        bail!("Size can't be zero");
+    }
+    let extent = vk::Extent2D { width, height };
+    let manifest = Manifest::load(shader_dir)?;
+
+    let instance = Instance::new(None::<&Window>, ValidationSettings::default())?;
+    let (device, queue, _) = instance.create_device_and_queues(None, None)?;
+    let device = Arc::new(device);
+    let mut pipeline_arena = PipelineArena::new(&device, Watcher::detached()?)?;
+    pipeline_arena
+        .shader_compiler
+        .set_settings(&manifest.compile.clone().relative_to(shader_dir))?;
+    let mut texture_arena = TextureArena::new(&device, &queue, extent, FORMAT)?;
+    texture_arena.set_sampler_settings(manifest.sampler)?;
+    let mut textures = Textures::default();
+    textures.configure(
+        &manifest.texture,
+        shader_dir,
+        &device,
+        &queue,
+        &mut texture_arena,
+    )?;
+    pipeline_arena.user_resources.configure(
+        &manifest.buffer,
+        &textures,
+        &texture_arena,
+        &device,
+    )?;
+    pipeline_arena
+        .shader_compiler
+        .set_defines(&[textures.defines(), &surface_defines(SURFACE_FORMAT)].concat())?;
+
+    let mut raster = SceneRaster::default();
+    raster.configure(manifest.raster, &device);
+    let (render_pipeline, compute_pipeline) = create_pipelines(
+        &mut pipeline_arena,
+        &texture_arena,
+        FORMAT,
+        vk::SampleCountFlags::TYPE_1,
+        None,
+        raster.image(),
+        shader_dir,
+    )?;
+    let init_pipeline = create_init_pipeline(&mut pipeline_arena, &texture_arena, shader_dir)?;
+    let target = texture_arena.push_render_target(
+        vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(FORMAT)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT
+                    | vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC,
+            )
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL),
+    )?;
+
+    // Nothing is bound in it, but shaders may still read the addresses
+    let resources = Resources::new_buffer(&device)?;
+    let push_constant = PushConstant {
+        time,
+        time_delta: 0.,
+        wh: [width as f32, height as f32],
+        resources: resources.address,
+        ..overrides
+    };
+    let bytes = unsafe {
+        std::slice::from_raw_parts(
+            std::ptr::from_ref(&push_constant).cast::<u8>(),
+            size_of::<PushConstant>(),
+        )
+    };
+    let stages = vk::ShaderStageFlags::VERTEX
+        | vk::ShaderStageFlags::FRAGMENT
+        | vk::ShaderStageFlags::COMPUTE;
+    let image = texture_arena.images[target].image;
+    device.one_time_submit(&queue, |device, cbuff| unsafe {
+        for handle in init_pipeline.into_iter().chain([compute_pipeline]) {
+            let pipeline = pipeline_arena.get_pipeline(handle);
+            device.cmd_push_constants(cbuff, pipeline.layout, stages, 0, bytes);
+            device.cmd_bind_descriptor_sets(
+                cbuff,
+                vk::PipelineBindPoint::COMPUTE,
+                pipeline.layout,
+                0,
+                &pipeline.descriptor_sets(texture_arena.images_set),
+                &[],
+            );
+            device.cmd_bind_pipeline(cbuff, vk::PipelineBindPoint::COMPUTE, pipeline.pipeline);
+            let [x, y, _] = pipeline.local_size;
+            device.cmd_dispatch(
+                cbuff,
+                dispatch_optimal(width, x),
+                dispatch_optimal(height, y),
+                1,
+            );
+            let memory_barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                .dst_stage_mask(
+                    vk::PipelineStageFlags2::COMPUTE_SHADER | vk::PipelineStageFlags2::ALL_GRAPHICS,
+                )
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE);
+            device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+        }
+
+        let to_attachment = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .image(image)
+            .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+            .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        device.cmd_pipeline_barrier2(
+            cbuff,
+            &vk::DependencyInfo::default()
+                .image_memory_barriers(std::slice::from_ref(&to_attachment)),
+        );
+        let color_attachments = [vk::RenderingAttachmentInfo::default()
+            .image_view(texture_arena.views[target])
+            .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .load_op(vk::AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::STORE)];
+        let rendering_info = vk::RenderingInfo::default()
+            .render_area(extent.into())
+            .layer_count(1)
+            .color_attachments(&color_attachments);
+        device
+            .dynamic_rendering
+            .cmd_begin_rendering(cbuff, &rendering_info);
+        // Flipped like the swapchain frames
+        let viewport = vk::Viewport {
+            x: 0.,
+            y: height as f32,
+            width: width as f32,
+            height: -(height as f32),
+            min_depth: 0.,
+            max_depth: 1.,
+        };
+        device.cmd_set_viewport(cbuff, 0, &[viewport]);
+        device.cmd_set_scissor(cbuff, 0, &[extent.into()]);
+        let pipeline = pipeline_arena.get_pipeline(render_pipeline);
+        device.cmd_push_constants(cbuff, pipeline.layout, stages, 0, bytes);
+        device.cmd_bind_descriptor_sets(
+            cbuff,
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            0,
+            &pipeline.descriptor_sets(texture_arena.images_set),
+            &[],
+        );
+        device.cmd_bind_pipeline(cbuff, vk::PipelineBindPoint::GRAPHICS, pipeline.pipeline);
+        device.cmd_draw(cbuff, 3, 1, 0, 0);
+        device.dynamic_rendering.cmd_end_rendering(cbuff);
+    })?;
+
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    let mut readback = Err(anyhow::anyhow!("Nothing was read back"));
+    device.capture_image_data(
+        &queue,
+        &image,
+        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+        FORMAT,
+        extent,
+        |mut frame| {
+            let (dims, format) = (frame.image_dimensions, frame.format);
+            readback = frame.map_memory().map_err(Into::into).map(|data| {
+                for row in rgba_rows(data, dims, format).take(height as usize) {
+                    pixels.extend_from_slice(&row[..width as usize * 4]);
+                }
+            });
+        },
+    )?;
+    readback?;
+    Ok(pixels)
+}
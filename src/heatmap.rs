@@ -0,0 +1,300 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use ash::vk;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, BlendMode, Device,
+    FragmentOutputDesc, FragmentShaderDesc, PipelineArena, RenderHandle, TextureArena,
+    VertexInputDesc, VertexShaderDesc, POST_FOLDER,
+};
+
+/// Tiles across and down the frame.
+const GRID: [u32; 2] = [16, 9];
+/// Frames whose queries can be in flight.
+const RING: usize = 4;
+/// Weight of the newest measurement of a tile.
+const SMOOTHING: f32 = 0.2;
+const OPACITY: f32 = 0.6;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct HeatmapPushConstant {
+    grid: [u32; 2],
+    max_ms: f32,
+    opacity: f32,
+    costs: u64,
+}
+
+struct QuerySlot {
+    pool: vk::QueryPool,
+    /// Row measured with the queries, if any.
+    row: Option<u32>,
+}
+
+/// Estimated cost of every screen region of the image pass. Each frame one
+/// row of tiles is drawn again with a scissor per tile and timestamps between
+/// them, the overlay colors the tiles from cheap blue to expensive red.
+pub struct Heatmap {
+    pub enabled: bool,
+    /// Smoothed milliseconds of every tile, row by row from the top.
+    costs: Vec<f32>,
+    next_row: u32,
+    slots: Vec<QuerySlot>,
+    current: usize,
+    measuring: bool,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    buffer: HostBuffer,
+    pipeline: RenderHandle,
+    device: Arc<Device>,
+}
+
+impl Heatmap {
+    pub fn new(
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let limits = device.device_properties.limits;
+        if limits.timestamp_compute_and_graphics != vk::TRUE {
+            bail!("The queue doesn't support timestamps");
+        }
+        let mut slots = vec![];
+        for i in 0..RING {
+            let info = vk::QueryPoolCreateInfo::default()
+                .query_type(vk::QueryType::TIMESTAMP)
+                .query_count(GRID[0] + 1);
+            let pool = unsafe { device.create_query_pool(&info, None)? };
+            device.name_object(pool, &format!("Heatmap Timestamps {i}"));
+            slots.push(QuerySlot { pool, row: None });
+        }
+
+        let tiles = (GRID[0] * GRID[1]) as usize;
+        let buffer = device.create_host_buffer(
+            (tiles * size_of::<f32>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        device.name_object(buffer.buffer, "Heatmap Costs");
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<HeatmapPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let pipeline = pipeline_arena.create_render_pipeline(
+            &VertexInputDesc::default(),
+            &VertexShaderDesc {
+                shader_path: dir.join("post.vert"),
+                ..Default::default()
+            },
+            &FragmentShaderDesc {
+                shader_path: dir.join("heatmap.frag"),
+            },
+            &FragmentOutputDesc {
+                surface_format,
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+
+        Ok(Self {
+            enabled: false,
+            costs: vec![0.; tiles],
+            next_row: 0,
+            slots,
+            current: 0,
+            measuring: false,
+            period: limits.timestamp_period,
+            buffer,
+            pipeline,
+            device: device.clone(),
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        let state = if self.enabled { "on" } else { "off" };
+        println!("Heatmap: {state}");
+    }
+
+    /// Collects the oldest row in the ring and resets the queries of this
+    /// frame. Has to be recorded outside of rendering.
+    pub fn begin_frame(&mut self, cbuff: vk::CommandBuffer) {
+        self.measuring = false;
+        if !self.enabled {
+            return;
+        }
+        self.current = (self.current + 1) % self.slots.len();
+        let slot = &mut self.slots[self.current];
+        if let Some(row) = slot.row {
+            let mut ticks = [0u64; GRID[0] as usize + 1];
+            let results = unsafe {
+                self.device.get_query_pool_results(
+                    slot.pool,
+                    0,
+                    &mut ticks,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match results {
+                Ok(()) => {}
+                Err(vk::Result::NOT_READY) => return,
+                Err(err) => {
+                    log::warn!("Failed to read heatmap timestamps: {err}");
+                    return;
+                }
+            }
+            let start = (row * GRID[0]) as usize;
+            let costs = &mut self.costs[start..start + GRID[0] as usize];
+            for (cost, pair) in costs.iter_mut().zip(ticks.windows(2)) {
+                let ms = pair[1].saturating_sub(pair[0]) as f32 * self.period / 1e6;
+                *cost = match *cost {
+                    0. => ms,
+                    avg => avg + (ms - avg) * SMOOTHING,
+                };
+            }
+            slot.row = None;
+        }
+        unsafe {
+            self.device
+                .cmd_reset_query_pool(cbuff, slot.pool, 0, GRID[0] + 1)
+        };
+        self.measuring = true;
+    }
+
+    /// Draws the bound fullscreen pipeline again over the next row of tiles,
+    /// one scissored draw per tile.
+    pub fn measure_row(&mut self, frame: &mut FrameGuard, extent: vk::Extent2D) {
+        if !self.measuring {
+            return;
+        }
+        self.measuring = false;
+        let slot = &mut self.slots[self.current];
+        let row = self.next_row;
+        let cbuff = *frame.command_buffer();
+        let timestamp = |query| unsafe {
+            self.device.cmd_write_timestamp2(
+                cbuff,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                slot.pool,
+                query,
+            )
+        };
+        let y0 = extent.height * row / GRID[1];
+        let y1 = extent.height * (row + 1) / GRID[1];
+        timestamp(0);
+        for column in 0..GRID[0] {
+            let x0 = extent.width * column / GRID[0];
+            let x1 = extent.width * (column + 1) / GRID[0];
+            frame.set_scissors(&[vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: x0 as i32,
+                    y: y0 as i32,
+                },
+                extent: vk::Extent2D {
+                    width: x1 - x0,
+                    height: y1 - y0,
+                },
+            }]);
+            frame.draw(3, 0, 1, 0);
+            timestamp(column + 1);
+        }
+        frame.set_scissors(&[extent.into()]);
+        slot.row = Some(row);
+        self.next_row = (row + 1) % GRID[1];
+    }
+
+    /// Uploads the tile costs and blends them over whatever is in `view`.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let bytes: Vec<u8> = self.costs.iter().flat_map(|ms| ms.to_ne_bytes()).collect();
+        let cbuff = *frame.command_buffer();
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE);
+        let upload_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ);
+        unsafe {
+            self.device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+            self.device
+                .cmd_update_buffer(cbuff, self.buffer.buffer, 0, &bytes);
+            self.device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&upload_barrier)),
+            );
+        }
+
+        let pipeline = pipeline_arena.get_pipeline(self.pipeline);
+        let push_constant = HeatmapPushConstant {
+            grid: GRID,
+            max_ms: self.costs.iter().copied().fold(0., f32::max),
+            opacity: OPACITY,
+            costs: self.buffer.address,
+        };
+        frame.resume_rendering(view);
+        frame.push_constant(
+            pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.images_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.draw(3, 0, 1, 0);
+        frame.end_rendering();
+    }
+}
+
+impl std::fmt::Display for Heatmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some((idx, ms)) = self
+            .costs
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.total_cmp(b.1))
+        else {
+            return Ok(());
+        };
+        let (column, row) = (idx as u32 % GRID[0], idx as u32 / GRID[0]);
+        writeln!(
+            f,
+            "heatmap:\tslowest tile {column},{row} of {}x{} at {ms:.3} ms",
+            GRID[0], GRID[1]
+        )
+    }
+}
+
+impl Drop for Heatmap {
+    fn drop(&mut self) {
+        for slot in &self.slots {
+            unsafe { self.device.destroy_query_pool(slot.pool, None) };
+        }
+    }
+}
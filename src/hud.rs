@@ -0,0 +1,241 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, BlendMode, Device,
+    FragmentOutputDesc, FragmentShaderDesc, PipelineArena, RenderHandle, TextureArena,
+    VertexInputDesc, VertexShaderDesc, POST_FOLDER,
+};
+
+const MAX_LINES: usize = 8;
+const MAX_COLUMNS: usize = 24;
+
+/// Elements of the HUD, the `[hud]` table of the manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HudSettings {
+    /// Shown from the start, the hotkey toggles it either way.
+    pub visible: bool,
+    pub fps: bool,
+    pub ms: bool,
+    pub resolution: bool,
+    pub time: bool,
+    pub frame: bool,
+    /// Device memory allocated by pilka, not the whole GPU.
+    pub vram: bool,
+    /// Distinct validation errors and warnings, once there are any.
+    pub validation: bool,
+}
+
+impl Default for HudSettings {
+    fn default() -> Self {
+        Self {
+            visible: false,
+            fps: true,
+            ms: true,
+            resolution: true,
+            time: true,
+            frame: true,
+            vram: true,
+            validation: true,
+        }
+    }
+}
+
+/// What the HUD shows this frame.
+#[derive(Debug, Clone)]
+pub struct HudValues {
+    pub fps: f32,
+    pub ms: f32,
+    pub resolution: vk::Extent2D,
+    pub time: f32,
+    pub frame: u32,
+    pub vram_bytes: u64,
+    /// Distinct validation errors and warnings.
+    pub validation: (usize, usize),
+    /// Skipped frames or a broken shader, shown even with the HUD hidden.
+    pub error: Option<String>,
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct HudPushConstant {
+    resolution: [f32; 2],
+    /// Window pixels per font pixel.
+    scale: f32,
+    columns: u32,
+    lines: u32,
+    text: u64,
+}
+
+/// Lines of text in the top right corner of the window, drawn after the
+/// exported copy of the frame so screenshots and recordings stay clean.
+pub struct Hud {
+    pub enabled: bool,
+    pub settings: HudSettings,
+    buffer: HostBuffer,
+    pipeline: RenderHandle,
+}
+
+impl Hud {
+    pub fn new(
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        surface_format: vk::Format,
+        settings: HudSettings,
+    ) -> Result<Self> {
+        let buffer = device.create_host_buffer(
+            (MAX_LINES * MAX_COLUMNS * size_of::<u32>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        device.name_object(buffer.buffer, "HUD Text");
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<HudPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let pipeline = pipeline_arena.create_render_pipeline(
+            &VertexInputDesc::default(),
+            &VertexShaderDesc {
+                shader_path: dir.join("post.vert"),
+                ..Default::default()
+            },
+            &FragmentShaderDesc {
+                shader_path: dir.join("hud.frag"),
+            },
+            &FragmentOutputDesc {
+                surface_format,
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+
+        Ok(Self {
+            enabled: false,
+            settings,
+            buffer,
+            pipeline,
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    fn lines(&self, values: &HudValues) -> Vec<String> {
+        if !self.enabled {
+            return values.error.iter().cloned().collect();
+        }
+        let settings = &self.settings;
+        let vk::Extent2D { width, height } = values.resolution;
+        [
+            settings.fps.then(|| format!("FPS {:.1}", values.fps)),
+            settings.ms.then(|| format!("MS {:.2}", values.ms)),
+            settings.resolution.then(|| format!("RES {width}X{height}")),
+            settings.time.then(|| format!("TIME {:.2}", values.time)),
+            settings.frame.then(|| format!("FRAME {}", values.frame)),
+            settings
+                .vram
+                .then(|| format!("VRAM {} MB", values.vram_bytes >> 20)),
+            values.error.clone(),
+            match values.validation {
+                (0, 0) => None,
+                (errors, warnings) => settings
+                    .validation
+                    .then(|| format!("VK {errors}E {warnings}W")),
+            },
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    /// Uploads the text and draws it over whatever is in `view`, `scale` is
+    /// the window scale factor.
+    #[allow(clippy::too_many_arguments)]
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        device: &Device,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        values: &HudValues,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+        scale: f64,
+    ) {
+        let lines = self.lines(values);
+        let columns = lines
+            .iter()
+            .map(|line| line.len().min(MAX_COLUMNS))
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let mut text = vec![b' ' as u32; lines.len() * columns];
+        for (row, line) in text.chunks_mut(columns).zip(&lines) {
+            for (cell, byte) in row.iter_mut().zip(line.bytes()) {
+                *cell = byte as u32;
+            }
+        }
+        let bytes: Vec<u8> = text.iter().copied().flat_map(u32::to_ne_bytes).collect();
+
+        let cbuff = *frame.command_buffer();
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE);
+        let upload_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ);
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+            device.cmd_update_buffer(cbuff, self.buffer.buffer, 0, &bytes);
+            device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&upload_barrier)),
+            );
+        }
+
+        let pipeline = pipeline_arena.get_pipeline(self.pipeline);
+        let push_constant = HudPushConstant {
+            resolution: [extent.width as f32, extent.height as f32],
+            scale: (2. * scale).round().max(1.) as f32,
+            columns: columns as u32,
+            lines: lines.len() as u32,
+            text: self.buffer.address,
+        };
+        frame.resume_rendering(view);
+        frame.push_constant(
+            pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.images_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.draw(3, 0, 1, 0);
+        frame.end_rendering();
+    }
+}
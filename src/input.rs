@@ -1,9 +1,12 @@
 use super::PushConstant;
 use winit::{
-    event::{ElementState, KeyEvent, RawKeyEvent},
+    event::{ElementState, KeyEvent, MouseButton, MouseScrollDelta, RawKeyEvent},
     keyboard::{KeyCode, PhysicalKey},
 };
 
+/// Pixels of a touchpad scroll counted as one wheel notch.
+const PIXELS_PER_LINE: f32 = 20.;
+
 #[derive(Debug, Default)]
 pub struct Input {
     pub move_forward: bool,
@@ -12,6 +15,10 @@ pub struct Input {
     pub move_left: bool,
     pub move_up: bool,
     pub move_down: bool,
+    /// `pc.mouse_buttons` bits: 1 left, 2 right, 4 middle.
+    pub mouse_buttons: u32,
+    /// Wheel notches since the last frame.
+    pub scroll: [f32; 2],
 }
 
 impl Input {
@@ -47,6 +54,38 @@ impl Input {
         }
     }
 
+    pub fn update_mouse_button(&mut self, button: MouseButton, state: ElementState) {
+        let bit = match button {
+            MouseButton::Left => 1,
+            MouseButton::Right => 2,
+            MouseButton::Middle => 4,
+            _ => return,
+        };
+        match state {
+            ElementState::Pressed => self.mouse_buttons |= bit,
+            ElementState::Released => self.mouse_buttons &= !bit,
+        }
+    }
+
+    pub fn update_scroll(&mut self, delta: MouseScrollDelta) {
+        let [x, y] = match delta {
+            MouseScrollDelta::LineDelta(x, y) => [x, y],
+            MouseScrollDelta::PixelDelta(pos) => [
+                pos.x as f32 / PIXELS_PER_LINE,
+                pos.y as f32 / PIXELS_PER_LINE,
+            ],
+        };
+        self.scroll[0] += x;
+        self.scroll[1] += y;
+    }
+
+    /// Hands the buttons and the scroll gathered since the last call to the
+    /// frame about to be drawn.
+    pub fn process_mouse(&mut self, push_constant: &mut PushConstant) {
+        push_constant.mouse_buttons = self.mouse_buttons;
+        push_constant.scroll = std::mem::take(&mut self.scroll);
+    }
+
     pub fn process_position(&self, push_constant: &mut PushConstant) {
         let dx = 0.01;
         if self.move_left {
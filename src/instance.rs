@@ -1,35 +1,70 @@
-use std::{collections::HashSet, ffi::CStr, sync::Arc};
+use std::{
+    collections::HashSet,
+    ffi::{CStr, CString},
+    sync::Arc,
+};
 
-use crate::{device::Device, surface::Surface};
+use crate::{device::Device, surface::Surface, ObjectAudit, ValidationLog, ValidationSettings};
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use ash::{ext, khr, vk, Entry};
 use parking_lot::Mutex;
 use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
 
+/// Hands the messages to the [`ValidationLog`] in `user_data`.
 unsafe extern "system" fn vulkan_debug_callback(
     message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    _message_type: vk::DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::os::raw::c_void,
+    user_data: *mut std::os::raw::c_void,
 ) -> vk::Bool32 {
     assert!(!p_callback_data.is_null());
     let callback_data = &unsafe { *p_callback_data };
     let message = unsafe { CStr::from_ptr(callback_data.p_message) }.to_string_lossy();
-
     if message.starts_with("Validation Performance Warning") {
-    } else if message.starts_with("Validation Warning: [ VUID_Undefined ]") {
-        log::warn!("{:?}:\n{:?}: {}\n", message_severity, message_type, message,);
-    } else {
-        log::error!("{:?}:\n{:?}: {}\n", message_severity, message_type, message,);
+        return vk::FALSE;
     }
+    let id = match callback_data.p_message_id_name.is_null() {
+        true => Default::default(),
+        false => unsafe { CStr::from_ptr(callback_data.p_message_id_name) }.to_string_lossy(),
+    };
+    let log = unsafe { &*(user_data as *const ValidationLog) };
+    log.push(message_severity, &id, &message);
 
     vk::FALSE
 }
 
+const VALIDATION_LAYER: &CStr = c"VK_LAYER_KHRONOS_validation";
+
+const REQUIRED_DEVICE_EXTENSIONS: [&CStr; 8] = [
+    khr::swapchain::NAME,
+    khr::dynamic_rendering::NAME,
+    ext::extended_dynamic_state2::NAME,
+    ext::extended_dynamic_state::NAME,
+    khr::synchronization2::NAME,
+    khr::buffer_device_address::NAME,
+    khr::create_renderpass2::NAME,
+    ext::descriptor_indexing::NAME,
+];
+
+/// Faster reloads, render pipelines are built whole without them.
+const PIPELINE_LIBRARY_EXTENSIONS: [&CStr; 2] = [
+    ext::graphics_pipeline_library::NAME,
+    khr::pipeline_library::NAME,
+];
+
+/// Ray queries against the mesh, see [`crate::SceneAccel`].
+const RAY_QUERY_EXTENSIONS: [&CStr; 3] = [
+    khr::acceleration_structure::NAME,
+    khr::ray_query::NAME,
+    khr::deferred_host_operations::NAME,
+];
+
 pub struct Instance {
     pub entry: ash::Entry,
     pub inner: ash::Instance,
+    /// Outlives the messenger writing into it, destroyed in `drop`.
+    pub validation: Arc<ValidationLog>,
     dbg_loader: ext::debug_utils::Instance,
     dbg_callbk: vk::DebugUtilsMessengerEXT,
 }
@@ -43,22 +78,51 @@ impl std::ops::Deref for Instance {
 }
 
 impl Instance {
-    pub fn new(display_handle: Option<&impl HasDisplayHandle>) -> Result<Self> {
+    pub fn new(
+        display_handle: Option<&impl HasDisplayHandle>,
+        validation: ValidationSettings,
+    ) -> Result<Self> {
         let entry = unsafe { Entry::load() }?;
-        let layers = [
-            #[cfg(debug_assertions)]
-            c"VK_LAYER_KHRONOS_validation".as_ptr(),
-        ];
+        let mut layers = vec![];
+        if validation.layers {
+            let available = unsafe { entry.enumerate_instance_layer_properties()? };
+            match available
+                .iter()
+                .any(|layer| layer.layer_name_as_c_str() == Ok(VALIDATION_LAYER))
+            {
+                true => layers.push(VALIDATION_LAYER.as_ptr()),
+                false => log::warn!("Validation layer not found, is the Vulkan SDK installed?"),
+            }
+        }
+        let available = unsafe { entry.enumerate_instance_extension_properties(None)? };
+        let has = |name: &CStr| {
+            available
+                .iter()
+                .any(|extension| extension.extension_name_as_c_str() == Ok(name))
+        };
         let mut extensions = vec![
             ext::debug_utils::NAME.as_ptr(),
             khr::surface::NAME.as_ptr(),
-            khr::display::NAME.as_ptr(),
             khr::get_physical_device_properties2::NAME.as_ptr(),
         ];
+        // MoltenVK has no direct display access
+        if has(khr::display::NAME) {
+            extensions.push(khr::display::NAME.as_ptr());
+        }
+        // Lists MoltenVK and the other non-conformant implementations
+        let mut flags = vk::InstanceCreateFlags::empty();
+        if has(khr::portability_enumeration::NAME) {
+            extensions.push(khr::portability_enumeration::NAME.as_ptr());
+            flags |= vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR;
+        }
         if let Some(handle) = display_handle {
             extensions.extend(ash_window::enumerate_required_extensions(
                 handle.display_handle()?.as_raw(),
             )?);
+            // Color spaces beyond sRGB for the `[swapchain]` settings
+            if has(ext::swapchain_colorspace::NAME) {
+                extensions.push(ext::swapchain_colorspace::NAME.as_ptr());
+            }
         }
 
         let appinfo = vk::ApplicationInfo::default()
@@ -66,121 +130,238 @@ impl Instance {
             .api_version(vk::API_VERSION_1_3);
         let instance_info = vk::InstanceCreateInfo::default()
             .application_info(&appinfo)
-            .flags(vk::InstanceCreateFlags::default())
+            .flags(flags)
             .enabled_layer_names(&layers)
             .enabled_extension_names(&extensions);
         let inner = unsafe { entry.create_instance(&instance_info, None) }?;
 
+        let log = Arc::new(ValidationLog::default());
         let dbg_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
-            .message_severity(
-                vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
-                    // | vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE
-                    // | vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                    | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING,
-            )
+            .message_severity(validation.level.severities())
             .message_type(
                 vk::DebugUtilsMessageTypeFlagsEXT::VALIDATION
                     | vk::DebugUtilsMessageTypeFlagsEXT::DEVICE_ADDRESS_BINDING
                     | vk::DebugUtilsMessageTypeFlagsEXT::GENERAL
                     | vk::DebugUtilsMessageTypeFlagsEXT::PERFORMANCE,
             )
-            .pfn_user_callback(Some(vulkan_debug_callback));
+            .pfn_user_callback(Some(vulkan_debug_callback))
+            .user_data(Arc::as_ptr(&log) as *mut _);
         let dbg_loader = ext::debug_utils::Instance::new(&entry, &inner);
         let dbg_callbk = unsafe { dbg_loader.create_debug_utils_messenger(&dbg_info, None)? };
 
         Ok(Self {
             dbg_loader,
             dbg_callbk,
+            validation: log,
             entry,
             inner,
         })
     }
 
+    fn device_extensions(&self, device: vk::PhysicalDevice) -> HashSet<CString> {
+        let extensions = unsafe { self.enumerate_device_extension_properties(device) };
+        extensions
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|x| x.extension_name_as_c_str().ok())
+            .map(CStr::to_owned)
+            .collect()
+    }
+
+    /// Whether `device` has every extension pilka needs.
+    fn has_required_extensions(&self, device: vk::PhysicalDevice) -> bool {
+        let extensions = self.device_extensions(device);
+        REQUIRED_DEVICE_EXTENSIONS
+            .iter()
+            .all(|&name| extensions.contains(name))
+    }
+
+    /// One line per physical device in the order `--gpu` indexes them.
+    pub fn describe_gpus(&self) -> Result<Vec<String>> {
+        let devices = unsafe { self.enumerate_physical_devices() }?;
+        Ok(devices
+            .into_iter()
+            .enumerate()
+            .map(|(idx, device)| {
+                let properties = unsafe { self.get_physical_device_properties(device) };
+                let name = properties.device_name_as_c_str().unwrap_or_default();
+                let support = match self.has_required_extensions(device) {
+                    true => "",
+                    false => ", unsupported",
+                };
+                format!(
+                    "{idx}: {} ({:?}{support})",
+                    name.to_string_lossy(),
+                    properties.device_type
+                )
+            })
+            .collect())
+    }
+
+    /// Uses the first suitable device, or the one picked by index or by a
+    /// part of its name with `gpu`. Without a `surface` the main queue
+    /// doesn't have to support presenting.
     pub fn create_device_and_queues(
         &self,
-        surface: &Surface,
+        surface: Option<&Surface>,
+        gpu: Option<&str>,
     ) -> Result<(Device, vk::Queue, vk::Queue)> {
-        let required_device_extensions = [
-            khr::swapchain::NAME,
-            ext::graphics_pipeline_library::NAME,
-            khr::pipeline_library::NAME,
-            khr::dynamic_rendering::NAME,
-            ext::extended_dynamic_state2::NAME,
-            ext::extended_dynamic_state::NAME,
-            khr::synchronization2::NAME,
-            khr::buffer_device_address::NAME,
-            khr::create_renderpass2::NAME,
-            ext::descriptor_indexing::NAME,
-        ];
-        let required_device_extensions_set = HashSet::from(required_device_extensions);
+        let mut devices = unsafe { self.enumerate_physical_devices() }?;
+        if let Some(query) = gpu {
+            let found = match query.parse::<usize>() {
+                Ok(idx) => devices.get(idx).copied(),
+                Err(_) => devices.iter().copied().find(|&device| {
+                    let properties = unsafe { self.get_physical_device_properties(device) };
+                    properties.device_name_as_c_str().is_ok_and(|name| {
+                        name.to_string_lossy()
+                            .to_lowercase()
+                            .contains(&query.to_lowercase())
+                    })
+                }),
+            };
+            let Some(found) = found else {
+                let available = self
+                    .describe_gpus()?
+                    .iter()
+                    .map(|line| format!("\n\t{line}"))
+                    .collect::<String>();
+                bail!("GPU `{query}` not found, available:{available}");
+            };
+            devices = vec![found];
+        }
 
-        let devices = unsafe { self.enumerate_physical_devices() }?;
-        let (pdevice, main_queue_family_idx, transfer_queue_family_idx) =
-            devices
-                .into_iter()
-                .find_map(|device| {
-                    let extensions =
-                        unsafe { self.enumerate_device_extension_properties(device) }.ok()?;
-                    let extensions: HashSet<_> = extensions
+        let (pdevice, main_queue_family_idx, transfer_queue_family_idx) = devices
+            .into_iter()
+            .find_map(|device| {
+                if !self.has_required_extensions(device) {
+                    return None;
+                }
+
+                use vk::QueueFlags as QF;
+                let queue_properties =
+                    unsafe { self.get_physical_device_queue_family_properties(device) };
+                let main_queue_idx =
+                    queue_properties
                         .iter()
-                        .map(|x| x.extension_name_as_c_str().unwrap())
-                        .collect();
-                    let missing = required_device_extensions_set.difference(&extensions);
-                    if missing.count() > 0 {
-                        return None;
-                    }
-
-                    use vk::QueueFlags as QF;
-                    let queue_properties =
-                        unsafe { self.get_physical_device_queue_family_properties(device) };
-                    let main_queue_idx =
-                        queue_properties
-                            .iter()
-                            .enumerate()
-                            .find_map(|(family_idx, properties)| {
-                                let family_idx = family_idx as u32;
-
-                                let queue_support =
-                                    properties.queue_flags.contains(QF::GRAPHICS | QF::TRANSFER);
-                                let surface_support =
-                                    surface.get_device_surface_support(device, family_idx);
-                                (queue_support && surface_support).then_some(family_idx)
+                        .enumerate()
+                        .find_map(|(family_idx, properties)| {
+                            let family_idx = family_idx as u32;
+
+                            let queue_support =
+                                properties.queue_flags.contains(QF::GRAPHICS | QF::TRANSFER);
+                            let surface_support = surface.is_none_or(|surface| {
+                                surface.get_device_surface_support(device, family_idx)
                             });
+                            (queue_support && surface_support).then_some(family_idx)
+                        });
+
+                let main_queue_idx = main_queue_idx?;
+                // Apple GPUs have a single family, the transfer queue is
+                // the main one there
+                let transfer_queue_idx = queue_properties
+                    .iter()
+                    .enumerate()
+                    .find_map(|(family_idx, properties)| {
+                        let family_idx = family_idx as u32;
+                        let queue_support = properties.queue_flags.contains(QF::TRANSFER)
+                            && !properties.queue_flags.contains(QF::GRAPHICS);
+                        (family_idx != main_queue_idx && queue_support).then_some(family_idx)
+                    })
+                    .unwrap_or(main_queue_idx);
+
+                Some((device, main_queue_idx, transfer_queue_idx))
+            })
+            .with_context(|| {
+                let available = self
+                    .describe_gpus()
+                    .unwrap_or_default()
+                    .iter()
+                    .map(|line| format!("\n\t{line}"))
+                    .collect::<String>();
+                let extensions = REQUIRED_DEVICE_EXTENSIONS
+                    .map(|name| name.to_string_lossy())
+                    .join(", ");
+                format!(
+                    "No GPU has the extensions pilka needs ({extensions}) and a graphics \
+                     queue, available:{available}"
+                )
+            })?;
+
+        let mut queue_infos = vec![vk::DeviceQueueCreateInfo::default()
+            .queue_family_index(main_queue_family_idx)
+            .queue_priorities(&[1.0])];
+        if transfer_queue_family_idx != main_queue_family_idx {
+            queue_infos.push(
+                vk::DeviceQueueCreateInfo::default()
+                    .queue_family_index(transfer_queue_family_idx)
+                    .queue_priorities(&[0.5]),
+            );
+        }
 
-                    let transfer_queue_idx = queue_properties.iter().enumerate().find_map(
-                        |(family_idx, properties)| {
-                            let family_idx = family_idx as u32;
-                            let queue_support = properties.queue_flags.contains(QF::TRANSFER)
-                                && !properties.queue_flags.contains(QF::GRAPHICS);
-                            (Some(family_idx) != main_queue_idx && queue_support)
-                                .then_some(family_idx)
-                        },
-                    )?;
-
-                    Some((device, main_queue_idx?, transfer_queue_idx))
-                })
-                .context("Failed to find suitable device.")?;
-
-        let queue_infos = [
-            vk::DeviceQueueCreateInfo::default()
-                .queue_family_index(main_queue_family_idx)
-                .queue_priorities(&[1.0]),
-            vk::DeviceQueueCreateInfo::default()
-                .queue_family_index(transfer_queue_family_idx)
-                .queue_priorities(&[0.5]),
-        ];
+        let available_extensions = self.device_extensions(pdevice);
+        let mut supported_pipeline_library =
+            vk::PhysicalDeviceGraphicsPipelineLibraryFeaturesEXT::default();
+        let mut supported_descriptor_indexing =
+            vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut supported_acceleration_structure =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default();
+        let mut supported_ray_query = vk::PhysicalDeviceRayQueryFeaturesKHR::default();
+        let mut supported_features = vk::PhysicalDeviceFeatures2::default()
+            .push_next(&mut supported_pipeline_library)
+            .push_next(&mut supported_descriptor_indexing)
+            .push_next(&mut supported_acceleration_structure)
+            .push_next(&mut supported_ray_query);
+        unsafe {
+            self.inner
+                .get_physical_device_features2(pdevice, &mut supported_features)
+        };
+        let supported = supported_features.features;
+        let pipeline_libraries = PIPELINE_LIBRARY_EXTENSIONS
+            .iter()
+            .all(|&name| available_extensions.contains(name))
+            && supported_pipeline_library.graphics_pipeline_library == vk::TRUE;
+        if !pipeline_libraries {
+            log::info!("No graphics pipeline libraries, reloads rebuild whole pipelines");
+        }
+
+        let ray_query = RAY_QUERY_EXTENSIONS
+            .iter()
+            .all(|&name| available_extensions.contains(name))
+            && supported_acceleration_structure.acceleration_structure == vk::TRUE
+            && supported_ray_query.ray_query == vk::TRUE;
 
-        let required_device_extensions = required_device_extensions.map(|x| x.as_ptr());
+        let mut device_extensions = REQUIRED_DEVICE_EXTENSIONS.to_vec();
+        if pipeline_libraries {
+            device_extensions.extend(PIPELINE_LIBRARY_EXTENSIONS);
+        }
+        if ray_query {
+            device_extensions.extend(RAY_QUERY_EXTENSIONS);
+        }
+        // Has to be enabled where listed, e.g. on MoltenVK
+        if available_extensions.contains(khr::portability_subset::NAME) {
+            device_extensions.push(khr::portability_subset::NAME);
+        }
+        let required_device_extensions: Vec<_> =
+            device_extensions.iter().map(|x| x.as_ptr()).collect();
 
         let mut feature_dynamic_state =
             vk::PhysicalDeviceExtendedDynamicState2FeaturesEXT::default();
+        // Non-uniform indexing of the other descriptor kinds is missing on
+        // MoltenVK, shaders rarely need it
+        let indexing = supported_descriptor_indexing;
         let mut feature_descriptor_indexing =
             vk::PhysicalDeviceDescriptorIndexingFeatures::default()
                 .runtime_descriptor_array(true)
                 .shader_sampled_image_array_non_uniform_indexing(true)
-                .shader_storage_image_array_non_uniform_indexing(true)
-                .shader_storage_buffer_array_non_uniform_indexing(true)
-                .shader_uniform_buffer_array_non_uniform_indexing(true)
+                .shader_storage_image_array_non_uniform_indexing(
+                    indexing.shader_storage_image_array_non_uniform_indexing == vk::TRUE,
+                )
+                .shader_storage_buffer_array_non_uniform_indexing(
+                    indexing.shader_storage_buffer_array_non_uniform_indexing == vk::TRUE,
+                )
+                .shader_uniform_buffer_array_non_uniform_indexing(
+                    indexing.shader_uniform_buffer_array_non_uniform_indexing == vk::TRUE,
+                )
                 .descriptor_binding_sampled_image_update_after_bind(true)
                 .descriptor_binding_partially_bound(true)
                 .descriptor_binding_variable_descriptor_count(true)
@@ -194,8 +375,16 @@ impl Instance {
                 .graphics_pipeline_library(true);
         let mut feature_dynamic_rendering =
             vk::PhysicalDeviceDynamicRenderingFeatures::default().dynamic_rendering(true);
-
-        let mut features = vk::PhysicalDeviceFeatures::default().shader_int64(true);
+        let mut feature_acceleration_structure =
+            vk::PhysicalDeviceAccelerationStructureFeaturesKHR::default()
+                .acceleration_structure(true);
+        let mut feature_ray_query =
+            vk::PhysicalDeviceRayQueryFeaturesKHR::default().ray_query(true);
+
+        let mut features = vk::PhysicalDeviceFeatures::default()
+            .shader_int64(true)
+            .sampler_anisotropy(supported.sampler_anisotropy == vk::TRUE)
+            .fill_mode_non_solid(supported.fill_mode_non_solid == vk::TRUE);
         if cfg!(debug_assertions) {
             features.robust_buffer_access = 1;
         }
@@ -206,8 +395,15 @@ impl Instance {
             .push_next(&mut feature_buffer_device_address)
             .push_next(&mut feature_synchronization2)
             .push_next(&mut feature_dynamic_state)
-            .push_next(&mut feature_pipeline_library)
             .push_next(&mut feature_dynamic_rendering);
+        if pipeline_libraries {
+            default_features = default_features.push_next(&mut feature_pipeline_library);
+        }
+        if ray_query {
+            default_features = default_features
+                .push_next(&mut feature_acceleration_structure)
+                .push_next(&mut feature_ray_query);
+        }
 
         let device_info = vk::DeviceCreateInfo::default()
             .queue_create_infos(&queue_infos)
@@ -218,6 +414,8 @@ impl Instance {
         let memory_properties = unsafe { self.get_physical_device_memory_properties(pdevice) };
 
         let dynamic_rendering = khr::dynamic_rendering::Device::new(self, &device);
+        let acceleration_structure =
+            ray_query.then(|| khr::acceleration_structure::Device::new(self, &device));
 
         let device_alloc_properties =
             unsafe { gpu_alloc_ash::device_properties(self, vk::API_VERSION_1_3, pdevice)? };
@@ -229,6 +427,15 @@ impl Instance {
         let mut device_properties =
             vk::PhysicalDeviceProperties2::default().push_next(&mut descriptor_indexing_props);
         unsafe { self.get_physical_device_properties2(pdevice, &mut device_properties) };
+        let pci_bus_info = available_extensions
+            .contains(ext::pci_bus_info::NAME)
+            .then(|| {
+                let mut pci_bus_info = vk::PhysicalDevicePCIBusInfoPropertiesEXT::default();
+                let mut properties =
+                    vk::PhysicalDeviceProperties2::default().push_next(&mut pci_bus_info);
+                unsafe { self.get_physical_device_properties2(pdevice, &mut properties) };
+                pci_bus_info
+            });
 
         let command_pool = unsafe {
             device.create_command_pool(
@@ -243,9 +450,13 @@ impl Instance {
         let dbg_utils = ext::debug_utils::Device::new(&self.inner, &device);
 
         let device = Device {
+            instance: self.inner.clone(),
             physical_device: pdevice,
             device_properties: device_properties.properties,
+            pci_bus_info,
+            enabled_features: features,
             descriptor_indexing_props,
+            pipeline_libraries,
             main_queue_family_idx,
             transfer_queue_family_idx,
             command_pool,
@@ -253,7 +464,9 @@ impl Instance {
             allocator: Arc::new(Mutex::new(allocator)),
             device,
             dynamic_rendering,
+            acceleration_structure,
             dbg_utils,
+            audit: ObjectAudit::default(),
         };
         let main_queue = unsafe { device.get_device_queue(main_queue_family_idx, 0) };
         let transfer_queue = unsafe { device.get_device_queue(transfer_queue_family_idx, 0) };
@@ -0,0 +1,213 @@
+use std::collections::HashMap;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use winit::keyboard::{Key, NamedKey};
+
+/// Everything bound to a key besides `Esc` and the movement keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Help,
+    Pause,
+    StepBack,
+    StepForward,
+    SeekBack,
+    SeekForward,
+    SeekBackFar,
+    SeekForwardFar,
+    LoopRegion,
+    Restart,
+    PrintInfo,
+    ClearFeedback,
+    ColorPicker,
+    ScreenshotExr,
+    SaveShaders,
+    SaveSpirv,
+    Screenshot,
+    ConvergedScreenshot,
+    Record,
+    Guides,
+    GuidesAspect,
+    FrameGraph,
+    Heatmap,
+    Hud,
+    OutputWindow,
+    Wireframe,
+    OpacityUp,
+    OpacityDown,
+    SaveBaseline,
+    Validation,
+}
+
+impl Action {
+    fn description(self) -> &'static str {
+        match self {
+            Action::Help => "Print help",
+            Action::Pause => "Toggle play/pause",
+            Action::StepBack => "Pause and step back one frame",
+            Action::StepForward => "Pause and step forward one frame",
+            Action::SeekBack => "Seek back 1 second",
+            Action::SeekForward => "Seek forward 1 second",
+            Action::SeekBackFar => "Seek back 10 seconds",
+            Action::SeekForwardFar => "Seek forward 10 seconds",
+            Action::LoopRegion => "Mark loop start, then end, then clear the loop",
+            Action::Restart => "Restart playback at frame 0 (`Time` and `Pos` = 0)",
+            Action::PrintInfo => "Print parameters, frame time statistics and GPU pass timings",
+            Action::ClearFeedback => "Clear feedback buffers without resetting time",
+            Action::ColorPicker => "Toggle color picker, click to set `picked_color`",
+            Action::ScreenshotExr => "Take float EXR screenshot of the post chain output",
+            Action::SaveShaders => "Save shaders",
+            Action::SaveSpirv => "Save the SPIR-V of every pipeline",
+            Action::Screenshot => "Take Screenshot",
+            Action::ConvergedScreenshot => "Take screenshot averaged over frozen time",
+            Action::Record => "Start/Stop record video",
+            Action::Guides => "Toggle composition guides",
+            Action::GuidesAspect => "Cycle the guides aspect ratio",
+            Action::FrameGraph => "Toggle frame time graph",
+            Action::Heatmap => "Toggle image pass cost heatmap",
+            Action::Hud => "Toggle the fps, resolution and time HUD",
+            Action::OutputWindow => "Open or close the clean output window",
+            Action::Wireframe => "Toggle wireframe drawing of the scene",
+            Action::OpacityUp => "Raise reference image opacity",
+            Action::OpacityDown => "Lower reference image opacity",
+            Action::SaveBaseline => "Save the GPU frame time as the performance baseline",
+            Action::Validation => "Print the validation messages so far with their counts",
+        }
+    }
+}
+
+const NAMED_KEYS: [(&str, NamedKey); 22] = [
+    ("F1", NamedKey::F1),
+    ("F2", NamedKey::F2),
+    ("F3", NamedKey::F3),
+    ("F4", NamedKey::F4),
+    ("F5", NamedKey::F5),
+    ("F6", NamedKey::F6),
+    ("F7", NamedKey::F7),
+    ("F8", NamedKey::F8),
+    ("F9", NamedKey::F9),
+    ("F10", NamedKey::F10),
+    ("F11", NamedKey::F11),
+    ("F12", NamedKey::F12),
+    ("Home", NamedKey::Home),
+    ("End", NamedKey::End),
+    ("Insert", NamedKey::Insert),
+    ("Delete", NamedKey::Delete),
+    ("PageUp", NamedKey::PageUp),
+    ("PageDown", NamedKey::PageDown),
+    ("Tab", NamedKey::Tab),
+    ("Space", NamedKey::Space),
+    ("Enter", NamedKey::Enter),
+    ("Backspace", NamedKey::Backspace),
+];
+
+/// Key of every action, the defaults can be changed in the `[keys]` table of
+/// the manifest.
+#[derive(Debug, Clone)]
+pub struct Keybindings {
+    bindings: Vec<(Action, Key)>,
+}
+
+impl Default for Keybindings {
+    fn default() -> Self {
+        let bindings = [
+            (Action::Help, NamedKey::F1),
+            (Action::Pause, NamedKey::F2),
+            (Action::StepBack, NamedKey::F3),
+            (Action::StepForward, NamedKey::F4),
+            (Action::Restart, NamedKey::F5),
+            (Action::PrintInfo, NamedKey::F6),
+            (Action::ClearFeedback, NamedKey::F7),
+            (Action::ColorPicker, NamedKey::F8),
+            (Action::ScreenshotExr, NamedKey::F9),
+            (Action::SaveShaders, NamedKey::F10),
+            (Action::Screenshot, NamedKey::F11),
+            (Action::ConvergedScreenshot, NamedKey::Delete),
+            (Action::Record, NamedKey::F12),
+            (Action::Guides, NamedKey::Home),
+            (Action::GuidesAspect, NamedKey::End),
+            (Action::FrameGraph, NamedKey::Insert),
+            (Action::Heatmap, NamedKey::Tab),
+            (Action::OpacityUp, NamedKey::PageUp),
+            (Action::OpacityDown, NamedKey::PageDown),
+        ];
+        let seek = [
+            (Action::SeekBack, "["),
+            (Action::SeekForward, "]"),
+            (Action::SeekBackFar, "{"),
+            (Action::SeekForwardFar, "}"),
+            (Action::LoopRegion, "l"),
+            (Action::SaveBaseline, "b"),
+            (Action::SaveSpirv, "c"),
+            (Action::Hud, "h"),
+            (Action::OutputWindow, "o"),
+            (Action::Wireframe, "x"),
+            (Action::Validation, "v"),
+        ];
+        Self {
+            bindings: bindings
+                .into_iter()
+                .map(|(action, key)| (action, Key::Named(key)))
+                .chain(seek.map(|(action, c)| (action, Key::Character(c.into()))))
+                .collect(),
+        }
+    }
+}
+
+impl Keybindings {
+    /// Defaults with `overrides` applied, a key taken from another action
+    /// leaves that one unbound.
+    pub fn new(overrides: &HashMap<Action, String>) -> Result<Self> {
+        let mut keys = Self::default();
+        for (&action, name) in overrides {
+            let key = parse_key(name)?;
+            keys.bindings.retain(|(a, k)| *a != action && *k != key);
+            keys.bindings.push((action, key));
+        }
+        Ok(keys)
+    }
+
+    pub fn action(&self, key: &Key) -> Option<Action> {
+        let key = match key {
+            Key::Character(c) => Key::Character(c.to_lowercase().into()),
+            key => key.clone(),
+        };
+        self.bindings
+            .iter()
+            .find(|(_, k)| *k == key)
+            .map(|&(action, _)| action)
+    }
+}
+
+impl std::fmt::Display for Keybindings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (action, key) in &self.bindings {
+            let name = match key {
+                Key::Named(named) => NAMED_KEYS
+                    .iter()
+                    .find(|(_, k)| k == named)
+                    .map_or("?", |(name, _)| name),
+                Key::Character(c) => c.as_str(),
+                _ => "?",
+            };
+            writeln!(f, "- `{name}`:\t{}", action.description())?;
+        }
+        Ok(())
+    }
+}
+
+/// A named key like `F5` or `PageUp`, or a single character.
+fn parse_key(name: &str) -> Result<Key> {
+    if let Some(&(_, key)) = NAMED_KEYS
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+    {
+        return Ok(Key::Named(key));
+    }
+    let mut chars = name.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Ok(Key::Character(c.to_lowercase().to_string().into())),
+        _ => bail!("Unknown key `{name}`, expected a single character, F1..F12, Home, End, Insert, Delete, PageUp, PageDown, Tab, Space, Enter or Backspace"),
+    }
+}
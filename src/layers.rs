@@ -0,0 +1,412 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use either::Either;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders,
+    post::{self, to_attachment, to_sampled},
+    swapchain::FrameGuard,
+    BlendMode, Device, Error, FragmentOutputDesc, FragmentShaderDesc, PipelineArena,
+    PostPushConstant, PushConstant, RenderHandle, TextureArena, VertexInputDesc, VertexShaderDesc,
+    POST_FOLDER, POST_TARGET_FORMAT,
+};
+
+/// How a layer is mixed into the frame below it.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayerBlend {
+    /// Crossfade by opacity and the layer alpha.
+    #[default]
+    Normal,
+    Add,
+    Multiply,
+    Screen,
+}
+
+impl LayerBlend {
+    const ALL: [LayerBlend; 4] = [
+        LayerBlend::Normal,
+        LayerBlend::Add,
+        LayerBlend::Multiply,
+        LayerBlend::Screen,
+    ];
+
+    pub(crate) fn blend_mode(self) -> BlendMode {
+        match self {
+            LayerBlend::Normal => BlendMode::Alpha,
+            LayerBlend::Add => BlendMode::Add,
+            LayerBlend::Multiply => BlendMode::Multiply,
+            LayerBlend::Screen => BlendMode::Screen,
+        }
+    }
+}
+
+impl std::fmt::Display for LayerBlend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LayerBlend::Normal => "normal",
+            LayerBlend::Add => "add",
+            LayerBlend::Multiply => "multiply",
+            LayerBlend::Screen => "screen",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for LayerBlend {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "normal" => LayerBlend::Normal,
+            "add" => LayerBlend::Add,
+            "multiply" => LayerBlend::Multiply,
+            "screen" => LayerBlend::Screen,
+            _ => bail!("Unknown blend mode `{s}`, expected normal, add, multiply or screen"),
+        })
+    }
+}
+
+/// Another project drawn over this one, from the `[[layer]]` tables of the
+/// manifest.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LayerDesc {
+    /// Project folder, relative to the shader folder.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub blend: LayerBlend,
+    #[serde(default = "default_one")]
+    pub opacity: f32,
+    /// Rate of the layer clock relative to the main one.
+    #[serde(default = "default_one")]
+    pub speed: f32,
+    #[serde(default)]
+    pub paused: bool,
+    /// Start and end seconds the layer clock wraps between.
+    #[serde(rename = "loop")]
+    pub loop_region: Option<[f32; 2]>,
+}
+
+fn default_one() -> f32 {
+    1.
+}
+
+/// Time of a layer, advanced by the main clock at its own speed.
+#[derive(Debug, Clone, Copy)]
+struct Transport {
+    time: f32,
+    /// Advance of `time` in the current frame.
+    delta: f32,
+    speed: f32,
+    paused: bool,
+    loop_region: Option<[f32; 2]>,
+}
+
+impl Transport {
+    fn advance(&mut self, delta: f32) {
+        self.delta = match self.paused {
+            true => 0.,
+            false => delta * self.speed,
+        };
+        self.time += self.delta;
+        if let Some([start, end]) = self.loop_region {
+            if self.time >= end || self.time < start {
+                self.time = start;
+            }
+        }
+        self.time = self.time.max(0.);
+    }
+}
+
+struct Layer {
+    dir: PathBuf,
+    pipeline: RenderHandle,
+    blend: LayerBlend,
+    opacity: f32,
+    transport: Transport,
+    /// Texture arena index of the image the layer renders into.
+    target: usize,
+}
+
+/// Image passes of other project folders. Each renders with the shared push
+/// constants into its own float target before the scene, then gets blended
+/// over the scene after the image pass and the fragment passes.
+#[derive(Default)]
+pub struct Layers {
+    layers: Vec<Layer>,
+    /// Composite pipeline of every blend mode, only while there are layers.
+    composite: Vec<(LayerBlend, RenderHandle)>,
+    /// Texture arena slots reused across reloads, the arena never shrinks.
+    targets: Vec<usize>,
+}
+
+impl Layers {
+    /// Replaces the layers with `descs`. The GPU has to be idle.
+    pub fn configure(
+        &mut self,
+        descs: &[LayerDesc],
+        shader_dir: &Path,
+        queue: &vk::Queue,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &mut TextureArena,
+        scene_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        self.clear(pipeline_arena);
+        if descs.is_empty() {
+            return Ok(());
+        }
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let post_range = vk::PushConstantRange::default()
+            .size(size_of::<PostPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        for blend in LayerBlend::ALL {
+            let handle = pipeline_arena
+                .create_render_pipeline(
+                    &VertexInputDesc::default(),
+                    &VertexShaderDesc {
+                        shader_path: dir.join("post.vert"),
+                        ..Default::default()
+                    },
+                    &FragmentShaderDesc {
+                        shader_path: dir.join("layer.frag"),
+                    },
+                    &FragmentOutputDesc {
+                        surface_format: scene_format,
+                        blend: blend.blend_mode(),
+                        ..Default::default()
+                    },
+                    &[post_range],
+                    &[texture_arena.images_set_layout],
+                )
+                .map_err(Error::pass("layer.frag"))?;
+            self.composite.push((blend, handle));
+        }
+
+        let range = vk::PushConstantRange::default()
+            .size(size_of::<PushConstant>() as _)
+            .stage_flags(
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE,
+            );
+        for (i, desc) in descs.iter().enumerate() {
+            let dir = shader_dir.join(&desc.path);
+            check_loop(desc.loop_region)?;
+            if !dir.join("shader.frag").is_file() {
+                bail!("Layer {} has no shader.frag", dir.display());
+            }
+            let pipeline = pipeline_arena
+                .create_render_pipeline(
+                    &VertexInputDesc::default(),
+                    &VertexShaderDesc {
+                        shader_path: dir.join("shader.vert"),
+                        ..Default::default()
+                    },
+                    &FragmentShaderDesc {
+                        shader_path: dir.join("shader.frag"),
+                    },
+                    &FragmentOutputDesc {
+                        surface_format: POST_TARGET_FORMAT,
+                        ..Default::default()
+                    },
+                    &[range],
+                    &[texture_arena.images_set_layout],
+                )
+                .map_err(Error::pass(format!("layer {}", desc.path.display())))?;
+            let target = match self.targets.get(i) {
+                Some(&target) => {
+                    texture_arena.image_infos[target].extent = extent.into();
+                    texture_arena.update_images(queue, &[target])?;
+                    target
+                }
+                None => {
+                    let target = texture_arena.push_render_target(target_info(extent))?;
+                    self.targets.push(target);
+                    target
+                }
+            };
+            self.layers.push(Layer {
+                dir,
+                pipeline,
+                blend: desc.blend,
+                opacity: desc.opacity,
+                transport: Transport {
+                    time: desc.loop_region.map_or(0., |[start, _]| start),
+                    delta: 0.,
+                    speed: desc.speed,
+                    paused: desc.paused,
+                    loop_region: desc.loop_region,
+                },
+                target,
+            });
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self, pipeline_arena: &mut PipelineArena) {
+        for layer in self.layers.drain(..) {
+            pipeline_arena.remove_pipeline(Either::Left(layer.pipeline));
+        }
+        for (_, handle) in self.composite.drain(..) {
+            pipeline_arena.remove_pipeline(Either::Left(handle));
+        }
+    }
+
+    /// Texture arena indices of the layer targets, resized with the swapchain.
+    pub fn targets(&self) -> impl Iterator<Item = usize> + '_ {
+        self.layers.iter().map(|layer| layer.target)
+    }
+
+    /// Moves every layer clock on by `delta` seconds of the main clock.
+    pub fn advance(&mut self, delta: f32) {
+        for layer in &mut self.layers {
+            layer.transport.advance(delta);
+        }
+    }
+
+    /// Rewinds every layer clock along with the main one.
+    pub fn restart(&mut self) {
+        for layer in &mut self.layers {
+            let transport = &mut layer.transport;
+            transport.time = transport.loop_region.map_or(0., |[start, _]| start);
+        }
+    }
+
+    /// Toggles the layer clock without `pause`.
+    pub fn set_pause(&mut self, idx: usize, pause: Option<bool>) -> Result<()> {
+        let layer = self.layers.get_mut(idx).context("No such layer")?;
+        let transport = &mut layer.transport;
+        transport.paused = pause.unwrap_or(!transport.paused);
+        let state = if transport.paused {
+            "paused"
+        } else {
+            "playing"
+        };
+        println!("Layer {}: {state}", layer.dir.display());
+        Ok(())
+    }
+
+    pub fn set_speed(&mut self, idx: usize, speed: f32) -> Result<()> {
+        let layer = self.layers.get_mut(idx).context("No such layer")?;
+        layer.transport.speed = speed;
+        Ok(())
+    }
+
+    /// Wraps the layer clock between two seconds, `None` plays it on.
+    pub fn set_loop(&mut self, idx: usize, region: Option<[f32; 2]>) -> Result<()> {
+        check_loop(region)?;
+        let layer = self.layers.get_mut(idx).context("No such layer")?;
+        layer.transport.loop_region = region;
+        Ok(())
+    }
+
+    pub fn set_opacity(&mut self, idx: usize, opacity: f32) -> Result<()> {
+        let layer = self.layers.get_mut(idx).context("No such layer")?;
+        layer.opacity = opacity.clamp(0., 1.);
+        Ok(())
+    }
+
+    pub fn set_blend(&mut self, idx: usize, blend: LayerBlend) -> Result<()> {
+        let layer = self.layers.get_mut(idx).context("No such layer")?;
+        layer.blend = blend;
+        println!("Layer {}: {blend}", layer.dir.display());
+        Ok(())
+    }
+
+    /// Renders every visible layer into its target at its own time, outside of
+    /// any rendering.
+    pub fn render(
+        &self,
+        frame: &mut FrameGuard,
+        device: &Device,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        push_constant: &PushConstant,
+        extent: vk::Extent2D,
+    ) {
+        let stages = vk::ShaderStageFlags::VERTEX
+            | vk::ShaderStageFlags::FRAGMENT
+            | vk::ShaderStageFlags::COMPUTE;
+        for layer in self.visible(pipeline_arena) {
+            let image = texture_arena.images[layer.target].image;
+            to_attachment(device, frame, image);
+            frame.begin_rendering_extent(&texture_arena.views[layer.target], extent, [0.; 4]);
+            let pipeline = pipeline_arena.get_pipeline(layer.pipeline);
+            let push_constant = PushConstant {
+                time: layer.transport.time,
+                time_delta: layer.transport.delta,
+                ..*push_constant
+            };
+            frame.push_constant(pipeline.layout, stages, &[push_constant]);
+            frame.bind_descriptor_sets(
+                vk::PipelineBindPoint::GRAPHICS,
+                pipeline.layout,
+                &pipeline.descriptor_sets(texture_arena.images_set),
+            );
+            frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+            frame.draw(3, 0, 1, 0);
+            frame.end_rendering();
+            to_sampled(device, frame, image);
+        }
+    }
+
+    /// Blends the layers rendered this frame into the scene being rendered.
+    pub fn composite(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        extent: vk::Extent2D,
+    ) {
+        for layer in self.visible(pipeline_arena) {
+            let Some(&(_, handle)) = self.composite.iter().find(|(b, _)| *b == layer.blend) else {
+                continue;
+            };
+            let push_constant = PostPushConstant {
+                resolution: [extent.width as f32, extent.height as f32],
+                src: layer.target as u32,
+                mode: layer.blend as u32,
+                strength: layer.opacity,
+                ..Default::default()
+            };
+            post::draw(frame, pipeline_arena, texture_arena, handle, push_constant);
+        }
+    }
+
+    fn visible<'a>(&'a self, pipeline_arena: &'a PipelineArena) -> impl Iterator<Item = &'a Layer> {
+        self.layers.iter().filter(|layer| {
+            layer.opacity > 0. && !pipeline_arena.is_quarantined(Either::Left(layer.pipeline))
+        })
+    }
+}
+
+fn check_loop(region: Option<[f32; 2]>) -> Result<()> {
+    if let Some([start, end]) = region {
+        if !(start >= 0. && start < end) {
+            bail!("Layer loop needs 0 <= start < end, got {start}..{end}");
+        }
+    }
+    Ok(())
+}
+
+fn target_info(extent: vk::Extent2D) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .extent(extent.into())
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(POST_TARGET_FORMAT)
+        .usage(vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .mip_levels(1)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+}
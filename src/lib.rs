@@ -1,51 +1,148 @@
 #![allow(clippy::new_without_default)]
 #![allow(clippy::too_many_arguments)]
 
+mod artnet;
+#[cfg(feature = "audio")]
+mod audio;
+mod audit;
+mod baseline;
+mod bindings;
+mod capture_cache;
+mod data;
 pub mod default_shaders;
+mod depth;
 mod device;
+mod error;
+mod export;
+mod headless;
+mod heatmap;
+mod hud;
 mod input;
 mod instance;
+mod keys;
+mod layers;
+mod macros;
+mod manifest;
+mod mesh;
+mod msaa;
+mod osc;
+mod output_window;
+mod overlay;
+mod particles;
+mod passes;
 mod pipeline_arena;
+mod post;
+mod profiler;
+mod raster;
 mod recorder;
+mod reflect;
+mod reload_check;
+mod scene_accel;
+#[cfg(feature = "sensors")]
+mod sensors;
 mod shader_compiler;
+mod shadertoy;
+mod slices;
+mod spirv_cache;
+mod stats;
+mod status;
+mod streaming;
 mod surface;
 mod swapchain;
+mod sync;
+mod tables;
 mod texture_arena;
+mod textures;
+mod validation;
 mod watcher;
+mod window_state;
 
 use std::{
+    borrow::Cow,
     fs::File,
     io,
     mem::ManuallyDrop,
     ops::{Add, BitAnd, Not, Sub},
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
     sync::Arc,
     time::Duration,
 };
 
+#[cfg(feature = "audio")]
+pub use self::audio::{SoundPass, SoundPushConstant};
+#[cfg(feature = "sensors")]
+pub use self::sensors::{GpuReading, GpuSensors};
 pub use self::{
-    device::{Device, HostBufferTyped},
+    artnet::{DmxOutput, DmxSettings, ARTNET_PORT},
+    audit::{MemoryKind, ObjectAudit, ObjectKind},
+    baseline::{PerfBaseline, BASELINE_FILE},
+    bindings::{BufferDesc, UserResources},
+    capture_cache::{CaptureCache, CaptureKind},
+    data::{parse_values, DataBuffers, DataChannelDesc, DataPublisher},
+    depth::{DepthMode, DepthSettings, SceneDepth},
+    device::{capture_format, Device, HostBufferTyped},
+    error::Error,
+    export::{ExportCheckpoint, ExportProgress, EXPORT_CHECKPOINT_FILE},
+    headless::render_frame_to_buffer,
+    heatmap::Heatmap,
+    hud::{Hud, HudSettings, HudValues},
     input::Input,
     instance::Instance,
+    keys::{Action, Keybindings},
+    layers::{LayerBlend, LayerDesc, Layers},
+    macros::{MacroCommand, MacroPlayer, MacroRecorder},
+    manifest::{Manifest, WindowSettings, MANIFEST_FILE},
+    mesh::{Mesh, MeshAttribute, MeshSettings},
+    msaa::SceneMsaa,
+    osc::{OscArg, OscCommand, OscMessage, OscServer},
+    output_window::OutputWindow,
+    overlay::{Guides, ReferenceOverlay},
+    particles::{ParticleSettings, Particles, Topology},
+    passes::Passes,
     pipeline_arena::*,
-    recorder::{RecordEvent, Recorder},
-    shader_compiler::ShaderCompiler,
+    post::{PostPass, PostProcess, PostPushConstant, POST_FOLDER, POST_TARGET_FORMAT},
+    profiler::GpuProfiler,
+    raster::{CullMode, FrontFace, PolygonMode, RasterSettings, RasterState, SceneRaster},
+    recorder::{
+        Accumulation, FfmpegCaps, ImageFormat, PngCompression, RecordEvent, Recorder,
+        ScreenshotSettings, VideoCodec, VideoSettings,
+    },
+    reflect::{set_user_param, ParamValue, UserParam},
+    reload_check::ReloadCheck,
+    scene_accel::SceneAccel,
+    shader_compiler::{CompileSettings, ShaderCompiler},
+    shadertoy::to_shadertoy,
+    slices::{SliceDesc, Slices},
+    stats::{FrameStats, StatsGraph, TitleTimer},
+    status::{Status, StatusPublisher},
+    streaming::{StreamDesc, Streams},
     surface::Surface,
-    swapchain::Swapchain,
+    swapchain::{
+        is_hdr, surface_defines, ColorSpace, SceneAttachments, SurfaceEncoding, Swapchain,
+        SwapchainSettings,
+    },
+    sync::{ClockSync, SyncMode, SyncState},
+    tables::{TableDesc, Tables},
     texture_arena::*,
+    textures::{TextureDesc, Textures},
+    validation::{ValidationLevel, ValidationLog, ValidationSettings, VALIDATION_LOG_FILE},
     watcher::Watcher,
+    window_state::{OverlayFlags, WindowState, WINDOW_STATE_FILE},
 };
 
 use anyhow::{bail, Context};
 use ash::vk::{self, DeviceMemory};
-use gpu_alloc::{GpuAllocator, MapError, MemoryBlock};
+use gpu_alloc::{MapError, MemoryBlock};
 use gpu_alloc_ash::AshMemoryDevice;
-use parking_lot::Mutex;
+
+use self::default_shaders::Template;
 
 pub const SHADER_DUMP_FOLDER: &str = "shader_dump";
 pub const SHADER_FOLDER: &str = "shaders";
 pub const VIDEO_FOLDER: &str = "recordings";
 pub const SCREENSHOT_FOLDER: &str = "screenshots";
+pub const VIDEO_FRAMERATE: u64 = 60;
 
 pub const COLOR_SUBRESOURCE_MASK: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
     aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -72,11 +169,109 @@ macro_rules! impl_one {
 }
 impl_one!(i32, u32, i64, u64, usize);
 
+/// `init.comp` is optional and runs once before `shader.comp` on start,
+/// restart, folder switch or request, to seed simulation state.
+pub fn create_init_pipeline(
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    shader_dir: &Path,
+) -> anyhow::Result<Option<ComputeHandle>> {
+    let path = shader_dir.join("init.comp");
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let push_constant_range = vk::PushConstantRange::default()
+        .size(size_of::<PushConstant>() as _)
+        .stage_flags(
+            vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
+        );
+    let handle = pipeline_arena
+        .create_compute_pipeline(
+            path,
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )
+        .map_err(Error::pass("init"))?;
+    Ok(Some(handle))
+}
+
+/// The image pass and `shader.comp` of a project folder.
+pub fn create_pipelines(
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    surface_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    depth: Option<DepthDesc>,
+    raster: RasterState,
+    shader_dir: &Path,
+) -> anyhow::Result<(RenderHandle, ComputeHandle)> {
+    let vertex_shader_desc = raster.apply(VertexShaderDesc {
+        shader_path: shader_dir.join("shader.vert"),
+        ..Default::default()
+    });
+    let fragment_shader_desc = FragmentShaderDesc {
+        shader_path: shader_dir.join("shader.frag"),
+    };
+    let fragment_output_desc = FragmentOutputDesc {
+        surface_format,
+        multisample_state: samples,
+        depth,
+        ..Default::default()
+    };
+    let push_constant_range = vk::PushConstantRange::default()
+        .size(size_of::<PushConstant>() as _)
+        .stage_flags(
+            vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
+        );
+    let render_pipeline = pipeline_arena
+        .create_render_pipeline(
+            &VertexInputDesc::default(),
+            &vertex_shader_desc,
+            &fragment_shader_desc,
+            &fragment_output_desc,
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )
+        .map_err(Error::pass("image"))?;
+
+    let compute_pipeline = pipeline_arena
+        .create_compute_pipeline(
+            shader_dir.join("shader.comp"),
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )
+        .map_err(Error::pass("compute"))?;
+
+    Ok((render_pipeline, compute_pipeline))
+}
+
 pub fn dispatch_optimal(len: u32, subgroup_size: u32) -> u32 {
     let padded_size = (subgroup_size - len % subgroup_size) % subgroup_size;
     (len + padded_size) / subgroup_size
 }
 
+/// Largest rect with the aspect ratio of `content` centered in `window`.
+pub fn letterbox(content: vk::Extent2D, window: vk::Extent2D) -> vk::Rect2D {
+    let scale = (window.width as f32 / content.width as f32)
+        .min(window.height as f32 / content.height as f32);
+    let extent = vk::Extent2D {
+        width: ((content.width as f32 * scale).round() as u32).clamp(1, window.width.max(1)),
+        height: ((content.height as f32 * scale).round() as u32).clamp(1, window.height.max(1)),
+    };
+    // The extent stays at least 1, an empty window puts it at the origin
+    vk::Rect2D {
+        offset: vk::Offset2D {
+            x: window.width.saturating_sub(extent.width) as i32 / 2,
+            y: window.height.saturating_sub(extent.height) as i32 / 2,
+        },
+        extent,
+    }
+}
+
 pub fn create_folder<P: AsRef<Path>>(name: P) -> io::Result<()> {
     match std::fs::create_dir(name) {
         Ok(_) => {}
@@ -87,34 +282,105 @@ pub fn create_folder<P: AsRef<Path>>(name: P) -> io::Result<()> {
     Ok(())
 }
 
-pub fn print_help() {
+pub fn print_help(keys: &Keybindings) {
     println!();
-    println!("- `F1`:   Print help");
-    println!("- `F2`:   Toggle play/pause");
-    println!("- `F3`:   Pause and step back one frame");
-    println!("- `F4`:   Pause and step forward one frame");
-    println!("- `F5`:   Restart playback at frame 0 (`Time` and `Pos` = 0)");
-    println!("- `F6`:   Print parameters");
-    println!("- `F10`:  Save shaders");
-    println!("- `F11`:  Take Screenshot");
-    println!("- `F12`:  Start/Stop record video");
+    print!("{keys}");
     println!("- `ESC`:  Exit the application");
     println!("- `Arrows`: Change `Pos`\n");
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// Borderless window covering the monitor.
+    Borderless,
+    /// Takes over the monitor with its best video mode.
+    Exclusive,
+}
+
+impl FromStr for FullscreenMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        Ok(match s {
+            "borderless" => FullscreenMode::Borderless,
+            "exclusive" => FullscreenMode::Exclusive,
+            _ => bail!("Unknown fullscreen mode `{s}`, expected borderless or exclusive"),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Args {
+    /// Folder with the shaders, `shaders` in the working directory by default.
+    pub shader_dir: PathBuf,
     pub inner_size: Option<(u32, u32)>,
+    /// Fixed resolution of the image pass, scaled to the window.
+    pub render_size: Option<(u32, u32)>,
+    /// Physical position of the window, relative to `monitor` when given.
+    pub position: Option<(i32, i32)>,
+    pub maximized: bool,
+    pub overlay: OverlayFlags,
     pub record_time: Option<Duration>,
+    /// Seconds of video between the checkpoints of a `--record` export.
+    pub checkpoint: Option<Duration>,
+    /// Continue the export of the checkpoint in the video folder.
+    pub resume: bool,
+    /// Advance `time` by this step every frame instead of following the
+    /// wall clock.
+    pub fixed_dt: Option<Duration>,
+    /// Macro file replayed from startup.
+    pub macro_file: Option<PathBuf>,
+    /// Where to record the keyboard actions and OSC commands as a macro.
+    pub record_macro: Option<PathBuf>,
+    pub osc_port: Option<u16>,
+    pub split_chapters: bool,
+    pub audio_file: Option<PathBuf>,
+    pub reference: Option<PathBuf>,
+    /// Monitor index or part of its name.
+    pub monitor: Option<String>,
+    pub fullscreen: Option<FullscreenMode>,
+    pub sync: Option<SyncMode>,
+    /// Where to send the periodic OSC status reports.
+    pub status: Option<std::net::SocketAddr>,
+    /// Print the Vulkan object counts of the device on `F6`.
+    pub audit: bool,
+    /// Physical device index or part of its name.
+    pub gpu: Option<String>,
+    /// Print the physical devices and exit.
+    pub list_gpus: bool,
+    /// Monitor index or part of its name for the clean output window.
+    pub output_monitor: Option<String>,
+    /// Extra `#include` folders, searched after the ones of the manifest.
+    pub include: Vec<PathBuf>,
+    /// `pilka new`, write a project from `template` into `shader_dir`.
+    pub new_project: bool,
+    /// Shaders written into a missing `shader_dir`.
+    pub template: Template,
+    /// Where to write `shader.frag` converted for Shadertoy, `-` prints it.
+    pub shadertoy: Option<PathBuf>,
+    pub validation: ValidationSettings,
+    /// Save the SPIR-V of every pipeline once they are built.
+    pub dump_spirv: bool,
+    /// SPIR-V dumps also get the disassembly.
+    pub spirv_asm: bool,
+    pub video: VideoSettings,
 }
 
 pub fn parse_args() -> anyhow::Result<Args> {
-    let mut inner_size = None;
-    let mut record_time = None;
-    let args = std::env::args().skip(1).step_by(2);
-    for (flag, value) in args.zip(std::env::args().skip(2).step_by(2)) {
+    let mut parsed = Args {
+        shader_dir: PathBuf::from(SHADER_FOLDER),
+        ..Default::default()
+    };
+    let mut named = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .with_context(|| format!("Missing value for `{flag}`"))
+        };
         match flag.trim() {
             "--record" => {
+                let value = value()?;
                 let time = match value.split_once('.') {
                     Some((sec, ms)) => {
                         let seconds = sec.parse()?;
@@ -123,26 +389,176 @@ pub fn parse_args() -> anyhow::Result<Args> {
                     }
                     None => Duration::from_secs(value.parse()?),
                 };
-                record_time = Some(time)
+                parsed.record_time = Some(time)
+            }
+            "--checkpoint" => {
+                let secs: f32 = value()?
+                    .parse()
+                    .context("Failed to parse checkpoint interval")?;
+                if !(secs > 0. && secs.is_finite()) {
+                    bail!("Checkpoint interval has to be a positive number of seconds");
+                }
+                parsed.checkpoint = Some(Duration::try_from_secs_f32(secs)?);
+            }
+            "--resume" => parsed.resume = true,
+            "--fixed-dt" => {
+                let dt: f32 = value()?
+                    .parse()
+                    .context("Failed to parse fixed time step")?;
+                if !(dt > 0. && dt.is_finite()) {
+                    bail!("Fixed time step has to be a positive number of seconds");
+                }
+                parsed.fixed_dt = Some(Duration::try_from_secs_f32(dt)?);
             }
+            "--macro" => {
+                let path = PathBuf::from(value()?);
+                if !path.is_file() {
+                    bail!("Macro file doesn't exist: {}", path.display());
+                }
+                parsed.macro_file = Some(path);
+            }
+            "--record-macro" => parsed.record_macro = Some(PathBuf::from(value()?)),
             "--size" => {
-                let (w, h) = value
-                    .split_once('x')
-                    .context("Failed to parse window size: Missing 'x' delimiter")?;
-                inner_size = Some((w.parse()?, h.parse()?));
+                let size = parse_size(&value()?).context("Failed to parse window size")?;
+                parsed.inner_size = Some(size);
+            }
+            "--render-size" => {
+                let size = parse_size(&value()?).context("Failed to parse render size")?;
+                parsed.render_size = Some(size);
+            }
+            "--position" => {
+                let value = value()?;
+                let (x, y) = value
+                    .split_once(',')
+                    .context("Failed to parse window position: Missing ',' delimiter")?;
+                parsed.position = Some((x.trim().parse()?, y.trim().parse()?));
+            }
+            "--maximized" => parsed.maximized = true,
+            "--always-on-top" => parsed.overlay.always_on_top = true,
+            "--borderless" => parsed.overlay.borderless = true,
+            "--click-through" => parsed.overlay.click_through = true,
+            "--transparent" => parsed.overlay.transparent = true,
+            "--overlay" => parsed.overlay = OverlayFlags::ALL,
+            "--audit" => parsed.audit = true,
+            "--osc" => {
+                let port = value()?.parse().context("Failed to parse OSC port")?;
+                parsed.osc_port = Some(port);
+            }
+            "--split-chapters" => parsed.split_chapters = true,
+            "--audio" => {
+                let path = PathBuf::from(value()?);
+                if !path.is_file() {
+                    bail!("Audio file doesn't exist: {}", path.display());
+                }
+                parsed.audio_file = Some(path);
+            }
+            "--reference" => {
+                let path = PathBuf::from(value()?);
+                if !path.is_file() {
+                    bail!("Reference image doesn't exist: {}", path.display());
+                }
+                parsed.reference = Some(path);
+            }
+            "--monitor" => parsed.monitor = Some(value()?),
+            "--gpu" => parsed.gpu = Some(value()?),
+            "--list-gpus" => parsed.list_gpus = true,
+            "--output-monitor" => parsed.output_monitor = Some(value()?),
+            "-I" | "--include" => parsed.include.push(PathBuf::from(value()?)),
+            dir if dir.starts_with("-I") => parsed.include.push(PathBuf::from(&dir[2..])),
+            "--fullscreen" => parsed.fullscreen = Some(value()?.parse()?),
+            "--sync" => parsed.sync = Some(value()?.parse()?),
+            "--status" => {
+                let addr = value()?.parse().context("Failed to parse status address")?;
+                parsed.status = Some(addr);
+            }
+            "--codec" => parsed.video.codec = Some(value()?.parse()?),
+            "--crf" => {
+                let crf = value()?.parse().context("Failed to parse CRF")?;
+                parsed.video.crf = Some(crf);
+            }
+            "--bitrate" => parsed.video.bitrate = Some(value()?),
+            "--pix-fmt" => parsed.video.pixel_format = Some(value()?),
+            "--container" => parsed.video.container = Some(value()?),
+            "--gif" => parsed.video.container = Some("gif".into()),
+            "--fps" => {
+                let fps = value()?.parse().context("Failed to parse fps")?;
+                parsed.video.fps = Some(fps);
+            }
+            "--max-duration" => {
+                let secs = value()?.parse().context("Failed to parse max duration")?;
+                parsed.video.max_duration = Some(secs);
+            }
+            "--captions" => parsed.video.captions = Some(PathBuf::from(value()?)),
+            "--template" => parsed.template = value()?.parse()?,
+            "--shadertoy" => parsed.shadertoy = Some(PathBuf::from(value()?)),
+            "--validation" => parsed.validation.layers = true,
+            "--validation-level" => parsed.validation.level = value()?.parse()?,
+            "--dump-spirv" => parsed.dump_spirv = true,
+            "--spirv-asm" => parsed.spirv_asm = true,
+            "new" if !parsed.new_project => parsed.new_project = true,
+            path if !path.starts_with('-') => {
+                parsed.shader_dir = PathBuf::from(path);
+                named = true;
             }
             _ => {}
         }
     }
+    if parsed.new_project && parsed.shadertoy.is_some() {
+        bail!("`--shadertoy` converts an existing project, not a new one");
+    }
+    if parsed.new_project && !named {
+        bail!("`pilka new` needs the folder of the project, e.g. `pilka new sketch`");
+    }
+
+    Ok(parsed)
+}
 
-    Ok(Args {
-        record_time,
-        inner_size,
-    })
+fn parse_size(value: &str) -> anyhow::Result<(u32, u32)> {
+    let (w, h) = value.split_once('x').context("Missing 'x' delimiter")?;
+    let (w, h) = (w.parse()?, h.parse()?);
+    if w == 0 || h == 0 {
+        bail!("Size can't be zero");
+    }
+    Ok((w, h))
 }
 
+/// Buffer addresses shaders reach through `pc.resources`, kept out of the
+/// push constants so they fit the 128 bytes every device supports.
 #[repr(C)]
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Resources {
+    /// Address of the data channel table, see [`DataBuffers`].
+    pub data: u64,
+    /// Address of the Art-Net channels, see [`DmxOutput`].
+    pub dmx: u64,
+    /// Address of the table list, see [`Tables`].
+    pub tables: u64,
+    /// Address of the stream list, see [`Streams`].
+    pub streams: u64,
+    /// Address of the particle buffer, see [`Particles`].
+    pub particles: u64,
+    pub particle_count: u32,
+    /// Vertices drawn per particle.
+    pub particle_vertices: u32,
+    /// Address of the top level acceleration structure of the mesh, see
+    /// [`SceneAccel`].
+    pub scene: u64,
+}
+
+impl Resources {
+    /// Zeroed block in mapped memory, written only while the GPU is idle.
+    pub fn new_buffer(device: &Arc<Device>) -> anyhow::Result<HostBufferTyped<Self>> {
+        let mut buffer = device.clone().create_host_buffer_typed(
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::UPLOAD,
+        )?;
+        *buffer = Self::default();
+        Ok(buffer)
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct PushConstant {
     pub pos: [f32; 3],
     pub time: f32,
@@ -152,8 +568,23 @@ pub struct PushConstant {
     pub frame: u32,
     pub time_delta: f32,
     pub record_time: f32,
+    pub picked_color: [f32; 4],
+    /// Address of the [`Resources`] block.
+    pub resources: u64,
+    /// Shader hot reloads since the folder was loaded.
+    pub reload_count: u32,
+    /// Held buttons, bit 1 left, 2 right, 4 middle.
+    pub mouse_buttons: u32,
+    /// Wheel notches since the previous frame, `y` is positive away from the user.
+    pub scroll: [f32; 2],
+    /// Extra fields shaders declare after `scroll`, see [`UserParam`]. Long
+    /// enough for a `vec3` and a `float`, GLSL starts a `vec3` at byte 96.
+    pub user: [f32; 6],
 }
 
+// The minimum `maxPushConstantsSize`, new buffers go in `Resources`
+const _: () = assert!(size_of::<PushConstant>() <= 128);
+
 impl Default for PushConstant {
     fn default() -> Self {
         Self {
@@ -165,6 +596,12 @@ impl Default for PushConstant {
             frame: 0,
             time_delta: 1. / 60.,
             record_time: 10.,
+            picked_color: [0., 0., 0., 1.],
+            resources: 0,
+            reload_count: 0,
+            mouse_buttons: 0,
+            scroll: [0.; 2],
+            user: [0.; 6],
         }
     }
 }
@@ -177,17 +614,20 @@ impl std::fmt::Display for PushConstant {
             f,
             "position:\t{:?}\n\
              time:\t\t{:#.2?}\n\
-             time delta:\t{:#.3?}, fps: {:#.2?}\n\
+             time delta:\t{:#.3?}\n\
              width, height:\t{:?}\nmouse:\t\t{:.2?}\n\
-             frame:\t\t{}\nrecord_period:\t{}\n",
+             frame:\t\t{}\nrecord_period:\t{}\npicked_color:\t{:.3?}\n\
+             reload_count:\t{}\nmouse_buttons:\t{:#05b}\n",
             self.pos,
             time,
             time_delta,
-            1. / self.time_delta,
             self.wh,
             self.mouse,
             self.frame,
-            self.record_time
+            self.record_time,
+            self.picked_color,
+            self.reload_count,
+            self.mouse_buttons
         )
     }
 }
@@ -220,9 +660,59 @@ pub fn save_shaders<P: AsRef<Path>>(path: P) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Writes the SPIR-V of `sources` into a new dump folder, named after their
+/// path in `shader_dir` with `.spv`, and their disassembly as `.spvasm`
+/// next to it when asked. A shader that doesn't compile is skipped.
+pub fn save_spirv(
+    compiler: &ShaderCompiler,
+    shader_dir: &Path,
+    sources: &[ShaderSource],
+    disassembly: bool,
+) -> anyhow::Result<()> {
+    let dump_folder = Path::new(SHADER_DUMP_FOLDER);
+    create_folder(dump_folder)?;
+    let dump_folder =
+        dump_folder.join(chrono::Local::now().format("%Y-%m-%d_%H-%M-%S").to_string());
+    create_folder(&dump_folder)?;
+    let dump_folder = dump_folder.join("spirv");
+    create_folder(&dump_folder)?;
+
+    let shader_dir = shader_dir.canonicalize()?;
+    for ShaderSource { path, kind } in sources {
+        let name = match path.strip_prefix(&shader_dir) {
+            Ok(relative) => relative.to_string_lossy(),
+            Err(_) => path.file_name().unwrap_or_default().to_string_lossy(),
+        };
+        // Pass folders flattened, `pass1/shader.frag` becomes `pass1_shader.frag`
+        let name = name.replace(['/', '\\'], "_");
+        let words = match compiler.compile(path, (*kind).into()) {
+            Ok(words) => words,
+            Err(err) => {
+                log::error!("{err}");
+                continue;
+            }
+        };
+        let to = dump_folder.join(format!("{name}.spv"));
+        std::fs::write(&to, bytemuck::cast_slice(&words))?;
+        println!("Saved: {}", to.display());
+        if disassembly {
+            let text = compiler.disassemble(path, (*kind).into())?;
+            let to = dump_folder.join(format!("{name}.spvasm"));
+            std::fs::write(&to, text)?;
+            println!("Saved: {}", to.display());
+        }
+    }
+
+    Ok(())
+}
+
 #[derive(Debug)]
 pub enum UserEvent {
     Glsl { path: std::path::PathBuf },
+    Table { path: std::path::PathBuf },
+    Manifest,
+    Passes,
+    Osc(OscCommand),
 }
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
@@ -277,7 +767,6 @@ pub struct ManagedImage {
     pub data: Option<&'static mut [u8]>,
     pub format: vk::Format,
     device: Arc<Device>,
-    allocator: Arc<Mutex<GpuAllocator<DeviceMemory>>>,
 }
 
 impl ManagedImage {
@@ -288,13 +777,39 @@ impl ManagedImage {
     ) -> anyhow::Result<Self> {
         let image = unsafe { device.create_image(info, None)? };
         let memory_reqs = unsafe { device.get_image_memory_requirements(image) };
-        let memory = device.alloc_memory(memory_reqs, usage)?;
+        // Only readback images are mapped
+        let kind = match usage.contains(gpu_alloc::UsageFlags::DOWNLOAD) {
+            true => MemoryKind::Readback,
+            false => MemoryKind::Attachments,
+        };
+        let memory = device.alloc_memory(memory_reqs, usage, kind)?;
         unsafe { device.bind_image_memory(image, *memory.memory(), memory.offset()) }?;
-        let image_dimensions = ImageDimensions::new(
-            info.extent.width as _,
-            info.extent.height as _,
-            memory_reqs.alignment,
-        );
+        let image_dimensions = match info.tiling {
+            // Linear images have a driver chosen row pitch
+            vk::ImageTiling::LINEAR => {
+                let layout = unsafe {
+                    device.get_image_subresource_layout(
+                        image,
+                        vk::ImageSubresource {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            array_layer: 0,
+                        },
+                    )
+                };
+                ImageDimensions {
+                    width: info.extent.width as _,
+                    height: info.extent.height as _,
+                    padded_bytes_per_row: layout.row_pitch as _,
+                    unpadded_bytes_per_row: info.extent.width as usize * texel_size(info.format),
+                }
+            }
+            _ => ImageDimensions::new(
+                info.extent.width as _,
+                info.extent.height as _,
+                memory_reqs.alignment,
+            ),
+        };
         Ok(Self {
             image,
             memory: ManuallyDrop::new(memory),
@@ -302,7 +817,6 @@ impl ManagedImage {
             format: info.format,
             data: None,
             device: device.clone(),
-            allocator: device.allocator.clone(),
         })
     }
 
@@ -322,21 +836,124 @@ impl ManagedImage {
 
         Ok(self.data.as_mut().unwrap())
     }
+
+    /// Reads a single pixel as 8-bit RGBA.
+    pub fn pixel(&mut self, x: usize, y: usize) -> Option<[u8; 4]> {
+        let image_dimensions = self.image_dimensions;
+        let format = self.format;
+        if x >= image_dimensions.width {
+            return None;
+        }
+        let data = self.map_memory().ok()?;
+        let row = rgba_rows(data, image_dimensions, format).nth(y)?;
+        row[x * 4..x * 4 + 4].try_into().ok()
+    }
 }
 
 impl Drop for ManagedImage {
     fn drop(&mut self) {
         unsafe {
             self.device.destroy_image(self.image, None);
-            {
-                let mut allocator = self.allocator.lock();
-                let memory = ManuallyDrop::take(&mut self.memory);
-                allocator.dealloc(AshMemoryDevice::wrap(&self.device), memory);
-            }
+            self.device
+                .dealloc_memory(ManuallyDrop::take(&mut self.memory));
         }
     }
 }
 
+fn texel_size(format: vk::Format) -> usize {
+    match format {
+        vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_UNORM => 8,
+        _ => 4,
+    }
+}
+
+/// Splits mapped image data into rows of tightly packed 8-bit RGBA,
+/// converting from `format` where needed.
+pub fn rgba_rows(
+    data: &[u8],
+    image_dimensions: ImageDimensions,
+    format: vk::Format,
+) -> impl Iterator<Item = Cow<'_, [u8]>> {
+    let unpadded_bytes = image_dimensions.unpadded_bytes_per_row;
+    data.chunks(image_dimensions.padded_bytes_per_row)
+        .take(image_dimensions.height)
+        .map(move |row| rgba_row(&row[..unpadded_bytes], format))
+}
+
+fn rgba_row(row: &[u8], format: vk::Format) -> Cow<'_, [u8]> {
+    match format {
+        vk::Format::B8G8R8A8_SRGB | vk::Format::B8G8R8A8_UNORM => row
+            .chunks_exact(4)
+            .flat_map(|p| [p[2], p[1], p[0], p[3]])
+            .collect(),
+        vk::Format::A2B10G10R10_UNORM_PACK32 | vk::Format::A2R10G10B10_UNORM_PACK32 => row
+            .chunks_exact(4)
+            .flat_map(|p| {
+                let v = u32::from_le_bytes(p.try_into().unwrap());
+                let c = |shift: u32| ((v >> shift & 0x3ff) >> 2) as u8;
+                let a = ((v >> 30) * 85) as u8;
+                match format {
+                    vk::Format::A2B10G10R10_UNORM_PACK32 => [c(0), c(10), c(20), a],
+                    _ => [c(20), c(10), c(0), a],
+                }
+            })
+            .collect(),
+        vk::Format::R16G16B16A16_UNORM => row
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]).to_be_bytes()[0])
+            .collect(),
+        vk::Format::R16G16B16A16_SFLOAT => row
+            .chunks_exact(8)
+            .flat_map(|p| {
+                let c = |i: usize| f16_to_f32(u16::from_le_bytes([p[i], p[i + 1]]));
+                let srgb = |v: f32| (linear_to_srgb(v.clamp(0., 1.)) * 255. + 0.5) as u8;
+                let alpha = (c(6).clamp(0., 1.) * 255. + 0.5) as u8;
+                [srgb(c(0)), srgb(c(2)), srgb(c(4)), alpha]
+            })
+            .collect(),
+        _ => Cow::Borrowed(row),
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1. } else { 1. };
+    let exp = (bits >> 10 & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exp {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f => sign * f32::INFINITY,
+        _ => sign * (1. + mantissa / 1024.) * 2f32.powi(exp - 15),
+    }
+}
+
+/// Whether sampling and blitting `format` decodes sRGB.
+pub fn is_srgb(format: vk::Format) -> bool {
+    matches!(
+        format,
+        vk::Format::B8G8R8A8_SRGB
+            | vk::Format::R8G8B8A8_SRGB
+            | vk::Format::A8B8G8R8_SRGB_PACK32
+            | vk::Format::B8G8R8_SRGB
+            | vk::Format::R8G8B8_SRGB
+    )
+}
+
+pub fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1. / 2.4) - 0.055
+    }
+}
+
 pub fn find_memory_type_index(
     memory_prop: &vk::PhysicalDeviceMemoryProperties,
     memory_type_bits: u32,
@@ -0,0 +1,172 @@
+use std::{
+    fs::File,
+    io::{LineWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context, Result};
+use serde::{de::IntoDeserializer, Deserialize};
+
+use crate::{Action, OscArg, OscCommand, OscMessage};
+
+#[derive(Debug, Clone)]
+pub enum MacroCommand {
+    Action(Action),
+    Osc(OscCommand),
+}
+
+enum Step {
+    Run(MacroCommand),
+    /// Starts the macro over, measuring its times from here.
+    Loop,
+}
+
+/// Replays a macro file, one `<seconds> <command>` per line. The command is
+/// an action name like `restart`, an OSC address with its arguments like
+/// `/pilka/pos 0 1 0`, or `loop`.
+pub struct MacroPlayer {
+    steps: Vec<(Duration, Step)>,
+    next: usize,
+    start: Instant,
+}
+
+impl MacroPlayer {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read macro {}", path.display()))?;
+        let mut steps = vec![];
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_step(line)
+                .with_context(|| format!("{}:{}: `{line}`", path.display(), i + 1))?;
+            steps.push(step);
+        }
+        steps.sort_by_key(|(at, _)| *at);
+        let loops_at_start = steps
+            .iter()
+            .any(|(at, step)| at.is_zero() && matches!(step, Step::Loop));
+        if loops_at_start {
+            bail!("`loop` at 0 seconds would never let the macro advance");
+        }
+        Ok(Self {
+            steps,
+            next: 0,
+            start: Instant::now(),
+        })
+    }
+
+    /// The next command whose time has come.
+    pub fn next_due(&mut self) -> Option<MacroCommand> {
+        let (at, step) = self.steps.get(self.next)?;
+        if self.start.elapsed() < *at {
+            return None;
+        }
+        self.next += 1;
+        match step {
+            Step::Run(command) => Some(command.clone()),
+            Step::Loop => {
+                self.start += *at;
+                self.next = 0;
+                self.next_due()
+            }
+        }
+    }
+}
+
+/// Writes the actions run from the keyboard and the OSC commands received
+/// into a macro file, line by line so an unclean exit keeps them.
+pub struct MacroRecorder {
+    file: LineWriter<File>,
+    start: Instant,
+}
+
+impl MacroRecorder {
+    pub fn new(path: &Path) -> Result<Self> {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create macro {}", path.display()))?;
+        Ok(Self {
+            file: LineWriter::new(file),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn action(&mut self, action: Action) {
+        if let Ok(toml::Value::String(name)) = toml::Value::try_from(action) {
+            self.write(&name);
+        }
+    }
+
+    pub fn osc(&mut self, command: &OscCommand) {
+        self.write(&command.to_message().to_string());
+    }
+
+    fn write(&mut self, command: &str) {
+        let seconds = self.start.elapsed().as_secs_f32();
+        if let Err(err) = writeln!(self.file, "{seconds:.3} {command}") {
+            log::warn!("Failed to write macro: {err}");
+        }
+    }
+}
+
+fn parse_step(line: &str) -> Result<(Duration, Step)> {
+    let (time, command) = line
+        .split_once(char::is_whitespace)
+        .context("Expected `<seconds> <command>`")?;
+    let seconds: f32 = time.parse().context("Failed to parse seconds")?;
+    if !seconds.is_finite() || seconds < 0. {
+        bail!("Time has to be a positive number of seconds");
+    }
+    let command = command.trim();
+    let step = match command {
+        "loop" => Step::Loop,
+        osc if osc.starts_with('/') => {
+            let message = parse_message(osc)?;
+            Step::Run(MacroCommand::Osc(OscCommand::from_message(&message)?))
+        }
+        name => {
+            let action = Action::deserialize(name.into_deserializer())
+                .map_err(|err: serde::de::value::Error| anyhow::anyhow!("{err}"))?;
+            Step::Run(MacroCommand::Action(action))
+        }
+    };
+    Ok((Duration::from_secs_f32(seconds), step))
+}
+
+/// `/address arg ...` with `true`/`false`, numbers, bare words and
+/// `"quoted strings"` as arguments.
+fn parse_message(text: &str) -> Result<OscMessage> {
+    let (address, mut rest) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mut args = vec![];
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (arg, tail) = quoted.split_once('"').context("Unclosed quote")?;
+            args.push(OscArg::Str(arg.to_string()));
+            rest = tail;
+            continue;
+        }
+        let (token, tail) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let arg = match token {
+            "true" => OscArg::Bool(true),
+            "false" => OscArg::Bool(false),
+            token => match (token.parse(), token.parse()) {
+                (Ok(int), _) => OscArg::Int(int),
+                (_, Ok(float)) => OscArg::Float(float),
+                _ => OscArg::Str(token.to_string()),
+            },
+        };
+        args.push(arg);
+        rest = tail;
+    }
+    Ok(OscMessage {
+        address: address.to_string(),
+        args,
+    })
+}
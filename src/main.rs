@@ -1,33 +1,53 @@
 use core::panic;
 use std::{
+    collections::{HashMap, HashSet},
     io::Write,
     path::{Path, PathBuf},
     sync::Arc,
     time::{Duration, Instant},
 };
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use ash::{khr, vk};
 use either::Either;
 use pilka::{
-    align_to, default_shaders, dispatch_optimal, parse_args, print_help, save_shaders, Args,
-    ComputeHandle, Device, FragmentOutputDesc, FragmentShaderDesc, Input, Instance, PipelineArena,
-    PushConstant, Recorder, RenderHandle, ShaderKind, ShaderSource, Surface, Swapchain,
-    TextureArena, UserEvent, VertexInputDesc, VertexShaderDesc, Watcher, COLOR_SUBRESOURCE_MASK,
-    PREV_FRAME_IMAGE_IDX, SCREENSIZED_IMAGE_INDICES, SHADER_FOLDER,
+    align_to, capture_format, create_init_pipeline, create_pipelines, default_shaders,
+    dispatch_optimal, is_hdr, is_srgb, letterbox, parse_args, print_help, save_shaders, save_spirv,
+    set_user_param, srgb_to_linear, surface_defines, to_shadertoy, Accumulation, Action, Args,
+    CaptureCache, CaptureKind, ClockSync, ComputeHandle, DataBuffers, Device, DmxOutput, Error,
+    ExportCheckpoint, ExportProgress, FrameStats, FullscreenMode, GpuProfiler, Guides, Heatmap,
+    HostBufferTyped, Hud, HudSettings, HudValues, ImageDimensions, Input, Instance, Keybindings,
+    Layers, MacroCommand, MacroPlayer, MacroRecorder, ManagedImage, Manifest, Mesh, OscCommand,
+    OscServer, OutputWindow, ParamValue, Particles, Passes, PerfBaseline, PipelineArena,
+    PostProcess, PushConstant, Recorder, ReferenceOverlay, ReloadCheck, RenderHandle, Resources,
+    SceneAttachments, SceneDepth, SceneMsaa, SceneRaster, ShaderSource, SliceDesc, Slices,
+    StatsGraph, Status, StatusPublisher, Streams, Surface, Swapchain, SwapchainSettings, SyncState,
+    Tables, TextureArena, Textures, TitleTimer, UserEvent, UserParam, VideoSettings, Watcher,
+    WindowState, FEEDBACK_IMAGE_INDICES, HISTORY_IMAGE_IDX, MANIFEST_FILE, POST_TARGET_FORMAT,
+    PREV_FRAME_IMAGE_IDX, QUARANTINE_AFTER, SCREENSIZED_IMAGE_INDICES, VIDEO_FOLDER,
 };
 use winit::{
     application::ApplicationHandler,
     dpi::{LogicalSize, PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyEvent, MouseButton, StartCause, WindowEvent},
-    event_loop::EventLoopProxy,
+    event_loop::{ControlFlow, EventLoopProxy},
     keyboard::{Key, NamedKey},
-    window::{Window, WindowAttributes},
+    monitor::{MonitorHandle, VideoModeHandle},
+    window::{Fullscreen, Window, WindowAttributes},
 };
 
+const WINDOW_TITLE: &str = "myndgera";
+
 pub const UPDATES_PER_SECOND: u32 = 60;
 pub const FIXED_TIME_STEP: f64 = 1. / UPDATES_PER_SECOND as f64;
 pub const MAX_FRAME_TIME: f64 = 15. * FIXED_TIME_STEP; // 0.25;
+/// Frames in a row that may fail to acquire or present before giving up.
+const MAX_FAILED_FRAMES: u32 = 300;
+/// Latest time in seconds the clock can be set to, a day.
+const MAX_CLOCK_TIME: f32 = 86_400.;
+/// Compute shader that generates the soundtrack, optional.
+#[cfg(feature = "audio")]
+const SOUND_SHADER: &str = "sound.comp";
 
 #[allow(dead_code)]
 struct AppInit {
@@ -37,24 +57,117 @@ struct AppInit {
     pause: bool,
     timeline: Instant,
     backup_time: Duration,
+    /// Deterministic clock: `backup_time` advances by this step per frame.
+    fixed_dt: Option<Duration>,
+    /// Playback jumps back to the start when it reaches the end.
+    loop_region: Option<std::ops::Range<Duration>>,
+    /// Loop start marked with the key, waiting for the end.
+    loop_mark: Option<f32>,
     frame_instant: Instant,
     frame_accumulated_time: f64,
+    /// `Time` of the previous frame, `time_delta` is measured from it.
+    prev_frame_time: f32,
 
     texture_arena: TextureArena,
+    /// Copy of the presented frame before the overlays, read by exports.
+    output_copy: usize,
+    history_len: u32,
+    render_size: Option<vk::Extent2D>,
+    picking: bool,
+    cursor: PhysicalPosition<f64>,
 
     file_watcher: Watcher,
+    osc_server: Option<OscServer>,
+    sync: Option<ClockSync>,
+    status: Option<StatusPublisher>,
+    macro_player: Option<MacroPlayer>,
+    macro_recorder: Option<MacroRecorder>,
+    /// The last failed shader reload, reported in the status.
+    last_error: Option<String>,
+    /// Saved shader files waiting for the end of the event batch, so a save
+    /// touching several files rebuilds each pipeline once.
+    pending_reloads: Vec<PathBuf>,
+    shader_dir: PathBuf,
     recorder: Recorder,
+    cli_video: VideoSettings,
+    /// `-I` folders, searched after the ones of the manifest.
+    cli_include: Vec<PathBuf>,
     video_recording: bool,
+    recorded_frames: u64,
+    /// Wall clock start of the recording, frames are paced against it.
+    record_start: Instant,
     record_time: Option<Duration>,
+    /// Progress and checkpoints of the `--record` export.
+    export: Option<ExportProgress>,
+    /// Converged screenshot in progress and the time frozen for it.
+    converging: Option<(Accumulation, f32)>,
+    captures: CaptureCache,
 
     push_constant: PushConstant,
+    resources: HostBufferTyped<Resources>,
+    data_buffers: DataBuffers,
+    dmx: Option<DmxOutput>,
+    tables: Tables,
+    streams: Streams,
+    textures: Textures,
+    keys: Keybindings,
+    /// Window size from the manifest, ignored when `--size` was given.
+    window_size: Option<[u32; 2]>,
+    size_locked: bool,
+    /// Save the window geometry into the project folder when leaving it.
+    remember_window: bool,
+    #[cfg(feature = "audio")]
+    sound_pass: Option<pilka::SoundPass>,
+    #[cfg(feature = "sensors")]
+    sensors: Option<pilka::GpuSensors>,
+    post_process: PostProcess,
+    layers: Layers,
+    reference: Option<ReferenceOverlay>,
+    guides: Option<Guides>,
+    stats: FrameStats,
+    profiler: GpuProfiler,
+    baseline: PerfBaseline,
+    title_timer: TitleTimer,
+    stats_graph: Option<StatsGraph>,
+    heatmap: Option<Heatmap>,
+    hud: Option<Hud>,
+    hud_settings: HudSettings,
+    slices: Option<Slices>,
     render_pipeline: RenderHandle,
     compute_pipeline: ComputeHandle,
+    init_pipeline: Option<ComputeHandle>,
+    passes: Passes,
+    particles: Particles,
+    mesh: Mesh,
+    scene_depth: SceneDepth,
+    scene_raster: SceneRaster,
+    scene_msaa: SceneMsaa,
+    run_init: bool,
+    audit: bool,
+    /// SPIR-V dumps also write the disassembly.
+    spirv_asm: bool,
+    keep_state: bool,
+    validate_reloads: bool,
+    reload_check: ReloadCheck,
+    /// Why the event loop was asked to exit, returned from `main`.
+    error: Option<anyhow::Error>,
+    /// Why the last frame was skipped, shown until one is presented again.
+    frame_error: Option<vk::Result>,
+    failed_frames: u32,
+    /// The GPU was lost, `App` replaces this renderer with a new one.
+    device_lost: bool,
     pipeline_arena: PipelineArena,
 
     queue: vk::Queue,
     transfer_queue: vk::Queue,
 
+    /// Clean feed in a second window, dropped before the instance.
+    output_window: Option<OutputWindow>,
+    /// The key asked to open or close it, done once the loop is idle.
+    toggle_output_window: bool,
+    swapchain_settings: SwapchainSettings,
+    /// The window is see-through where the frame ends with alpha below 1.
+    transparent: bool,
     swapchain: Swapchain,
     surface: Surface,
     device: Arc<Device>,
@@ -66,146 +179,1527 @@ impl AppInit {
         event_loop: &winit::event_loop::ActiveEventLoop,
         proxy: EventLoopProxy<UserEvent>,
         window_attributes: WindowAttributes,
-        record_time: Option<Duration>,
+        args: &Args,
     ) -> Result<Self> {
-        let window = event_loop.create_window(window_attributes)?;
-        let watcher = Watcher::new(proxy)?;
+        let shader_dir = args.shader_dir.clone();
+        let manifest = Manifest::load(&shader_dir)?;
+        let overlay = args.overlay.union(manifest.window.overlay());
+        let resumed = match args.resume {
+            true => ExportCheckpoint::load().or_else(|| {
+                log::warn!("No export checkpoint in {VIDEO_FOLDER}, starting over");
+                None
+            }),
+            false => None,
+        };
+        let record_time = match &resumed {
+            Some(checkpoint) => Some(Duration::from_secs_f32(checkpoint.duration)),
+            None => args.record_time,
+        };
+        let window = event_loop.create_window(overlay.apply(window_attributes))?;
+        if overlay.click_through {
+            if let Err(err) = window.set_cursor_hittest(false) {
+                log::warn!("The window can't let clicks through: {err}");
+            }
+        }
+        let osc_server = args
+            .osc_port
+            .map(|port| OscServer::new(port, proxy.clone()))
+            .transpose()?;
+        let sync = args.sync.map(ClockSync::new).transpose()?;
+        let status = args.status.map(StatusPublisher::new).transpose()?;
+        let macro_player = args
+            .macro_file
+            .as_deref()
+            .map(MacroPlayer::load)
+            .transpose()?;
+        let macro_recorder = args
+            .record_macro
+            .as_deref()
+            .map(MacroRecorder::new)
+            .transpose()?;
+        let mut watcher = Watcher::new(proxy)?;
         let mut recorder = Recorder::new();
-
-        let instance = Instance::new(Some(&window))?;
+        recorder.split_chapters = args.split_chapters;
+        recorder.audio_file = args.audio_file.clone();
+        recorder.video = manifest
+            .record
+            .relative_to(&shader_dir)
+            .merge(args.video.clone());
+        recorder.screenshot = manifest.screenshot;
+
+        let instance = Instance::new(Some(&window), args.validation)?;
         let surface = instance.create_surface(&window)?;
-        let (device, queue, transfer_queue) = instance.create_device_and_queues(&surface)?;
+        let (device, queue, transfer_queue) =
+            instance.create_device_and_queues(Some(&surface), args.gpu.as_deref())?;
         let device = Arc::new(device);
 
         let swapchain_loader = khr::swapchain::Device::new(&instance, &device);
-        let swapchain = Swapchain::new(&device, &surface, swapchain_loader)?;
+        let swapchain = Swapchain::new(
+            &device,
+            &surface,
+            swapchain_loader,
+            &manifest.swapchain,
+            overlay.transparent,
+        )?;
 
         let mut pipeline_arena = PipelineArena::new(&device, watcher.clone())?;
-
-        let extent = swapchain.extent();
-        let video_recording = record_time.is_some();
-        let push_constant = PushConstant {
+        let mut compile = manifest.compile.clone().relative_to(&shader_dir);
+        compile.include.extend(args.include.iter().cloned());
+        pipeline_arena.shader_compiler.set_settings(&compile)?;
+
+        let render_size = args
+            .render_size
+            .map(|(width, height)| vk::Extent2D { width, height });
+        let extent = render_size.unwrap_or(swapchain.extent());
+        let mut video_recording = record_time.is_some();
+        let data_buffers = DataBuffers::new(&device, &manifest.data, &shader_dir)?;
+        let dmx = manifest
+            .dmx
+            .map(|settings| DmxOutput::new(&device, settings))
+            .transpose()?;
+        let mut resources = Resources::new_buffer(&device)?;
+        (*resources).data = data_buffers.address();
+        resources.dmx = dmx.as_ref().map_or(0, DmxOutput::address);
+        let mut push_constant = PushConstant {
             wh: [extent.width as f32, extent.height as f32],
             record_time: record_time.map(|t| t.as_secs_f32()).unwrap_or(10.),
+            resources: resources.address,
             ..Default::default()
         };
 
-        let texture_arena = TextureArena::new(&device, &queue, swapchain.extent())?;
-
-        let vertex_shader_desc = VertexShaderDesc {
-            shader_path: "shaders/shader.vert".into(),
-            ..Default::default()
-        };
-        let fragment_shader_desc = FragmentShaderDesc {
-            shader_path: "shaders/shader.frag".into(),
-        };
-        let fragment_output_desc = FragmentOutputDesc {
-            surface_format: swapchain.format(),
-            ..Default::default()
-        };
-        let push_constant_range = vk::PushConstantRange::default()
-            .size(size_of::<PushConstant>() as _)
-            .stage_flags(
-                vk::ShaderStageFlags::VERTEX
-                    | vk::ShaderStageFlags::FRAGMENT
-                    | vk::ShaderStageFlags::COMPUTE,
-            );
-        let render_pipeline = pipeline_arena.create_render_pipeline(
-            &VertexInputDesc::default(),
-            &vertex_shader_desc,
-            &fragment_shader_desc,
-            &fragment_output_desc,
-            &[push_constant_range],
-            &[texture_arena.images_set_layout],
+        let mut texture_arena =
+            TextureArena::new(&device, &queue, extent, capture_format(swapchain.format()))?;
+        texture_arena.set_sampler_settings(manifest.sampler)?;
+        if manifest.history > 0 {
+            texture_arena.set_history_len(&queue, manifest.history, extent)?;
+        }
+        let output_copy = texture_arena.push_render_target(
+            texture_arena.image_infos[PREV_FRAME_IMAGE_IDX].usage(
+                vk::ImageUsageFlags::SAMPLED
+                    | vk::ImageUsageFlags::TRANSFER_SRC
+                    | vk::ImageUsageFlags::TRANSFER_DST,
+            ),
         )?;
-
-        let compute_pipeline = pipeline_arena.create_compute_pipeline(
-            "shaders/shader.comp",
-            &[push_constant_range],
-            &[texture_arena.images_set_layout],
+        device.name_object(texture_arena.images[output_copy].image, "Output Copy");
+        let profiler = GpuProfiler::new(&device)?;
+        let baseline = PerfBaseline::load(&shader_dir);
+        let mut tables = Tables::default();
+        tables.configure(
+            &manifest.table,
+            &shader_dir,
+            &device,
+            &queue,
+            &mut texture_arena,
+            &mut watcher,
+        )?;
+        resources.tables = tables.address();
+        let mut streams = Streams::default();
+        streams.configure(
+            &manifest.stream,
+            &shader_dir,
+            &device,
+            &queue,
+            &mut texture_arena,
+        )?;
+        resources.streams = streams.address();
+        let mut textures = Textures::default();
+        textures.configure(
+            &manifest.texture,
+            &shader_dir,
+            &device,
+            &queue,
+            &mut texture_arena,
         )?;
+        pipeline_arena.user_resources.configure(
+            &manifest.buffer,
+            &textures,
+            &texture_arena,
+            &device,
+        )?;
+        pipeline_arena.shader_compiler.set_defines(
+            &[
+                textures.defines(),
+                &surface_defines(swapchain.surface_format()),
+            ]
+            .concat(),
+        )?;
+        let keys = Keybindings::new(&manifest.keys)?;
+        let size_locked = args.inner_size.is_some();
+        let window_size = manifest.window.size.filter(|_| !size_locked);
+        if let Some([width, height]) = window_size {
+            let _ = window.request_inner_size(LogicalSize::new(width, height));
+        }
+        let manifest_path = shader_dir.join(MANIFEST_FILE);
+        if manifest_path.is_file() {
+            watcher.watch_file(&manifest_path)?;
+        }
 
+        let mut post_process = PostProcess::default();
+        post_process.configure(
+            manifest.post,
+            render_size.is_some() || !manifest.slice.is_empty(),
+            &shader_dir,
+            &mut pipeline_arena,
+            &mut texture_arena,
+            swapchain.format(),
+            extent,
+        )?;
+        let mut layers = Layers::default();
+        layers.configure(
+            &manifest.layer,
+            &shader_dir,
+            &queue,
+            &mut pipeline_arena,
+            &mut texture_arena,
+            post_process.color_format(swapchain.format()),
+            extent,
+        )?;
+        let mut scene_msaa = SceneMsaa::default();
+        scene_msaa.configure(
+            manifest.samples.unwrap_or(1),
+            &device,
+            post_process.color_format(swapchain.format()),
+            extent,
+        )?;
+        let mut scene_depth = SceneDepth::default();
+        scene_depth.configure(
+            manifest.depth,
+            manifest.mesh.is_some(),
+            scene_msaa.samples(),
+            &device,
+            extent,
+        )?;
+        let mut scene_raster = SceneRaster::default();
+        scene_raster.configure(manifest.raster, &device);
+        let (render_pipeline, compute_pipeline) = create_pipelines(
+            &mut pipeline_arena,
+            &texture_arena,
+            post_process.color_format(swapchain.format()),
+            scene_msaa.samples(),
+            scene_depth.image(),
+            scene_raster.image(),
+            &shader_dir,
+        )?;
+        let init_pipeline = create_init_pipeline(&mut pipeline_arena, &texture_arena, &shader_dir)?;
+        let mut passes = Passes::default();
+        if let Err(err) = passes.sync(
+            &shader_dir,
+            &mut pipeline_arena,
+            &texture_arena,
+            post_process.color_format(swapchain.format()),
+            scene_msaa.samples(),
+            scene_depth.passes(),
+            scene_raster.passes(),
+            &mut watcher,
+        ) {
+            log::error!("{err}");
+        }
+        let mut particles = Particles::default();
+        particles.configure(
+            manifest.particles.as_ref(),
+            &shader_dir,
+            &device,
+            &mut pipeline_arena,
+            &texture_arena,
+            post_process.color_format(swapchain.format()),
+            scene_msaa.samples(),
+            scene_depth.particles(),
+            scene_raster.particles(),
+        )?;
+        particles.bind(&mut resources);
+        let mut mesh = Mesh::default();
+        mesh.configure(
+            manifest.mesh.as_ref(),
+            &shader_dir,
+            &device,
+            &queue,
+            &mut pipeline_arena,
+            &texture_arena,
+            post_process.color_format(swapchain.format()),
+            scene_msaa.samples(),
+            scene_depth.mesh(),
+            scene_raster.mesh(),
+        )?;
+        mesh.bind(&mut resources);
+        apply_params(
+            &mut push_constant,
+            &pipeline_arena.shader_compiler.user_params(),
+            &manifest.params,
+        );
+        let slices = create_slices(
+            &manifest.slice,
+            &shader_dir,
+            &device,
+            &mut pipeline_arena,
+            &texture_arena,
+            swapchain.format(),
+        )?;
+        let hud = match manifest.hud.visible {
+            true => Hud::new(
+                &shader_dir,
+                &device,
+                &mut pipeline_arena,
+                &texture_arena,
+                swapchain.format(),
+                manifest.hud,
+            )
+            .map(|mut hud| {
+                hud.enabled = true;
+                hud
+            })
+            .map_err(|err| log::error!("Failed to create HUD: {err}"))
+            .ok(),
+            false => None,
+        };
+        let reference = args
+            .reference
+            .as_deref()
+            .map(|path| {
+                ReferenceOverlay::new(
+                    path,
+                    &shader_dir,
+                    &device,
+                    &queue,
+                    &mut pipeline_arena,
+                    &mut texture_arena,
+                    swapchain.format(),
+                )
+            })
+            .transpose()?;
+        let output_window = select_monitor(event_loop, args.output_monitor.as_deref())
+            .map_err(|err| log::error!("{err}"))
+            .ok()
+            .flatten()
+            .map(|monitor| {
+                let attributes = output_window_attributes()
+                    .with_fullscreen(Some(Fullscreen::Borderless(Some(monitor))));
+                OutputWindow::new(
+                    event_loop,
+                    attributes,
+                    &instance,
+                    &device,
+                    &manifest.swapchain,
+                    &shader_dir,
+                    &mut pipeline_arena,
+                    &texture_arena,
+                )
+            })
+            .transpose()
+            .context("Failed to open the output window")?;
+        #[cfg(feature = "audio")]
+        let sound_pass =
+            create_sound_pass(&device, &mut pipeline_arena, &texture_arena, &shader_dir);
+        #[cfg(feature = "sensors")]
+        let sensors = pilka::GpuSensors::new(&device);
+
+        let mut checkpoint = args.checkpoint;
+        if checkpoint.is_some() && recorder.video.is_gif() {
+            log::warn!("GIF exports can't be checkpointed");
+            checkpoint = None;
+        }
+        let export = record_time.map(|duration| {
+            let resumed_frames = resumed.as_ref().map_or(0, |checkpoint| checkpoint.frames);
+            ExportProgress::new(
+                duration,
+                checkpoint,
+                recorder.video.capture_rate(),
+                resumed_frames,
+            )
+        });
         if record_time.is_some() {
-            let mut image_dimensions = swapchain.image_dimensions;
-            image_dimensions.width = align_to(image_dimensions.width, 2);
-            image_dimensions.height = align_to(image_dimensions.height, 2);
-            recorder.start(image_dimensions);
+            let dims = record_dimensions(extent);
+            let started = match resumed.clone() {
+                Some(checkpoint) => recorder.resume(dims, checkpoint),
+                None => {
+                    if checkpoint.is_some() {
+                        ExportCheckpoint::remove();
+                    }
+                    recorder.start(dims)
+                }
+            };
+            match started {
+                Ok(()) => recorder.chapter(folder_title(&shader_dir)),
+                Err(err) => {
+                    log::error!("{err}");
+                    video_recording = false;
+                }
+            }
         }
 
-        Ok(Self {
+        let mut app = Self {
             window,
             input: Input::default(),
 
             pause: false,
             timeline: Instant::now(),
             backup_time: Duration::from_secs(0),
+            fixed_dt: args.fixed_dt,
+            loop_region: None,
+            loop_mark: None,
             frame_instant: Instant::now(),
+            prev_frame_time: 0.,
             frame_accumulated_time: 0.,
 
             texture_arena,
+            output_copy,
+            history_len: manifest.history,
+            render_size,
+            picking: false,
+            cursor: PhysicalPosition::default(),
 
             file_watcher: watcher,
+            osc_server,
+            sync,
+            status,
+            macro_player,
+            macro_recorder,
+            last_error: None,
+            pending_reloads: vec![],
+            frame_error: None,
+            failed_frames: 0,
+            device_lost: false,
+            shader_dir,
+            cli_video: args.video.clone(),
+            cli_include: args.include.clone(),
             video_recording,
+            recorded_frames: 0,
+            record_start: Instant::now(),
             record_time,
+            export,
+            converging: None,
+            captures: CaptureCache::default(),
             recorder,
 
             push_constant,
+            resources,
+            data_buffers,
+            dmx,
+            tables,
+            streams,
+            textures,
+            keys,
+            window_size,
+            remember_window: manifest.window.remember,
+            size_locked,
+            #[cfg(feature = "audio")]
+            sound_pass,
+            #[cfg(feature = "sensors")]
+            sensors,
+            post_process,
+            layers,
+            reference,
+            guides: None,
+            stats: FrameStats::new(),
+            profiler,
+            baseline,
+            title_timer: TitleTimer::new(),
+            stats_graph: None,
+            heatmap: None,
+            hud,
+            hud_settings: manifest.hud,
+            slices,
             render_pipeline,
             compute_pipeline,
+            init_pipeline,
+            passes,
+            particles,
+            mesh,
+            scene_depth,
+            scene_raster,
+            scene_msaa,
+            run_init: true,
+            audit: args.audit,
+            spirv_asm: args.spirv_asm,
+            keep_state: manifest.keep_state,
+            validate_reloads: manifest.validate_reloads,
+            reload_check: ReloadCheck::new(&device),
+            error: None,
             pipeline_arena,
 
             queue,
             transfer_queue,
 
+            output_window,
+            toggle_output_window: false,
+            swapchain_settings: manifest.swapchain,
+            transparent: overlay.transparent,
             surface,
             swapchain,
             device,
             instance,
-        })
+        };
+        if let Some(checkpoint) = resumed {
+            app.resume_export(&checkpoint);
+        }
+        if args.dump_spirv {
+            app.save_spirv()?;
+        }
+        Ok(app)
+    }
+
+    /// Dumps the SPIR-V of the shaders every pipeline was built from.
+    fn save_spirv(&self) -> Result<()> {
+        let mut sources: Vec<ShaderSource> = {
+            let mapping = self.file_watcher.include_mapping.lock();
+            mapping.values().flatten().cloned().collect()
+        };
+        sources.sort_by(|a, b| a.path.cmp(&b.path));
+        sources.dedup();
+        save_spirv(
+            &self.pipeline_arena.shader_compiler,
+            &self.shader_dir,
+            &sources,
+            self.spirv_asm,
+        )
+    }
+
+    /// Puts the clock and counters where the checkpointed export stopped.
+    /// Feedback, particles and other GPU state start over from there.
+    fn resume_export(&mut self, checkpoint: &ExportCheckpoint) {
+        self.recorded_frames = checkpoint.frames;
+        let now = Instant::now();
+        self.record_start = now.checked_sub(self.recorded_duration()).unwrap_or(now);
+        self.set_clock(Duration::from_secs_f64(checkpoint.time));
+        self.push_constant.frame = checkpoint.frame;
+        println!(
+            "Resuming the export at frame {} ({:.2}s)",
+            checkpoint.frames, checkpoint.time
+        );
     }
 
     fn update(&mut self) {
         self.input.process_position(&mut self.push_constant);
     }
 
-    fn reload_shaders(&mut self, path: PathBuf) -> Result<()> {
-        if let Some(frame) = self.swapchain.get_current_frame() {
-            let fences = std::slice::from_ref(&frame.present_finished);
-            unsafe { self.device.wait_for_fences(fences, true, u64::MAX)? };
+    /// While recording with an audio track the shader clock follows the
+    /// encoded frames rather than the wall clock, so the video never drifts
+    /// away from the muxed audio.
+    fn audio_locked(&self) -> bool {
+        self.video_recording && self.recorder.audio_file.is_some()
+    }
+
+    /// Resolution of the image pass and of everything exported from it.
+    fn render_extent(&self) -> vk::Extent2D {
+        self.render_size.unwrap_or(self.swapchain.extent())
+    }
+
+    /// The frame has to stay in an offscreen target until the final copy.
+    fn offscreen(&self) -> bool {
+        self.render_size.is_some() || self.slices.is_some()
+    }
+
+    fn recorded_duration(&self) -> Duration {
+        let rate = self.recorder.video.capture_rate();
+        Duration::from_secs_f64(self.recorded_frames as f64 / rate as f64)
+    }
+
+    /// Times the current frame goes into the video. It is duplicated when the
+    /// display runs slower than the capture rate and skipped when it runs
+    /// faster. Frames driving the clock themselves are written once.
+    fn frames_due(&self) -> u32 {
+        if self.audio_locked() || self.fixed_dt.is_some() {
+            return 1;
+        }
+        let elapsed = self.record_start.elapsed().as_secs_f64();
+        let due = (elapsed * self.recorder.video.capture_rate() as f64) as u64 + 1;
+        due.saturating_sub(self.recorded_frames) as u32
+    }
+
+    fn set_pause(&mut self, pause: bool) {
+        if pause == self.pause {
+            return;
+        }
+        if pause {
+            self.backup_time = self.clock();
+        } else if self.fixed_dt.is_none() {
+            self.timeline = Instant::now() - self.backup_time;
+        }
+        self.pause = pause;
+    }
+
+    /// Playback time, frozen while paused and counted in frames with
+    /// `--fixed-dt`.
+    fn clock(&self) -> Duration {
+        match self.pause || self.fixed_dt.is_some() {
+            true => self.backup_time,
+            false => self.timeline.elapsed(),
+        }
+    }
+
+    fn set_clock(&mut self, time: Duration) {
+        if self.pause || self.fixed_dt.is_some() {
+            self.backup_time = time;
+        } else {
+            let now = Instant::now();
+            self.timeline = now.checked_sub(time).unwrap_or(now);
+        }
+    }
+
+    /// Jumps the shader clock to `time` seconds, keeping play or pause.
+    fn seek(&mut self, time: f32) {
+        let Some(time) = clock_time(time) else {
+            log::warn!("Can't seek to {time}s");
+            return;
+        };
+        self.set_clock(time);
+        println!("Time: {:.2}s", time.as_secs_f32());
+    }
+
+    /// Loops playback between `start` and `end` seconds, `None` plays on.
+    fn set_loop(&mut self, region: Option<[f32; 2]>) {
+        let region = region.map(|[a, b]| (clock_time(a.min(b)), clock_time(a.max(b))));
+        match region {
+            Some((Some(start), Some(end))) if start < end => {
+                self.loop_region = Some(start..end);
+                println!(
+                    "Looping {:.2}s..{:.2}s",
+                    start.as_secs_f32(),
+                    end.as_secs_f32()
+                );
+            }
+            Some((Some(_), Some(_))) => log::warn!("The loop has to span some time"),
+            Some(_) => log::warn!("The loop bounds have to be finite"),
+            None => {
+                self.loop_region = None;
+                println!("Loop off");
+            }
+        }
+    }
+
+    /// First press marks the loop start, the second the end, the third
+    /// clears the loop.
+    fn mark_loop(&mut self) {
+        let now = self.clock().as_secs_f32();
+        match (&self.loop_region, self.loop_mark) {
+            (Some(_), _) => self.set_loop(None),
+            (None, Some(start)) => {
+                self.loop_mark = None;
+                self.set_loop(Some([start, now]));
+            }
+            (None, None) => {
+                self.loop_mark = Some(now);
+                println!("Loop start: {now:.2}s");
+            }
+        }
+    }
+
+    /// Keeps the window geometry for the next launch of the current project.
+    fn save_window_state(&self) {
+        if !self.remember_window {
+            return;
+        }
+        if let Some(state) = WindowState::capture(&self.window) {
+            let _ = state
+                .save(&self.shader_dir)
+                .map_err(|err| log::warn!("Failed to save the window geometry: {err}"));
+        }
+    }
+
+    /// Saves the window geometry and waits for the recorder and the GPU.
+    fn shut_down(&mut self) {
+        self.save_window_state();
+        self.recorder.close_thread();
+        if let Some(handle) = self.recorder.thread_handle.take() {
+            let _ = handle.join();
+        }
+        let _ = unsafe { self.device.device_wait_idle() };
+    }
+
+    fn seek_by(&mut self, seconds: f32) {
+        self.seek(self.clock().as_secs_f32() + seconds);
+    }
+
+    /// Exports read the copy of the frame taken before the reference image
+    /// and the guides are drawn on top.
+    fn capture_output(&self, callback: impl FnOnce(ManagedImage)) -> Result<()> {
+        self.device.capture_image_data(
+            &self.queue,
+            &self.texture_arena.images[self.output_copy].image,
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            self.texture_arena.image_infos[self.output_copy].format,
+            self.render_extent(),
+            callback,
+        )
+    }
+
+    /// Guides are only compiled once they are asked for.
+    fn guides_mut(&mut self) -> Result<&mut Guides> {
+        if self.guides.is_none() {
+            self.guides = Some(Guides::new(
+                &self.shader_dir,
+                &mut self.pipeline_arena,
+                &self.texture_arena,
+                self.swapchain.format(),
+            )?);
+        }
+        Ok(self.guides.as_mut().unwrap())
+    }
+
+    fn stats_graph_mut(&mut self) -> Result<&mut StatsGraph> {
+        if self.stats_graph.is_none() {
+            self.stats_graph = Some(StatsGraph::new(
+                &self.shader_dir,
+                &self.device,
+                &mut self.pipeline_arena,
+                &self.texture_arena,
+                self.swapchain.format(),
+            )?);
+        }
+        Ok(self.stats_graph.as_mut().unwrap())
+    }
+
+    fn hud_mut(&mut self) -> Result<&mut Hud> {
+        if self.hud.is_none() {
+            self.hud = Some(Hud::new(
+                &self.shader_dir,
+                &self.device,
+                &mut self.pipeline_arena,
+                &self.texture_arena,
+                self.swapchain.format(),
+                self.hud_settings,
+            )?);
+        }
+        Ok(self.hud.as_mut().unwrap())
+    }
+
+    fn heatmap_mut(&mut self) -> Result<&mut Heatmap> {
+        if self.heatmap.is_none() {
+            self.heatmap = Some(Heatmap::new(
+                &self.shader_dir,
+                &self.device,
+                &mut self.pipeline_arena,
+                &self.texture_arena,
+                self.swapchain.format(),
+            )?);
+        }
+        Ok(self.heatmap.as_mut().unwrap())
+    }
+
+    fn screenshot(&mut self) {
+        if self.recorder.screenshot.hdr_exr && is_hdr(self.swapchain.surface_format()) {
+            return self.screenshot_exr();
+        }
+        let res = self.cached_capture(CaptureKind::Output, |app, image| {
+            app.capture_output(|tex| *image = Some(tex))
+        });
+        match res {
+            Ok(image) => self.recorder.screenshot(image),
+            Err(err) => log::error!("{err}"),
+        }
+    }
+
+    /// Reuses the read back of a paused frame, `capture` only runs when the
+    /// frame changed since.
+    fn cached_capture(
+        &mut self,
+        kind: CaptureKind,
+        capture: impl FnOnce(&Self, &mut Option<ManagedImage>) -> Result<()>,
+    ) -> Result<Arc<ManagedImage>> {
+        if let Some(image) = self.captures.get(kind) {
+            return Ok(image);
+        }
+        let mut image = None;
+        capture(self, &mut image)?;
+        let image = image.context("The capture produced no image")?;
+        self.captures.insert(kind, image)
+    }
+
+    /// Averages the next `samples` frames with time frozen, `pc.frame` keeps
+    /// counting so stochastic shaders vary their seed.
+    fn converged_screenshot(&mut self, samples: Option<u32>) {
+        if self.converging.is_some() {
+            log::warn!("A converged screenshot is already in progress");
+            return;
+        }
+        let samples = samples.unwrap_or(self.recorder.screenshot.samples);
+        println!("Converging screenshot over {samples} frames");
+        self.converging = Some((Accumulation::new(samples), self.push_constant.time));
+    }
+
+    /// Saves the float output of the post chain, or the swapchain image of
+    /// an scRGB surface, anything else is already clamped to 8 bits.
+    fn screenshot_exr(&mut self) {
+        let source = match self.post_process.output_target() {
+            Some(target) => (
+                self.texture_arena.images[target].image,
+                vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                self.render_extent(),
+            ),
+            None if self.swapchain.format() == POST_TARGET_FORMAT => (
+                *self.swapchain.get_current_image(),
+                vk::ImageLayout::PRESENT_SRC_KHR,
+                self.swapchain.extent(),
+            ),
+            None => {
+                log::warn!(
+                    "EXR screenshots need a `post` chain in {MANIFEST_FILE} or a `float` swapchain to render in RGBA16F"
+                );
+                return;
+            }
+        };
+        let (src_image, src_layout, extent) = source;
+        let res = self.cached_capture(CaptureKind::Float, |app, image| {
+            app.device.capture_image_raw(
+                &app.queue,
+                &src_image,
+                src_layout,
+                POST_TARGET_FORMAT,
+                extent,
+                |tex| *image = Some(tex),
+            )
+        });
+        match res {
+            Ok(image) => self.recorder.screenshot(image),
+            Err(err) => log::error!("{err}"),
+        }
+    }
+
+    /// Samples the last presented frame under the cursor into `picked_color`.
+    fn pick_color(&mut self) {
+        let (x, y) = (self.cursor.x as usize, self.cursor.y as usize);
+        let mut pixel = None;
+        let res = self.device.capture_image_data(
+            &self.queue,
+            self.swapchain.get_current_image(),
+            vk::ImageLayout::PRESENT_SRC_KHR,
+            self.swapchain.format(),
+            self.swapchain.extent(),
+            |mut image| pixel = image.pixel(x, y),
+        );
+        if let Err(err) = res {
+            log::error!("Failed to pick color: {err}");
+            return;
+        }
+        let Some([r, g, b, a]) = pixel else {
+            return;
+        };
+        // A UNORM surface shows what the shader wrote as is
+        let linear = |c: u8| match is_srgb(capture_format(self.swapchain.format())) {
+            true => srgb_to_linear(c as f32 / 255.),
+            false => c as f32 / 255.,
+        };
+        self.push_constant.picked_color = [linear(r), linear(g), linear(b), a as f32 / 255.];
+        let [r_lin, g_lin, b_lin, a_lin] = self.push_constant.picked_color;
+        println!(
+            "Picked #{r:02x}{g:02x}{b:02x}{a:02x}, linear: vec4({r_lin:.4}, {g_lin:.4}, {b_lin:.4}, {a_lin:.4})"
+        );
+    }
+
+    fn run_action(&mut self, action: Action) {
+        let dt = Duration::from_secs_f32(1. / 60.);
+        match action {
+            Action::Help => print_help(&self.keys),
+            Action::Pause => self.set_pause(!self.pause),
+            Action::StepBack => {
+                self.set_pause(true);
+                self.backup_time = self.backup_time.saturating_sub(dt);
+            }
+            Action::StepForward => {
+                self.set_pause(true);
+                self.backup_time += dt;
+            }
+            Action::SeekBack => self.seek_by(-1.),
+            Action::SeekForward => self.seek_by(1.),
+            Action::SeekBackFar => self.seek_by(-10.),
+            Action::SeekForwardFar => self.seek_by(10.),
+            Action::LoopRegion => self.mark_loop(),
+            Action::Restart => {
+                #[cfg(feature = "audio")]
+                if let Some(sound_pass) = &mut self.sound_pass {
+                    sound_pass.restart();
+                }
+                self.push_constant.pos = [0.; 3];
+                self.push_constant.time = 0.;
+                self.push_constant.frame = 0;
+                self.run_init = true;
+                self.timeline = Instant::now();
+                self.backup_time = Duration::ZERO;
+                self.layers.restart();
+            }
+            Action::PrintInfo => {
+                println!("{}{}{}", self.push_constant, self.stats, self.profiler);
+                for param in self.pipeline_arena.shader_compiler.user_params() {
+                    let values = &self.push_constant.user[param.index..][..param.len];
+                    println!("{}:\t{values:.3?}", param.name);
+                }
+                if let Some(heatmap) = self.heatmap.as_ref().filter(|h| h.enabled) {
+                    println!("{heatmap}");
+                }
+                #[cfg(feature = "sensors")]
+                if let Some(reading) = self.sensors.as_ref().and_then(|s| s.reading()) {
+                    println!("gpu sensors:\t{reading}");
+                }
+                let heaps = &self.device.memory_properties.memory_heaps;
+                let heap_count = self.device.memory_properties.memory_heap_count as usize;
+                print!("{}", self.device.audit.memory_report(&heaps[..heap_count]));
+                if self.audit {
+                    println!("{}", self.device.audit);
+                }
+            }
+            Action::ClearFeedback => self.clear_feedback(),
+            Action::Validation => self.instance.validation.print(),
+            Action::SaveBaseline => {
+                let extent = self.render_extent();
+                if let Err(err) = self.baseline.save(self.profiler.total_ms(), extent) {
+                    log::error!("{err}");
+                }
+            }
+            Action::ColorPicker => {
+                self.picking = !self.picking;
+                let state = if self.picking { "on" } else { "off" };
+                println!("Color picker: {state}");
+            }
+            Action::ScreenshotExr => self.screenshot_exr(),
+            Action::Guides => match self.guides_mut() {
+                Ok(guides) => guides.toggle(),
+                Err(err) => log::error!("Failed to create guides: {err}"),
+            },
+            Action::GuidesAspect => {
+                if let Some(guides) = &mut self.guides {
+                    guides.next_aspect();
+                }
+            }
+            Action::FrameGraph => match self.stats_graph_mut() {
+                Ok(graph) => graph.toggle(),
+                Err(err) => log::error!("Failed to create frame time graph: {err}"),
+            },
+            Action::Heatmap => match self.heatmap_mut() {
+                Ok(heatmap) => heatmap.toggle(),
+                Err(err) => log::error!("Failed to create heatmap: {err}"),
+            },
+            Action::Hud => match self.hud_mut() {
+                Ok(hud) => hud.toggle(),
+                Err(err) => log::error!("Failed to create HUD: {err}"),
+            },
+            Action::OutputWindow => self.toggle_output_window = true,
+            Action::Wireframe => {
+                if let Err(err) = self.toggle_wireframe() {
+                    log::error!("Failed to toggle wireframe: {err}");
+                }
+            }
+            Action::OpacityUp | Action::OpacityDown => {
+                if let Some(reference) = &mut self.reference {
+                    let steps = if action == Action::OpacityUp { 1. } else { -1. };
+                    reference.adjust_opacity(steps);
+                }
+            }
+            Action::SaveShaders => {
+                let _ = save_shaders(&self.shader_dir).map_err(|err| log::error!("{err}"));
+            }
+            Action::SaveSpirv => {
+                let _ = self.save_spirv().map_err(|err| log::error!("{err}"));
+            }
+            Action::Screenshot => self.screenshot(),
+            Action::ConvergedScreenshot => self.converged_screenshot(None),
+            Action::Record => {
+                if !self.video_recording {
+                    let dims = record_dimensions(self.render_extent());
+                    match self.recorder.start(dims) {
+                        Ok(()) => {
+                            self.recorder.chapter(folder_title(&self.shader_dir));
+                            self.video_recording = true;
+                            self.recorded_frames = 0;
+                            self.record_start = Instant::now();
+                        }
+                        Err(err) => log::error!("{err}"),
+                    }
+                } else {
+                    self.recorder.finish();
+                    self.video_recording = false;
+                }
+            }
+        }
+    }
+
+    /// Opens the clean output window next to the main one, or closes it.
+    fn toggle_output_window(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        if let Some(output) = self.output_window.take() {
+            output.close(&self.device, &mut self.pipeline_arena);
+            return;
+        }
+        let res = OutputWindow::new(
+            event_loop,
+            output_window_attributes(),
+            &self.instance,
+            &self.device,
+            &self.swapchain_settings,
+            &self.shader_dir,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+        );
+        match res {
+            Ok(output) => self.output_window = Some(output),
+            Err(err) => log::error!("Failed to open the output window: {err}"),
+        }
+    }
+
+    /// Stops the event loop, `main` returns `error`.
+    fn fail(&mut self, event_loop: &winit::event_loop::ActiveEventLoop, error: Error) {
+        self.error = Some(error.into());
+        event_loop.exit();
+    }
+
+    /// Skips a frame that failed to acquire or present. A lost device is
+    /// rebuilt by `App`, anything else stops the loop only after
+    /// `MAX_FAILED_FRAMES` in a row.
+    fn skip_frame(
+        &mut self,
+        event_loop: &winit::event_loop::ActiveEventLoop,
+        context: &'static str,
+        result: vk::Result,
+    ) {
+        if result == vk::Result::ERROR_DEVICE_LOST {
+            log::error!("{context}: the GPU device was lost, restarting the renderer");
+            self.device_lost = true;
+            return;
+        }
+        self.failed_frames += 1;
+        if self.failed_frames >= MAX_FAILED_FRAMES {
+            self.fail(event_loop, Error::Vulkan { context, result });
+            return;
+        }
+        if self.frame_error != Some(result) {
+            log::warn!("{context}: {result}, skipping frames until it recovers");
+            self.frame_error = Some(result);
+        }
+        self.window.request_redraw();
+    }
+
+    /// Short text for the HUD while frames are skipped or a shader is broken.
+    fn error_banner(&self) -> Option<String> {
+        match (self.frame_error, &self.last_error) {
+            (Some(result), _) => {
+                let name = format!("{result:?}");
+                Some(format!(
+                    "GPU {}",
+                    name.trim_start_matches("ERROR_").replace('_', " ")
+                ))
+            }
+            (None, Some(_)) => Some("SHADER ERROR".into()),
+            (None, None) => None,
+        }
+    }
+
+    fn clear_feedback(&mut self) {
+        let _ = self
+            .texture_arena
+            .clear_images(&self.queue, &FEEDBACK_IMAGE_INDICES)
+            .map_err(|err| log::error!("Failed to clear buffers: {err}"));
+    }
+
+    fn load_shader_folder(&mut self, dir: PathBuf) -> Result<()> {
+        if !dir.is_dir() {
+            bail!("Shader folder doesn't exist: {}", dir.display());
+        }
+        unsafe { self.device.device_wait_idle()? };
+
+        self.save_window_state();
+        self.apply_manifest(&dir)?;
+        self.run_init = true;
+        self.push_constant.reload_count = 0;
+        self.stats.reset();
+        let _ = self
+            .file_watcher
+            .unwatch_file(self.shader_dir.join(MANIFEST_FILE));
+        let manifest_path = dir.join(MANIFEST_FILE);
+        if manifest_path.is_file() {
+            self.file_watcher.watch_file(&manifest_path)?;
+        }
+        println!("Switched shader folder to: {}", dir.display());
+        self.recorder.chapter(folder_title(&dir));
+        self.baseline = PerfBaseline::load(&dir);
+        self.shader_dir = dir;
+        Ok(())
+    }
+
+    /// Rereads `pilka.toml` of the current folder, keeping time and, with
+    /// `keep_state`, the simulation state.
+    fn reload_manifest(&mut self) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        let dir = self.shader_dir.clone();
+        self.apply_manifest(&dir)?;
+        if !self.keep_state {
+            self.run_init = true;
+        }
+        self.stats.reset();
+        println!("Reloaded {MANIFEST_FILE}");
+        Ok(())
+    }
+
+    /// Sets up everything the manifest of `dir` describes and rebuilds the
+    /// pipelines. The GPU has to be idle.
+    fn apply_manifest(&mut self, dir: &Path) -> Result<()> {
+        let manifest = Manifest::load(dir)?;
+        self.captures.invalidate();
+        self.keys = Keybindings::new(&manifest.keys)?;
+        self.recorder.video = manifest
+            .record
+            .relative_to(dir)
+            .merge(self.cli_video.clone());
+        self.recorder.screenshot = manifest.screenshot;
+        let mut compile = manifest.compile.clone().relative_to(dir);
+        compile.include.extend(self.cli_include.iter().cloned());
+        self.pipeline_arena.shader_compiler.set_settings(&compile)?;
+        // Sources are released first, a new channel may listen on the same port
+        self.data_buffers = DataBuffers::default();
+        (*self.resources).data = 0;
+        self.data_buffers = DataBuffers::new(&self.device, &manifest.data, dir)?;
+        (*self.resources).data = self.data_buffers.address();
+        self.dmx = manifest
+            .dmx
+            .map(|settings| DmxOutput::new(&self.device, settings))
+            .transpose()?;
+        self.resources.dmx = self.dmx.as_ref().map_or(0, DmxOutput::address);
+        self.tables.configure(
+            &manifest.table,
+            dir,
+            &self.device,
+            &self.queue,
+            &mut self.texture_arena,
+            &mut self.file_watcher,
+        )?;
+        self.resources.tables = self.tables.address();
+        self.streams.configure(
+            &manifest.stream,
+            dir,
+            &self.device,
+            &self.queue,
+            &mut self.texture_arena,
+        )?;
+        self.resources.streams = self.streams.address();
+        self.texture_arena.set_sampler_settings(manifest.sampler)?;
+        self.textures.configure(
+            &manifest.texture,
+            dir,
+            &self.device,
+            &self.queue,
+            &mut self.texture_arena,
+        )?;
+        self.pipeline_arena.user_resources.configure(
+            &manifest.buffer,
+            &self.textures,
+            &self.texture_arena,
+            &self.device,
+        )?;
+        self.pipeline_arena.shader_compiler.set_defines(
+            &[
+                self.textures.defines(),
+                &surface_defines(self.swapchain.surface_format()),
+            ]
+            .concat(),
+        )?;
+        let window_size = manifest.window.size.filter(|_| !self.size_locked);
+        if window_size != self.window_size {
+            if let Some([width, height]) = window_size {
+                let _ = self
+                    .window
+                    .request_inner_size(LogicalSize::new(width, height));
+            }
+            self.window_size = window_size;
+        }
+        self.remember_window = manifest.window.remember;
+        self.hud_settings = manifest.hud;
+        if let Some(hud) = &mut self.hud {
+            hud.settings = manifest.hud;
+        }
+        if manifest.history != self.history_len {
+            self.texture_arena.set_history_len(
+                &self.queue,
+                manifest.history,
+                self.render_extent(),
+            )?;
+            self.history_len = manifest.history;
+        }
+        let extent = self.render_extent();
+        self.post_process.configure(
+            manifest.post,
+            self.render_size.is_some() || !manifest.slice.is_empty(),
+            dir,
+            &mut self.pipeline_arena,
+            &mut self.texture_arena,
+            self.swapchain.format(),
+            extent,
+        )?;
+        self.layers.configure(
+            &manifest.layer,
+            dir,
+            &self.queue,
+            &mut self.pipeline_arena,
+            &mut self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            extent,
+        )?;
+        self.scene_msaa.configure(
+            manifest.samples.unwrap_or(1),
+            &self.device,
+            self.post_process.color_format(self.swapchain.format()),
+            extent,
+        )?;
+        self.scene_depth.configure(
+            manifest.depth,
+            manifest.mesh.is_some(),
+            self.scene_msaa.samples(),
+            &self.device,
+            extent,
+        )?;
+        self.scene_raster.configure(manifest.raster, &self.device);
+        let (render_pipeline, compute_pipeline) = create_pipelines(
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            self.scene_msaa.samples(),
+            self.scene_depth.image(),
+            self.scene_raster.image(),
+            dir,
+        )?;
+        let old_render = std::mem::replace(&mut self.render_pipeline, render_pipeline);
+        let init_pipeline =
+            create_init_pipeline(&mut self.pipeline_arena, &self.texture_arena, dir)?;
+        let old_compute = std::mem::replace(&mut self.compute_pipeline, compute_pipeline);
+        if let Some(old_init) = std::mem::replace(&mut self.init_pipeline, init_pipeline) {
+            self.pipeline_arena.remove_pipeline(Either::Right(old_init));
+        }
+        self.particles.configure(
+            manifest.particles.as_ref(),
+            dir,
+            &self.device,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            self.scene_msaa.samples(),
+            self.scene_depth.particles(),
+            self.scene_raster.particles(),
+        )?;
+        self.particles.bind(&mut self.resources);
+        self.mesh.configure(
+            manifest.mesh.as_ref(),
+            dir,
+            &self.device,
+            &self.queue,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            self.scene_msaa.samples(),
+            self.scene_depth.mesh(),
+            self.scene_raster.mesh(),
+        )?;
+        self.mesh.bind(&mut self.resources);
+        apply_params(
+            &mut self.push_constant,
+            &self.pipeline_arena.shader_compiler.user_params(),
+            &manifest.params,
+        );
+        let slices = create_slices(
+            &manifest.slice,
+            dir,
+            &self.device,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.swapchain.format(),
+        )?;
+        if let Some(old) = std::mem::replace(&mut self.slices, slices) {
+            old.destroy(&mut self.pipeline_arena);
+        }
+        self.passes
+            .clear(&mut self.pipeline_arena, &mut self.file_watcher);
+        if let Err(err) = self.passes.sync(
+            dir,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            self.scene_msaa.samples(),
+            self.scene_depth.passes(),
+            self.scene_raster.passes(),
+            &mut self.file_watcher,
+        ) {
+            log::error!("{err}");
+        }
+        self.keep_state = manifest.keep_state;
+        self.validate_reloads = manifest.validate_reloads;
+        self.pipeline_arena
+            .remove_pipeline(Either::Left(old_render));
+        self.pipeline_arena
+            .remove_pipeline(Either::Right(old_compute));
+        #[cfg(feature = "audio")]
+        self.configure_sound_pass(dir);
+        // Every pipeline with a set 1 is rebuilt by now
+        self.pipeline_arena.user_resources.release_retired();
+        self.texture_arena.release_retired();
+        Ok(())
+    }
+
+    /// Redraws the scene pipelines as lines or back with their own polygon
+    /// mode, keeping the particles and feedback.
+    fn toggle_wireframe(&mut self) -> Result<()> {
+        if !self.scene_raster.toggle_wireframe() {
+            bail!("The GPU doesn't support line polygon mode");
+        }
+        unsafe { self.device.device_wait_idle()? };
+        let raster = &self.scene_raster;
+        let pipelines = std::iter::once((self.render_pipeline, raster.image()))
+            .chain(self.passes.render().map(|handle| (handle, raster.passes())))
+            .chain(
+                self.particles
+                    .pipeline()
+                    .map(|handle| (handle, raster.particles())),
+            )
+            .chain(self.mesh.pipeline().map(|handle| (handle, raster.mesh())));
+        let arena = &mut self.pipeline_arena;
+        let errors: Vec<_> = pipelines
+            .filter_map(|(handle, state)| {
+                arena.render.pipelines[handle]
+                    .set_polygon_mode(&arena.shader_compiler, state.polygon_mode())
+                    .err()
+            })
+            .map(|err| err.to_string())
+            .collect();
+        if !errors.is_empty() {
+            bail!(errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    fn update_passes(&mut self) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        self.passes.sync(
+            &self.shader_dir,
+            &mut self.pipeline_arena,
+            &self.texture_arena,
+            self.post_process.color_format(self.swapchain.format()),
+            self.scene_msaa.samples(),
+            self.scene_depth.passes(),
+            self.scene_raster.passes(),
+            &mut self.file_watcher,
+        )
+    }
+
+    fn reload_table(&mut self, path: &Path) -> Result<()> {
+        unsafe { self.device.device_wait_idle()? };
+        self.captures.invalidate();
+        self.tables
+            .reload(path, &self.device, &self.queue, &mut self.texture_arena)?;
+        Ok(())
+    }
+
+    fn handle_osc(&mut self, command: OscCommand) {
+        match command {
+            OscCommand::Pause(pause) => self.set_pause(pause.unwrap_or(!self.pause)),
+            OscCommand::Screenshot => self.screenshot(),
+            OscCommand::ScreenshotExr => self.screenshot_exr(),
+            OscCommand::ConvergedScreenshot(samples) => self.converged_screenshot(samples),
+            OscCommand::Clear => self.clear_feedback(),
+            OscCommand::Init => self.run_init = true,
+            OscCommand::Seek(time) => self.seek(time),
+            OscCommand::Loop(region) => self.set_loop(region),
+            OscCommand::LayerOpacity(layer, opacity) => {
+                let _ = self
+                    .layers
+                    .set_opacity(layer, opacity)
+                    .map_err(|err| log::error!("{err}"));
+            }
+            OscCommand::LayerBlend(layer, blend) => {
+                let _ = self
+                    .layers
+                    .set_blend(layer, blend)
+                    .map_err(|err| log::error!("{err}"));
+            }
+            OscCommand::LayerPause(layer, pause) => {
+                let _ = self
+                    .layers
+                    .set_pause(layer, pause)
+                    .map_err(|err| log::error!("{err}"));
+            }
+            OscCommand::LayerSpeed(layer, speed) => {
+                let _ = self
+                    .layers
+                    .set_speed(layer, speed)
+                    .map_err(|err| log::error!("{err}"));
+            }
+            OscCommand::LayerLoop(layer, region) => {
+                let _ = self
+                    .layers
+                    .set_loop(layer, region)
+                    .map_err(|err| log::error!("{err}"));
+            }
+            OscCommand::Position(pos) => self.push_constant.pos = pos,
+            OscCommand::Mouse(mouse) => self.push_constant.mouse = mouse,
+            OscCommand::MousePressed(pressed) => self.push_constant.mouse_pressed = pressed as u32,
+            OscCommand::Param(name, values) => {
+                let params = self.pipeline_arena.shader_compiler.user_params();
+                if let Err(err) =
+                    set_user_param(&mut self.push_constant.user, &params, &name, &values)
+                {
+                    log::error!("{err}");
+                }
+            }
+            OscCommand::ShaderFolder(dir) => {
+                let _ = self
+                    .load_shader_folder(dir)
+                    .map_err(|err| log::error!("{err}"));
+            }
+        }
+    }
+
+    /// Compiles the sound pass against the current settings, it comes and
+    /// goes with `sound.comp`.
+    #[cfg(feature = "audio")]
+    fn configure_sound_pass(&mut self, dir: &Path) {
+        let path = dir.join(SOUND_SHADER);
+        let Some(sound_pass) = &mut self.sound_pass else {
+            self.sound_pass = create_sound_pass(
+                &self.device,
+                &mut self.pipeline_arena,
+                &self.texture_arena,
+                dir,
+            );
+            return;
+        };
+        if !path.is_file() {
+            self.drop_sound_pass();
+        } else if let Err(err) =
+            sound_pass.rebuild(&mut self.pipeline_arena, &self.texture_arena, &path)
+        {
+            log::error!("Failed to rebuild sound pass: {err}");
+            self.drop_sound_pass();
+        }
+    }
+
+    #[cfg(feature = "audio")]
+    fn drop_sound_pass(&mut self) {
+        if let Some(sound_pass) = self.sound_pass.take() {
+            self.pipeline_arena
+                .remove_pipeline(Either::Right(sound_pass.pipeline));
+        }
+    }
+
+    fn reload_shaders(&mut self, paths: &[PathBuf]) -> Result<()> {
+        // A sound pass that failed comes back once its shader changes
+        #[cfg(feature = "audio")]
+        if self.sound_pass.is_none() {
+            let sound_shader = self.shader_dir.join(SOUND_SHADER).canonicalize();
+            if sound_shader.is_ok_and(|path| paths.contains(&path)) {
+                self.sound_pass = create_sound_pass(
+                    &self.device,
+                    &mut self.pipeline_arena,
+                    &self.texture_arena,
+                    &self.shader_dir,
+                );
+            }
         }
 
-        let resolved = {
+        // The folder watch also reports files no pipeline uses
+        let resolved: HashSet<ShaderSource> = {
             let mapping = self.file_watcher.include_mapping.lock();
-            mapping[&path].clone()
+            paths
+                .iter()
+                .filter_map(|path| mapping.get(path))
+                .flatten()
+                .cloned()
+                .collect()
         };
+        if resolved.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(frame) = self.swapchain.get_current_frame() {
+            let fences = std::slice::from_ref(&frame.present_finished);
+            unsafe { self.device.wait_for_fences(fences, true, u64::MAX)? };
+        }
 
+        // Every pipeline gets its chance, one failing doesn't hold back the rest
+        let mut errors = vec![];
+        let mut rebuild = false;
         for ShaderSource { path, kind } in resolved {
-            let handles = &self.pipeline_arena.path_mapping[&path];
+            let handles: Vec<_> = self
+                .pipeline_arena
+                .path_mapping
+                .get(&path)
+                .into_iter()
+                .flatten()
+                .copied()
+                .collect();
             for handle in handles {
                 let compiler = &self.pipeline_arena.shader_compiler;
-                match handle {
+                let result = match handle {
                     Either::Left(handle) => {
-                        let pipeline = &mut self.pipeline_arena.render.pipelines[*handle];
-                        match kind {
-                            ShaderKind::Vertex => pipeline.reload_vertex_lib(compiler, &path),
-                            ShaderKind::Fragment => pipeline.reload_fragment_lib(compiler, &path),
-                            ShaderKind::Compute => {
-                                bail!("Supplied compute shader into the render pipeline!")
-                            }
-                        }?;
-                        pipeline.link()?;
+                        let pipeline = &self.pipeline_arena.render.pipelines[handle];
+                        let staged = pipeline.stage(compiler, &path, kind);
+                        let checked = match self.validate_reloads {
+                            true => staged.and_then(|staged| {
+                                self.reload_check.render(
+                                    &self.queue,
+                                    pipeline,
+                                    staged,
+                                    self.texture_arena.images_set,
+                                    &self.push_constant,
+                                )
+                            }),
+                            false => staged,
+                        };
+                        checked.map(|staged| {
+                            self.pipeline_arena.render.pipelines[handle].apply(staged)
+                        })
                     }
                     Either::Right(handle) => {
-                        let pipeline = &mut self.pipeline_arena.compute.pipelines[*handle];
-                        pipeline.reload(compiler)?;
+                        let pipeline = &mut self.pipeline_arena.compute.pipelines[handle];
+                        let result = pipeline.reload(compiler);
+                        // A changed seed is only visible once it runs again
+                        if result.is_ok() && Some(handle) == self.init_pipeline && !self.keep_state
+                        {
+                            self.run_init = true;
+                        }
+                        result
+                    }
+                };
+                match result {
+                    Err(err)
+                        if matches!(
+                            err.downcast_ref(),
+                            Some(Error::Vulkan {
+                                result: vk::Result::TIMEOUT,
+                                ..
+                            })
+                        ) =>
+                    {
+                        self.device_lost = true;
+                        bail!("{err}, restarting the renderer");
+                    }
+                    Ok(()) => {
+                        if self.pipeline_arena.reload_succeeded(handle) {
+                            println!("{} is out of quarantine", path.display());
+                        }
+                    }
+                    Err(err) if matches!(err.downcast_ref(), Some(Error::Bindings { .. })) => {
+                        rebuild = true;
+                    }
+                    Err(err) => {
+                        if self.pipeline_arena.reload_failed(handle) {
+                            log::error!(
+                                "{} failed {QUARANTINE_AFTER} reloads in a row, \
+                                 its pass is skipped until it compiles",
+                                path.display()
+                            );
+                        }
+                        errors.push(err);
                     }
                 }
             }
         }
+        // Only new layouts fit other bindings, and the manifest rebuilds them all
+        if rebuild {
+            println!("Set 1 bindings changed, rebuilding the pipelines");
+            self.reload_manifest()?;
+        }
+        if !errors.is_empty() {
+            let messages: Vec<_> = errors.iter().map(ToString::to_string).collect();
+            bail!(messages.join("\n"));
+        }
+        self.push_constant.reload_count += 1;
         Ok(())
     }
 
     fn recreate_swapchain(&mut self) -> Result<()> {
+        self.captures.invalidate();
         if let Some(frame) = self.swapchain.get_current_frame() {
             let fences = std::slice::from_ref(&frame.present_finished);
             unsafe { self.device.wait_for_fences(fences, true, u64::MAX)? };
@@ -213,11 +1707,21 @@ impl AppInit {
 
         self.swapchain
             .recreate(&self.device, &self.surface)
-            .expect("Failed to recreate swapchain");
+            .map_err(Error::vulkan("Failed to recreate swapchain"))?;
+        // A fixed render resolution is only rescaled on presentation
+        if self.render_size.is_some() {
+            return Ok(());
+        }
         let extent = self.swapchain.extent();
         self.push_constant.wh = [extent.width as f32, extent.height as f32];
 
-        for i in SCREENSIZED_IMAGE_INDICES {
+        let screensized: Vec<_> = SCREENSIZED_IMAGE_INDICES
+            .into_iter()
+            .chain([self.output_copy])
+            .chain(self.post_process.targets().iter().copied())
+            .chain(self.layers.targets())
+            .collect();
+        for &i in &screensized {
             self.texture_arena.image_infos[i].extent = vk::Extent3D {
                 width: extent.width,
                 height: extent.height,
@@ -225,7 +1729,15 @@ impl AppInit {
             };
         }
         self.texture_arena
-            .update_images(&SCREENSIZED_IMAGE_INDICES)?;
+            .update_images(&self.queue, &screensized)?;
+        self.post_process
+            .resize_bloom(&mut self.texture_arena, &self.queue, extent)?;
+        self.scene_depth.resize(&self.device, extent)?;
+        self.scene_msaa.resize(&self.device, extent)?;
+        if self.history_len > 0 {
+            self.texture_arena
+                .set_history_len(&self.queue, self.history_len, extent)?;
+        }
 
         Ok(())
     }
@@ -237,11 +1749,49 @@ impl ApplicationHandler<UserEvent> for AppInit {
         event_loop: &winit::event_loop::ActiveEventLoop,
         cause: winit::event::StartCause,
     ) {
-        self.push_constant.time = if !self.pause {
-            self.timeline.elapsed().as_secs_f32()
+        if let Some(region) = &self.loop_region {
+            if self.clock() >= region.end {
+                self.set_clock(region.start);
+            }
+        }
+        self.push_constant.time = if self.audio_locked() {
+            self.recorded_duration().as_secs_f32()
         } else {
-            self.backup_time.as_secs_f32()
+            self.clock().as_secs_f32()
         };
+        if let Some((_, time)) = self.converging {
+            self.push_constant.time = time;
+        }
+        while let Some(command) = self.macro_player.as_mut().and_then(MacroPlayer::next_due) {
+            match command {
+                MacroCommand::Action(action) => self.run_action(action),
+                MacroCommand::Osc(command) => self.handle_osc(command),
+            }
+        }
+        if let Some(sync) = &self.sync {
+            match sync.state() {
+                Some(state) => {
+                    self.set_pause(state.paused);
+                    self.push_constant.time = state.time as f32;
+                    self.push_constant.frame = state.frame;
+                }
+                None => sync.publish(SyncState {
+                    time: self.push_constant.time as f64,
+                    frame: self.push_constant.frame,
+                    paused: self.pause,
+                }),
+            }
+        }
+        if let Some(status) = self.status.as_mut().filter(|status| status.due()) {
+            let project = folder_title(&self.shader_dir);
+            status.publish(&Status {
+                project: &project,
+                paused: self.pause,
+                recording: self.video_recording,
+                frame: self.push_constant.frame,
+                error: self.last_error.as_deref(),
+            });
+        }
         if let StartCause::WaitCancelled { .. } = cause {
             let new_instant = Instant::now();
             let frame_time = new_instant
@@ -249,7 +1799,6 @@ impl ApplicationHandler<UserEvent> for AppInit {
                 .as_secs_f64()
                 .min(MAX_FRAME_TIME);
             self.frame_instant = new_instant;
-            self.push_constant.time_delta = frame_time as _;
 
             self.frame_accumulated_time += frame_time;
             while self.frame_accumulated_time >= FIXED_TIME_STEP {
@@ -260,7 +1809,11 @@ impl ApplicationHandler<UserEvent> for AppInit {
         }
 
         if let Some(limit) = self.record_time {
-            if self.timeline.elapsed() >= limit && self.recorder.is_active() {
+            let elapsed = match self.audio_locked() {
+                true => self.recorded_duration(),
+                false => self.clock(),
+            };
+            if elapsed >= limit && self.recorder.is_active() {
                 self.recorder.finish();
                 event_loop.exit();
             }
@@ -281,9 +1834,20 @@ impl ApplicationHandler<UserEvent> for AppInit {
     fn window_event(
         &mut self,
         event_loop: &winit::event_loop::ActiveEventLoop,
-        _window_id: winit::window::WindowId,
+        window_id: winit::window::WindowId,
         event: WindowEvent,
     ) {
+        // The output window only takes keys, it is redrawn with the main one
+        if self.output_window.as_ref().map(OutputWindow::id) == Some(window_id) {
+            match event {
+                WindowEvent::CloseRequested => {
+                    self.toggle_output_window = true;
+                    return;
+                }
+                WindowEvent::KeyboardInput { .. } => {}
+                _ => return,
+            }
+        }
         match event {
             WindowEvent::CloseRequested
             | WindowEvent::KeyboardInput {
@@ -296,102 +1860,57 @@ impl ApplicationHandler<UserEvent> for AppInit {
                 ..
             } => event_loop.exit(),
 
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        logical_key: Key::Named(key),
-                        state: ElementState::Pressed,
-                        repeat: false,
-                        ..
-                    },
-                ..
-            } => {
-                let dt = Duration::from_secs_f32(1. / 60.);
-                match key {
-                    NamedKey::F1 => print_help(),
-                    NamedKey::F2 => {
-                        if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
-                        } else {
-                            self.timeline = Instant::now() - self.backup_time;
-                        }
-                        self.pause = !self.pause;
-                    }
-                    NamedKey::F3 => {
-                        if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
-                            self.pause = true;
-                        }
-                        self.backup_time = self.backup_time.saturating_sub(dt);
-                    }
-                    NamedKey::F4 => {
-                        if !self.pause {
-                            self.backup_time = self.timeline.elapsed();
-                            self.pause = true;
-                        }
-                        self.backup_time += dt;
-                    }
-                    NamedKey::F5 => {
-                        self.push_constant.pos = [0.; 3];
-                        self.push_constant.time = 0.;
-                        self.push_constant.frame = 0;
-                        self.timeline = Instant::now();
-                        self.backup_time = self.timeline.elapsed();
-                    }
-                    NamedKey::F6 => {
-                        println!("{}", self.push_constant);
-                    }
-                    NamedKey::F10 => {
-                        let _ = save_shaders(SHADER_FOLDER).map_err(|err| log::error!("{err}"));
-                    }
-                    NamedKey::F11 => {
-                        let _ = self
-                            .device
-                            .capture_image_data(
-                                &self.queue,
-                                self.swapchain.get_current_image(),
-                                self.swapchain.extent(),
-                                |tex| self.recorder.screenshot(tex),
-                            )
-                            .map_err(|err| log::error!("{err}"));
-                    }
-                    NamedKey::F12 => {
-                        if !self.video_recording {
-                            let mut image_dimensions = self.swapchain.image_dimensions;
-                            image_dimensions.width = align_to(image_dimensions.width, 2);
-                            image_dimensions.height = align_to(image_dimensions.height, 2);
-                            self.recorder.start(image_dimensions);
-                        } else {
-                            self.recorder.finish();
+            WindowEvent::KeyboardInput { event, .. } => {
+                let action = (event.state == ElementState::Pressed && !event.repeat)
+                    .then(|| self.keys.action(&event.logical_key))
+                    .flatten();
+                match action {
+                    Some(action) => {
+                        if let Some(recorder) = &mut self.macro_recorder {
+                            recorder.action(action);
                         }
-                        self.video_recording = !self.video_recording;
+                        self.run_action(action)
                     }
-                    _ => {}
+                    None => self.input.update_window_input(&event),
                 }
             }
-            WindowEvent::KeyboardInput { event, .. } => {
-                self.input.update_window_input(&event);
-            }
 
             WindowEvent::MouseInput {
-                state,
+                state: ElementState::Pressed,
                 button: MouseButton::Left,
                 ..
-            } => {
-                self.push_constant.mouse_pressed = (ElementState::Pressed == state) as u32;
+            } if self.picking => self.pick_color(),
+            WindowEvent::MouseInput { state, button, .. } => {
+                if button == MouseButton::Left {
+                    self.push_constant.mouse_pressed = (ElementState::Pressed == state) as u32;
+                }
+                self.input.update_mouse_button(button, state);
             }
-            WindowEvent::CursorMoved {
-                position: PhysicalPosition { x, y },
-                ..
-            } => {
+            WindowEvent::MouseWheel { delta, .. } => self.input.update_scroll(delta),
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor = position;
                 if !self.pause {
                     let PhysicalSize { width, height } = self.window.inner_size();
-                    let x = (x as f32 / width as f32 - 0.5) * 2.;
-                    let y = -(y as f32 / height as f32 - 0.5) * 2.;
+                    let window = vk::Extent2D { width, height };
+                    let area = letterbox(self.render_extent(), window);
+                    let (offset, size) = (area.offset, area.extent);
+                    let x = ((position.x as f32 - offset.x as f32) / size.width as f32 - 0.5) * 2.;
+                    let y =
+                        -((position.y as f32 - offset.y as f32) / size.height as f32 - 0.5) * 2.;
                     self.push_constant.mouse = [x, y];
                 }
             }
             WindowEvent::RedrawRequested => {
+                self.stats.tick();
+                if self.title_timer.ready() {
+                    #[allow(unused_mut)]
+                    let mut title = format!("{WINDOW_TITLE} | {}", self.stats.summary());
+                    #[cfg(feature = "sensors")]
+                    if let Some(reading) = self.sensors.as_ref().and_then(|s| s.reading()) {
+                        title.push_str(&format!(" | {reading}"));
+                    }
+                    self.window.set_title(&title);
+                }
                 let mut frame = match self.swapchain.acquire_next_image() {
                     Ok(frame) => frame,
                     Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
@@ -399,91 +1918,450 @@ impl ApplicationHandler<UserEvent> for AppInit {
                         self.window.request_redraw();
                         return;
                     }
-                    Err(e) => panic!("error: {e}\n"),
+                    Err(result) => {
+                        self.skip_frame(event_loop, "Failed to acquire swapchain image", result);
+                        return;
+                    }
                 };
 
+                self.data_buffers
+                    .upload(&self.device, *frame.command_buffer());
+                self.streams.upload(
+                    &self.device,
+                    &self.queue,
+                    &mut self.texture_arena,
+                    *frame.command_buffer(),
+                );
+                if let Some(dmx) = &mut self.dmx {
+                    dmx.update();
+                }
+                let cbuff = *frame.command_buffer();
+                self.profiler.begin_frame(cbuff);
+                if let Some(heatmap) = &mut self.heatmap {
+                    heatmap.begin_frame(cbuff);
+                }
+
+                #[cfg(feature = "audio")]
+                if let Some(sound_pass) = &mut self.sound_pass {
+                    let res = sound_pass.update(
+                        &self.device,
+                        &self.queue,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        !self.pause,
+                    );
+                    if let Err(err) = res {
+                        log::error!(
+                            "Sound shader failed, muted until {SOUND_SHADER} changes: {err}"
+                        );
+                        self.drop_sound_pass();
+                    }
+                }
+
+                self.input.process_mouse(&mut self.push_constant);
+                // How far the clock moved, so zero while paused and after
+                // jumping back, and bounded after seeking ahead
+                let time_delta = self.push_constant.time - self.prev_frame_time;
+                self.push_constant.time_delta = time_delta.clamp(0., MAX_FRAME_TIME as f32);
+                self.prev_frame_time = self.push_constant.time;
+                self.layers.advance(self.push_constant.time_delta);
+                self.captures.update(self.pause, &self.push_constant);
                 let stages = vk::ShaderStageFlags::VERTEX
                     | vk::ShaderStageFlags::FRAGMENT
                     | vk::ShaderStageFlags::COMPUTE;
-                let pipeline = self.pipeline_arena.get_pipeline(self.compute_pipeline);
-                frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
-                frame.bind_descriptor_sets(
-                    vk::PipelineBindPoint::COMPUTE,
-                    pipeline.layout,
-                    &[self.texture_arena.images_set],
-                );
-                frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
-                const SUBGROUP_SIZE: u32 = 16;
-                let extent = self.swapchain.extent();
-                frame.dispatch(
-                    dispatch_optimal(extent.width, SUBGROUP_SIZE),
-                    dispatch_optimal(extent.height, SUBGROUP_SIZE),
-                    1,
-                );
+                let init = self.init_pipeline.filter(|_| self.run_init);
+                self.run_init = false;
+                let compute: Vec<_> = init
+                    .into_iter()
+                    .chain([self.compute_pipeline])
+                    .chain(self.passes.compute())
+                    .filter(|&handle| !self.pipeline_arena.is_quarantined(Either::Right(handle)))
+                    .collect();
+                for (i, &handle) in compute.iter().enumerate() {
+                    let pipeline = self.pipeline_arena.get_pipeline(handle);
+                    frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
+                    frame.bind_descriptor_sets(
+                        vk::PipelineBindPoint::COMPUTE,
+                        pipeline.layout,
+                        &pipeline.descriptor_sets(self.texture_arena.images_set),
+                    );
+                    frame.bind_pipeline(vk::PipelineBindPoint::COMPUTE, &pipeline.pipeline);
+                    let extent = self.render_extent();
+                    let [x, y, _] = pipeline.local_size;
+                    frame.dispatch(
+                        dispatch_optimal(extent.width, x),
+                        dispatch_optimal(extent.height, y),
+                        1,
+                    );
+                    let label = if Some(handle) == init {
+                        "init"
+                    } else if handle == self.compute_pipeline {
+                        "compute"
+                    } else {
+                        "pass"
+                    };
+                    self.profiler.mark(cbuff, label);
+
+                    // Every compute pass sees the writes of the ones before
+                    if i + 1 < compute.len() {
+                        let memory_barrier = vk::MemoryBarrier2::default()
+                            .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                            .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
+                            .dst_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                            .dst_access_mask(
+                                vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::SHADER_WRITE,
+                            );
+                        unsafe {
+                            self.device.cmd_pipeline_barrier2(
+                                *frame.command_buffer(),
+                                &vk::DependencyInfo::default()
+                                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+                            )
+                        };
+                    }
+                }
 
                 unsafe {
-                    let image_barrier = vk::ImageMemoryBarrier2::default()
-                        .subresource_range(COLOR_SUBRESOURCE_MASK)
+                    let memory_barrier = vk::MemoryBarrier2::default()
                         .src_stage_mask(vk::PipelineStageFlags2::COMPUTE_SHADER)
+                        .src_access_mask(vk::AccessFlags2::SHADER_WRITE)
                         .dst_stage_mask(vk::PipelineStageFlags2::ALL_GRAPHICS)
-                        .image(self.texture_arena.images[PREV_FRAME_IMAGE_IDX].image);
+                        .dst_access_mask(vk::AccessFlags2::SHADER_READ);
                     self.device.cmd_pipeline_barrier2(
                         *frame.command_buffer(),
                         &vk::DependencyInfo::default()
-                            .image_memory_barriers(std::slice::from_ref(&image_barrier)),
+                            .memory_barriers(std::slice::from_ref(&memory_barrier)),
                     )
                 };
 
-                frame.begin_rendering(
-                    self.swapchain.get_current_image_view(),
-                    [0., 0.025, 0.025, 1.0],
+                const CLEAR_COLOR: [f32; 4] = [0., 0.025, 0.025, 1.0];
+                // Stands in for the image pass while it is quarantined
+                const ERROR_COLOR: [f32; 4] = [0.5, 0., 0.25, 1.0];
+                let broken = self
+                    .pipeline_arena
+                    .is_quarantined(Either::Left(self.render_pipeline));
+                let clear_color = match (broken, self.transparent) {
+                    (true, _) => ERROR_COLOR,
+                    (false, true) => [0.; 4],
+                    (false, false) => CLEAR_COLOR,
+                };
+                self.layers.render(
+                    &mut frame,
+                    &self.device,
+                    &self.pipeline_arena,
+                    &self.texture_arena,
+                    &self.push_constant,
+                    self.render_extent(),
                 );
-                let pipeline = self.pipeline_arena.get_pipeline(self.render_pipeline);
-                frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
-                frame.bind_descriptor_sets(
-                    vk::PipelineBindPoint::GRAPHICS,
-                    pipeline.layout,
-                    &[self.texture_arena.images_set],
+                let attachments = SceneAttachments {
+                    msaa: self.scene_msaa.begin(&frame, &self.device),
+                    depth: self.scene_depth.begin(&frame, &self.device),
+                };
+                let offscreen = self.post_process.begin_scene(
+                    &mut frame,
+                    &self.device,
+                    &self.texture_arena,
+                    self.render_extent(),
+                    clear_color,
+                    attachments,
+                );
+                if !offscreen {
+                    let view = self.swapchain.get_current_image_view();
+                    let extent = frame.extent();
+                    frame.begin_rendering_scene(view, attachments, extent, clear_color);
+                }
+                if !broken {
+                    let pipeline = self.pipeline_arena.get_pipeline(self.render_pipeline);
+                    frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
+                    frame.bind_descriptor_sets(
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.layout,
+                        &pipeline.descriptor_sets(self.texture_arena.images_set),
+                    );
+                    frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+                    frame.draw(3, 0, 1, 0);
+                    let extent = self.render_extent();
+                    if let Some(heatmap) = &mut self.heatmap {
+                        heatmap.measure_row(&mut frame, extent);
+                    }
+                }
+                self.mesh.draw(
+                    &mut frame,
+                    &self.pipeline_arena,
+                    &self.texture_arena,
+                    &self.push_constant,
+                );
+                self.particles.draw(
+                    &mut frame,
+                    &self.pipeline_arena,
+                    &self.texture_arena,
+                    &self.push_constant,
+                );
+                for handle in self
+                    .passes
+                    .render()
+                    .filter(|&handle| !self.pipeline_arena.is_quarantined(Either::Left(handle)))
+                {
+                    let pipeline = self.pipeline_arena.get_pipeline(handle);
+                    frame.push_constant(pipeline.layout, stages, &[self.push_constant]);
+                    frame.bind_descriptor_sets(
+                        vk::PipelineBindPoint::GRAPHICS,
+                        pipeline.layout,
+                        &pipeline.descriptor_sets(self.texture_arena.images_set),
+                    );
+                    frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+                    frame.draw(3, 0, 1, 0);
+                }
+                if !attachments.is_empty() {
+                    // Resolves the scene, layer pipelines are single sampled
+                    // and built without a depth format
+                    frame.end_rendering();
+                    let (view, extent) = match self.post_process.scene_target() {
+                        Some(idx) => (self.texture_arena.views[idx], self.render_extent()),
+                        None => (*self.swapchain.get_current_image_view(), frame.extent()),
+                    };
+                    frame.resume_rendering_extent(&view, extent);
+                }
+                self.layers.composite(
+                    &mut frame,
+                    &self.pipeline_arena,
+                    &self.texture_arena,
+                    self.render_extent(),
                 );
-                frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
-
-                frame.draw(3, 0, 1, 0);
                 frame.end_rendering();
+                self.profiler.mark(cbuff, "image");
 
-                self.device.blit_image(
+                // Feedback is the image pass output, before the post chain
+                let scene = match self.post_process.scene_target() {
+                    Some(idx) => &self.texture_arena.images[idx].image,
+                    None => self.swapchain.get_current_image(),
+                };
+                self.device.blit_image_to_layer(
                     frame.command_buffer(),
-                    self.swapchain.get_current_image(),
-                    self.swapchain.extent(),
+                    scene,
                     vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
                     &self.texture_arena.images[PREV_FRAME_IMAGE_IDX].image,
-                    self.swapchain.extent(),
-                    vk::ImageLayout::UNDEFINED,
+                    self.render_extent(),
+                    0,
                 );
+                self.profiler.mark(cbuff, "feedback");
+
+                if self.post_process.is_enabled() {
+                    self.post_process.apply(
+                        &mut frame,
+                        &self.device,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.render_extent(),
+                    );
+                    self.profiler.mark(cbuff, "post");
+                }
+                match (&self.slices, self.post_process.output_target()) {
+                    (Some(slices), Some(output)) => slices.draw(
+                        &mut frame,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.swapchain.get_current_image_view(),
+                        output,
+                    ),
+                    _ => self.post_process.present(
+                        &mut frame,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.swapchain.get_current_image_view(),
+                        self.render_extent(),
+                        match self.transparent {
+                            true => [0.; 4],
+                            false => [0., 0., 0., 1.],
+                        },
+                    ),
+                }
+                self.profiler.mark(cbuff, "present");
+
+                // A scaled or sliced frame is copied from the offscreen target
+                let (src_image, src_layout) = match self.offscreen() {
+                    true => (
+                        &self.texture_arena.images[self.post_process.output_target().unwrap()]
+                            .image,
+                        vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ),
+                    false => (
+                        self.swapchain.get_current_image(),
+                        vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+                    ),
+                };
+                // Leaves the copy in the layout its descriptor promises
+                self.device.blit_image_to_layer(
+                    frame.command_buffer(),
+                    src_image,
+                    src_layout,
+                    &self.texture_arena.images[self.output_copy].image,
+                    self.render_extent(),
+                    0,
+                );
+                if self.history_len > 0 {
+                    self.device.blit_image_to_layer(
+                        frame.command_buffer(),
+                        src_image,
+                        src_layout,
+                        &self.texture_arena.images[HISTORY_IMAGE_IDX].image,
+                        self.render_extent(),
+                        self.push_constant.frame % self.history_len,
+                    );
+                }
+                self.profiler.mark(cbuff, "copy");
+
+                if let Some(reference) = &self.reference {
+                    reference.draw(
+                        &mut frame,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.swapchain.get_current_image_view(),
+                        self.swapchain.extent(),
+                    );
+                }
+                if let Some(guides) = &self.guides {
+                    guides.draw(
+                        &mut frame,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.swapchain.get_current_image_view(),
+                        self.swapchain.extent(),
+                    );
+                }
+                if let Some(graph) = &self.stats_graph {
+                    graph.draw(
+                        &mut frame,
+                        &self.device,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        &self.stats,
+                        self.swapchain.get_current_image_view(),
+                        self.swapchain.extent(),
+                    );
+                }
+                if let Some(heatmap) = &self.heatmap {
+                    heatmap.draw(
+                        &mut frame,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.swapchain.get_current_image_view(),
+                    );
+                }
+                if let Some(hud) = &self.hud {
+                    let values = HudValues {
+                        fps: self.stats.fps(),
+                        ms: self.stats.average_ms(),
+                        resolution: self.render_extent(),
+                        time: self.push_constant.time,
+                        frame: self.push_constant.frame,
+                        vram_bytes: self.device.audit.memory_in_use(),
+                        validation: self.instance.validation.counts(),
+                        error: self.error_banner(),
+                    };
+                    hud.draw(
+                        &mut frame,
+                        &self.device,
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        &values,
+                        self.swapchain.get_current_image_view(),
+                        self.swapchain.extent(),
+                        self.window.scale_factor(),
+                    );
+                }
+                if self.reference.is_some()
+                    || self.guides.is_some()
+                    || self.stats_graph.is_some()
+                    || self.heatmap.is_some()
+                    || self.hud.is_some()
+                {
+                    self.profiler.mark(cbuff, "overlays");
+                }
 
                 match self.swapchain.submit_image(&self.queue, frame) {
-                    Ok(_) => {}
+                    Ok(_) => {
+                        self.failed_frames = 0;
+                        self.frame_error = None;
+                        if let Some(status) = &mut self.status {
+                            status.frame_presented();
+                        }
+                    }
                     Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
                         let _ = self.recreate_swapchain().map_err(|err| log::warn!("{err}"));
                     }
-                    Err(e) => panic!("error: {e}\n"),
+                    Err(result) => {
+                        self.skip_frame(event_loop, "Failed to present", result);
+                        return;
+                    }
                 }
 
-                self.window.request_redraw();
-
-                if self.video_recording && self.recorder.ffmpeg_installed() {
-                    let res = self.device.capture_image_data(
+                let render_extent = self.render_extent();
+                if let Some(output) = &mut self.output_window {
+                    let res = output.present(
+                        &self.device,
                         &self.queue,
-                        self.swapchain.get_current_image(),
-                        self.swapchain.extent(),
-                        |tex| self.recorder.record(tex),
+                        &self.pipeline_arena,
+                        &self.texture_arena,
+                        self.output_copy,
+                        render_extent,
                     );
                     if let Err(err) = res {
-                        log::error!("{err}");
-                        self.video_recording = false;
+                        log::error!("Closing the output window: {err}");
+                        self.toggle_output_window = true;
+                    }
+                }
+
+                self.window.request_redraw();
+
+                let recorded_before = self.recorded_frames;
+                let copies = self.frames_due();
+                if self.video_recording && self.recorder.ffmpeg_installed() && copies > 0 {
+                    let res = self.capture_output(|tex| self.recorder.record(tex, copies));
+                    match res {
+                        Ok(()) => self.recorded_frames += copies as u64,
+                        Err(err) => {
+                            log::error!("{err}");
+                            self.video_recording = false;
+                        }
+                    }
+                }
+
+                if let Some((mut accumulation, time)) = self.converging.take() {
+                    let mut added = Ok(());
+                    let res = self
+                        .capture_output(|tex| added = accumulation.add(tex))
+                        .and(added);
+                    match res {
+                        Ok(()) if accumulation.is_done() => {
+                            accumulation.save(self.recorder.screenshot)
+                        }
+                        Ok(()) => self.converging = Some((accumulation, time)),
+                        Err(err) => log::error!("Converged screenshot failed: {err}"),
                     }
                 }
 
+                let extent = self.render_extent();
+                self.baseline.frame(self.profiler.total_ms(), extent);
+
                 self.push_constant.frame = self.push_constant.frame.saturating_add(1);
+                if let Some(dt) = self.fixed_dt.filter(|_| !self.pause) {
+                    self.backup_time += dt;
+                }
+
+                let time = self.clock().as_secs_f64();
+                if let Some(export) = self.export.as_mut().filter(|_| self.video_recording) {
+                    export.report(self.recorded_frames);
+                    if export.checkpoint_due(recorded_before, self.recorded_frames) {
+                        self.recorder.checkpoint(ExportCheckpoint {
+                            parts: vec![],
+                            frames: self.recorded_frames,
+                            time,
+                            frame: self.push_constant.frame,
+                            duration: self.record_time.map_or(0., |t| t.as_secs_f32()),
+                        });
+                    }
+                }
             }
             _ => {}
         }
@@ -491,31 +2369,84 @@ impl ApplicationHandler<UserEvent> for AppInit {
 
     fn user_event(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop, event: UserEvent) {
         match event {
-            UserEvent::Glsl { path } => {
-                match self.reload_shaders(path) {
-                    Err(err) => eprintln!("{err}"),
-                    Ok(()) => {
-                        const ESC: &str = "\x1B[";
-                        const RESET: &str = "\x1B[0m";
-                        eprint!("\r{}42m{}K{}\r", ESC, ESC, RESET);
-                        std::io::stdout().flush().unwrap();
-                        std::thread::spawn(|| {
-                            std::thread::sleep(std::time::Duration::from_millis(50));
-                            eprint!("\r{}40m{}K{}\r", ESC, ESC, RESET);
-                            std::io::stdout().flush().unwrap();
-                        });
+            UserEvent::Glsl { path } if self.passes.is_missing(&self.shader_dir, &path) => {
+                match self.update_passes() {
+                    Err(err) => {
+                        eprintln!("{err}");
+                        self.last_error = Some(err.to_string());
                     }
-                };
+                    Ok(()) => self.last_error = None,
+                }
+            }
+            UserEvent::Glsl { path } => {
+                if !self.pending_reloads.contains(&path) {
+                    self.pending_reloads.push(path);
+                }
+            }
+            UserEvent::Table { path } => {
+                if let Err(err) = self.reload_table(&path) {
+                    log::error!("{err:#}");
+                }
+            }
+            UserEvent::Passes => {
+                if let Err(err) = self.update_passes() {
+                    eprintln!("{err}");
+                }
+            }
+            UserEvent::Manifest => {
+                if let Err(err) = self.reload_manifest() {
+                    log::error!("{err:#}");
+                }
+            }
+            UserEvent::Osc(command) => {
+                if let Some(recorder) = &mut self.macro_recorder {
+                    recorder.osc(&command);
+                }
+                self.handle_osc(command)
             }
         }
     }
 
-    fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
-        self.recorder.close_thread();
-        if let Some(handle) = self.recorder.thread_handle.take() {
-            let _ = handle.join();
+    fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let paths = std::mem::take(&mut self.pending_reloads);
+        if !paths.is_empty() {
+            match self.reload_shaders(&paths) {
+                Err(err) => {
+                    eprintln!("{err}");
+                    self.last_error = Some(err.to_string());
+                }
+                Ok(()) => {
+                    self.last_error = None;
+                    self.baseline.reloaded();
+                    const ESC: &str = "\x1B[";
+                    const RESET: &str = "\x1B[0m";
+                    eprint!("\r{}42m{}K{}\r", ESC, ESC, RESET);
+                    let _ = std::io::stdout().flush();
+                    std::thread::spawn(|| {
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        eprint!("\r{}40m{}K{}\r", ESC, ESC, RESET);
+                        let _ = std::io::stdout().flush();
+                    });
+                }
+            };
         }
-        let _ = unsafe { self.device.device_wait_idle() };
+        if std::mem::take(&mut self.toggle_output_window) {
+            self.toggle_output_window(event_loop);
+        }
+        // Errors show on the HUD even while it is hidden
+        if self.hud.is_none() && self.error_banner().is_some() {
+            if let Err(err) = self.hud_mut() {
+                log::error!("Failed to create HUD: {err}");
+            }
+        }
+        // Keeps reporting while a minimized window gets no redraws
+        if let Some(status) = &self.status {
+            event_loop.set_control_flow(ControlFlow::WaitUntil(status.next_report()));
+        }
+    }
+
+    fn exiting(&mut self, _event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.shut_down();
         println!("// End from the loop. Bye bye~⏎ ");
     }
 
@@ -528,40 +2459,200 @@ fn main() -> Result<()> {
     env_logger::init();
     let event_loop = winit::event_loop::EventLoop::with_user_event().build()?;
 
-    let Args {
-        record_time,
-        inner_size,
-    } = parse_args()?;
+    let args = parse_args()?;
+    if args.list_gpus {
+        let instance = Instance::new(None::<&Window>, args.validation)?;
+        for line in instance.describe_gpus()? {
+            println!("{line}");
+        }
+        return Ok(());
+    }
 
-    let shader_dir = PathBuf::new().join(SHADER_FOLDER);
-    if !shader_dir.is_dir() {
-        default_shaders::create_default_shaders(&shader_dir)?;
+    if let Some(out) = &args.shadertoy {
+        let manifest = Manifest::load(&args.shader_dir)?;
+        let mut compile = manifest.compile.relative_to(&args.shader_dir);
+        compile.include.extend(args.include.iter().cloned());
+        let source = to_shadertoy(&args.shader_dir.join("shader.frag"), &compile.include)?;
+        if out.as_os_str() == "-" {
+            print!("{source}");
+        } else {
+            std::fs::write(out, source)?;
+            println!("Saved {}", out.display());
+        }
+        return Ok(());
     }
 
-    let mut app = App::new(event_loop.create_proxy(), record_time, inner_size);
+    if args.new_project {
+        if args.shader_dir.exists() {
+            bail!("{} already exists", args.shader_dir.display());
+        }
+        default_shaders::create_project(&args.shader_dir, args.template)?;
+        println!("Created project {}", args.shader_dir.display());
+    } else if !args.shader_dir.is_dir() {
+        default_shaders::create_default_shaders(&args.shader_dir, args.template)?;
+        println!("Created {}", args.shader_dir.display());
+    }
+
+    let mut app = App::new(event_loop.create_proxy(), args);
     event_loop.run_app(&mut app)?;
-    Ok(())
+    match app.take_error() {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+/// Seconds as a point on the clock, negative times clamp to the start and
+/// anything past [`MAX_CLOCK_TIME`] to it.
+fn clock_time(secs: f32) -> Option<Duration> {
+    if !secs.is_finite() {
+        return None;
+    }
+    Some(Duration::from_secs_f32(secs.clamp(0., MAX_CLOCK_TIME)))
+}
+
+/// The sound pass is optional, a project opts in by providing [`SOUND_SHADER`].
+#[cfg(feature = "audio")]
+fn create_sound_pass(
+    device: &Arc<Device>,
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    shader_dir: &Path,
+) -> Option<pilka::SoundPass> {
+    let path = shader_dir.join(SOUND_SHADER);
+    if !path.is_file() {
+        return None;
+    }
+    pilka::SoundPass::new(device, pipeline_arena, texture_arena, path)
+        .map_err(|err| log::error!("Failed to create sound pass: {err}"))
+        .ok()
+}
+
+fn create_slices(
+    descs: &[SliceDesc],
+    shader_dir: &Path,
+    device: &Arc<Device>,
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    surface_format: vk::Format,
+) -> Result<Option<Slices>> {
+    if descs.is_empty() {
+        return Ok(None);
+    }
+    let slices = Slices::new(
+        descs,
+        shader_dir,
+        device,
+        pipeline_arena,
+        texture_arena,
+        surface_format,
+    )?;
+    Ok(Some(slices))
+}
+
+fn record_dimensions(extent: vk::Extent2D) -> ImageDimensions {
+    let width = align_to(extent.width as usize, 2);
+    let height = align_to(extent.height as usize, 2);
+    ImageDimensions::new(width, height, 1)
+}
+
+/// Sets the user push constant fields from the `[params]` of the manifest.
+fn apply_params(
+    push_constant: &mut PushConstant,
+    params: &[UserParam],
+    values: &HashMap<String, ParamValue>,
+) {
+    for (name, value) in values {
+        if let Err(err) = set_user_param(&mut push_constant.user, params, name, value.values()) {
+            log::warn!("[params] {err}");
+        }
+    }
+}
+
+fn folder_title(dir: &Path) -> String {
+    dir.canonicalize()
+        .ok()
+        .as_deref()
+        .and_then(Path::file_name)
+        .map_or_else(
+            || dir.display().to_string(),
+            |name| name.to_string_lossy().into_owned(),
+        )
+}
+
+/// Picks a monitor by index or by a part of its name, `None` leaves the
+/// choice to the window manager.
+fn select_monitor(
+    event_loop: &winit::event_loop::ActiveEventLoop,
+    query: Option<&str>,
+) -> Result<Option<MonitorHandle>> {
+    let Some(query) = query else {
+        return Ok(None);
+    };
+    let monitors: Vec<_> = event_loop.available_monitors().collect();
+    let found = match query.parse::<usize>() {
+        Ok(idx) => monitors.get(idx).cloned(),
+        Err(_) => monitors
+            .iter()
+            .find(|monitor| {
+                monitor
+                    .name()
+                    .is_some_and(|name| name.to_lowercase().contains(&query.to_lowercase()))
+            })
+            .cloned(),
+    };
+    if found.is_none() {
+        let available = monitors
+            .iter()
+            .enumerate()
+            .map(|(idx, monitor)| {
+                let name = monitor.name().unwrap_or_else(|| "unnamed".into());
+                format!("\n\t{idx}: {name}")
+            })
+            .collect::<String>();
+        bail!("Monitor `{query}` not found, available:{available}");
+    }
+    Ok(found)
+}
+
+fn output_window_attributes() -> WindowAttributes {
+    WindowAttributes::default().with_title(format!("{WINDOW_TITLE} | output"))
+}
+
+/// Prefers the largest resolution, then the highest refresh rate and depth.
+fn video_mode_rank(mode: &VideoModeHandle) -> (u32, u32, u16) {
+    let size = mode.size();
+    (
+        size.width * size.height,
+        mode.refresh_rate_millihertz(),
+        mode.bit_depth(),
+    )
 }
 
 struct App {
     proxy: EventLoopProxy<UserEvent>,
-    record_time: Option<Duration>,
-    initial_window_size: Option<(u32, u32)>,
+    args: Args,
     inner: AppEnum,
+    /// Startup failure, returned from `main`.
+    error: Option<anyhow::Error>,
 }
 
 impl App {
-    fn new(
-        proxy: EventLoopProxy<UserEvent>,
-        record_time: Option<Duration>,
-        inner_size: Option<(u32, u32)>,
-    ) -> Self {
+    fn new(proxy: EventLoopProxy<UserEvent>, args: Args) -> Self {
         Self {
             proxy,
-            record_time,
-            initial_window_size: inner_size,
+            args,
             inner: AppEnum::Uninitialized,
+            error: None,
+        }
+    }
+
+    /// The error that ended the event loop, if any.
+    fn take_error(&mut self) -> Option<anyhow::Error> {
+        match &mut self.inner {
+            AppEnum::Init(app) => app.error.take(),
+            AppEnum::Uninitialized => None,
         }
+        .or_else(|| self.error.take())
     }
 }
 
@@ -569,40 +2660,125 @@ impl App {
 enum AppEnum {
     #[default]
     Uninitialized,
-    Init(AppInit),
+    Init(Box<AppInit>),
 }
 
-impl ApplicationHandler<UserEvent> for App {
-    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        let mut window_attributes = WindowAttributes::default().with_title("myndgera");
-        if let Some(size) = self.initial_window_size {
+impl App {
+    fn window_attributes(
+        &self,
+        event_loop: &winit::event_loop::ActiveEventLoop,
+    ) -> WindowAttributes {
+        let mut window_attributes = WindowAttributes::default().with_title(WINDOW_TITLE);
+        let remember =
+            Manifest::load(&self.args.shader_dir).map_or(true, |manifest| manifest.window.remember);
+        if let Some(state) = WindowState::load(&self.args.shader_dir).filter(|_| remember) {
+            window_attributes = state.apply(window_attributes, event_loop.available_monitors());
+        }
+        if let Some(size) = self.args.inner_size {
             window_attributes = window_attributes
                 .with_resizable(false)
                 .with_inner_size(LogicalSize::<u32>::from(size));
         }
+        let monitor = select_monitor(event_loop, self.args.monitor.as_deref())
+            .map_err(|err| log::error!("{err}"))
+            .ok()
+            .flatten();
+        let origin = monitor.as_ref().map(MonitorHandle::position);
+        if let Some(position) = match (self.args.position, origin) {
+            (Some((x, y)), Some(origin)) => Some(PhysicalPosition::new(origin.x + x, origin.y + y)),
+            (Some((x, y)), None) => Some(PhysicalPosition::new(x, y)),
+            (None, origin) => origin,
+        } {
+            window_attributes = window_attributes.with_position(position);
+        }
+        if self.args.maximized {
+            window_attributes = window_attributes.with_maximized(true);
+        }
+        window_attributes = window_attributes.with_fullscreen(match self.args.fullscreen {
+            Some(FullscreenMode::Borderless) => Some(Fullscreen::Borderless(monitor)),
+            Some(FullscreenMode::Exclusive) => monitor
+                .or_else(|| event_loop.primary_monitor())
+                .and_then(|monitor| monitor.video_modes().max_by_key(video_mode_rank))
+                .map(Fullscreen::Exclusive),
+            None => None,
+        });
+        window_attributes
+    }
+
+    /// Replaces a renderer whose GPU device was lost with a new one, the
+    /// window comes back where it was.
+    fn restart(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        // The restarted renderer picks up where the lost one was
+        let mut playback = None;
+        if let AppEnum::Init(mut app) = std::mem::take(&mut self.inner) {
+            app.shut_down();
+            self.args.shader_dir = app.shader_dir.clone();
+            playback = Some((app.clock(), app.pause));
+        }
+        let window_attributes = self.window_attributes(event_loop);
+        match AppInit::new(
+            event_loop,
+            self.proxy.clone(),
+            window_attributes,
+            &self.args,
+        ) {
+            Ok(mut app) => {
+                if let Some((time, pause)) = playback {
+                    app.set_pause(pause);
+                    app.set_clock(time);
+                }
+                println!("// Renderer restarted after a lost device⏎ ");
+                self.inner = AppEnum::Init(Box::new(app));
+            }
+            Err(err) => {
+                self.error = Some(err.context("Failed to restart after a lost device"));
+                event_loop.exit();
+            }
+        }
+    }
+}
+
+impl ApplicationHandler<UserEvent> for App {
+    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        let window_attributes = self.window_attributes(event_loop);
         match self.inner {
             AppEnum::Uninitialized => {
-                let app = AppInit::new(
+                let app = match AppInit::new(
                     event_loop,
                     self.proxy.clone(),
                     window_attributes,
-                    self.record_time,
-                )
-                .expect("Failed to create application");
+                    &self.args,
+                ) {
+                    Ok(app) => app,
+                    Err(err) => {
+                        self.error = Some(err.context("Failed to create application"));
+                        event_loop.exit();
+                        return;
+                    }
+                };
 
                 println!("{}", app.device.get_info());
                 println!("{}", app.recorder.ffmpeg_version);
+                if let Err(err) = app.recorder.check_requirements() {
+                    println!("Video recording is unavailable: {err}");
+                }
                 println!(
-                    "Default shader path:\n\t{}",
-                    Path::new(SHADER_FOLDER).canonicalize().unwrap().display()
+                    "Shader path:\n\t{}",
+                    app.shader_dir.canonicalize().unwrap().display()
                 );
-                print_help();
+                if let Some(osc) = &app.osc_server {
+                    println!("Listening for OSC on port {}", osc.port);
+                }
+                if let Some(sync) = &app.sync {
+                    println!("{sync}");
+                }
+                print_help(&app.keys);
 
                 println!("// Set up our new world⏎ ");
                 println!("// And let's begin the⏎ ");
                 println!("\tSIMULATION⏎ \n");
 
-                self.inner = AppEnum::Init(app);
+                self.inner = AppEnum::Init(Box::new(app));
             }
             AppEnum::Init(_) => {}
         }
@@ -647,8 +2823,16 @@ impl ApplicationHandler<UserEvent> for App {
     }
 
     fn about_to_wait(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
-        if let AppEnum::Init(app) = &mut self.inner {
-            app.about_to_wait(event_loop)
+        match &mut self.inner {
+            AppEnum::Init(app) if app.device_lost => self.restart(event_loop),
+            AppEnum::Init(app) => {
+                app.about_to_wait(event_loop);
+                // A reload may hang the GPU, no frame should wait on it
+                if app.device_lost {
+                    self.restart(event_loop);
+                }
+            }
+            AppEnum::Uninitialized => {}
         }
     }
 
@@ -0,0 +1,115 @@
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{
+    Action, BufferDesc, CompileSettings, DataChannelDesc, DepthSettings, DmxSettings, HudSettings,
+    LayerDesc, MeshSettings, OverlayFlags, ParamValue, ParticleSettings, PostPass, RasterSettings,
+    SamplerSettings, ScreenshotSettings, SliceDesc, StreamDesc, SwapchainSettings, TableDesc,
+    TextureDesc, VideoSettings,
+};
+
+pub const MANIFEST_FILE: &str = "pilka.toml";
+
+/// Optional per-project settings, read from `pilka.toml` in the shader folder.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub window: WindowSettings,
+    /// Keys of actions replacing the default bindings, e.g. `screenshot = "p"`.
+    pub keys: HashMap<Action, String>,
+    /// PNGs loaded into the texture arena.
+    pub texture: Vec<TextureDesc>,
+    /// Zeroed buffers bound to the set 1 blocks of the same name.
+    pub buffer: Vec<BufferDesc>,
+    /// Filtering of the built-in linear and nearest samplers.
+    pub sampler: SamplerSettings,
+    /// Other projects blended over the image pass, bottom to top.
+    pub layer: Vec<LayerDesc>,
+    /// Built-in passes chained after the image pass, in order.
+    pub post: Vec<PostPass>,
+    /// Number of past frames kept in the history ring, `0` disables it.
+    pub history: u32,
+    /// Don't rerun `init.comp` when it or a shared include is edited, so a
+    /// running simulation survives tweaks to its shaders.
+    pub keep_state: bool,
+    /// Test draw rebuilt render pipelines before swapping them in.
+    pub validate_reloads: bool,
+    pub record: VideoSettings,
+    pub screenshot: ScreenshotSettings,
+    pub compile: CompileSettings,
+    /// CPU arrays uploaded to storage buffers every frame they change.
+    pub data: Vec<DataChannelDesc>,
+    /// Projector regions of the window, replacing the plain final copy.
+    pub slice: Vec<SliceDesc>,
+    /// Art-Net output of the `pc.resources.dmx` channels.
+    pub dmx: Option<DmxSettings>,
+    /// CSV and JSON files loaded into buffers and textures, reloaded on change.
+    pub table: Vec<TableDesc>,
+    /// Huge PNGs streamed tile by tile into a fixed size atlas.
+    pub stream: Vec<StreamDesc>,
+    /// Particle buffer drawn after the image pass.
+    pub particles: Option<ParticleSettings>,
+    /// OBJ or glTF mesh drawn after the image pass.
+    pub mesh: Option<MeshSettings>,
+    /// Depth test and write of the passes drawn into the scene.
+    pub depth: DepthSettings,
+    /// Polygon mode, culling and winding of the passes drawn into the scene.
+    pub raster: RasterSettings,
+    /// Samples per pixel of the scene, resolved after the passes drawn into it.
+    pub samples: Option<u32>,
+    pub hud: HudSettings,
+    pub swapchain: SwapchainSettings,
+    /// Values of the extra push constant fields by name, e.g. `zoom = 2.0`.
+    pub params: HashMap<String, ParamValue>,
+}
+
+/// Window setup from the `[window]` table, `--size` takes precedence.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowSettings {
+    /// Inner size in logical pixels.
+    pub size: Option<[u32; 2]>,
+    /// Save the window geometry on exit and restore it on the next launch.
+    pub remember: bool,
+    pub always_on_top: bool,
+    pub borderless: bool,
+    pub click_through: bool,
+    pub transparent: bool,
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        Self {
+            size: None,
+            remember: true,
+            always_on_top: false,
+            borderless: false,
+            click_through: false,
+            transparent: false,
+        }
+    }
+}
+
+impl WindowSettings {
+    pub fn overlay(&self) -> OverlayFlags {
+        OverlayFlags {
+            always_on_top: self.always_on_top,
+            borderless: self.borderless,
+            click_through: self.click_through,
+            transparent: self.transparent,
+        }
+    }
+}
+
+impl Manifest {
+    pub fn load(shader_dir: impl AsRef<Path>) -> Result<Self> {
+        let path = shader_dir.as_ref().join(MANIFEST_FILE);
+        if !path.is_file() {
+            return Ok(Self::default());
+        }
+        let text = std::fs::read_to_string(&path)?;
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+}
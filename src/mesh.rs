@@ -0,0 +1,587 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use either::Either;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, DepthDesc, Device, Error,
+    FragmentOutputDesc, FragmentShaderDesc, PipelineArena, PushConstant, RasterState, RenderHandle,
+    Resources, SceneAccel, TextureArena, VertexInputDesc, VertexShaderDesc,
+};
+
+/// Vertex attribute of a mesh, bound at the location of its position in
+/// [`MeshSettings::attributes`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MeshAttribute {
+    /// `vec3`
+    Position,
+    /// `vec3`, computed from the faces when the file has none.
+    Normal,
+    /// `vec2`, zero when the file has none.
+    Uv,
+    /// `vec4`, white when the file has none.
+    Color,
+}
+
+impl MeshAttribute {
+    fn format(self) -> vk::Format {
+        match self {
+            MeshAttribute::Position | MeshAttribute::Normal => vk::Format::R32G32B32_SFLOAT,
+            MeshAttribute::Uv => vk::Format::R32G32_SFLOAT,
+            MeshAttribute::Color => vk::Format::R32G32B32A32_SFLOAT,
+        }
+    }
+
+    fn floats(self, vertex: &Vertex) -> &[f32] {
+        match self {
+            MeshAttribute::Position => &vertex.position,
+            MeshAttribute::Normal => &vertex.normal,
+            MeshAttribute::Uv => &vertex.uv,
+            MeshAttribute::Color => &vertex.color,
+        }
+    }
+}
+
+/// The `[mesh]` table of the manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MeshSettings {
+    /// `.obj`, `.gltf` or `.glb` file relative to the shader folder.
+    pub path: PathBuf,
+    /// Interleaved vertex layout, has to match the inputs of `mesh.vert`.
+    pub attributes: Vec<MeshAttribute>,
+    /// Center the mesh and scale it into the -1 to 1 cube.
+    pub fit: bool,
+}
+
+impl Default for MeshSettings {
+    fn default() -> Self {
+        Self {
+            path: PathBuf::new(),
+            attributes: vec![
+                MeshAttribute::Position,
+                MeshAttribute::Normal,
+                MeshAttribute::Uv,
+            ],
+            fit: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Vertex {
+    position: [f32; 3],
+    /// Zero until computed for files without normals.
+    normal: [f32; 3],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+impl Default for Vertex {
+    fn default() -> Self {
+        Self {
+            position: [0.; 3],
+            normal: [0.; 3],
+            uv: [0.; 2],
+            color: [1.; 4],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct MeshData {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
+}
+
+impl MeshData {
+    fn load(path: &Path) -> Result<Self> {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_ascii_lowercase);
+        let mut data = match extension.as_deref() {
+            Some("obj") => load_obj(path)?,
+            Some("gltf" | "glb") => load_gltf(path)?,
+            _ => bail!(
+                "Unknown mesh format of {}, expected .obj, .gltf or .glb",
+                path.display()
+            ),
+        };
+        if data.indices.is_empty() {
+            bail!("{} has no triangles", path.display());
+        }
+        if data
+            .indices
+            .iter()
+            .any(|&index| index as usize >= data.vertices.len())
+        {
+            bail!("{} indexes past its vertices", path.display());
+        }
+        data.compute_normals();
+        Ok(data)
+    }
+
+    /// Smooth normals for the vertices the file left without one.
+    fn compute_normals(&mut self) {
+        let missing: Vec<_> = self.vertices.iter().map(|v| v.normal == [0.; 3]).collect();
+        if !missing.contains(&true) {
+            return;
+        }
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[triangle[i] as usize].position);
+            // Area weighted, the cross product is twice the triangle area
+            let normal = cross(sub(b, a), sub(c, a));
+            for &index in triangle {
+                if missing[index as usize] {
+                    let vertex = &mut self.vertices[index as usize];
+                    vertex.normal = add(vertex.normal, normal);
+                }
+            }
+        }
+        for (vertex, _) in self.vertices.iter_mut().zip(missing).filter(|(_, m)| *m) {
+            vertex.normal = normalize(vertex.normal);
+        }
+    }
+
+    fn fit(&mut self) {
+        let mut min = [f32::INFINITY; 3];
+        let mut max = [f32::NEG_INFINITY; 3];
+        for vertex in &self.vertices {
+            for i in 0..3 {
+                min[i] = min[i].min(vertex.position[i]);
+                max[i] = max[i].max(vertex.position[i]);
+            }
+        }
+        let size = (0..3).map(|i| max[i] - min[i]).fold(0., f32::max);
+        if !size.is_normal() {
+            return;
+        }
+        let center = [0, 1, 2].map(|i| (min[i] + max[i]) / 2.);
+        for vertex in &mut self.vertices {
+            vertex.position = [0, 1, 2].map(|i| (vertex.position[i] - center[i]) * 2. / size);
+        }
+    }
+
+    fn interleave(&self, attributes: &[MeshAttribute]) -> Vec<f32> {
+        self.vertices
+            .iter()
+            .flat_map(|vertex| {
+                attributes
+                    .iter()
+                    .flat_map(|attribute| attribute.floats(vertex))
+            })
+            .copied()
+            .collect()
+    }
+}
+
+fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let len = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    match len > 0. {
+        true => v.map(|x| x / len),
+        false => [0., 1., 0.],
+    }
+}
+
+/// Resolves a 1-based or negative relative OBJ index.
+fn obj_index(word: &str, len: usize) -> Result<usize> {
+    let index: i64 = word
+        .parse()
+        .with_context(|| format!("`{word}` is not an index"))?;
+    let resolved = match index {
+        1.. => index - 1,
+        ..=-1 => len as i64 + index,
+        0 => bail!("OBJ indices start at 1"),
+    };
+    if resolved < 0 || resolved as usize >= len {
+        bail!("Index {index} is out of range");
+    }
+    Ok(resolved as usize)
+}
+
+fn obj_floats<'a>(words: impl Iterator<Item = &'a str>, min: usize) -> Result<Vec<f32>> {
+    let floats = words
+        .map(|word| {
+            word.parse()
+                .with_context(|| format!("`{word}` is not a number"))
+        })
+        .collect::<Result<Vec<f32>>>()?;
+    if floats.len() < min {
+        bail!("Expected at least {min} numbers");
+    }
+    Ok(floats)
+}
+
+fn load_obj(path: &Path) -> Result<MeshData> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut positions: Vec<Vec<f32>> = vec![];
+    let mut uvs: Vec<Vec<f32>> = vec![];
+    let mut normals: Vec<Vec<f32>> = vec![];
+    let mut data = MeshData::default();
+    // Corners sharing position, uv and normal share a vertex
+    let mut corners = HashMap::new();
+    for (i, line) in source.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let parsed = match words.next() {
+            Some("v") => obj_floats(words, 3).map(|v| positions.push(v)),
+            Some("vt") => obj_floats(words, 2).map(|v| uvs.push(v)),
+            Some("vn") => obj_floats(words, 3).map(|v| normals.push(v)),
+            Some("f") => (|| {
+                let mut face = vec![];
+                for corner in words {
+                    let mut refs = corner.split('/');
+                    let position = obj_index(refs.next().unwrap_or_default(), positions.len())?;
+                    let uv = match refs.next() {
+                        Some("") | None => None,
+                        Some(word) => Some(obj_index(word, uvs.len())?),
+                    };
+                    let normal = match refs.next() {
+                        Some("") | None => None,
+                        Some(word) => Some(obj_index(word, normals.len())?),
+                    };
+                    let index = *corners.entry((position, uv, normal)).or_insert_with(|| {
+                        let p = &positions[position];
+                        data.vertices.push(Vertex {
+                            position: [p[0], p[1], p[2]],
+                            normal: normal.map_or([0.; 3], |n| {
+                                let n = &normals[n];
+                                [n[0], n[1], n[2]]
+                            }),
+                            uv: uv.map_or([0.; 2], |uv| [uvs[uv][0], uvs[uv][1]]),
+                            // Vertex colors some exporters append to positions
+                            color: match p.len() {
+                                6.. => [p[3], p[4], p[5], 1.],
+                                _ => [1.; 4],
+                            },
+                        });
+                        data.vertices.len() as u32 - 1
+                    });
+                    face.push(index);
+                }
+                if face.len() < 3 {
+                    bail!("A face needs 3 corners");
+                }
+                // Fan triangulation, fine for the convex polygons exporters write
+                for j in 1..face.len() - 1 {
+                    data.indices.extend([face[0], face[j], face[j + 1]]);
+                }
+                Ok(())
+            })(),
+            _ => Ok(()),
+        };
+        parsed.with_context(|| format!("{}:{}", path.display(), i + 1))?;
+    }
+    Ok(data)
+}
+
+type Mat4 = [[f32; 4]; 4];
+
+const IDENTITY: Mat4 = [
+    [1., 0., 0., 0.],
+    [0., 1., 0., 0.],
+    [0., 0., 1., 0.],
+    [0., 0., 0., 1.],
+];
+
+/// Product of column-major matrices.
+fn mul(a: &Mat4, b: &Mat4) -> Mat4 {
+    std::array::from_fn(|col| {
+        std::array::from_fn(|row| (0..4).map(|k| a[k][row] * b[col][k]).sum())
+    })
+}
+
+fn transform(m: &Mat4, v: [f32; 3], w: f32) -> [f32; 3] {
+    std::array::from_fn(|row| {
+        m[0][row] * v[0] + m[1][row] * v[1] + m[2][row] * v[2] + m[3][row] * w
+    })
+}
+
+fn load_gltf(path: &Path) -> Result<MeshData> {
+    let gltf =
+        gltf::Gltf::open(path).with_context(|| format!("Failed to load {}", path.display()))?;
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let buffers = gltf
+        .buffers()
+        .map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Bin => gltf
+                .blob
+                .clone()
+                .with_context(|| format!("{} has no binary chunk", path.display())),
+            gltf::buffer::Source::Uri(uri) if uri.starts_with("data:") => bail!(
+                "{} embeds its buffers as data URIs, export it as .glb or with a separate .bin",
+                path.display()
+            ),
+            gltf::buffer::Source::Uri(uri) => std::fs::read(dir.join(uri))
+                .with_context(|| format!("Failed to read {uri} of {}", path.display())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let scene = gltf
+        .default_scene()
+        .or_else(|| gltf.scenes().next())
+        .with_context(|| format!("{} has no scene", path.display()))?;
+    let mut data = MeshData::default();
+    let mut nodes: Vec<_> = scene.nodes().map(|node| (node, IDENTITY)).collect();
+    while let Some((node, parent)) = nodes.pop() {
+        let matrix = mul(&parent, &node.transform().matrix());
+        nodes.extend(node.children().map(|child| (child, matrix)));
+        let Some(mesh) = node.mesh() else {
+            continue;
+        };
+        for primitive in mesh.primitives() {
+            if primitive.mode() != gltf::mesh::Mode::Triangles {
+                continue;
+            }
+            let reader = primitive.reader(|buffer| buffers.get(buffer.index()).map(Vec::as_slice));
+            let Some(positions) = reader.read_positions() else {
+                continue;
+            };
+            let mut normals = reader.read_normals();
+            let mut uvs = reader.read_tex_coords(0).map(|uvs| uvs.into_f32());
+            let mut colors = reader.read_colors(0).map(|colors| colors.into_rgba_f32());
+            let base = data.vertices.len() as u32;
+            for position in positions {
+                data.vertices.push(Vertex {
+                    position: transform(&matrix, position, 1.),
+                    // Exact for rotations and uniform scales
+                    normal: normals
+                        .as_mut()
+                        .and_then(Iterator::next)
+                        .map_or([0.; 3], |n| normalize(transform(&matrix, n, 0.))),
+                    uv: uvs.as_mut().and_then(Iterator::next).unwrap_or_default(),
+                    color: colors.as_mut().and_then(Iterator::next).unwrap_or([1.; 4]),
+                });
+            }
+            let count = data.vertices.len() as u32 - base;
+            match reader.read_indices() {
+                Some(indices) => data
+                    .indices
+                    .extend(indices.into_u32().map(|index| base + index)),
+                None => data.indices.extend(base..base + count),
+            }
+        }
+    }
+    Ok(data)
+}
+
+struct MeshDraw {
+    vertices: HostBuffer,
+    indices: HostBuffer,
+    index_count: u32,
+    pipeline: RenderHandle,
+    /// Built when the device supports ray queries.
+    accel: Option<SceneAccel>,
+}
+
+/// A triangle mesh drawn by `mesh.vert` and `mesh.frag` over the image pass,
+/// depth tested and written in the scene depth buffer. Shaders can also ray
+/// query it through `pc.resources.scene` where the device supports that.
+#[derive(Default)]
+pub struct Mesh {
+    draw: Option<MeshDraw>,
+}
+
+impl Mesh {
+    /// Loads the mesh and recreates the buffers and the pipeline, writing the
+    /// default shaders into `shader_dir` if they are missing.
+    #[allow(clippy::too_many_arguments)]
+    pub fn configure(
+        &mut self,
+        settings: Option<&MeshSettings>,
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        color_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        depth: Option<DepthDesc>,
+        raster: RasterState,
+    ) -> Result<()> {
+        self.clear(pipeline_arena);
+        let Some(settings) = settings else {
+            return Ok(());
+        };
+        if depth.is_none() {
+            bail!("A mesh needs the scene depth buffer");
+        }
+        if settings.attributes.is_empty() {
+            bail!("A mesh needs at least one attribute");
+        }
+
+        let mut data = MeshData::load(&shader_dir.join(&settings.path))?;
+        if settings.fit {
+            data.fit();
+        }
+        let positions: Vec<_> = data.vertices.iter().map(|vertex| vertex.position).collect();
+        let accel = SceneAccel::new(device, queue, &positions, &data.indices)
+            .context("Failed to build the mesh acceleration structure")?;
+        let vertex_data = data.interleave(&settings.attributes);
+        let vertices = upload(
+            device,
+            bytemuck::cast_slice(&vertex_data),
+            vk::BufferUsageFlags::VERTEX_BUFFER,
+            "Mesh Vertices",
+        )?;
+        let indices = upload(
+            device,
+            bytemuck::cast_slice(&data.indices),
+            vk::BufferUsageFlags::INDEX_BUFFER,
+            "Mesh Indices",
+        )?;
+
+        let mut offset = 0;
+        let attributes = settings
+            .attributes
+            .iter()
+            .enumerate()
+            .map(|(location, attribute)| {
+                let description = vk::VertexInputAttributeDescription::default()
+                    .location(location as u32)
+                    .binding(0)
+                    .format(attribute.format())
+                    .offset(offset);
+                offset += attribute.floats(&Vertex::default()).len() as u32 * 4;
+                description
+            })
+            .collect();
+        let bindings = vec![vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .stride(offset)
+            .input_rate(vk::VertexInputRate::VERTEX)];
+
+        default_shaders::create_mesh_shaders(shader_dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<PushConstant>() as _)
+            .stage_flags(
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE,
+            );
+        let pipeline = pipeline_arena
+            .create_render_pipeline(
+                &VertexInputDesc {
+                    bindings,
+                    attributes,
+                    ..Default::default()
+                },
+                &raster.apply(VertexShaderDesc {
+                    shader_path: shader_dir.join("mesh.vert"),
+                    cull_mode: vk::CullModeFlags::NONE,
+                    ..Default::default()
+                }),
+                &FragmentShaderDesc {
+                    shader_path: shader_dir.join("mesh.frag"),
+                },
+                &FragmentOutputDesc {
+                    surface_format: color_format,
+                    multisample_state: samples,
+                    depth,
+                    ..Default::default()
+                },
+                &[push_constant_range],
+                &[texture_arena.images_set_layout],
+            )
+            .map_err(Error::pass("mesh"))?;
+
+        self.draw = Some(MeshDraw {
+            vertices,
+            indices,
+            index_count: data.indices.len() as u32,
+            pipeline,
+            accel,
+        });
+        Ok(())
+    }
+
+    pub fn pipeline(&self) -> Option<RenderHandle> {
+        self.draw.as_ref().map(|draw| draw.pipeline)
+    }
+
+    /// Fills `scene` of the resources block, zero without a mesh or ray
+    /// queries.
+    pub fn bind(&self, resources: &mut Resources) {
+        let accel = self.draw.as_ref().and_then(|draw| draw.accel.as_ref());
+        resources.scene = accel.map_or(0, |accel| accel.address);
+    }
+
+    pub fn clear(&mut self, pipeline_arena: &mut PipelineArena) {
+        if let Some(draw) = self.draw.take() {
+            pipeline_arena.remove_pipeline(Either::Left(draw.pipeline));
+        }
+    }
+
+    /// Draws the mesh into the current rendering.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        push_constant: &PushConstant,
+    ) {
+        let Some(draw) = &self.draw else {
+            return;
+        };
+        if pipeline_arena.is_quarantined(Either::Left(draw.pipeline)) {
+            return;
+        }
+        let pipeline = pipeline_arena.get_pipeline(draw.pipeline);
+        frame.push_constant(
+            pipeline.layout,
+            vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
+            &[*push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &pipeline.descriptor_sets(texture_arena.images_set),
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.bind_vertex_buffer(draw.vertices.buffer);
+        frame.bind_index_buffer(draw.indices.buffer, 0);
+        frame.draw_indexed(draw.index_count, 0, 0, 1, 0);
+    }
+}
+
+fn upload(
+    device: &Arc<Device>,
+    bytes: &[u8],
+    usage: vk::BufferUsageFlags,
+    name: &str,
+) -> Result<HostBuffer> {
+    let mut buffer = device.create_host_buffer(
+        bytes.len() as u64,
+        usage,
+        gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+    )?;
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    device.name_object(buffer.buffer, name);
+    Ok(buffer)
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use ash::vk;
+
+use crate::{swapchain::FrameGuard, Device, ManagedImage, COLOR_SUBRESOURCE_MASK};
+
+struct ColorTarget {
+    image: ManagedImage,
+    view: vk::ImageView,
+    device: Arc<Device>,
+}
+
+impl ColorTarget {
+    fn new(
+        device: &Arc<Device>,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        extent: vk::Extent2D,
+    ) -> Result<Self> {
+        let info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(
+                vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::TRANSIENT_ATTACHMENT,
+            );
+        let image = ManagedImage::new(device, &info, gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS)?;
+        device.name_object(image.image, "Scene Multisampled Color");
+        let view = unsafe {
+            device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .image(image.image)
+                    .format(format)
+                    .subresource_range(COLOR_SUBRESOURCE_MASK),
+                None,
+            )?
+        };
+        Ok(Self {
+            image,
+            view,
+            device: device.clone(),
+        })
+    }
+}
+
+impl Drop for ColorTarget {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_image_view(self.view, None) };
+    }
+}
+
+/// Multisampled color target the scene is drawn into and resolved from into
+/// the image pass output. Only allocated above one sample.
+pub struct SceneMsaa {
+    samples: vk::SampleCountFlags,
+    format: vk::Format,
+    target: Option<ColorTarget>,
+}
+
+impl Default for SceneMsaa {
+    fn default() -> Self {
+        Self {
+            samples: vk::SampleCountFlags::TYPE_1,
+            format: vk::Format::UNDEFINED,
+            target: None,
+        }
+    }
+}
+
+impl SceneMsaa {
+    /// `format` is the color format of the image pass output.
+    pub fn configure(
+        &mut self,
+        samples: u32,
+        device: &Arc<Device>,
+        format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let flags = sample_count(samples)?;
+        let limits = device.device_properties.limits;
+        let supported =
+            limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        if !supported.contains(flags) {
+            bail!("The GPU doesn't support {samples} samples per pixel");
+        }
+        self.samples = flags;
+        self.format = format;
+        self.target = match flags {
+            vk::SampleCountFlags::TYPE_1 => None,
+            _ => Some(ColorTarget::new(device, format, flags, extent)?),
+        };
+        Ok(())
+    }
+
+    pub fn resize(&mut self, device: &Arc<Device>, extent: vk::Extent2D) -> Result<()> {
+        if self.target.is_some() {
+            self.target = Some(ColorTarget::new(device, self.format, self.samples, extent)?);
+        }
+        Ok(())
+    }
+
+    /// Sample count of every pipeline drawn into the scene.
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    /// Readies the target for the scene rendering and returns its view.
+    pub fn begin(&self, frame: &FrameGuard, device: &Device) -> Option<vk::ImageView> {
+        let target = self.target.as_ref()?;
+        let barrier = vk::ImageMemoryBarrier2::default()
+            .subresource_range(COLOR_SUBRESOURCE_MASK)
+            .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(
+                vk::AccessFlags2::COLOR_ATTACHMENT_READ | vk::AccessFlags2::COLOR_ATTACHMENT_WRITE,
+            )
+            .old_layout(vk::ImageLayout::UNDEFINED)
+            .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .image(target.image.image);
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                *frame.command_buffer(),
+                &vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&barrier)),
+            )
+        };
+        Some(target.view)
+    }
+}
+
+fn sample_count(samples: u32) -> Result<vk::SampleCountFlags> {
+    Ok(match samples {
+        1 => vk::SampleCountFlags::TYPE_1,
+        2 => vk::SampleCountFlags::TYPE_2,
+        4 => vk::SampleCountFlags::TYPE_4,
+        8 => vk::SampleCountFlags::TYPE_8,
+        16 => vk::SampleCountFlags::TYPE_16,
+        _ => bail!("`samples` has to be 1, 2, 4, 8 or 16, not {samples}"),
+    })
+}
@@ -0,0 +1,405 @@
+use anyhow::{bail, Context, Result};
+use std::{net::UdpSocket, path::PathBuf, thread::JoinHandle};
+use winit::event_loop::EventLoopProxy;
+
+use crate::{LayerBlend, UserEvent};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum OscArg {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    Bool(bool),
+}
+
+impl OscArg {
+    pub fn as_f32(&self) -> Option<f32> {
+        match *self {
+            OscArg::Int(i) => Some(i as f32),
+            OscArg::Float(f) => Some(f),
+            OscArg::Bool(b) => Some(b as u32 as f32),
+            OscArg::Str(_) => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        self.as_f32().map(|v| v > 0.5)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OscMessage {
+    pub address: String,
+    pub args: Vec<OscArg>,
+}
+
+/// Text form used by macro files, floats keep their decimal point and
+/// strings are quoted.
+impl std::fmt::Display for OscMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.address)?;
+        for arg in &self.args {
+            match arg {
+                OscArg::Int(i) => write!(f, " {i}")?,
+                OscArg::Float(x) => write!(f, " {x:?}")?,
+                OscArg::Str(s) => write!(f, " \"{s}\"")?,
+                OscArg::Bool(b) => write!(f, " {b}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum OscCommand {
+    Pause(Option<bool>),
+    Screenshot,
+    ScreenshotExr,
+    ConvergedScreenshot(Option<u32>),
+    Clear,
+    Init,
+    Seek(f32),
+    Loop(Option<[f32; 2]>),
+    LayerOpacity(usize, f32),
+    LayerBlend(usize, LayerBlend),
+    LayerPause(usize, Option<bool>),
+    LayerSpeed(usize, f32),
+    LayerLoop(usize, Option<[f32; 2]>),
+    Position([f32; 3]),
+    Mouse([f32; 2]),
+    MousePressed(bool),
+    Param(String, Vec<f32>),
+    ShaderFolder(PathBuf),
+}
+
+impl OscCommand {
+    pub fn from_message(msg: &OscMessage) -> Result<Self> {
+        let floats = || -> Vec<f32> { msg.args.iter().filter_map(OscArg::as_f32).collect() };
+        let command = match msg.address.as_str() {
+            "/pilka/pause" => OscCommand::Pause(msg.args.first().and_then(OscArg::as_bool)),
+            "/pilka/screenshot" => OscCommand::Screenshot,
+            "/pilka/screenshot_exr" => OscCommand::ScreenshotExr,
+            "/pilka/screenshot_converged" => OscCommand::ConvergedScreenshot(
+                msg.args
+                    .first()
+                    .and_then(OscArg::as_f32)
+                    .map(|samples| samples as u32),
+            ),
+            "/pilka/clear" => OscCommand::Clear,
+            "/pilka/init" => OscCommand::Init,
+            "/pilka/time" => OscCommand::Seek(
+                msg.args
+                    .first()
+                    .and_then(OscArg::as_f32)
+                    .context("`/pilka/time` expects seconds")?,
+            ),
+            "/pilka/loop" => match floats()[..] {
+                [start, end] => OscCommand::Loop(Some([start, end])),
+                [] => OscCommand::Loop(None),
+                _ => bail!("`/pilka/loop` expects start and end seconds or nothing"),
+            },
+            "/pilka/layer/opacity" => match floats()[..] {
+                [layer, opacity] => OscCommand::LayerOpacity(layer as usize, opacity),
+                _ => bail!("`/pilka/layer/opacity` expects a layer index and an opacity"),
+            },
+            "/pilka/layer/blend" => match &msg.args[..] {
+                [layer, OscArg::Str(blend)] => OscCommand::LayerBlend(
+                    layer
+                        .as_f32()
+                        .context("`/pilka/layer/blend` expects a layer index")?
+                        as usize,
+                    blend.parse()?,
+                ),
+                _ => bail!("`/pilka/layer/blend` expects a layer index and a blend mode"),
+            },
+            "/pilka/layer/pause" => match &msg.args[..] {
+                [layer, pause @ ..] => OscCommand::LayerPause(
+                    layer
+                        .as_f32()
+                        .context("`/pilka/layer/pause` expects a layer index")?
+                        as usize,
+                    pause.first().and_then(OscArg::as_bool),
+                ),
+                [] => bail!("`/pilka/layer/pause` expects a layer index"),
+            },
+            "/pilka/layer/speed" => match floats()[..] {
+                [layer, speed] => OscCommand::LayerSpeed(layer as usize, speed),
+                _ => bail!("`/pilka/layer/speed` expects a layer index and a speed"),
+            },
+            "/pilka/layer/loop" => match floats()[..] {
+                [layer, start, end] => OscCommand::LayerLoop(layer as usize, Some([start, end])),
+                [layer] => OscCommand::LayerLoop(layer as usize, None),
+                _ => {
+                    bail!("`/pilka/layer/loop` expects a layer index and optionally start and end")
+                }
+            },
+            "/pilka/pos" => match floats()[..] {
+                [x, y, z] => OscCommand::Position([x, y, z]),
+                _ => bail!("`/pilka/pos` expects 3 numbers"),
+            },
+            "/pilka/mouse" => match floats()[..] {
+                [x, y] => OscCommand::Mouse([x, y]),
+                _ => bail!("`/pilka/mouse` expects 2 numbers"),
+            },
+            "/pilka/mouse_pressed" => OscCommand::MousePressed(
+                msg.args
+                    .first()
+                    .and_then(OscArg::as_bool)
+                    .context("`/pilka/mouse_pressed` expects a value")?,
+            ),
+            "/pilka/param" => match &msg.args[..] {
+                [OscArg::Str(name), values @ ..] if !values.is_empty() => OscCommand::Param(
+                    name.clone(),
+                    values.iter().filter_map(OscArg::as_f32).collect(),
+                ),
+                _ => bail!("`/pilka/param` expects a field name and its values"),
+            },
+            "/pilka/folder" => match msg.args.first() {
+                Some(OscArg::Str(path)) => OscCommand::ShaderFolder(path.into()),
+                _ => bail!("`/pilka/folder` expects a path string"),
+            },
+            address => bail!("Unknown OSC address: {address}"),
+        };
+        Ok(command)
+    }
+
+    /// The message `from_message` turns back into this command.
+    pub fn to_message(&self) -> OscMessage {
+        let (address, args) = match self {
+            OscCommand::Pause(pause) => ("pause", pause.map(OscArg::Bool).into_iter().collect()),
+            OscCommand::Screenshot => ("screenshot", vec![]),
+            OscCommand::ScreenshotExr => ("screenshot_exr", vec![]),
+            OscCommand::ConvergedScreenshot(samples) => (
+                "screenshot_converged",
+                samples.map(|s| OscArg::Int(s as i32)).into_iter().collect(),
+            ),
+            OscCommand::Clear => ("clear", vec![]),
+            OscCommand::Init => ("init", vec![]),
+            OscCommand::Seek(time) => ("time", vec![OscArg::Float(*time)]),
+            OscCommand::Loop(region) => (
+                "loop",
+                region.map_or(vec![], |r| r.map(OscArg::Float).to_vec()),
+            ),
+            OscCommand::LayerOpacity(layer, opacity) => (
+                "layer/opacity",
+                vec![OscArg::Int(*layer as i32), OscArg::Float(*opacity)],
+            ),
+            OscCommand::LayerBlend(layer, blend) => (
+                "layer/blend",
+                vec![OscArg::Int(*layer as i32), OscArg::Str(blend.to_string())],
+            ),
+            OscCommand::LayerPause(layer, pause) => (
+                "layer/pause",
+                std::iter::once(OscArg::Int(*layer as i32))
+                    .chain(pause.map(OscArg::Bool))
+                    .collect(),
+            ),
+            OscCommand::LayerSpeed(layer, speed) => (
+                "layer/speed",
+                vec![OscArg::Int(*layer as i32), OscArg::Float(*speed)],
+            ),
+            OscCommand::LayerLoop(layer, region) => (
+                "layer/loop",
+                std::iter::once(OscArg::Int(*layer as i32))
+                    .chain(region.iter().flatten().copied().map(OscArg::Float))
+                    .collect(),
+            ),
+            OscCommand::Position(pos) => ("pos", pos.map(OscArg::Float).to_vec()),
+            OscCommand::Mouse(mouse) => ("mouse", mouse.map(OscArg::Float).to_vec()),
+            OscCommand::MousePressed(pressed) => ("mouse_pressed", vec![OscArg::Bool(*pressed)]),
+            OscCommand::Param(name, values) => (
+                "param",
+                std::iter::once(OscArg::Str(name.clone()))
+                    .chain(values.iter().copied().map(OscArg::Float))
+                    .collect(),
+            ),
+            OscCommand::ShaderFolder(dir) => (
+                "folder",
+                vec![OscArg::Str(dir.to_string_lossy().into_owned())],
+            ),
+        };
+        OscMessage {
+            address: format!("/pilka/{address}"),
+            args,
+        }
+    }
+}
+
+pub struct OscServer {
+    pub port: u16,
+    _thread_handle: JoinHandle<()>,
+}
+
+impl OscServer {
+    pub fn new(port: u16, proxy: EventLoopProxy<UserEvent>) -> Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", port))
+            .with_context(|| format!("Failed to bind OSC socket on port {port}"))?;
+        let thread_handle = std::thread::spawn(move || osc_thread(socket, proxy));
+        Ok(Self {
+            port,
+            _thread_handle: thread_handle,
+        })
+    }
+}
+
+fn osc_thread(socket: UdpSocket, proxy: EventLoopProxy<UserEvent>) {
+    let mut buf = [0u8; 4096];
+    loop {
+        let len = match socket.recv(&mut buf) {
+            Ok(len) => len,
+            Err(err) => {
+                log::error!("OSC socket error: {err}");
+                return;
+            }
+        };
+        let messages = match decode_packet(&buf[..len]) {
+            Ok(messages) => messages,
+            Err(err) => {
+                log::warn!("Malformed OSC packet: {err}");
+                continue;
+            }
+        };
+        for msg in messages {
+            match OscCommand::from_message(&msg) {
+                Ok(command) => {
+                    if proxy.send_event(UserEvent::Osc(command)).is_err() {
+                        return;
+                    }
+                }
+                Err(err) => log::warn!("{err}"),
+            }
+        }
+    }
+}
+
+pub fn decode_packet(data: &[u8]) -> Result<Vec<OscMessage>> {
+    let mut messages = vec![];
+    decode_into(data, &mut messages)?;
+    Ok(messages)
+}
+
+fn decode_into(data: &[u8], out: &mut Vec<OscMessage>) -> Result<()> {
+    if data.starts_with(b"#bundle\0") {
+        // Skip the bundle tag and the 8-byte timetag, elements are executed immediately
+        let mut rest = data.get(16..).context("Truncated OSC bundle")?;
+        while !rest.is_empty() {
+            let (size, tail) = read_i32(rest)?;
+            let size = size as usize;
+            let element = tail.get(..size).context("Truncated OSC bundle element")?;
+            decode_into(element, out)?;
+            rest = &tail[size..];
+        }
+        return Ok(());
+    }
+
+    let (address, rest) = read_string(data)?;
+    if !address.starts_with('/') {
+        bail!("OSC address must start with '/'");
+    }
+    let (tags, mut rest) = match read_string(rest) {
+        Ok((tags, rest)) if tags.starts_with(',') => (tags, rest),
+        _ => (String::from(","), &[][..]),
+    };
+
+    let mut args = vec![];
+    for tag in tags.chars().skip(1) {
+        let arg = match tag {
+            'i' => {
+                let (v, tail) = read_i32(rest)?;
+                rest = tail;
+                OscArg::Int(v)
+            }
+            'f' => {
+                let (v, tail) = read_i32(rest)?;
+                rest = tail;
+                OscArg::Float(f32::from_bits(v as u32))
+            }
+            's' | 'S' => {
+                let (v, tail) = read_string(rest)?;
+                rest = tail;
+                OscArg::Str(v)
+            }
+            'T' => OscArg::Bool(true),
+            'F' => OscArg::Bool(false),
+            'N' | 'I' => continue,
+            'h' | 't' | 'd' => {
+                let bytes = rest.get(..8).context("Truncated OSC argument")?;
+                let v = u64::from_be_bytes(bytes.try_into().unwrap());
+                rest = &rest[8..];
+                match tag {
+                    'd' => OscArg::Float(f64::from_bits(v) as f32),
+                    _ => OscArg::Int(v as i64 as i32),
+                }
+            }
+            'b' => {
+                let (size, tail) = read_i32(rest)?;
+                let size = usize::try_from(size).context("Negative OSC blob size")?;
+                let padded = size.next_multiple_of(4);
+                rest = tail.get(padded..).context("Truncated OSC blob")?;
+                continue;
+            }
+            _ => bail!("Unsupported OSC type tag: {tag}"),
+        };
+        args.push(arg);
+    }
+
+    out.push(OscMessage { address, args });
+    Ok(())
+}
+
+/// Packs `messages` into a bundle executed immediately on arrival.
+pub fn encode_bundle(messages: &[OscMessage]) -> Vec<u8> {
+    let mut out = b"#bundle\0".to_vec();
+    out.extend_from_slice(&1u64.to_be_bytes());
+    for msg in messages {
+        let element = encode_message(msg);
+        out.extend_from_slice(&(element.len() as i32).to_be_bytes());
+        out.extend_from_slice(&element);
+    }
+    out
+}
+
+pub fn encode_message(msg: &OscMessage) -> Vec<u8> {
+    let mut out = vec![];
+    write_string(&mut out, &msg.address);
+    let tags: String = std::iter::once(',')
+        .chain(msg.args.iter().map(|arg| match arg {
+            OscArg::Int(_) => 'i',
+            OscArg::Float(_) => 'f',
+            OscArg::Str(_) => 's',
+            OscArg::Bool(true) => 'T',
+            OscArg::Bool(false) => 'F',
+        }))
+        .collect();
+    write_string(&mut out, &tags);
+    for arg in &msg.args {
+        match arg {
+            OscArg::Int(v) => out.extend_from_slice(&v.to_be_bytes()),
+            OscArg::Float(v) => out.extend_from_slice(&v.to_be_bytes()),
+            OscArg::Str(v) => write_string(&mut out, v),
+            OscArg::Bool(_) => {}
+        }
+    }
+    out
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(s.as_bytes());
+    let padded = (s.len() + 1).next_multiple_of(4);
+    out.resize(out.len() + padded - s.len(), 0);
+}
+
+fn read_i32(data: &[u8]) -> Result<(i32, &[u8])> {
+    let bytes = data.get(..4).context("Truncated OSC argument")?;
+    Ok((i32::from_be_bytes(bytes.try_into().unwrap()), &data[4..]))
+}
+
+fn read_string(data: &[u8]) -> Result<(String, &[u8])> {
+    let len = data
+        .iter()
+        .position(|&b| b == 0)
+        .context("Unterminated OSC string")?;
+    let s = std::str::from_utf8(&data[..len])?.to_string();
+    let padded = (len + 1).next_multiple_of(4);
+    Ok((s, data.get(padded..).unwrap_or_default()))
+}
@@ -0,0 +1,137 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Result;
+use ash::{khr, vk};
+use either::Either;
+use winit::{
+    event_loop::ActiveEventLoop,
+    window::{Window, WindowAttributes, WindowId},
+};
+
+use crate::{
+    default_shaders, letterbox, post, Device, FragmentOutputDesc, FragmentShaderDesc, Instance,
+    PipelineArena, PostPushConstant, RenderHandle, Surface, Swapchain, SwapchainSettings,
+    TextureArena, VertexInputDesc, VertexShaderDesc, POST_FOLDER,
+};
+
+/// A second window showing the clean output copy, e.g. on a projector while
+/// the main window keeps the overlays. It has its own surface and swapchain
+/// and is presented right after the main one.
+pub struct OutputWindow {
+    swapchain: Swapchain,
+    surface: Surface,
+    window: Window,
+    pipeline: RenderHandle,
+}
+
+impl OutputWindow {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        event_loop: &ActiveEventLoop,
+        attributes: WindowAttributes,
+        instance: &Instance,
+        device: &Arc<Device>,
+        settings: &SwapchainSettings,
+        shader_dir: &Path,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+    ) -> Result<Self> {
+        let window = event_loop.create_window(attributes)?;
+        let surface = instance.create_surface(&window)?;
+        if !surface.get_device_surface_support(device.physical_device, device.main_queue_family_idx)
+        {
+            anyhow::bail!("The GPU can't present to the output window");
+        }
+        let loader = khr::swapchain::Device::new(instance, device);
+        let swapchain = Swapchain::new(device, &surface, loader, settings, false)?;
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<PostPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let pipeline = pipeline_arena.create_render_pipeline(
+            &VertexInputDesc::default(),
+            &VertexShaderDesc {
+                shader_path: dir.join("post.vert"),
+                ..Default::default()
+            },
+            &FragmentShaderDesc {
+                shader_path: dir.join("present.frag"),
+            },
+            &FragmentOutputDesc {
+                surface_format: swapchain.format(),
+                ..Default::default()
+            },
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+
+        Ok(Self {
+            swapchain,
+            surface,
+            window,
+            pipeline,
+        })
+    }
+
+    pub fn id(&self) -> WindowId {
+        self.window.id()
+    }
+
+    /// Draws texture `src` of `extent` letterboxed into the window.
+    pub fn present(
+        &mut self,
+        device: &Device,
+        queue: &vk::Queue,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        src: usize,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let mut frame = match self.swapchain.acquire_next_image() {
+            Ok(frame) => frame,
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => return self.recreate(device),
+            Err(err) => return Err(err.into()),
+        };
+        let window = frame.extent();
+        let push_constant = PostPushConstant {
+            resolution: [window.width as f32, window.height as f32],
+            src: src as u32,
+            ..Default::default()
+        };
+        frame.begin_rendering(self.swapchain.get_current_image_view(), [0., 0., 0., 1.]);
+        frame.set_area(letterbox(extent, window));
+        post::draw(
+            &mut frame,
+            pipeline_arena,
+            texture_arena,
+            self.pipeline,
+            push_constant,
+        );
+        frame.end_rendering();
+        match self.swapchain.submit_image(queue, frame) {
+            Ok(()) => Ok(()),
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.recreate(device),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn recreate(&mut self, device: &Device) -> Result<()> {
+        if let Some(frame) = self.swapchain.get_current_frame() {
+            let fences = std::slice::from_ref(&frame.present_finished);
+            unsafe { device.wait_for_fences(fences, true, u64::MAX)? };
+        }
+        self.swapchain.recreate(device, &self.surface)?;
+        Ok(())
+    }
+
+    /// Waits for the presents in flight, the window closes when dropped.
+    pub fn close(self, device: &Device, pipeline_arena: &mut PipelineArena) {
+        if let Some(frame) = self.swapchain.get_current_frame() {
+            let fences = std::slice::from_ref(&frame.present_finished);
+            let _ = unsafe { device.wait_for_fences(fences, true, u64::MAX) };
+        }
+        pipeline_arena.remove_pipeline(Either::Left(self.pipeline));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{fs::File, path::Path, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+
+use crate::{
+    default_shaders, post, swapchain::FrameGuard, BlendMode, Device, FragmentOutputDesc,
+    FragmentShaderDesc, PipelineArena, PostPushConstant, RenderHandle, TextureArena,
+    VertexInputDesc, VertexShaderDesc, POST_FOLDER,
+};
+
+const OPACITY_STEP: f32 = 0.1;
+/// Frame aspect ratios the guides cycle through, `0` follows the window.
+const GUIDE_ASPECTS: [(&str, f32); 6] = [
+    ("window", 0.),
+    ("16:9", 16. / 9.),
+    ("2.39:1", 2.39),
+    ("4:3", 4. / 3.),
+    ("1:1", 1.),
+    ("9:16", 9. / 16.),
+];
+
+/// Reference image blended over the presented frame. It is drawn after the
+/// feedback copies, so neither shaders nor exports see it.
+pub struct ReferenceOverlay {
+    pub opacity: f32,
+    texture: u32,
+    pipeline: RenderHandle,
+}
+
+impl ReferenceOverlay {
+    pub fn new(
+        path: &Path,
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &mut TextureArena,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let (width, height, pixels) = load_png(path)
+            .with_context(|| format!("Failed to load reference image {}", path.display()))?;
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width,
+                height,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(vk::Format::R8G8B8A8_SRGB)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(1)
+            .tiling(vk::ImageTiling::OPTIMAL);
+        let texture = texture_arena.push_image(device, queue, info, &pixels)?;
+        device.name_object(
+            texture_arena.images[texture as usize].image,
+            "Reference Image",
+        );
+
+        let pipeline = create_pipeline(
+            shader_dir,
+            "overlay.frag",
+            pipeline_arena,
+            texture_arena,
+            surface_format,
+        )?;
+
+        Ok(Self {
+            opacity: 0.5,
+            texture,
+            pipeline,
+        })
+    }
+
+    pub fn adjust_opacity(&mut self, steps: f32) {
+        self.opacity = (self.opacity + steps * OPACITY_STEP).clamp(0., 1.);
+        println!("Reference opacity: {:.0}%", self.opacity * 100.);
+    }
+
+    /// Blends the reference image over whatever is already in `view`.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        if self.opacity <= 0. {
+            return;
+        }
+        let push_constant = PostPushConstant {
+            resolution: [extent.width as f32, extent.height as f32],
+            src: self.texture,
+            strength: self.opacity,
+            ..Default::default()
+        };
+        frame.resume_rendering(view);
+        post::draw(
+            frame,
+            pipeline_arena,
+            texture_arena,
+            self.pipeline,
+            push_constant,
+        );
+        frame.end_rendering();
+    }
+}
+
+/// Rule of thirds, center cross and title/action safe areas inside a frame
+/// of the chosen aspect ratio. Drawn after the exported copy of the frame.
+pub struct Guides {
+    pub enabled: bool,
+    aspect: usize,
+    pipeline: RenderHandle,
+}
+
+impl Guides {
+    pub fn new(
+        shader_dir: &Path,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let pipeline = create_pipeline(
+            shader_dir,
+            "guides.frag",
+            pipeline_arena,
+            texture_arena,
+            surface_format,
+        )?;
+        Ok(Self {
+            enabled: false,
+            aspect: 0,
+            pipeline,
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+        let state = if self.enabled { "on" } else { "off" };
+        println!("Guides: {state}");
+    }
+
+    pub fn next_aspect(&mut self) {
+        self.aspect = (self.aspect + 1) % GUIDE_ASPECTS.len();
+        println!("Guides aspect: {}", GUIDE_ASPECTS[self.aspect].0);
+    }
+
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let push_constant = PostPushConstant {
+            resolution: [extent.width as f32, extent.height as f32],
+            strength: GUIDE_ASPECTS[self.aspect].1,
+            ..Default::default()
+        };
+        frame.resume_rendering(view);
+        post::draw(
+            frame,
+            pipeline_arena,
+            texture_arena,
+            self.pipeline,
+            push_constant,
+        );
+        frame.end_rendering();
+    }
+}
+
+/// Fullscreen pass blended over the swapchain image, sharing the post
+/// vertex shader and push constants.
+fn create_pipeline(
+    shader_dir: &Path,
+    fragment: &str,
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    surface_format: vk::Format,
+) -> Result<RenderHandle> {
+    let dir = shader_dir.join(POST_FOLDER);
+    default_shaders::create_post_shaders(&dir)?;
+    let push_constant_range = vk::PushConstantRange::default()
+        .size(size_of::<PostPushConstant>() as _)
+        .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+    pipeline_arena.create_render_pipeline(
+        &VertexInputDesc::default(),
+        &VertexShaderDesc {
+            shader_path: dir.join("post.vert"),
+            ..Default::default()
+        },
+        &FragmentShaderDesc {
+            shader_path: dir.join(fragment),
+        },
+        &FragmentOutputDesc {
+            surface_format,
+            blend: BlendMode::Alpha,
+            ..Default::default()
+        },
+        &[push_constant_range],
+        &[texture_arena.images_set_layout],
+    )
+}
+
+/// Decodes any PNG into 8-bit RGBA.
+pub(crate) fn load_png(path: &Path) -> Result<(u32, u32, Vec<u8>)> {
+    let mut decoder = png::Decoder::new(File::open(path)?);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info()?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf)?;
+    buf.truncate(info.buffer_size());
+    let pixels = expand_to_rgba(info.color_type, &buf)?;
+    Ok((info.width, info.height, pixels))
+}
+
+/// Widens pixels decoded with `normalize_to_color8` to RGBA.
+pub(crate) fn expand_to_rgba(color_type: png::ColorType, buf: &[u8]) -> Result<Vec<u8>> {
+    Ok(match color_type {
+        png::ColorType::Rgba => buf.to_vec(),
+        png::ColorType::Rgb => buf
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => buf
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+        png::ColorType::Indexed => bail!("Indexed PNG wasn't expanded"),
+    })
+}
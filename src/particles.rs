@@ -0,0 +1,199 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use ash::vk;
+use either::Either;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, DepthDesc, Device, Error,
+    FragmentOutputDesc, FragmentShaderDesc, LayerBlend, PipelineArena, PushConstant, RasterState,
+    RenderHandle, Resources, TextureArena, VertexInputDesc, VertexShaderDesc,
+};
+
+/// Primitives the particle vertices are assembled into.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Topology {
+    #[default]
+    Points,
+    Lines,
+    Triangles,
+}
+
+impl Topology {
+    fn primitive_topology(self) -> vk::PrimitiveTopology {
+        match self {
+            Topology::Points => vk::PrimitiveTopology::POINT_LIST,
+            Topology::Lines => vk::PrimitiveTopology::LINE_LIST,
+            Topology::Triangles => vk::PrimitiveTopology::TRIANGLE_LIST,
+        }
+    }
+}
+
+/// The `[particles]` table of the manifest.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ParticleSettings {
+    pub count: u32,
+    /// Bytes per particle in the buffer, a `vec4` by default.
+    pub stride: u32,
+    /// Vertices drawn per particle, e.g. 6 for a quad of two triangles.
+    pub vertices: u32,
+    pub topology: Topology,
+    pub blend: LayerBlend,
+}
+
+impl Default for ParticleSettings {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            stride: 16,
+            vertices: 1,
+            topology: Topology::Points,
+            blend: LayerBlend::Add,
+        }
+    }
+}
+
+struct ParticleDraw {
+    settings: ParticleSettings,
+    buffer: HostBuffer,
+    pipeline: RenderHandle,
+}
+
+/// A buffer of particles the compute shaders write through
+/// `pc.resources.particles`, drawn by `particles.vert` and `particles.frag`
+/// after the image pass with `count * vertices` vertices.
+#[derive(Default)]
+pub struct Particles {
+    draw: Option<ParticleDraw>,
+}
+
+impl Particles {
+    /// Recreates the buffer zeroed and the pipeline, writing the default
+    /// shaders into `shader_dir` if they are missing.
+    #[allow(clippy::too_many_arguments)]
+    pub fn configure(
+        &mut self,
+        settings: Option<&ParticleSettings>,
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        color_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        depth: Option<DepthDesc>,
+        raster: RasterState,
+    ) -> Result<()> {
+        self.clear(pipeline_arena);
+        let Some(settings) = settings.filter(|s| s.count > 0) else {
+            return Ok(());
+        };
+        if settings.stride == 0 || settings.stride % 4 != 0 {
+            bail!("Particle stride has to be a positive multiple of 4 bytes");
+        }
+        if settings.vertices == 0 {
+            bail!("Particles need at least one vertex");
+        }
+
+        let size = settings.count as u64 * settings.stride as u64;
+        let mut buffer = device.create_host_buffer(
+            size,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        buffer.fill(0);
+        device.name_object(buffer.buffer, "Particles");
+
+        default_shaders::create_particle_shaders(shader_dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<PushConstant>() as _)
+            .stage_flags(
+                vk::ShaderStageFlags::VERTEX
+                    | vk::ShaderStageFlags::FRAGMENT
+                    | vk::ShaderStageFlags::COMPUTE,
+            );
+        let pipeline = pipeline_arena
+            .create_render_pipeline(
+                &VertexInputDesc {
+                    primitive_topology: settings.topology.primitive_topology(),
+                    ..Default::default()
+                },
+                &raster.apply(VertexShaderDesc {
+                    shader_path: shader_dir.join("particles.vert"),
+                    cull_mode: vk::CullModeFlags::NONE,
+                    ..Default::default()
+                }),
+                &FragmentShaderDesc {
+                    shader_path: shader_dir.join("particles.frag"),
+                },
+                &FragmentOutputDesc {
+                    surface_format: color_format,
+                    multisample_state: samples,
+                    blend: settings.blend.blend_mode(),
+                    depth,
+                },
+                &[push_constant_range],
+                &[texture_arena.images_set_layout],
+            )
+            .map_err(Error::pass("particles"))?;
+
+        self.draw = Some(ParticleDraw {
+            settings: settings.clone(),
+            buffer,
+            pipeline,
+        });
+        Ok(())
+    }
+
+    pub fn pipeline(&self) -> Option<RenderHandle> {
+        self.draw.as_ref().map(|draw| draw.pipeline)
+    }
+
+    pub fn clear(&mut self, pipeline_arena: &mut PipelineArena) {
+        if let Some(draw) = self.draw.take() {
+            pipeline_arena.remove_pipeline(Either::Left(draw.pipeline));
+        }
+    }
+
+    /// Fills the particle fields of the resources block.
+    pub fn bind(&self, resources: &mut Resources) {
+        let draw = self.draw.as_ref();
+        resources.particles = draw.map_or(0, |draw| draw.buffer.address);
+        resources.particle_count = draw.map_or(0, |draw| draw.settings.count);
+        resources.particle_vertices = draw.map_or(0, |draw| draw.settings.vertices);
+    }
+
+    /// Draws the particles into the current rendering.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        push_constant: &PushConstant,
+    ) {
+        let Some(draw) = &self.draw else {
+            return;
+        };
+        if pipeline_arena.is_quarantined(Either::Left(draw.pipeline)) {
+            return;
+        }
+        let pipeline = pipeline_arena.get_pipeline(draw.pipeline);
+        frame.push_constant(
+            pipeline.layout,
+            vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
+            &[*push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &pipeline.descriptor_sets(texture_arena.images_set),
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        let vertices = draw.settings.count.saturating_mul(draw.settings.vertices);
+        frame.draw(vertices, 0, 1, 0);
+    }
+}
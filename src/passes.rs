@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use ash::vk;
+use either::Either;
+
+use crate::{
+    BlendMode, ComputeHandle, DepthDesc, Error, FragmentOutputDesc, FragmentShaderDesc,
+    PipelineArena, PushConstant, RasterState, RenderHandle, TextureArena, VertexInputDesc,
+    VertexShaderDesc, Watcher,
+};
+
+struct Pass {
+    path: PathBuf,
+    handle: Either<RenderHandle, ComputeHandle>,
+}
+
+/// Pipelines of the `passN.comp`/`passN.frag` files and `passN/` folders
+/// next to the main shaders. Compute passes run after `shader.comp`, fragment
+/// passes blend over the image pass, both by ascending `N`.
+#[derive(Default)]
+pub struct Passes {
+    passes: Vec<Pass>,
+    /// The shader folder and its pass folders, watched for new files.
+    watched: Vec<PathBuf>,
+}
+
+impl Passes {
+    /// Creates pipelines for new pass files and drops the ones of removed
+    /// files. A pass failing to compile is retried on its next save.
+    pub fn sync(
+        &mut self,
+        shader_dir: &Path,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        color_format: vk::Format,
+        samples: vk::SampleCountFlags,
+        depth: Option<DepthDesc>,
+        raster: RasterState,
+        watcher: &mut Watcher,
+    ) -> Result<()> {
+        let (files, dirs) = discover(shader_dir)?;
+        for dir in dirs {
+            if !self.watched.contains(&dir) {
+                watcher.watch_file(&dir)?;
+                self.watched.push(dir);
+            }
+        }
+
+        let (mut kept, removed): (Vec<_>, _) = std::mem::take(&mut self.passes)
+            .into_iter()
+            .partition(|pass| files.contains(&pass.path));
+        for pass in removed {
+            pipeline_arena.remove_pipeline(pass.handle);
+            println!("Removed pass {}", pass.path.display());
+        }
+
+        let mut errors = vec![];
+        for path in files {
+            if let Some(idx) = kept.iter().position(|pass| pass.path == path) {
+                self.passes.push(kept.swap_remove(idx));
+                continue;
+            }
+            let created = create_pass(
+                &path,
+                shader_dir,
+                pipeline_arena,
+                texture_arena,
+                color_format,
+                samples,
+                depth,
+                raster,
+            )
+            .map_err(Error::pass(path.display().to_string()));
+            match created {
+                Ok(handle) => {
+                    println!("Added pass {}", path.display());
+                    self.passes.push(Pass { path, handle });
+                }
+                Err(err) => errors.push(err.to_string()),
+            }
+        }
+        if !errors.is_empty() {
+            bail!(errors.join("\n"));
+        }
+        Ok(())
+    }
+
+    /// Whether the canonical `path` would be a pass but has no pipeline yet.
+    pub fn is_missing(&self, shader_dir: &Path, path: &Path) -> bool {
+        let Ok(shader_dir) = shader_dir.canonicalize() else {
+            return false;
+        };
+        pass_order(&shader_dir, path).is_some() && !self.passes.iter().any(|p| p.path == path)
+    }
+
+    /// Drops every pass and stops watching the folders.
+    pub fn clear(&mut self, pipeline_arena: &mut PipelineArena, watcher: &mut Watcher) {
+        for pass in self.passes.drain(..) {
+            pipeline_arena.remove_pipeline(pass.handle);
+        }
+        for dir in self.watched.drain(..) {
+            let _ = watcher.unwatch_file(dir);
+        }
+    }
+
+    pub fn compute(&self) -> impl Iterator<Item = ComputeHandle> + '_ {
+        self.passes.iter().filter_map(|pass| pass.handle.right())
+    }
+
+    pub fn render(&self) -> impl Iterator<Item = RenderHandle> + '_ {
+        self.passes.iter().filter_map(|pass| pass.handle.left())
+    }
+}
+
+fn create_pass(
+    path: &Path,
+    shader_dir: &Path,
+    pipeline_arena: &mut PipelineArena,
+    texture_arena: &TextureArena,
+    color_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    depth: Option<DepthDesc>,
+    raster: RasterState,
+) -> Result<Either<RenderHandle, ComputeHandle>> {
+    let push_constant_range = vk::PushConstantRange::default()
+        .size(size_of::<PushConstant>() as _)
+        .stage_flags(
+            vk::ShaderStageFlags::VERTEX
+                | vk::ShaderStageFlags::FRAGMENT
+                | vk::ShaderStageFlags::COMPUTE,
+        );
+    if path.extension().is_some_and(|ext| ext == "comp") {
+        let handle = pipeline_arena.create_compute_pipeline(
+            path,
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+        return Ok(Either::Right(handle));
+    }
+    let handle = pipeline_arena.create_render_pipeline(
+        &VertexInputDesc::default(),
+        &raster.apply(VertexShaderDesc {
+            shader_path: shader_dir.join("shader.vert"),
+            ..Default::default()
+        }),
+        &FragmentShaderDesc {
+            shader_path: path.to_path_buf(),
+        },
+        &FragmentOutputDesc {
+            surface_format: color_format,
+            multisample_state: samples,
+            blend: BlendMode::Alpha,
+            depth,
+        },
+        &[push_constant_range],
+        &[texture_arena.images_set_layout],
+    )?;
+    Ok(Either::Left(handle))
+}
+
+/// `N` of `passN.comp`, `passN.frag`, `passN/shader.comp` or
+/// `passN/shader.frag` inside `shader_dir`.
+fn pass_order(shader_dir: &Path, path: &Path) -> Option<u32> {
+    let ext = path.extension()?.to_str()?;
+    if ext != "comp" && ext != "frag" {
+        return None;
+    }
+    let relative = path.strip_prefix(shader_dir).ok()?;
+    let name = match relative.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => relative.file_stem()?.to_str()?,
+        Some(parent) if relative.file_stem()? == "shader" => parent.to_str()?,
+        _ => return None,
+    };
+    name.strip_prefix("pass")?.parse().ok()
+}
+
+/// Canonical paths of the pass files in run order, and the folders to watch.
+fn discover(shader_dir: &Path) -> Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let shader_dir = shader_dir.canonicalize()?;
+    let mut dirs = vec![shader_dir.clone()];
+    let mut candidates = vec![];
+    for entry in std::fs::read_dir(&shader_dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let is_pass = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("pass"))
+                .is_some_and(|n| n.parse::<u32>().is_ok());
+            if is_pass {
+                candidates.push(path.join("shader.comp"));
+                candidates.push(path.join("shader.frag"));
+                dirs.push(path);
+            }
+        } else {
+            candidates.push(path);
+        }
+    }
+    let mut files: Vec<_> = candidates
+        .into_iter()
+        .filter(|path| path.is_file())
+        .filter_map(|path| Some((pass_order(&shader_dir, &path)?, path)))
+        .collect();
+    files.sort();
+    Ok((files.into_iter().map(|(_, path)| path).collect(), dirs))
+}
@@ -1,5 +1,5 @@
 use ahash::{AHashMap, AHashSet};
-use anyhow::Result;
+use anyhow::{bail, Result};
 use either::Either;
 use slotmap::SlotMap;
 use std::{
@@ -12,12 +12,21 @@ use ash::{
     vk::{self},
 };
 
-use crate::{Device, ShaderCompiler, ShaderKind, ShaderSource, Watcher};
+use crate::{
+    bindings::{user_bindings, UserResources, UserSet, USER_SET},
+    reflect, Device, Error, ShaderCompiler, ShaderKind, ShaderSource, Watcher,
+};
+
+/// Workgroup size of compute shaders that don't declare a literal one.
+const DEFAULT_LOCAL_SIZE: [u32; 3] = [16, 16, 1];
 
 pub struct ComputePipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
+    /// Workgroup size the shader declares, dispatches are counted in it.
+    pub local_size: [u32; 3],
     shader_path: PathBuf,
+    user_set: Option<UserSet>,
     device: Arc<Device>,
 }
 
@@ -37,19 +46,23 @@ impl ComputePipeline {
         shader_path: impl AsRef<Path>,
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        resources: &UserResources,
     ) -> Result<Self> {
         let cs_bytes = shader_compiler.compile(&shader_path, shaderc::ShaderKind::Compute)?;
+        let local_size = reflect::local_size(&cs_bytes).unwrap_or(DEFAULT_LOCAL_SIZE);
+        let user_set = UserSet::new(device, user_bindings(&[&cs_bytes])?, resources)?;
+        let set_layouts = set_layouts(descriptor_set_layouts, user_set.as_ref())?;
 
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
                 &vk::PipelineLayoutCreateInfo::default()
-                    .set_layouts(descriptor_set_layouts)
+                    .set_layouts(&set_layouts)
                     .push_constant_ranges(push_constant_ranges),
                 None,
             )?
         };
 
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(cs_bytes.as_binary());
+        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(&cs_bytes);
         let shader_stage = vk::PipelineShaderStageCreateInfo::default()
             .stage(vk::ShaderStageFlags::COMPUTE)
             .name(c"main")
@@ -65,18 +78,34 @@ impl ComputePipeline {
 
         Ok(Self {
             pipeline,
+            local_size,
             shader_path: shader_path.as_ref().to_path_buf(),
             layout: pipeline_layout,
+            user_set,
             device: device.clone(),
         })
     }
 
+    /// Sets to bind from set 0, `images_set` of the texture arena followed
+    /// by set 1 when the shader declares one.
+    pub fn descriptor_sets(&self, images_set: vk::DescriptorSet) -> Vec<vk::DescriptorSet> {
+        descriptor_sets(images_set, self.user_set.as_ref())
+    }
+
     pub fn reload(&mut self, shader_compiler: &ShaderCompiler) -> Result<()> {
-        let cs_bytes = shader_compiler.compile(&self.shader_path, shaderc::ShaderKind::Compute)?;
+        let (pipeline, local_size) = self.stage(shader_compiler)?;
+        self.apply(pipeline, local_size);
+        Ok(())
+    }
 
-        unsafe { self.device.destroy_pipeline(self.pipeline, None) }
+    /// Builds the pipeline from the current source without replacing the
+    /// one in use, along with its workgroup size.
+    pub fn stage(&self, shader_compiler: &ShaderCompiler) -> Result<(vk::Pipeline, [u32; 3])> {
+        let cs_bytes = shader_compiler.compile(&self.shader_path, shaderc::ShaderKind::Compute)?;
+        let local_size = reflect::local_size(&cs_bytes).unwrap_or(DEFAULT_LOCAL_SIZE);
+        check_bindings(self.user_set.as_ref(), &[&cs_bytes], &self.shader_path)?;
 
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(cs_bytes.as_binary());
+        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(&cs_bytes);
         let shader_stage = vk::PipelineShaderStageCreateInfo::default()
             .stage(vk::ShaderStageFlags::COMPUTE)
             .name(c"main")
@@ -89,17 +118,24 @@ impl ComputePipeline {
             self.device
                 .create_compute_pipelines(vk::PipelineCache::null(), &[create_info], None)
         };
-        let pipeline = pipeline.map_err(|(_, err)| err)?[0];
+        Ok((pipeline.map_err(|(_, err)| err)?[0], local_size))
+    }
 
+    /// Swaps in a staged pipeline.
+    pub fn apply(&mut self, pipeline: vk::Pipeline, local_size: [u32; 3]) {
+        unsafe { self.device.destroy_pipeline(self.pipeline, None) }
         self.pipeline = pipeline;
-
-        Ok(())
+        self.local_size = local_size;
     }
 }
 
+#[derive(Clone)]
 pub struct VertexInputDesc {
     pub primitive_topology: vk::PrimitiveTopology,
     pub primitive_restart: bool,
+    /// Vertex buffers, none for pipelines generating their vertices.
+    pub bindings: Vec<vk::VertexInputBindingDescription>,
+    pub attributes: Vec<vk::VertexInputAttributeDescription>,
 }
 
 impl Default for VertexInputDesc {
@@ -107,10 +143,13 @@ impl Default for VertexInputDesc {
         Self {
             primitive_topology: vk::PrimitiveTopology::TRIANGLE_LIST,
             primitive_restart: false,
+            bindings: vec![],
+            attributes: vec![],
         }
     }
 }
 
+#[derive(Clone)]
 pub struct VertexShaderDesc {
     pub shader_path: PathBuf,
     pub dynamic_state: Vec<vk::DynamicState>,
@@ -141,9 +180,50 @@ pub struct FragmentShaderDesc {
     pub shader_path: PathBuf,
 }
 
+/// How a fragment output combines with what is already in the attachment.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Overwrite the attachment.
+    #[default]
+    Replace,
+    /// Blend over the attachment using the output alpha.
+    Alpha,
+    /// `dst + src`
+    Add,
+    /// `dst * src`
+    Multiply,
+    /// `dst + src * (1 - dst)`
+    Screen,
+}
+
+impl BlendMode {
+    fn color_factors(self) -> (vk::BlendFactor, vk::BlendFactor) {
+        match self {
+            BlendMode::Replace => (vk::BlendFactor::ONE, vk::BlendFactor::ZERO),
+            BlendMode::Alpha => (
+                vk::BlendFactor::SRC_ALPHA,
+                vk::BlendFactor::ONE_MINUS_SRC_ALPHA,
+            ),
+            BlendMode::Add => (vk::BlendFactor::ONE, vk::BlendFactor::ONE),
+            BlendMode::Multiply => (vk::BlendFactor::DST_COLOR, vk::BlendFactor::ZERO),
+            BlendMode::Screen => (vk::BlendFactor::ONE_MINUS_DST_COLOR, vk::BlendFactor::ONE),
+        }
+    }
+}
+
+/// Depth attachment of the rendering a pipeline draws in, tested with `LESS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DepthDesc {
+    pub format: vk::Format,
+    pub test: bool,
+    pub write: bool,
+}
+
 pub struct FragmentOutputDesc {
     pub surface_format: vk::Format,
     pub multisample_state: vk::SampleCountFlags,
+    pub blend: BlendMode,
+    pub depth: Option<DepthDesc>,
 }
 
 impl Default for FragmentOutputDesc {
@@ -151,10 +231,16 @@ impl Default for FragmentOutputDesc {
         Self {
             surface_format: vk::Format::B8G8R8A8_SRGB,
             multisample_state: vk::SampleCountFlags::TYPE_1,
+            blend: BlendMode::Replace,
+            depth: None,
         }
     }
 }
 
+/// A graphics pipeline linked from four libraries, so a reload only rebuilds
+/// the stage that changed. Without `VK_EXT_graphics_pipeline_library` the
+/// libraries are null and every link creates the whole pipeline from the
+/// kept SPIR-V and descriptions.
 pub struct RenderPipeline {
     pub layout: vk::PipelineLayout,
     pub pipeline: vk::Pipeline,
@@ -162,9 +248,30 @@ pub struct RenderPipeline {
     vertex_shader_lib: vk::Pipeline,
     fragment_shader_lib: vk::Pipeline,
     fragment_output_lib: vk::Pipeline,
+    vertex_code: Vec<u32>,
+    fragment_code: Vec<u32>,
+    /// Kept for rebuilding the shader libraries on reload.
+    vertex_shader_desc: VertexShaderDesc,
+    vertex_input_desc: VertexInputDesc,
+    color_format: vk::Format,
+    samples: vk::SampleCountFlags,
+    blend: BlendMode,
+    depth: Option<DepthDesc>,
+    push_constant_ranges: Vec<vk::PushConstantRange>,
+    vertex_buffers: bool,
+    user_set: Option<UserSet>,
     device: Arc<Device>,
 }
 
+/// Render pipeline rebuilt around a changed shader library, not in use until
+/// [`RenderPipeline::apply`].
+pub struct StagedRender {
+    kind: ShaderKind,
+    library: vk::Pipeline,
+    code: Vec<u32>,
+    pub pipeline: vk::Pipeline,
+}
+
 impl RenderPipeline {
     pub fn new(
         device: &Arc<Device>,
@@ -175,6 +282,7 @@ impl RenderPipeline {
         fragment_output_desc: &FragmentOutputDesc,
         push_constant_ranges: &[vk::PushConstantRange],
         descriptor_set_layouts: &[vk::DescriptorSetLayout],
+        resources: &UserResources,
     ) -> Result<Self> {
         let vs_bytes = shader_compiler
             .compile(&vertex_shader_desc.shader_path, shaderc::ShaderKind::Vertex)?;
@@ -182,11 +290,13 @@ impl RenderPipeline {
             &fragment_shader_desc.shader_path,
             shaderc::ShaderKind::Fragment,
         )?;
+        let user_set = UserSet::new(device, user_bindings(&[&vs_bytes, &fs_bytes])?, resources)?;
+        let set_layouts = set_layouts(descriptor_set_layouts, user_set.as_ref())?;
 
         let pipeline_layout = unsafe {
             device.create_pipeline_layout(
                 &vk::PipelineLayoutCreateInfo::default()
-                    .set_layouts(descriptor_set_layouts)
+                    .set_layouts(&set_layouts)
                     .push_constant_ranges(push_constant_ranges),
                 None,
             )?
@@ -197,7 +307,9 @@ impl RenderPipeline {
             let input_ass = vk::PipelineInputAssemblyStateCreateInfo::default()
                 .topology(vertex_input_desc.primitive_topology)
                 .primitive_restart_enable(vertex_input_desc.primitive_restart);
-            let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+            let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+                .vertex_binding_descriptions(&vertex_input_desc.bindings)
+                .vertex_attribute_descriptions(&vertex_input_desc.attributes);
 
             create_library(device, GPF::VERTEX_INPUT_INTERFACE, |desc| {
                 desc.vertex_input_state(&vertex_input)
@@ -205,200 +317,324 @@ impl RenderPipeline {
             })?
         };
 
-        let vertex_shader_lib = {
-            let mut shader_module =
-                vk::ShaderModuleCreateInfo::default().code(vs_bytes.as_binary());
-            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::VERTEX)
-                .name(c"main")
-                .push_next(&mut shader_module);
-            let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
-                .dynamic_states(&vertex_shader_desc.dynamic_state);
-            let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
-                .line_width(vertex_shader_desc.line_width)
-                .polygon_mode(vertex_shader_desc.polygon_mode)
-                .cull_mode(vertex_shader_desc.cull_mode)
-                .front_face(vertex_shader_desc.front_face);
-            let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-                .viewport_count(vertex_shader_desc.viewport_count)
-                .scissor_count(vertex_shader_desc.scissot_count);
-
-            create_library(device, GPF::PRE_RASTERIZATION_SHADERS, |desc| {
-                desc.layout(pipeline_layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .dynamic_state(&dynamic_state)
-                    .viewport_state(&viewport_state)
-                    .rasterization_state(&rasterization_state)
-            })?
-        };
-
-        let fragment_shader_lib = {
-            let mut shader_module =
-                vk::ShaderModuleCreateInfo::default().code(fs_bytes.as_binary());
-            let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-                .stage(vk::ShaderStageFlags::FRAGMENT)
-                .name(c"main")
-                .push_next(&mut shader_module);
-
-            let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
-
-            create_library(device, GPF::FRAGMENT_SHADER, |desc| {
-                desc.layout(pipeline_layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .depth_stencil_state(&depth_stencil_state)
-            })?
-        };
+        let vertex_shader_lib =
+            create_vertex_shader_lib(device, pipeline_layout, vertex_shader_desc, &vs_bytes)?;
+        let fragment_shader_lib = create_fragment_shader_lib(
+            device,
+            pipeline_layout,
+            fragment_output_desc.depth,
+            &fs_bytes,
+        )?;
 
         let fragment_output_lib = {
             let color_attachment_formats = [fragment_output_desc.surface_format];
             let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
-                .color_attachment_formats(&color_attachment_formats);
+                .color_attachment_formats(&color_attachment_formats)
+                .depth_attachment_format(
+                    fragment_output_desc
+                        .depth
+                        .map_or(vk::Format::UNDEFINED, |depth| depth.format),
+                );
 
             let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
-                .rasterization_samples(vk::SampleCountFlags::TYPE_1);
+                .rasterization_samples(fragment_output_desc.multisample_state);
+
+            let blend_attachments = [blend_attachment(fragment_output_desc.blend)];
+            let color_blend_state =
+                vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
 
             create_library(device, GPF::FRAGMENT_OUTPUT_INTERFACE, |desc| {
                 desc.multisample_state(&multisample_state)
+                    .color_blend_state(&color_blend_state)
                     .push_next(&mut dyn_render)
             })?
         };
 
-        let pipeline = Self::link_libraries(
-            device,
-            &pipeline_layout,
-            &vertex_input_lib,
-            &vertex_shader_lib,
-            &fragment_shader_lib,
-            &fragment_output_lib,
-        )?;
-
-        Ok(Self {
+        // Dropped on a failed link, which destroys the libraries
+        let mut render = Self {
             device: device.clone(),
             layout: pipeline_layout,
-            pipeline,
+            pipeline: vk::Pipeline::null(),
             vertex_input_lib,
             vertex_shader_lib,
             fragment_shader_lib,
             fragment_output_lib,
-        })
+            vertex_code: vs_bytes,
+            fragment_code: fs_bytes,
+            vertex_shader_desc: vertex_shader_desc.clone(),
+            vertex_input_desc: vertex_input_desc.clone(),
+            color_format: fragment_output_desc.surface_format,
+            samples: fragment_output_desc.multisample_state,
+            blend: fragment_output_desc.blend,
+            depth: fragment_output_desc.depth,
+            push_constant_ranges: push_constant_ranges.to_vec(),
+            vertex_buffers: !vertex_input_desc.bindings.is_empty(),
+            user_set,
+        };
+        render.pipeline = render.link(
+            render.vertex_shader_lib,
+            render.fragment_shader_lib,
+            &render.vertex_code,
+            &render.fragment_code,
+        )?;
+        Ok(render)
     }
 
-    pub fn reload_vertex_lib(
-        &mut self,
-        shader_compiler: &ShaderCompiler,
-        shader_path: impl AsRef<Path>,
-    ) -> Result<()> {
-        let vs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Vertex)?;
+    /// Sets to bind from set 0, `images_set` of the texture arena followed
+    /// by set 1 when the shaders declare one.
+    pub fn descriptor_sets(&self, images_set: vk::DescriptorSet) -> Vec<vk::DescriptorSet> {
+        descriptor_sets(images_set, self.user_set.as_ref())
+    }
 
-        unsafe { self.device.destroy_pipeline(self.vertex_shader_lib, None) };
+    pub fn color_format(&self) -> vk::Format {
+        self.color_format
+    }
 
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(vs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::VERTEX)
-            .name(c"main")
-            .push_next(&mut shader_module);
-        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
-            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
-        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
-            .line_width(1.0)
-            .polygon_mode(vk::PolygonMode::FILL)
-            .cull_mode(vk::CullModeFlags::BACK)
-            .front_face(vk::FrontFace::COUNTER_CLOCKWISE);
-        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
-            .viewport_count(1)
-            .scissor_count(1);
-        let vertex_shader_lib = create_library(
-            &self.device,
-            vk::GraphicsPipelineLibraryFlagsEXT::PRE_RASTERIZATION_SHADERS,
-            |desc| {
-                desc.layout(self.layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .dynamic_state(&dynamic_state)
-                    .viewport_state(&viewport_state)
-                    .rasterization_state(&rasterization_state)
-            },
-        )?;
+    pub fn samples(&self) -> vk::SampleCountFlags {
+        self.samples
+    }
+
+    pub fn depth_format(&self) -> Option<vk::Format> {
+        self.depth.map(|depth| depth.format)
+    }
 
-        self.vertex_shader_lib = vertex_shader_lib;
+    pub fn push_constant_ranges(&self) -> &[vk::PushConstantRange] {
+        &self.push_constant_ranges
+    }
 
-        Ok(())
+    /// Whether drawing needs bound vertex buffers.
+    pub fn takes_vertex_buffers(&self) -> bool {
+        self.vertex_buffers
     }
 
-    pub fn reload_fragment_lib(
+    pub fn reload(
         &mut self,
         shader_compiler: &ShaderCompiler,
         shader_path: impl AsRef<Path>,
+        kind: ShaderKind,
     ) -> Result<()> {
-        let fs_bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Fragment)?;
-
-        unsafe { self.device.destroy_pipeline(self.fragment_shader_lib, None) };
-
-        let mut shader_module = vk::ShaderModuleCreateInfo::default().code(fs_bytes.as_binary());
-        let shader_stage = vk::PipelineShaderStageCreateInfo::default()
-            .stage(vk::ShaderStageFlags::FRAGMENT)
-            .name(c"main")
-            .push_next(&mut shader_module);
-
-        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default();
-
-        let fragment_shader_lib = create_library(
-            &self.device,
-            vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_SHADER,
-            |desc| {
-                desc.layout(self.layout)
-                    .stages(std::slice::from_ref(&shader_stage))
-                    .depth_stencil_state(&depth_stencil_state)
-            },
-        )?;
+        let staged = self.stage(shader_compiler, shader_path, kind)?;
+        self.apply(staged);
+        Ok(())
+    }
 
-        self.fragment_shader_lib = fragment_shader_lib;
+    /// Rebuilds the vertex stage with another polygon mode, e.g. lines for a
+    /// wireframe view.
+    pub fn set_polygon_mode(
+        &mut self,
+        shader_compiler: &ShaderCompiler,
+        mode: vk::PolygonMode,
+    ) -> Result<()> {
+        if self.vertex_shader_desc.polygon_mode == mode {
+            return Ok(());
+        }
+        let previous = std::mem::replace(&mut self.vertex_shader_desc.polygon_mode, mode);
+        let path = self.vertex_shader_desc.shader_path.clone();
+        let res = self.reload(shader_compiler, path, ShaderKind::Vertex);
+        if res.is_err() {
+            self.vertex_shader_desc.polygon_mode = previous;
+        }
+        res
+    }
 
-        Ok(())
+    /// Rebuilds the library of the `kind` stage from `shader_path` and links
+    /// it with the others, leaving the pipeline in use alone.
+    pub fn stage(
+        &self,
+        shader_compiler: &ShaderCompiler,
+        shader_path: impl AsRef<Path>,
+        kind: ShaderKind,
+    ) -> Result<StagedRender> {
+        let shader_path = shader_path.as_ref();
+        let (library, code) = match kind {
+            ShaderKind::Vertex => {
+                let bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Vertex)?;
+                let modules = [&bytes[..], &self.fragment_code];
+                check_bindings(self.user_set.as_ref(), &modules, shader_path)?;
+                let library = create_vertex_shader_lib(
+                    &self.device,
+                    self.layout,
+                    &self.vertex_shader_desc,
+                    &bytes,
+                )?;
+                (library, bytes)
+            }
+            ShaderKind::Fragment => {
+                let bytes = shader_compiler.compile(shader_path, shaderc::ShaderKind::Fragment)?;
+                let modules = [&self.vertex_code[..], &bytes];
+                check_bindings(self.user_set.as_ref(), &modules, shader_path)?;
+                let library =
+                    create_fragment_shader_lib(&self.device, self.layout, self.depth, &bytes)?;
+                (library, bytes)
+            }
+            ShaderKind::Compute => bail!("Supplied compute shader into the render pipeline!"),
+        };
+        let pipeline = match kind {
+            ShaderKind::Vertex => self.link(
+                library,
+                self.fragment_shader_lib,
+                &code,
+                &self.fragment_code,
+            ),
+            _ => self.link(self.vertex_shader_lib, library, &self.vertex_code, &code),
+        };
+        match pipeline {
+            Ok(pipeline) => Ok(StagedRender {
+                kind,
+                library,
+                code,
+                pipeline,
+            }),
+            Err(err) => {
+                unsafe { self.device.destroy_pipeline(library, None) };
+                Err(err)
+            }
+        }
     }
 
-    pub fn link(&mut self) -> Result<()> {
-        unsafe { self.device.destroy_pipeline(self.pipeline, None) };
-        self.pipeline = Self::link_libraries(
-            &self.device,
-            &self.layout,
-            &self.vertex_input_lib,
-            &self.vertex_shader_lib,
-            &self.fragment_shader_lib,
-            &self.fragment_output_lib,
-        )?;
+    /// Swaps in a staged pipeline and its library.
+    pub fn apply(&mut self, staged: StagedRender) {
+        let (library, code) = match staged.kind {
+            ShaderKind::Vertex => (&mut self.vertex_shader_lib, &mut self.vertex_code),
+            _ => (&mut self.fragment_shader_lib, &mut self.fragment_code),
+        };
+        unsafe {
+            self.device.destroy_pipeline(*library, None);
+            self.device.destroy_pipeline(self.pipeline, None);
+        }
+        *library = staged.library;
+        *code = staged.code;
+        self.pipeline = staged.pipeline;
+    }
 
-        Ok(())
+    /// Destroys a staged pipeline that didn't make it.
+    pub fn discard(&self, staged: StagedRender) {
+        unsafe {
+            self.device.destroy_pipeline(staged.library, None);
+            self.device.destroy_pipeline(staged.pipeline, None);
+        }
     }
 
-    fn link_libraries(
-        device: &ash::Device,
-        layout: &vk::PipelineLayout,
-        vertex_input_lib: &vk::Pipeline,
-        vertex_shader_lib: &vk::Pipeline,
-        fragment_shader_lib: &vk::Pipeline,
-        fragment_output_lib: &vk::Pipeline,
+    /// Links the shader stages with the interface libraries, or builds the
+    /// whole pipeline from `vertex_code` and `fragment_code` without them.
+    fn link(
+        &self,
+        vertex_shader_lib: vk::Pipeline,
+        fragment_shader_lib: vk::Pipeline,
+        vertex_code: &[u32],
+        fragment_code: &[u32],
     ) -> Result<vk::Pipeline> {
+        if !self.device.pipeline_libraries {
+            return Ok(self.create_whole(vertex_code, fragment_code)?);
+        }
         let libraries = [
-            *vertex_input_lib,
-            *vertex_shader_lib,
-            *fragment_shader_lib,
-            *fragment_output_lib,
+            self.vertex_input_lib,
+            vertex_shader_lib,
+            fragment_shader_lib,
+            self.fragment_output_lib,
         ];
         let pipeline = {
             let mut linking_info =
                 vk::PipelineLibraryCreateInfoKHR::default().libraries(&libraries);
             let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
                 .flags(vk::PipelineCreateFlags::LINK_TIME_OPTIMIZATION_EXT)
-                .layout(*layout)
+                .layout(self.layout)
                 .push_next(&mut linking_info);
             let pipeline = unsafe {
-                device.create_graphics_pipelines(vk::PipelineCache::null(), &[pipeline_info], None)
+                self.device.create_graphics_pipelines(
+                    vk::PipelineCache::null(),
+                    &[pipeline_info],
+                    None,
+                )
             };
             pipeline.map_err(|(_, err)| err)?[0]
         };
 
         Ok(pipeline)
     }
+
+    /// The state of all four libraries in one pipeline.
+    fn create_whole(&self, vertex_code: &[u32], fragment_code: &[u32]) -> VkResult<vk::Pipeline> {
+        let input = &self.vertex_input_desc;
+        let input_ass = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(input.primitive_topology)
+            .primitive_restart_enable(input.primitive_restart);
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&input.bindings)
+            .vertex_attribute_descriptions(&input.attributes);
+
+        let desc = &self.vertex_shader_desc;
+        let mut vertex_module = vk::ShaderModuleCreateInfo::default().code(vertex_code);
+        let mut fragment_module = vk::ShaderModuleCreateInfo::default().code(fragment_code);
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::VERTEX)
+                .name(c"main")
+                .push_next(&mut vertex_module),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(vk::ShaderStageFlags::FRAGMENT)
+                .name(c"main")
+                .push_next(&mut fragment_module),
+        ];
+        let dynamic_state =
+            vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&desc.dynamic_state);
+        let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+            .line_width(desc.line_width)
+            .polygon_mode(desc.polygon_mode)
+            .cull_mode(desc.cull_mode)
+            .front_face(desc.front_face);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(desc.viewport_count)
+            .scissor_count(desc.scissot_count);
+        let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(self.depth.is_some_and(|depth| depth.test))
+            .depth_write_enable(self.depth.is_some_and(|depth| depth.write))
+            .depth_compare_op(vk::CompareOp::LESS);
+
+        let color_attachment_formats = [self.color_format];
+        let mut dyn_render = vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(&color_attachment_formats)
+            .depth_attachment_format(self.depth_format().unwrap_or(vk::Format::UNDEFINED));
+        let multisample_state =
+            vk::PipelineMultisampleStateCreateInfo::default().rasterization_samples(self.samples);
+        let blend_attachments = [blend_attachment(self.blend)];
+        let color_blend_state =
+            vk::PipelineColorBlendStateCreateInfo::default().attachments(&blend_attachments);
+
+        let pipeline_info = vk::GraphicsPipelineCreateInfo::default()
+            .layout(self.layout)
+            .stages(&stages)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_ass)
+            .dynamic_state(&dynamic_state)
+            .viewport_state(&viewport_state)
+            .rasterization_state(&rasterization_state)
+            .depth_stencil_state(&depth_stencil_state)
+            .multisample_state(&multisample_state)
+            .color_blend_state(&color_blend_state)
+            .push_next(&mut dyn_render);
+        let pipeline = unsafe {
+            self.device.create_graphics_pipelines(
+                vk::PipelineCache::null(),
+                std::slice::from_ref(&pipeline_info),
+                None,
+            )
+        };
+        Ok(pipeline.map_err(|(_, err)| err)?[0])
+    }
+}
+
+fn blend_attachment(blend: BlendMode) -> vk::PipelineColorBlendAttachmentState {
+    let (src_factor, dst_factor) = blend.color_factors();
+    vk::PipelineColorBlendAttachmentState::default()
+        .blend_enable(blend != BlendMode::Replace)
+        .src_color_blend_factor(src_factor)
+        .dst_color_blend_factor(dst_factor)
+        .color_blend_op(vk::BlendOp::ADD)
+        .src_alpha_blend_factor(vk::BlendFactor::ZERO)
+        .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+        .alpha_blend_op(vk::BlendOp::ADD)
+        .color_write_mask(vk::ColorComponentFlags::RGBA)
 }
 
 impl Drop for RenderPipeline {
@@ -414,14 +650,117 @@ impl Drop for RenderPipeline {
     }
 }
 
+/// `descriptor_set_layouts` followed by the layout of `user_set`, which
+/// has to come right after the texture arena.
+fn set_layouts(
+    descriptor_set_layouts: &[vk::DescriptorSetLayout],
+    user_set: Option<&UserSet>,
+) -> Result<Vec<vk::DescriptorSetLayout>> {
+    let mut layouts = descriptor_set_layouts.to_vec();
+    if let Some(user_set) = user_set {
+        if layouts.len() != USER_SET as usize {
+            bail!("Set {USER_SET} is only there for pipelines using the texture arena");
+        }
+        layouts.push(user_set.layout);
+    }
+    Ok(layouts)
+}
+
+fn descriptor_sets(
+    images_set: vk::DescriptorSet,
+    user_set: Option<&UserSet>,
+) -> Vec<vk::DescriptorSet> {
+    std::iter::once(images_set)
+        .chain(user_set.map(|user_set| user_set.set))
+        .collect()
+}
+
+/// Fails with [`Error::Bindings`] when `modules` declare other set 1
+/// bindings than the layout has.
+fn check_bindings(user_set: Option<&UserSet>, modules: &[&[u32]], path: &Path) -> Result<()> {
+    let bindings = user_bindings(modules)?;
+    if bindings != user_set.map_or(&[][..], UserSet::bindings) {
+        return Err(Error::Bindings {
+            path: path.to_path_buf(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+fn create_vertex_shader_lib(
+    device: &Device,
+    layout: vk::PipelineLayout,
+    desc: &VertexShaderDesc,
+    code: &[u32],
+) -> VkResult<vk::Pipeline> {
+    let mut shader_module = vk::ShaderModuleCreateInfo::default().code(code);
+    let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::VERTEX)
+        .name(c"main")
+        .push_next(&mut shader_module);
+    let dynamic_state =
+        vk::PipelineDynamicStateCreateInfo::default().dynamic_states(&desc.dynamic_state);
+    let rasterization_state = vk::PipelineRasterizationStateCreateInfo::default()
+        .line_width(desc.line_width)
+        .polygon_mode(desc.polygon_mode)
+        .cull_mode(desc.cull_mode)
+        .front_face(desc.front_face);
+    let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+        .viewport_count(desc.viewport_count)
+        .scissor_count(desc.scissot_count);
+
+    create_library(
+        device,
+        vk::GraphicsPipelineLibraryFlagsEXT::PRE_RASTERIZATION_SHADERS,
+        |info| {
+            info.layout(layout)
+                .stages(std::slice::from_ref(&shader_stage))
+                .dynamic_state(&dynamic_state)
+                .viewport_state(&viewport_state)
+                .rasterization_state(&rasterization_state)
+        },
+    )
+}
+
+fn create_fragment_shader_lib(
+    device: &Device,
+    layout: vk::PipelineLayout,
+    depth: Option<DepthDesc>,
+    code: &[u32],
+) -> VkResult<vk::Pipeline> {
+    let mut shader_module = vk::ShaderModuleCreateInfo::default().code(code);
+    let shader_stage = vk::PipelineShaderStageCreateInfo::default()
+        .stage(vk::ShaderStageFlags::FRAGMENT)
+        .name(c"main")
+        .push_next(&mut shader_module);
+    let depth_stencil_state = vk::PipelineDepthStencilStateCreateInfo::default()
+        .depth_test_enable(depth.is_some_and(|depth| depth.test))
+        .depth_write_enable(depth.is_some_and(|depth| depth.write))
+        .depth_compare_op(vk::CompareOp::LESS);
+
+    create_library(
+        device,
+        vk::GraphicsPipelineLibraryFlagsEXT::FRAGMENT_SHADER,
+        |info| {
+            info.layout(layout)
+                .stages(std::slice::from_ref(&shader_stage))
+                .depth_stencil_state(&depth_stencil_state)
+        },
+    )
+}
+
 fn create_library<'a, F>(
-    device: &ash::Device,
+    device: &Device,
     kind: vk::GraphicsPipelineLibraryFlagsEXT,
     f: F,
 ) -> VkResult<vk::Pipeline>
 where
     F: FnOnce(vk::GraphicsPipelineCreateInfo<'a>) -> vk::GraphicsPipelineCreateInfo<'a>,
 {
+    if !device.pipeline_libraries {
+        return Ok(vk::Pipeline::null());
+    }
     let mut library_type = vk::GraphicsPipelineLibraryCreateInfoEXT::default().flags(kind);
     let pipeline = unsafe {
         let pipeline_info = vk::GraphicsPipelineCreateInfo::default().flags(
@@ -442,6 +781,9 @@ where
     Ok(pipeline.map_err(|(_, err)| err)?[0])
 }
 
+/// Failed reloads in a row after which a pipeline is quarantined.
+pub const QUARANTINE_AFTER: u32 = 3;
+
 slotmap::new_key_type! {
     pub struct RenderHandle;
     pub struct ComputeHandle;
@@ -452,6 +794,10 @@ pub struct PipelineArena {
     pub compute: ComputeArena,
     pub path_mapping: AHashMap<PathBuf, AHashSet<Either<RenderHandle, ComputeHandle>>>,
     pub shader_compiler: ShaderCompiler,
+    /// What set 1 of the pipelines created from now on binds.
+    pub user_resources: UserResources,
+    /// Consecutive failed reloads of every pipeline that failed its last one.
+    failures: AHashMap<Either<RenderHandle, ComputeHandle>, u32>,
     file_watcher: Watcher,
     device: Arc<Device>,
 }
@@ -466,8 +812,10 @@ impl PipelineArena {
                 pipelines: SlotMap::with_key(),
             },
             shader_compiler: ShaderCompiler::new(&file_watcher)?,
+            user_resources: UserResources::default(),
             file_watcher,
             path_mapping: AHashMap::new(),
+            failures: AHashMap::new(),
             device: device.clone(),
         })
     }
@@ -496,6 +844,7 @@ impl PipelineArena {
             &path,
             push_constant_ranges,
             descriptor_set_layouts,
+            &self.user_resources,
         )?;
         let handle = self.compute.pipelines.insert(pipeline);
         self.path_mapping
@@ -536,6 +885,7 @@ impl PipelineArena {
             fragment_output_desc,
             push_constant_ranges,
             descriptor_set_layouts,
+            &self.user_resources,
         )?;
         let handle = self.render.pipelines.insert(pipeline);
         self.path_mapping
@@ -549,6 +899,55 @@ impl PipelineArena {
         Ok(handle)
     }
 
+    pub fn remove_pipeline(&mut self, handle: Either<RenderHandle, ComputeHandle>) {
+        match handle {
+            Either::Left(handle) => drop(self.render.pipelines.remove(handle)),
+            Either::Right(handle) => drop(self.compute.pipelines.remove(handle)),
+        }
+        self.failures.remove(&handle);
+
+        let mut orphaned = AHashSet::new();
+        self.path_mapping.retain(|path, handles| {
+            handles.remove(&handle);
+            if handles.is_empty() {
+                orphaned.insert(path.clone());
+            }
+            !handles.is_empty()
+        });
+
+        let include_mapping = self.file_watcher.include_mapping.clone();
+        let mut mapping = include_mapping.lock();
+        mapping.retain(|path, sources| {
+            sources.retain(|source| !orphaned.contains(&source.path));
+            if sources.is_empty() {
+                let _ = self.file_watcher.unwatch_file(path);
+            }
+            !sources.is_empty()
+        });
+    }
+
+    /// Counts a failed reload, returns `true` when it puts `handle` into quarantine.
+    pub fn reload_failed(&mut self, handle: Either<RenderHandle, ComputeHandle>) -> bool {
+        let failures = self.failures.entry(handle).or_default();
+        *failures += 1;
+        *failures == QUARANTINE_AFTER
+    }
+
+    /// Resets the count, returns `true` when `handle` leaves quarantine.
+    pub fn reload_succeeded(&mut self, handle: Either<RenderHandle, ComputeHandle>) -> bool {
+        self.failures
+            .remove(&handle)
+            .is_some_and(|failures| failures >= QUARANTINE_AFTER)
+    }
+
+    /// Pipelines failing to reload over and over are left out of the frame,
+    /// their last good pipeline may no longer agree with the rest.
+    pub fn is_quarantined(&self, handle: Either<RenderHandle, ComputeHandle>) -> bool {
+        self.failures
+            .get(&handle)
+            .is_some_and(|&failures| failures >= QUARANTINE_AFTER)
+    }
+
     pub fn get_pipeline<H: Handle>(&self, handle: H) -> &H::Pipeline {
         handle.get_pipeline(self)
     }
@@ -0,0 +1,486 @@
+use std::path::Path;
+
+use anyhow::Result;
+use ash::vk;
+use either::Either;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders, letterbox,
+    swapchain::{FrameGuard, SceneAttachments},
+    Device, Error, FragmentOutputDesc, FragmentShaderDesc, PipelineArena, RenderHandle,
+    TextureArena, VertexInputDesc, VertexShaderDesc, COLOR_SUBRESOURCE_MASK,
+};
+
+pub const POST_FOLDER: &str = "post";
+/// Float targets let bloom pick up values above one.
+pub const POST_TARGET_FORMAT: vk::Format = vk::Format::R16G16B16A16_SFLOAT;
+
+const BLOOM_PREFILTER: u32 = 0;
+const BLOOM_DOWNSAMPLE: u32 = 1;
+const BLOOM_UPSAMPLE: u32 = 2;
+const BLOOM_COMPOSITE: u32 = 3;
+/// Mips of the bloom chain, from half the resolution down to 1/32.
+const BLOOM_LEVELS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(tag = "pass", rename_all = "snake_case")]
+pub enum PostPass {
+    /// Separable Gaussian blur, `radius` scales the tap distance in pixels.
+    Blur {
+        #[serde(default = "default_blur_radius")]
+        radius: f32,
+    },
+    /// Dual filter bloom over everything brighter than `threshold`.
+    Bloom {
+        #[serde(default = "default_bloom_threshold")]
+        threshold: f32,
+        #[serde(default = "default_bloom_intensity")]
+        intensity: f32,
+    },
+    Fxaa,
+}
+
+fn default_blur_radius() -> f32 {
+    1.
+}
+
+fn default_bloom_threshold() -> f32 {
+    0.8
+}
+
+fn default_bloom_intensity() -> f32 {
+    0.5
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PostPushConstant {
+    pub resolution: [f32; 2],
+    pub direction: [f32; 2],
+    pub src: u32,
+    pub aux: u32,
+    pub mode: u32,
+    pub strength: f32,
+}
+
+/// Image a step reads or renders into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    /// Output of the step before, or for `dst` the other full resolution
+    /// target.
+    Chain,
+    /// Level of the bloom mip chain, each half the size of the one before.
+    Bloom(u32),
+}
+
+struct Step {
+    pipeline: RenderHandle,
+    push_constant: PostPushConstant,
+    src: Target,
+    dst: Target,
+}
+
+struct PostPipelines {
+    blur: RenderHandle,
+    bloom: RenderHandle,
+    fxaa: RenderHandle,
+    present: RenderHandle,
+}
+
+impl PostPipelines {
+    fn new(
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        dir: &Path,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<PostPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let mut create = |name: &str, format: vk::Format| {
+            pipeline_arena
+                .create_render_pipeline(
+                    &VertexInputDesc::default(),
+                    &VertexShaderDesc {
+                        shader_path: dir.join("post.vert"),
+                        ..Default::default()
+                    },
+                    &FragmentShaderDesc {
+                        shader_path: dir.join(name),
+                    },
+                    &FragmentOutputDesc {
+                        surface_format: format,
+                        ..Default::default()
+                    },
+                    &[push_constant_range],
+                    &[texture_arena.images_set_layout],
+                )
+                .map_err(Error::pass(name))
+        };
+        Ok(Self {
+            blur: create("blur.frag", POST_TARGET_FORMAT)?,
+            bloom: create("bloom.frag", POST_TARGET_FORMAT)?,
+            fxaa: create("fxaa.frag", POST_TARGET_FORMAT)?,
+            present: create("present.frag", surface_format)?,
+        })
+    }
+
+    fn handles(&self) -> [RenderHandle; 4] {
+        [self.blur, self.bloom, self.fxaa, self.present]
+    }
+}
+
+/// Built-in passes chained after the image pass.
+///
+/// With a non-empty chain the image pass renders into an offscreen target,
+/// every step ping-pongs between two targets and a final copy writes into
+/// the swapchain image. Bloom goes down and back up its own mip chain before
+/// adding onto the chain. A fixed render resolution keeps the targets even
+/// without passes, the final copy then scales them into the window.
+#[derive(Default)]
+pub struct PostProcess {
+    pub passes: Vec<PostPass>,
+    pipelines: Option<PostPipelines>,
+    targets: Vec<usize>,
+    /// Texture arena indices of the bloom mips, largest first.
+    bloom_levels: Vec<usize>,
+    output: usize,
+}
+
+impl PostProcess {
+    pub fn is_enabled(&self) -> bool {
+        self.pipelines.is_some()
+    }
+
+    /// Texture arena indices of the offscreen targets, resized with the swapchain.
+    pub fn targets(&self) -> &[usize] {
+        &self.targets
+    }
+
+    /// Texture arena index of the float image the image pass renders into.
+    pub fn scene_target(&self) -> Option<usize> {
+        self.is_enabled().then(|| self.targets[0])
+    }
+
+    /// Texture arena index of the float image the last frame was presented from.
+    pub fn output_target(&self) -> Option<usize> {
+        self.is_enabled().then(|| self.targets[self.output])
+    }
+
+    /// Color format the image pass has to render into.
+    pub fn color_format(&self, surface_format: vk::Format) -> vk::Format {
+        match self.is_enabled() {
+            true => POST_TARGET_FORMAT,
+            false => surface_format,
+        }
+    }
+
+    pub fn configure(
+        &mut self,
+        passes: Vec<PostPass>,
+        offscreen: bool,
+        shader_dir: &Path,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &mut TextureArena,
+        surface_format: vk::Format,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let pipelines = match passes.is_empty() && !offscreen {
+            true => None,
+            false => {
+                let dir = shader_dir.join(POST_FOLDER);
+                default_shaders::create_post_shaders(&dir)?;
+                Some(PostPipelines::new(
+                    pipeline_arena,
+                    texture_arena,
+                    &dir,
+                    surface_format,
+                )?)
+            }
+        };
+        if let Some(old) = std::mem::replace(&mut self.pipelines, pipelines) {
+            for handle in old.handles() {
+                pipeline_arena.remove_pipeline(Either::Left(handle));
+            }
+        }
+
+        if self.is_enabled() && self.targets.is_empty() {
+            let info = vk::ImageCreateInfo::default()
+                .extent(vk::Extent3D {
+                    width: extent.width,
+                    height: extent.height,
+                    depth: 1,
+                })
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(POST_TARGET_FORMAT)
+                .usage(
+                    vk::ImageUsageFlags::COLOR_ATTACHMENT
+                        | vk::ImageUsageFlags::SAMPLED
+                        | vk::ImageUsageFlags::TRANSFER_SRC
+                        | vk::ImageUsageFlags::TRANSFER_DST,
+                )
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .mip_levels(1)
+                .array_layers(1)
+                .tiling(vk::ImageTiling::OPTIMAL);
+            for _ in 0..2 {
+                let idx = texture_arena.push_render_target(info)?;
+                self.targets.push(idx);
+            }
+            for level in 0..BLOOM_LEVELS {
+                let info = info.extent(bloom_extent(extent, level).into());
+                let idx = texture_arena.push_render_target(info)?;
+                self.bloom_levels.push(idx);
+            }
+        }
+
+        self.passes = passes;
+        Ok(())
+    }
+
+    /// Resizes the bloom mips after the targets got `extent`.
+    pub fn resize_bloom(
+        &self,
+        texture_arena: &mut TextureArena,
+        queue: &vk::Queue,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        for (level, &idx) in self.bloom_levels.iter().enumerate() {
+            texture_arena.image_infos[idx].extent = bloom_extent(extent, level as u32).into();
+        }
+        texture_arena.update_images(queue, &self.bloom_levels)
+    }
+
+    /// Starts the image pass in the first offscreen target, returns `false`
+    /// when the chain is disabled and the swapchain image should be used.
+    pub fn begin_scene(
+        &self,
+        frame: &mut FrameGuard,
+        device: &Device,
+        texture_arena: &TextureArena,
+        extent: vk::Extent2D,
+        color: [f32; 4],
+        attachments: SceneAttachments,
+    ) -> bool {
+        if !self.is_enabled() {
+            return false;
+        }
+        let scene = self.targets[0];
+        to_attachment(device, frame, texture_arena.images[scene].image);
+        let view = &texture_arena.views[scene];
+        frame.begin_rendering_scene(view, attachments, extent, color);
+        true
+    }
+
+    /// Runs the chain over the image pass output at `extent`.
+    pub fn apply(
+        &mut self,
+        frame: &mut FrameGuard,
+        device: &Device,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        extent: vk::Extent2D,
+    ) {
+        let Some(pipelines) = &self.pipelines else {
+            return;
+        };
+        to_sampled(device, frame, texture_arena.images[self.targets[0]].image);
+
+        let mut current = 0;
+        for step in self.steps(pipelines) {
+            let (dst, dst_extent) = match step.dst {
+                Target::Chain => (self.targets[1 - current], extent),
+                Target::Bloom(level) => (
+                    self.bloom_levels[level as usize],
+                    bloom_extent(extent, level),
+                ),
+            };
+            let src = match step.src {
+                Target::Chain => self.targets[current],
+                Target::Bloom(level) => self.bloom_levels[level as usize],
+            };
+            let push_constant = PostPushConstant {
+                resolution: [dst_extent.width as f32, dst_extent.height as f32],
+                src: src as u32,
+                aux: self.targets[current] as u32,
+                ..step.push_constant
+            };
+
+            let image = texture_arena.images[dst].image;
+            to_attachment(device, frame, image);
+            frame.begin_rendering_extent(&texture_arena.views[dst], dst_extent, [0.; 4]);
+            draw(
+                frame,
+                pipeline_arena,
+                texture_arena,
+                step.pipeline,
+                push_constant,
+            );
+            frame.end_rendering();
+            to_sampled(device, frame, image);
+
+            if step.dst == Target::Chain {
+                current = 1 - current;
+            }
+        }
+        self.output = current;
+    }
+
+    /// Writes the chain output into `view`, letterboxed on `background`
+    /// when the frame has another size than `extent`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn present(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+        background: [f32; 4],
+    ) {
+        let Some(pipelines) = &self.pipelines else {
+            return;
+        };
+        let resolution = [extent.width as f32, extent.height as f32];
+        let push_constant = PostPushConstant {
+            resolution,
+            src: self.targets[self.output] as u32,
+            ..Default::default()
+        };
+        frame.begin_rendering(view, background);
+        frame.set_area(letterbox(extent, frame.extent()));
+        draw(
+            frame,
+            pipeline_arena,
+            texture_arena,
+            pipelines.present,
+            push_constant,
+        );
+        frame.end_rendering();
+    }
+
+    fn steps(&self, pipelines: &PostPipelines) -> Vec<Step> {
+        let step = |pipeline, direction, mode, strength, src, dst| Step {
+            pipeline,
+            push_constant: PostPushConstant {
+                direction,
+                mode,
+                strength,
+                ..Default::default()
+            },
+            src,
+            dst,
+        };
+        let chain =
+            |pipeline, direction| step(pipeline, direction, 0, 0., Target::Chain, Target::Chain);
+
+        let mut steps = vec![];
+        for pass in &self.passes {
+            match *pass {
+                PostPass::Blur { radius } => {
+                    steps.push(chain(pipelines.blur, [radius, 0.]));
+                    steps.push(chain(pipelines.blur, [0., radius]));
+                }
+                PostPass::Bloom {
+                    threshold,
+                    intensity,
+                } => {
+                    // Taps half a destination pixel apart, so every bilinear
+                    // fetch averages four source texels
+                    let bloom = |mode, strength, src, dst| {
+                        step(pipelines.bloom, [0.5; 2], mode, strength, src, dst)
+                    };
+                    steps.push(bloom(
+                        BLOOM_PREFILTER,
+                        threshold,
+                        Target::Chain,
+                        Target::Bloom(0),
+                    ));
+                    for level in 1..BLOOM_LEVELS {
+                        let (src, dst) = (Target::Bloom(level - 1), Target::Bloom(level));
+                        steps.push(bloom(BLOOM_DOWNSAMPLE, 0., src, dst));
+                    }
+                    for level in (0..BLOOM_LEVELS - 1).rev() {
+                        let (src, dst) = (Target::Bloom(level + 1), Target::Bloom(level));
+                        steps.push(bloom(BLOOM_UPSAMPLE, 0., src, dst));
+                    }
+                    // The last upsample adds onto the chain output as `aux`
+                    steps.push(bloom(
+                        BLOOM_COMPOSITE,
+                        intensity,
+                        Target::Bloom(0),
+                        Target::Chain,
+                    ));
+                }
+                PostPass::Fxaa => steps.push(chain(pipelines.fxaa, [0.; 2])),
+            }
+        }
+        steps
+    }
+}
+
+/// Size of a bloom mip, never below a pixel.
+fn bloom_extent(extent: vk::Extent2D, level: u32) -> vk::Extent2D {
+    vk::Extent2D {
+        width: (extent.width >> (level + 1)).max(1),
+        height: (extent.height >> (level + 1)).max(1),
+    }
+}
+
+pub(crate) fn draw(
+    frame: &mut FrameGuard,
+    pipeline_arena: &PipelineArena,
+    texture_arena: &TextureArena,
+    handle: RenderHandle,
+    push_constant: PostPushConstant,
+) {
+    let pipeline = pipeline_arena.get_pipeline(handle);
+    frame.push_constant(
+        pipeline.layout,
+        vk::ShaderStageFlags::FRAGMENT,
+        &[push_constant],
+    );
+    frame.bind_descriptor_sets(
+        vk::PipelineBindPoint::GRAPHICS,
+        pipeline.layout,
+        &[texture_arena.images_set],
+    );
+    frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+    frame.draw(3, 0, 1, 0);
+}
+
+pub(crate) fn to_attachment(device: &Device, frame: &FrameGuard, image: vk::Image) {
+    let barrier = vk::ImageMemoryBarrier2::default()
+        .subresource_range(COLOR_SUBRESOURCE_MASK)
+        .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+        .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+        .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+        .old_layout(vk::ImageLayout::UNDEFINED)
+        .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .image(image);
+    unsafe {
+        device.cmd_pipeline_barrier2(
+            *frame.command_buffer(),
+            &vk::DependencyInfo::default().image_memory_barriers(std::slice::from_ref(&barrier)),
+        )
+    };
+}
+
+pub(crate) fn to_sampled(device: &Device, frame: &FrameGuard, image: vk::Image) {
+    let barrier = vk::ImageMemoryBarrier2::default()
+        .subresource_range(COLOR_SUBRESOURCE_MASK)
+        .src_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+        .src_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+        .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+        .dst_access_mask(vk::AccessFlags2::SHADER_SAMPLED_READ)
+        .old_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+        .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        .image(image);
+    unsafe {
+        device.cmd_pipeline_barrier2(
+            *frame.command_buffer(),
+            &vk::DependencyInfo::default().image_memory_barriers(std::slice::from_ref(&barrier)),
+        )
+    };
+}
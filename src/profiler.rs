@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::Device;
+
+/// Frames whose queries can be in flight, a frame finding its pool still
+/// busy goes unmeasured.
+const RING: usize = 8;
+const MAX_QUERIES: u32 = 32;
+/// Weight of the newest frame in the averaged timings.
+const SMOOTHING: f32 = 0.1;
+
+struct QuerySlot {
+    pool: vk::QueryPool,
+    /// Pass ending at each query after the first.
+    labels: Vec<&'static str>,
+    pending: bool,
+}
+
+/// GPU time of every pass, measured with timestamps written between them.
+pub struct GpuProfiler {
+    slots: Vec<QuerySlot>,
+    current: usize,
+    recording: bool,
+    /// Nanoseconds per timestamp tick.
+    period: f32,
+    timings: Vec<(&'static str, f32)>,
+    device: Arc<Device>,
+}
+
+impl GpuProfiler {
+    pub fn new(device: &Arc<Device>) -> Result<Self> {
+        let limits = device.device_properties.limits;
+        let mut slots = vec![];
+        if limits.timestamp_compute_and_graphics == vk::TRUE {
+            for i in 0..RING {
+                let info = vk::QueryPoolCreateInfo::default()
+                    .query_type(vk::QueryType::TIMESTAMP)
+                    .query_count(MAX_QUERIES);
+                let pool = unsafe { device.create_query_pool(&info, None)? };
+                device.name_object(pool, &format!("Timestamps {i}"));
+                slots.push(QuerySlot {
+                    pool,
+                    labels: vec![],
+                    pending: false,
+                });
+            }
+        } else {
+            log::warn!("The queue doesn't support timestamps, GPU timings are disabled");
+        }
+
+        Ok(Self {
+            slots,
+            current: 0,
+            recording: false,
+            period: limits.timestamp_period,
+            timings: vec![],
+            device: device.clone(),
+        })
+    }
+
+    /// Collects the timings of the oldest frame in the ring and starts
+    /// measuring the one recorded into `cbuff`.
+    pub fn begin_frame(&mut self, cbuff: vk::CommandBuffer) {
+        self.recording = false;
+        if self.slots.is_empty() {
+            return;
+        }
+        self.current = (self.current + 1) % self.slots.len();
+        let slot = &mut self.slots[self.current];
+        if slot.pending {
+            let mut ticks = vec![0u64; slot.labels.len() + 1];
+            let results = unsafe {
+                self.device.get_query_pool_results(
+                    slot.pool,
+                    0,
+                    &mut ticks,
+                    vk::QueryResultFlags::TYPE_64,
+                )
+            };
+            match results {
+                Ok(()) => {}
+                Err(vk::Result::NOT_READY) => return,
+                Err(err) => {
+                    log::warn!("Failed to read timestamps: {err}");
+                    return;
+                }
+            }
+            let timings = slot
+                .labels
+                .iter()
+                .zip(ticks.windows(2))
+                .map(|(&label, pair)| {
+                    let ms = pair[1].saturating_sub(pair[0]) as f32 * self.period / 1e6;
+                    let previous = self.timings.iter().find(|(l, _)| *l == label);
+                    let ms = previous.map_or(ms, |&(_, avg)| avg + (ms - avg) * SMOOTHING);
+                    (label, ms)
+                })
+                .collect();
+            self.timings = timings;
+            slot.labels.clear();
+        }
+
+        unsafe {
+            self.device
+                .cmd_reset_query_pool(cbuff, slot.pool, 0, MAX_QUERIES);
+            self.device.cmd_write_timestamp2(
+                cbuff,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                slot.pool,
+                0,
+            );
+        }
+        slot.pending = true;
+        self.recording = true;
+    }
+
+    /// Ends the pass `label` once every command recorded before has finished.
+    pub fn mark(&mut self, cbuff: vk::CommandBuffer, label: &'static str) {
+        if !self.recording {
+            return;
+        }
+        let slot = &mut self.slots[self.current];
+        let query = slot.labels.len() as u32 + 1;
+        if query >= MAX_QUERIES {
+            return;
+        }
+        unsafe {
+            self.device.cmd_write_timestamp2(
+                cbuff,
+                vk::PipelineStageFlags2::ALL_COMMANDS,
+                slot.pool,
+                query,
+            )
+        };
+        slot.labels.push(label);
+    }
+
+    /// Averaged milliseconds of the whole frame, once measured.
+    pub fn total_ms(&self) -> Option<f32> {
+        match self.timings.is_empty() {
+            true => None,
+            false => Some(self.timings.iter().map(|(_, ms)| ms).sum()),
+        }
+    }
+
+    /// Averaged milliseconds per pass in frame order.
+    pub fn timings(&self) -> &[(&'static str, f32)] {
+        &self.timings
+    }
+}
+
+impl std::fmt::Display for GpuProfiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.timings.is_empty() {
+            return writeln!(f, "gpu:\t\tno timings");
+        }
+        for (label, ms) in &self.timings {
+            writeln!(f, "gpu {label}:\t{ms:.3} ms")?;
+        }
+        let total = self.total_ms().unwrap_or_default();
+        writeln!(f, "gpu total:\t{total:.3} ms")
+    }
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        for slot in &self.slots {
+            unsafe { self.device.destroy_query_pool(slot.pool, None) };
+        }
+    }
+}
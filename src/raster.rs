@@ -0,0 +1,159 @@
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{Device, VertexShaderDesc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PolygonMode {
+    Fill,
+    Line,
+    Point,
+}
+
+impl From<PolygonMode> for vk::PolygonMode {
+    fn from(mode: PolygonMode) -> Self {
+        match mode {
+            PolygonMode::Fill => vk::PolygonMode::FILL,
+            PolygonMode::Line => vk::PolygonMode::LINE,
+            PolygonMode::Point => vk::PolygonMode::POINT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
+    FrontAndBack,
+}
+
+impl From<CullMode> for vk::CullModeFlags {
+    fn from(mode: CullMode) -> Self {
+        match mode {
+            CullMode::None => vk::CullModeFlags::NONE,
+            CullMode::Front => vk::CullModeFlags::FRONT,
+            CullMode::Back => vk::CullModeFlags::BACK,
+            CullMode::FrontAndBack => vk::CullModeFlags::FRONT_AND_BACK,
+        }
+    }
+}
+
+/// Winding of the front facing triangles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FrontFace {
+    Ccw,
+    Cw,
+}
+
+impl From<FrontFace> for vk::FrontFace {
+    fn from(face: FrontFace) -> Self {
+        match face {
+            FrontFace::Ccw => vk::FrontFace::COUNTER_CLOCKWISE,
+            FrontFace::Cw => vk::FrontFace::CLOCKWISE,
+        }
+    }
+}
+
+/// Rasterizer state of one graphics pipeline, unset fields keep the
+/// pipeline's own defaults.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RasterState {
+    pub polygon: Option<PolygonMode>,
+    pub cull: Option<CullMode>,
+    pub front_face: Option<FrontFace>,
+}
+
+impl RasterState {
+    pub fn apply(self, desc: VertexShaderDesc) -> VertexShaderDesc {
+        VertexShaderDesc {
+            polygon_mode: self.polygon.map_or(desc.polygon_mode, Into::into),
+            cull_mode: self.cull.map_or(desc.cull_mode, Into::into),
+            front_face: self.front_face.map_or(desc.front_face, Into::into),
+            ..desc
+        }
+    }
+
+    /// The polygon mode of a scene pipeline, they all start from fill.
+    pub fn polygon_mode(self) -> vk::PolygonMode {
+        self.polygon.map_or(vk::PolygonMode::FILL, Into::into)
+    }
+}
+
+/// The `[raster]` table of the manifest, the rasterizer state of every
+/// pipeline drawn into the scene.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RasterSettings {
+    pub image: RasterState,
+    pub passes: RasterState,
+    pub particles: RasterState,
+    pub mesh: RasterState,
+}
+
+/// Rasterizer state of the scene pipelines with the wireframe toggle on top.
+#[derive(Default)]
+pub struct SceneRaster {
+    settings: RasterSettings,
+    /// `fillModeNonSolid` is enabled, lines and points fall back to fill
+    /// without it.
+    non_solid: bool,
+    wireframe: bool,
+}
+
+impl SceneRaster {
+    pub fn configure(&mut self, settings: RasterSettings, device: &Device) {
+        self.non_solid = device.enabled_features.fill_mode_non_solid == vk::TRUE;
+        let states = [
+            settings.image,
+            settings.passes,
+            settings.particles,
+            settings.mesh,
+        ];
+        let non_solid = states
+            .iter()
+            .any(|state| state.polygon.is_some_and(|mode| mode != PolygonMode::Fill));
+        if non_solid && !self.non_solid {
+            log::warn!("The GPU doesn't support line and point polygon modes, filling instead");
+        }
+        self.settings = settings;
+    }
+
+    /// Flips the wireframe toggle, `false` when the GPU can't draw it.
+    pub fn toggle_wireframe(&mut self) -> bool {
+        if !self.non_solid {
+            return false;
+        }
+        self.wireframe = !self.wireframe;
+        true
+    }
+
+    fn state(&self, state: RasterState) -> RasterState {
+        let polygon = match (self.wireframe, self.non_solid) {
+            (true, _) => Some(PolygonMode::Line),
+            (false, true) => state.polygon,
+            (false, false) => None,
+        };
+        RasterState { polygon, ..state }
+    }
+
+    pub fn image(&self) -> RasterState {
+        self.state(self.settings.image)
+    }
+
+    pub fn passes(&self) -> RasterState {
+        self.state(self.settings.passes)
+    }
+
+    pub fn particles(&self) -> RasterState {
+        self.state(self.settings.particles)
+    }
+
+    pub fn mesh(&self) -> RasterState {
+        self.state(self.settings.mesh)
+    }
+}
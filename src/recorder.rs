@@ -1,29 +1,420 @@
-use anyhow::{Context, Result};
+use ahash::AHashSet;
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
 use std::{
     fs::File,
-    io::{BufWriter, Write},
-    path::Path,
+    io::{BufWriter, Seek, Write},
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
+    str::FromStr,
+    sync::Arc,
     thread::JoinHandle,
     time::Instant,
 };
 
-use crate::{create_folder, ImageDimensions, ManagedImage, SCREENSHOT_FOLDER, VIDEO_FOLDER};
+use crate::{
+    create_folder, linear_to_srgb, rgba_rows, srgb_to_linear, ExportCheckpoint, ImageDimensions,
+    ManagedImage, SCREENSHOT_FOLDER, VIDEO_FOLDER, VIDEO_FRAMERATE,
+};
 use crossbeam_channel::{Receiver, Sender};
 
 pub enum RecordEvent {
-    Start(ImageDimensions),
-    Record(ManagedImage),
+    Start(ImageDimensions, Option<PathBuf>, VideoSettings),
+    /// Continues a checkpointed export with a new segment.
+    Resume(
+        ImageDimensions,
+        Option<PathBuf>,
+        VideoSettings,
+        ExportCheckpoint,
+    ),
+    /// A frame written this many times in a row.
+    Record(ManagedImage, u32),
+    Chapter {
+        title: String,
+        split: bool,
+    },
+    /// Finishes the segment and saves the checkpoint, the thread fills in the
+    /// parts and frames.
+    Checkpoint(ExportCheckpoint),
     Finish,
-    Screenshot(ManagedImage),
+    /// A frame with its memory already mapped, shared with the capture cache.
+    Screenshot(Arc<ManagedImage>, ScreenshotSettings),
     CloseThread,
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    #[default]
+    X264,
+    X265,
+    Vp9,
+    Av1,
+}
+
+impl VideoCodec {
+    pub const ALL: [Self; 4] = [Self::X264, Self::X265, Self::Vp9, Self::Av1];
+
+    /// Name taken by `--codec` and the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoCodec::X264 => "x264",
+            VideoCodec::X265 => "x265",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1 => "av1",
+        }
+    }
+
+    pub fn encoder(self) -> &'static str {
+        match self {
+            VideoCodec::X264 => "libx264",
+            VideoCodec::X265 => "libx265",
+            VideoCodec::Vp9 => "libvpx-vp9",
+            VideoCodec::Av1 => "libaom-av1",
+        }
+    }
+
+    /// WebM only takes the open codecs and QuickTime only the H.26x ones,
+    /// other containers are left for ffmpeg to judge.
+    pub fn fits(self, container: &str) -> bool {
+        match container {
+            "webm" => matches!(self, VideoCodec::Vp9 | VideoCodec::Av1),
+            "mov" => matches!(self, VideoCodec::X264 | VideoCodec::X265),
+            _ => true,
+        }
+    }
+
+    /// Each encoder has its own CRF scale, these are roughly equal in quality.
+    pub fn default_crf(self) -> u32 {
+        match self {
+            VideoCodec::X264 => 23,
+            VideoCodec::X265 => 28,
+            VideoCodec::Vp9 => 31,
+            VideoCodec::Av1 => 30,
+        }
+    }
+}
+
+impl FromStr for VideoCodec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "x264" | "h264" => VideoCodec::X264,
+            "x265" | "h265" | "hevc" => VideoCodec::X265,
+            "vp9" => VideoCodec::Vp9,
+            "av1" => VideoCodec::Av1,
+            _ => bail!(
+                "Unknown video codec `{s}`, expected one of: {}",
+                VideoCodec::ALL.map(VideoCodec::name).join(", ")
+            ),
+        })
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    #[serde(alias = "jpg")]
+    Jpeg,
+    Bmp,
+    #[serde(alias = "tif")]
+    Tiff,
+}
+
+impl ImageFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Bmp => "bmp",
+            ImageFormat::Tiff => "tiff",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PngCompression {
+    Fast,
+    #[default]
+    Default,
+    Best,
+}
+
+impl From<PngCompression> for png::Compression {
+    fn from(value: PngCompression) -> Self {
+        match value {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Default => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        }
+    }
+}
+
+/// Read from the `[screenshot]` table of the manifest. Float captures are
+/// always written as EXR.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScreenshotSettings {
+    pub format: ImageFormat,
+    /// JPEG quality in `1..=100`.
+    pub quality: u8,
+    pub compression: PngCompression,
+    /// Frames averaged into a converged screenshot.
+    pub samples: u32,
+    /// Save screenshots of an HDR surface as EXR, like `F9`.
+    pub hdr_exr: bool,
+}
+
+impl Default for ScreenshotSettings {
+    fn default() -> Self {
+        Self {
+            format: ImageFormat::Png,
+            quality: 90,
+            compression: PngCompression::Default,
+            samples: 64,
+            hdr_exr: false,
+        }
+    }
+}
+
+/// Averages frames of a noisy shader in linear space into one still.
+pub struct Accumulation {
+    samples: u32,
+    added: u32,
+    width: usize,
+    height: usize,
+    /// Linear RGBA sums, row-major.
+    sum: Vec<f32>,
+}
+
+impl Accumulation {
+    pub fn new(samples: u32) -> Self {
+        Self {
+            samples: samples.max(1),
+            added: 0,
+            width: 0,
+            height: 0,
+            sum: vec![],
+        }
+    }
+
+    pub fn add(&mut self, mut image: ManagedImage) -> Result<()> {
+        let dims = image.image_dimensions;
+        let format = image.format;
+        let data = image.map_memory()?;
+        if self.sum.is_empty() {
+            self.width = dims.width;
+            self.height = dims.height;
+            self.sum = vec![0.; dims.width * dims.height * 4];
+        } else if (dims.width, dims.height) != (self.width, self.height) {
+            bail!("The output was resized while converging");
+        }
+        let rows = rgba_rows(data, dims, format);
+        for (row, sums) in rows.zip(self.sum.chunks_exact_mut(self.width * 4)) {
+            for (i, (&value, sum)) in row.iter().zip(sums).enumerate() {
+                let value = value as f32 / 255.;
+                *sum += if i % 4 == 3 {
+                    value
+                } else {
+                    srgb_to_linear(value)
+                };
+            }
+        }
+        self.added += 1;
+        Ok(())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.added >= self.samples
+    }
+
+    /// Encodes the average on a background thread.
+    pub fn save(self, settings: ScreenshotSettings) {
+        std::thread::spawn(move || {
+            let count = self.added.max(1) as f32;
+            let pixels: Vec<u8> = self
+                .sum
+                .iter()
+                .enumerate()
+                .map(|(i, &sum)| {
+                    let value = sum / count;
+                    let value = if i % 4 == 3 {
+                        value
+                    } else {
+                        linear_to_srgb(value)
+                    };
+                    (value.clamp(0., 1.) * 255. + 0.5) as u8
+                })
+                .collect();
+            let dims = ImageDimensions::new(self.width, self.height, 1);
+            match save_screenshot(&pixels, dims, vk::Format::R8G8B8A8_SRGB, settings) {
+                Ok(()) => println!("Saved screenshot converged over {} frames", self.added),
+                Err(err) => log::error!("{err}"),
+            }
+        });
+    }
+}
+
+/// Encode settings, every field left empty falls back to a default.
+///
+/// Read from the `[record]` table of the manifest and overridden by CLI flags.
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VideoSettings {
+    pub codec: Option<VideoCodec>,
+    pub crf: Option<u32>,
+    /// Target bitrate in ffmpeg notation (`8M`), takes precedence over `crf`.
+    pub bitrate: Option<String>,
+    pub pixel_format: Option<String>,
+    /// File extension of the output, `mp4`, `mkv`, `webm`, `mov` or `gif`.
+    pub container: Option<String>,
+    /// Frame rate the video is captured and encoded at, `VIDEO_FRAMERATE`
+    /// when unset.
+    pub fps: Option<u32>,
+    /// Stop writing frames after this many seconds.
+    pub max_duration: Option<f32>,
+    /// SRT or ASS captions burned into the video, timed from the start of
+    /// the recording. Relative to the shader folder in the manifest.
+    pub captions: Option<PathBuf>,
+}
+
+impl VideoSettings {
+    /// Fields set in `other` win.
+    pub fn merge(self, other: Self) -> Self {
+        Self {
+            codec: other.codec.or(self.codec),
+            crf: other.crf.or(self.crf),
+            bitrate: other.bitrate.or(self.bitrate),
+            pixel_format: other.pixel_format.or(self.pixel_format),
+            container: other.container.or(self.container),
+            fps: other.fps.or(self.fps),
+            max_duration: other.max_duration.or(self.max_duration),
+            captions: other.captions.or(self.captions),
+        }
+    }
+
+    /// Resolves the paths of the manifest against its folder.
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        self.captions = self.captions.map(|path| dir.join(path));
+        self
+    }
+
+    pub fn is_gif(&self) -> bool {
+        self.container() == "gif"
+    }
+
+    pub fn encoder(&self) -> &str {
+        match self.is_gif() {
+            true => "gif",
+            false => self.codec().encoder(),
+        }
+    }
+
+    /// Frames per second of video.
+    pub fn capture_rate(&self) -> u64 {
+        self.fps.map_or(VIDEO_FRAMERATE, |fps| fps.max(1) as u64)
+    }
+
+    fn max_frames(&self) -> Option<u64> {
+        self.max_duration
+            .map(|secs| (secs * self.capture_rate() as f32).round() as u64)
+    }
+
+    pub fn codec(&self) -> VideoCodec {
+        self.codec.unwrap_or_default()
+    }
+
+    pub fn pixel_format(&self) -> &str {
+        self.pixel_format.as_deref().unwrap_or("yuv444p")
+    }
+
+    pub fn container(&self) -> &str {
+        self.container.as_deref().unwrap_or("mp4")
+    }
+
+    pub fn muxer(&self) -> &str {
+        match self.container() {
+            "mkv" => "matroska",
+            container => container,
+        }
+    }
+
+    fn faststart(&self) -> bool {
+        matches!(self.container(), "mp4" | "mov")
+    }
+}
+
+/// Encoders and muxers reported by the installed ffmpeg.
+#[derive(Debug, Default)]
+pub struct FfmpegCaps {
+    pub encoders: AHashSet<String>,
+    pub muxers: AHashSet<String>,
+}
+
+impl FfmpegCaps {
+    pub fn probe() -> Result<Self> {
+        let list = |arg: &str| -> Result<String> {
+            let output = Command::new("ffmpeg")
+                .args(["-hide_banner", arg])
+                .output()
+                .with_context(|| format!("Failed to run `ffmpeg {arg}`"))?;
+            Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+        };
+
+        Ok(Self {
+            encoders: parse_ffmpeg_list(&list("-encoders")?, "------"),
+            muxers: parse_ffmpeg_list(&list("-muxers")?, "--"),
+        })
+    }
+
+    pub fn check(&self, video: &VideoSettings) -> Result<()> {
+        let (encoder, muxer) = (video.encoder(), video.muxer());
+        if !self.encoders.contains(encoder) {
+            let alternatives: Vec<_> = VideoCodec::ALL
+                .into_iter()
+                .filter(|codec| codec.fits(video.container()))
+                .filter(|codec| self.encoders.contains(codec.encoder()))
+                .map(VideoCodec::name)
+                .collect();
+            let hint = match alternatives.is_empty() || video.is_gif() {
+                true => String::new(),
+                false => format!(" or pick an available codec: {}", alternatives.join(", ")),
+            };
+            bail!("ffmpeg has no `{encoder}` encoder: install ffmpeg built with {encoder}{hint}");
+        }
+        if !self.muxers.contains(muxer) {
+            bail!("ffmpeg has no `{muxer}` muxer: install ffmpeg with {muxer} container support");
+        }
+        Ok(())
+    }
+}
+
+fn parse_ffmpeg_list(output: &str, separator: &str) -> AHashSet<String> {
+    output
+        .lines()
+        .skip_while(|line| line.trim() != separator)
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .flat_map(|names| names.split(','))
+        .map(String::from)
+        .collect()
+}
+
 pub struct Recorder {
     pub sender: Sender<RecordEvent>,
     ffmpeg_installed: bool,
     pub ffmpeg_version: String,
+    pub ffmpeg_caps: Option<FfmpegCaps>,
     pub thread_handle: Option<JoinHandle<()>>,
+    pub split_chapters: bool,
+    pub audio_file: Option<PathBuf>,
+    pub video: VideoSettings,
+    pub screenshot: ScreenshotSettings,
     is_active: bool,
 }
 
@@ -33,17 +424,24 @@ impl Recorder {
         command.arg("-version");
         let (version, installed) = match command.output() {
             Ok(output) => (
-                String::from_utf8(output.stdout)
-                    .unwrap()
+                String::from_utf8_lossy(&output.stdout)
                     .lines()
                     .next()
-                    .unwrap()
+                    .unwrap_or_default()
                     .to_string(),
                 true,
             ),
             Err(e) => (e.to_string(), false),
         };
 
+        let ffmpeg_caps = installed
+            .then(FfmpegCaps::probe)
+            .transpose()
+            .unwrap_or_else(|err| {
+                log::warn!("{err}");
+                None
+            });
+
         let (tx, rx) = crossbeam_channel::unbounded();
         let thread_handle = std::thread::spawn(move || record_thread(rx));
 
@@ -51,7 +449,12 @@ impl Recorder {
             sender: tx,
             ffmpeg_installed: installed,
             ffmpeg_version: version,
+            ffmpeg_caps,
             thread_handle: Some(thread_handle),
+            split_chapters: false,
+            audio_file: None,
+            video: VideoSettings::default(),
+            screenshot: ScreenshotSettings::default(),
             is_active: false,
         }
     }
@@ -64,20 +467,83 @@ impl Recorder {
         self.ffmpeg_installed
     }
 
-    pub fn screenshot(&self, image: ManagedImage) {
+    pub fn screenshot(&self, image: Arc<ManagedImage>) {
         let _ = self
             .sender
-            .send(RecordEvent::Screenshot(image))
+            .send(RecordEvent::Screenshot(image, self.screenshot))
             .context("Failed to send screenshot");
     }
 
-    pub fn start(&mut self, dims: ImageDimensions) {
+    /// Verifies that ffmpeg is able to produce the configured output.
+    pub fn check_requirements(&self) -> Result<()> {
+        if !self.ffmpeg_installed {
+            bail!("ffmpeg is not installed: {}", self.ffmpeg_version);
+        }
+        if let Some(captions) = self.video.captions.as_ref().filter(|path| !path.is_file()) {
+            bail!("Captions {} don't exist", captions.display());
+        }
+        let codec = self.video.codec();
+        if !self.video.is_gif() && !codec.fits(self.video.container()) {
+            let container = self.video.container();
+            let fitting: Vec<_> = VideoCodec::ALL
+                .into_iter()
+                .filter(|codec| codec.fits(container))
+                .map(VideoCodec::name)
+                .collect();
+            bail!(
+                "{} can't go into a {container} file, use one of: {}",
+                codec.name(),
+                fitting.join(", ")
+            );
+        }
+        match &self.ffmpeg_caps {
+            Some(caps) => caps.check(&self.video),
+            None => Ok(()),
+        }
+    }
+
+    pub fn start(&mut self, dims: ImageDimensions) -> Result<()> {
+        self.check_requirements()?;
         self.is_active = true;
-        self.send(RecordEvent::Start(dims));
+        self.send(RecordEvent::Start(
+            dims,
+            self.audio_file.clone(),
+            self.video.clone(),
+        ));
+        Ok(())
     }
 
-    pub fn record(&self, image: ManagedImage) {
-        self.send(RecordEvent::Record(image));
+    /// Starts the next segment of the export `checkpoint` was saved by.
+    pub fn resume(&mut self, dims: ImageDimensions, checkpoint: ExportCheckpoint) -> Result<()> {
+        self.check_requirements()?;
+        self.is_active = true;
+        self.send(RecordEvent::Resume(
+            dims,
+            self.audio_file.clone(),
+            self.video.clone(),
+            checkpoint,
+        ));
+        Ok(())
+    }
+
+    pub fn checkpoint(&self, checkpoint: ExportCheckpoint) {
+        if self.is_active {
+            self.send(RecordEvent::Checkpoint(checkpoint));
+        }
+    }
+
+    pub fn record(&self, image: ManagedImage, copies: u32) {
+        self.send(RecordEvent::Record(image, copies));
+    }
+
+    /// Marks the start of a new chapter in the active recording.
+    pub fn chapter(&self, title: impl Into<String>) {
+        if self.is_active {
+            self.send(RecordEvent::Chapter {
+                title: title.into(),
+                split: self.split_chapters,
+            });
+        }
     }
 
     pub fn finish(&mut self) {
@@ -86,52 +552,290 @@ impl Recorder {
     }
 
     pub fn close_thread(&self) {
-        self.sender.send(RecordEvent::CloseThread).unwrap();
+        let _ = self.sender.send(RecordEvent::CloseThread);
     }
 
     pub fn send(&self, event: RecordEvent) {
-        if !(self.ffmpeg_installed || matches!(event, RecordEvent::Screenshot(_))) {
+        if !(self.ffmpeg_installed || matches!(event, RecordEvent::Screenshot(..))) {
             return;
         }
-        self.sender.send(event).unwrap()
+        if self.sender.send(event).is_err() {
+            log::error!("The recorder thread stopped, nothing is captured");
+        }
     }
 }
 
 struct RecorderThread {
     process: Child,
+    filename: PathBuf,
+    image_dimensions: ImageDimensions,
+    video: VideoSettings,
+    audio_file: Option<PathBuf>,
+    audio_offset: u64,
+    frames: u64,
+    chapters: Vec<(u64, String)>,
+}
+
+impl RecorderThread {
+    fn new(
+        image_dimensions: ImageDimensions,
+        video: VideoSettings,
+        part: Option<usize>,
+        audio_file: Option<PathBuf>,
+        audio_offset: u64,
+    ) -> Result<Self> {
+        let filename = new_video_filename(part, video.container())?;
+        let process = new_ffmpeg_command(
+            image_dimensions,
+            &video,
+            &filename,
+            audio_file.as_deref(),
+            audio_offset,
+        )?;
+        Ok(Self {
+            process,
+            filename,
+            image_dimensions,
+            video,
+            audio_file,
+            audio_offset,
+            frames: 0,
+            chapters: vec![],
+        })
+    }
+
+    fn finish(mut self) -> Result<()> {
+        self.process.wait()?;
+        if self.chapters.len() > 1 && !self.video.is_gif() {
+            self.write_chapters()?;
+        }
+        println!("Recording finished: {}", self.filename.display());
+        Ok(())
+    }
+
+    /// Remuxes the finished video with chapter markers in place.
+    fn write_chapters(&self) -> Result<()> {
+        let rate = self.video.capture_rate();
+        let to_ms = |frame: u64| frame * 1000 / rate;
+        let mut metadata = String::from(";FFMETADATA1\n");
+        for (i, (start, title)) in self.chapters.iter().enumerate() {
+            let end = self.chapters.get(i + 1).map_or(self.frames, |c| c.0);
+            metadata += &format!(
+                "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                to_ms(*start),
+                to_ms(end),
+                escape_ffmetadata(title)
+            );
+        }
+        let metadata_path = self.filename.with_extension("ffmeta");
+        let chaptered_path = self
+            .filename
+            .with_extension(format!("chapters.{}", self.video.container()));
+        std::fs::write(&metadata_path, metadata)?;
+
+        let mut command = Command::new("ffmpeg");
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+            .arg(&self.filename)
+            .args(["-f", "ffmetadata", "-i"])
+            .arg(&metadata_path)
+            .args(["-map", "0", "-map_metadata", "1", "-map_chapters", "1"])
+            .args(["-c", "copy"]);
+        if self.video.faststart() {
+            command.args(["-movflags", "+faststart"]);
+        }
+        let status = command.arg(&chaptered_path).status();
+        let _ = std::fs::remove_file(&metadata_path);
+        if !status?.success() {
+            bail!("Failed to write chapters into {}", self.filename.display());
+        }
+        std::fs::rename(&chaptered_path, &self.filename)?;
+        Ok(())
+    }
+}
+
+/// Joins the segments of a checkpointed export into one video and deletes
+/// them with the checkpoint.
+fn join_parts(parts: &[PathBuf], video: &VideoSettings) -> Result<()> {
+    let filename = new_video_filename(None, video.container())?;
+    let list_path = filename.with_extension("parts.txt");
+    let mut list = String::new();
+    for part in parts {
+        let path = part.canonicalize()?;
+        list += &format!("file '{}'\n", path.to_string_lossy().replace('\'', "'\\''"));
+    }
+    std::fs::write(&list_path, list)?;
+
+    let mut command = Command::new("ffmpeg");
+    command
+        .args(["-hide_banner", "-loglevel", "error", "-y"])
+        .args(["-f", "concat", "-safe", "0", "-i"])
+        .arg(&list_path)
+        .args(["-c", "copy"]);
+    if video.faststart() {
+        command.args(["-movflags", "+faststart"]);
+    }
+    let status = command.arg(&filename).status();
+    let _ = std::fs::remove_file(&list_path);
+    if !status?.success() {
+        bail!("Failed to join the export segments, they are kept with the checkpoint");
+    }
+    for part in parts {
+        let _ = std::fs::remove_file(part);
+    }
+    ExportCheckpoint::remove();
+    println!("Export finished: {}", filename.display());
+    Ok(())
+}
+
+fn escape_ffmetadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn new_video_filename(part: Option<usize>, extension: &str) -> Result<PathBuf> {
+    create_folder(VIDEO_FOLDER)?;
+    let timestamp = chrono::Local::now().format("%Y-%m-%d_%H-%M-%S");
+    let name = match part {
+        Some(part) => format!("record-{timestamp}-{part}.{extension}"),
+        None => format!("record-{timestamp}.{extension}"),
+    };
+    Ok(Path::new(VIDEO_FOLDER).join(name))
 }
 
-fn new_ffmpeg_command(image_dimensions: ImageDimensions, filename: &str) -> Result<RecorderThread> {
+/// Picks a lossless way to carry the source audio into the video container.
+fn audio_codec(audio_file: &Path, container: &str) -> &'static str {
+    if container == "webm" {
+        return "libopus";
+    }
+    let extension = audio_file
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase());
+    match extension.as_deref() {
+        Some("wav" | "aif" | "aiff") => "alac",
+        _ => "copy",
+    }
+}
+
+fn new_ffmpeg_command(
+    image_dimensions: ImageDimensions,
+    video: &VideoSettings,
+    filename: &Path,
+    audio_file: Option<&Path>,
+    audio_offset: u64,
+) -> Result<Child> {
     #[rustfmt::skip]
     let args = [
-        "-framerate", "60",
         "-pix_fmt", "rgba",
         "-f", "rawvideo",
         // "-vcodec", "rawvideo",
         "-i", "pipe:",
-        "-c:v", "libx264",
-        "-crf", "23",
         // "-preset", "ultrafast",
         // "-tune", "animation",
-        // "-color_primaries", "bt709",
-        // "-color_trc", "bt709",
-        // "-colorspace", "bt709",
+        // "-y",
+    ];
+    // Frames are sRGB, tagged so players don't guess the transfer or matrix
+    #[rustfmt::skip]
+    let color_tags = [
+        "-color_primaries", "bt709",
+        "-color_trc", "iec61966-2-1",
+        "-colorspace", "bt709",
         "-color_range", "tv",
         "-chroma_sample_location", "center",
-        // "-pix_fmt", "yuv420p",
-        "-movflags", "+faststart",
-        "-vf", "scale=sws_flags=lanczos:in_color_matrix=bt709,format=yuv444p",
-        // "-y",
     ];
 
     let mut command = Command::new("ffmpeg");
     command
+        .arg("-framerate")
+        .arg(video.capture_rate().to_string())
         .arg("-video_size")
         .arg(format!(
             "{}x{}",
             image_dimensions.width, image_dimensions.height
         ))
-        .args(args)
+        .args(args);
+
+    // Burned in before scaling so they land on the frames as rendered
+    let captions = video.captions.as_ref().map_or(String::new(), |path| {
+        let path = path.canonicalize().unwrap_or_else(|_| path.clone());
+        format!(
+            "subtitles={},",
+            escape_filter_value(&path.to_string_lossy())
+        )
+    });
+    if video.is_gif() {
+        // One pass palette: `palettegen` buffers the whole stream and emits
+        // the palette at the end, `paletteuse` then dithers every frame.
+        let fps = video.fps.unwrap_or(25);
+        command.arg("-vf").arg(format!(
+            "{captions}fps={fps},split[frames][stats];[stats]palettegen=stats_mode=diff[palette];\
+             [frames][palette]paletteuse=dither=sierra2_4a:diff_mode=rectangle"
+        ));
+        command.args(["-loop", "0"]);
+        return spawn_ffmpeg(command, filename);
+    }
+
+    let codec = video.codec();
+    command.args(["-c:v", codec.encoder()]);
+    match &video.bitrate {
+        Some(bitrate) => {
+            command.arg("-b:v").arg(bitrate);
+        }
+        None => {
+            let crf = video.crf.unwrap_or(codec.default_crf());
+            command.arg("-crf").arg(crf.to_string());
+            // libvpx and libaom only run in constant quality mode without a bitrate cap
+            if matches!(codec, VideoCodec::Vp9 | VideoCodec::Av1) {
+                command.args(["-b:v", "0"]);
+            }
+        }
+    }
+    command.arg("-vf").arg(format!(
+        "{captions}scale=sws_flags=lanczos:out_color_matrix=bt709:out_range=tv,format={}",
+        video.pixel_format()
+    ));
+    command.args(color_tags);
+    if video.faststart() {
+        command.args(["-movflags", "+faststart"]);
+    }
+
+    if let Some(audio_file) = audio_file {
+        let offset = audio_offset as f64 / video.capture_rate() as f64;
+        command
+            .arg("-ss")
+            .arg(format!("{offset:.6}"))
+            .arg("-i")
+            .arg(audio_file)
+            .args(["-map", "0:v", "-map", "1:a", "-shortest"])
+            .args(["-c:a", audio_codec(audio_file, video.container())]);
+    }
+    spawn_ffmpeg(command, filename)
+}
+
+/// Escapes a filter option value, then the filtergraph description around it.
+fn escape_filter_value(value: &str) -> String {
+    let escape = |value: &str, special: &[char]| {
+        let mut escaped = String::with_capacity(value.len());
+        for c in value.chars() {
+            if special.contains(&c) {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    };
+    let option = escape(value, &['\\', '\'', ':']);
+    escape(&option, &['\\', '\'', '[', ']', ',', ';'])
+}
+
+fn spawn_ffmpeg(mut command: Command, filename: &Path) -> Result<Child> {
+    command
         .arg(filename)
         .stdin(Stdio::piped())
         .stdout(Stdio::inherit())
@@ -144,33 +848,54 @@ fn new_ffmpeg_command(image_dimensions: ImageDimensions, filename: &str) -> Resu
         command.creation_flags(WINAPI_UM_WINBASE_CREATE_NO_WINDOW);
     }
 
-    let child = command.spawn()?;
-
-    Ok(RecorderThread { process: child })
+    Ok(command.spawn()?)
 }
 
 fn record_thread(rx: Receiver<RecordEvent>) {
-    let mut recorder = None;
+    let mut recorder: Option<RecorderThread> = None;
+    let mut part = 0;
+    // Segments of a checkpointed export
+    let mut parts: Option<Vec<PathBuf>> = None;
 
     while let Ok(event) = rx.recv() {
         match event {
-            RecordEvent::Start(image_dimensions) => {
-                create_folder(VIDEO_FOLDER).unwrap();
-                let dir_path = Path::new(VIDEO_FOLDER);
-                let filename = dir_path.join(format!(
-                    "record-{}.mp4",
-                    chrono::Local::now().format("%Y-%m-%d_%H-%M-%S")
-                ));
-                recorder =
-                    Some(new_ffmpeg_command(image_dimensions, filename.to_str().unwrap()).unwrap());
+            RecordEvent::Start(image_dimensions, audio_file, video) => {
+                part = 0;
+                parts = None;
+                recorder = RecorderThread::new(image_dimensions, video, None, audio_file, 0)
+                    .map_err(|err| log::error!("Failed to start recording: {err}"))
+                    .ok();
+            }
+            RecordEvent::Resume(image_dimensions, audio_file, video, checkpoint) => {
+                part = checkpoint.parts.len();
+                recorder = RecorderThread::new(
+                    image_dimensions,
+                    video,
+                    Some(part),
+                    audio_file,
+                    checkpoint.frames,
+                )
+                .map_err(|err| log::error!("Failed to resume the export: {err}"))
+                .ok();
+                parts = Some(checkpoint.parts);
             }
-            RecordEvent::Record(mut frame) => {
+            RecordEvent::Record(mut frame, copies) => {
                 if let Some(ref mut recorder) = recorder {
+                    let mut copies = copies as u64;
+                    if let Some(max_frames) = recorder.video.max_frames() {
+                        if recorder.frames >= max_frames {
+                            continue;
+                        }
+                        if recorder.frames + copies >= max_frames {
+                            copies = max_frames - recorder.frames;
+                            println!("Reached max recording duration, skipping further frames");
+                        }
+                    }
                     let writer = recorder.process.stdin.as_mut().unwrap();
                     let mut writer = BufWriter::new(writer);
 
-                    let padded_bytes = frame.image_dimensions.padded_bytes_per_row as _;
-                    let unpadded_bytes = frame.image_dimensions.unpadded_bytes_per_row as _;
+                    let image_dimensions = frame.image_dimensions;
+                    let format = frame.format;
                     let data = match frame.map_memory() {
                         Ok(data) => data,
                         Err(err) => {
@@ -179,33 +904,99 @@ fn record_thread(rx: Receiver<RecordEvent>) {
                         }
                     };
 
-                    for chunk in data
-                        .chunks(padded_bytes)
-                        .map(|chunk| &chunk[..unpadded_bytes])
-                    {
-                        let _ = writer.write_all(chunk);
+                    let rows: Vec<_> = rgba_rows(data, image_dimensions, format).collect();
+                    for _ in 0..copies {
+                        for row in &rows {
+                            let _ = writer.write_all(row);
+                        }
                     }
                     let _ = writer.flush();
+                    recorder.frames += copies;
                 }
             }
+            RecordEvent::Chapter { title, split } => {
+                let Some(active) = recorder.as_mut() else {
+                    continue;
+                };
+                if split && active.frames > 0 {
+                    let image_dimensions = active.image_dimensions;
+                    let video = active.video.clone();
+                    let audio_file = active.audio_file.clone();
+                    let audio_offset = active.audio_offset + active.frames;
+                    if let Some(finished) = recorder.take() {
+                        let _ = finished.finish().map_err(|err| log::error!("{err}"));
+                    }
+                    part += 1;
+                    recorder = RecorderThread::new(
+                        image_dimensions,
+                        video,
+                        Some(part),
+                        audio_file,
+                        audio_offset,
+                    )
+                    .map_err(|err| log::error!("Failed to start recording: {err}"))
+                    .ok();
+                }
+                if let Some(ref mut recorder) = recorder {
+                    let frame = recorder.frames;
+                    recorder.chapters.retain(|(start, _)| *start != frame);
+                    recorder.chapters.push((frame, title));
+                }
+            }
+            RecordEvent::Checkpoint(mut checkpoint) => {
+                let Some(active) = recorder.take() else {
+                    continue;
+                };
+                let image_dimensions = active.image_dimensions;
+                let video = active.video.clone();
+                let audio_file = active.audio_file.clone();
+                let frames = active.audio_offset + active.frames;
+                let filename = active.filename.clone();
+                if let Err(err) = active.finish() {
+                    log::error!("Failed to finish the export segment: {err}");
+                    continue;
+                }
+                let done = parts.get_or_insert_with(Vec::new);
+                done.push(filename);
+                checkpoint.parts = done.clone();
+                checkpoint.frames = frames;
+                if let Err(err) = checkpoint.save() {
+                    log::error!("Failed to save the export checkpoint: {err}");
+                }
+                part += 1;
+                recorder =
+                    RecorderThread::new(image_dimensions, video, Some(part), audio_file, frames)
+                        .map_err(|err| log::error!("Failed to continue the export: {err}"))
+                        .ok();
+            }
             RecordEvent::Finish => {
-                if let Some(ref mut p) = recorder {
-                    p.process.wait().unwrap();
+                let Some(finished) = recorder.take() else {
+                    continue;
+                };
+                let video = finished.video.clone();
+                let filename = finished.filename.clone();
+                if let Err(err) = finished.finish() {
+                    log::error!("{err}");
+                    continue;
+                }
+                if let Some(mut done) = parts.take() {
+                    done.push(filename);
+                    let _ = join_parts(&done, &video).map_err(|err| log::error!("{err}"));
                 }
-                recorder = None;
-                println!("Recording finished");
             }
-            RecordEvent::Screenshot(mut frame) => {
+            RecordEvent::Screenshot(frame, settings) => {
                 let image_dimensions = frame.image_dimensions;
-                let data = match frame.map_memory() {
-                    Ok(data) => data,
-                    Err(err) => {
-                        log::error!("Failed to map memory: {err}");
-                        continue;
-                    }
+                let format = frame.format;
+                let Some(data) = frame.data.as_deref() else {
+                    log::error!("Screenshot memory isn't mapped");
+                    continue;
                 };
 
-                let _ = save_screenshot(data, image_dimensions).map_err(|err| log::error!("{err}"));
+                let res = match format {
+                    vk::Format::R16G16B16A16_SFLOAT => save_exr_screenshot(data, image_dimensions),
+                    _ => save_screenshot(data, image_dimensions, format, settings),
+                };
+                let _ = res.map_err(|err| log::error!("{err}"));
             }
             RecordEvent::CloseThread => {
                 return;
@@ -214,34 +1005,151 @@ fn record_thread(rx: Receiver<RecordEvent>) {
     }
 }
 
-pub fn save_screenshot(frame: &[u8], image_dimensions: ImageDimensions) -> Result<()> {
+pub fn save_screenshot(
+    frame: &[u8],
+    image_dimensions: ImageDimensions,
+    format: vk::Format,
+    settings: ScreenshotSettings,
+) -> Result<()> {
     let now = Instant::now();
     let screenshots_folder = Path::new(SCREENSHOT_FOLDER);
     create_folder(screenshots_folder)?;
     let path = screenshots_folder.join(format!(
-        "screenshot-{}.png",
+        "screenshot-{}.{}",
+        chrono::Local::now().format("%Y-%m-%d_%H-%M-%S%.9f"),
+        settings.format.extension()
+    ));
+    let rows = rgba_rows(frame, image_dimensions, format);
+    match settings.format {
+        ImageFormat::Png => {
+            let file = File::create(path)?;
+            let w = BufWriter::new(file);
+            let mut encoder =
+                png::Encoder::new(w, image_dimensions.width as _, image_dimensions.height as _);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_compression(settings.compression.into());
+            // sRGB chunk plus matching gAMA and cHRM for viewers without sRGB support
+            encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+            let mut writer = encoder
+                .write_header()?
+                .into_stream_writer_with_size(image_dimensions.width * 4)?;
+            writer.set_filter(png::FilterType::Paeth);
+            writer.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
+            for row in rows {
+                writer.write_all(&row)?;
+            }
+            writer.finish()?;
+        }
+        // Everything else goes through ffmpeg, it is already required for recording
+        _ => {
+            let mut command = Command::new("ffmpeg");
+            #[rustfmt::skip]
+            command.args([
+                "-hide_banner",
+                "-loglevel", "error",
+                "-y",
+                "-f", "rawvideo",
+                "-pix_fmt", "rgba",
+                "-s", &format!("{}x{}", image_dimensions.width, image_dimensions.height),
+                "-i", "-",
+                "-frames:v", "1",
+                "-update", "1",
+            ]);
+            if settings.format == ImageFormat::Jpeg {
+                // Map quality onto the mjpeg `qscale` range, 2 is the best
+                let quality = settings.quality.clamp(1, 100) as u32;
+                let qscale = 2 + (100 - quality) * 29 / 99;
+                command.arg("-q:v").arg(qscale.to_string());
+            }
+            let mut child = spawn_ffmpeg(command, &path)
+                .context("Failed to run ffmpeg, it is required for non PNG screenshots")?;
+            {
+                let mut writer = BufWriter::new(child.stdin.take().unwrap());
+                for row in rows {
+                    writer.write_all(&row)?;
+                }
+                writer.flush()?;
+            }
+            let status = child.wait()?;
+            if !status.success() {
+                bail!("ffmpeg failed to encode {}", path.display());
+            }
+        }
+    }
+    println!("Encode image: {:#.2?}", now.elapsed());
+    Ok(())
+}
+
+/// Writes an uncompressed scanline OpenEXR file from RGBA16F rows.
+pub fn save_exr_screenshot(frame: &[u8], image_dimensions: ImageDimensions) -> Result<()> {
+    let now = Instant::now();
+    let screenshots_folder = Path::new(SCREENSHOT_FOLDER);
+    create_folder(screenshots_folder)?;
+    let path = screenshots_folder.join(format!(
+        "screenshot-{}.exr",
         chrono::Local::now().format("%Y-%m-%d_%H-%M-%S%.9f")
     ));
-    let file = File::create(path)?;
-    let w = BufWriter::new(file);
-    let mut encoder =
-        png::Encoder::new(w, image_dimensions.width as _, image_dimensions.height as _);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    let padded_bytes = image_dimensions.padded_bytes_per_row;
+    let (width, height) = (image_dimensions.width, image_dimensions.height);
+    let mut w = BufWriter::new(File::create(path)?);
+
+    let attribute = |w: &mut BufWriter<File>, name: &str, kind: &str, value: &[u8]| {
+        w.write_all(name.as_bytes())?;
+        w.write_all(&[0])?;
+        w.write_all(kind.as_bytes())?;
+        w.write_all(&[0])?;
+        w.write_all(&(value.len() as i32).to_le_bytes())?;
+        w.write_all(value)
+    };
+    let i32s =
+        |values: &[i32]| -> Vec<u8> { values.iter().flat_map(|v| v.to_le_bytes()).collect() };
+
+    // Magic number and version 2, single part scanline image
+    w.write_all(&[0x76, 0x2f, 0x31, 0x01, 2, 0, 0, 0])?;
+    // Channels are stored in alphabetical order, all of them HALF
+    let mut channels = vec![];
+    for name in ["A", "B", "G", "R"] {
+        channels.extend_from_slice(name.as_bytes());
+        channels.push(0);
+        channels.extend(i32s(&[1, 0, 1, 1]));
+    }
+    channels.push(0);
+    attribute(&mut w, "channels", "chlist", &channels)?;
+    attribute(&mut w, "compression", "compression", &[0])?;
+    let window = i32s(&[0, 0, width as i32 - 1, height as i32 - 1]);
+    attribute(&mut w, "dataWindow", "box2i", &window)?;
+    attribute(&mut w, "displayWindow", "box2i", &window)?;
+    attribute(&mut w, "lineOrder", "lineOrder", &[0])?;
+    attribute(&mut w, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+    attribute(&mut w, "screenWindowCenter", "v2f", &[0; 8])?;
+    attribute(&mut w, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+    w.write_all(&[0])?;
+
+    let header_size = w.stream_position()?;
+    let line_size = width * 4 * size_of::<u16>();
+    let block_size = (2 * size_of::<i32>() + line_size) as u64;
+    let table_size = (height * size_of::<u64>()) as u64;
+    for y in 0..height as u64 {
+        let offset = header_size + table_size + y * block_size;
+        w.write_all(&offset.to_le_bytes())?;
+    }
+
     let unpadded_bytes = image_dimensions.unpadded_bytes_per_row;
-    let mut writer = encoder
-        .write_header()?
-        .into_stream_writer_with_size(unpadded_bytes)?;
-    writer.set_filter(png::FilterType::Paeth);
-    writer.set_adaptive_filter(png::AdaptiveFilterType::Adaptive);
-    for chunk in frame
-        .chunks(padded_bytes)
-        .map(|chunk| &chunk[..unpadded_bytes])
-    {
-        writer.write_all(chunk)?;
+    let rows = frame
+        .chunks(image_dimensions.padded_bytes_per_row)
+        .take(height)
+        .map(|row| &row[..unpadded_bytes]);
+    for (y, row) in rows.enumerate() {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        // Interleaved RGBA into planar A, B, G, R
+        for channel in [3, 2, 1, 0] {
+            for pixel in row.chunks_exact(8) {
+                w.write_all(&pixel[channel * 2..channel * 2 + 2])?;
+            }
+        }
     }
-    writer.finish()?;
+    w.flush()?;
     println!("Encode image: {:#.2?}", now.elapsed());
     Ok(())
 }
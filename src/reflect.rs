@@ -0,0 +1,416 @@
+//! Just enough SPIR-V parsing to check what a shader declares against what
+//! the renderer provides.
+
+use std::{collections::HashMap, mem::offset_of};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
+
+use crate::PushConstant;
+
+const OP_NAME: u32 = 5;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_MEMBER_NAME: u32 = 6;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+const OP_TYPE_ACCELERATION_STRUCTURE: u32 = 5341;
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+const DIM_BUFFER: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Float,
+    /// Ints, bools and buffer references, anything not editable as floats.
+    Other {
+        size: u32,
+    },
+    Vector {
+        float: bool,
+        size: u32,
+    },
+    Struct,
+}
+
+/// A resource the shader declares in a descriptor set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DescriptorBinding {
+    pub set: u32,
+    pub binding: u32,
+    /// `None` for types no descriptor holds.
+    pub ty: Option<vk::DescriptorType>,
+    /// Elements of an array, 0 when it's runtime sized.
+    pub count: u32,
+    /// Block name of buffers, variable name of everything else.
+    pub name: String,
+}
+
+/// Member of the push constant block of a shader.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockMember {
+    pub name: String,
+    pub offset: u32,
+    /// Bytes taken, 0 for types like matrices and arrays.
+    pub size: u32,
+    /// Number of floats for `float` and `vecN` members.
+    pub floats: Option<u32>,
+}
+
+/// Extra `float` or `vecN` field a shader declares after the built-in ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UserParam {
+    pub name: String,
+    /// First float in [`PushConstant::user`].
+    pub index: usize,
+    pub len: usize,
+}
+
+/// Value of a `[params]` entry in the manifest, a number or an array.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum ParamValue {
+    Scalar(f32),
+    Vector(Vec<f32>),
+}
+
+impl ParamValue {
+    pub fn values(&self) -> &[f32] {
+        match self {
+            ParamValue::Scalar(value) => std::slice::from_ref(value),
+            ParamValue::Vector(values) => values,
+        }
+    }
+}
+
+/// Writes `values` into the slots of the user field `name`.
+pub fn set_user_param(
+    user: &mut [f32],
+    params: &[UserParam],
+    name: &str,
+    values: &[f32],
+) -> Result<()> {
+    let param = params
+        .iter()
+        .find(|param| param.name == name)
+        .with_context(|| format!("No shader declares the push constant field `{name}`"))?;
+    if values.len() != param.len {
+        bail!("`{name}` takes {} numbers, got {}", param.len, values.len());
+    }
+    user[param.index..][..param.len].copy_from_slice(values);
+    Ok(())
+}
+
+/// Byte offsets where the built-in fields of [`PushConstant`] start and end.
+fn builtin_boundaries() -> [usize; 14] {
+    [
+        offset_of!(PushConstant, pos),
+        offset_of!(PushConstant, time),
+        offset_of!(PushConstant, wh),
+        offset_of!(PushConstant, mouse),
+        offset_of!(PushConstant, mouse_pressed),
+        offset_of!(PushConstant, frame),
+        offset_of!(PushConstant, time_delta),
+        offset_of!(PushConstant, record_time),
+        offset_of!(PushConstant, picked_color),
+        offset_of!(PushConstant, resources),
+        offset_of!(PushConstant, reload_count),
+        offset_of!(PushConstant, mouse_buttons),
+        offset_of!(PushConstant, scroll),
+        offset_of!(PushConstant, user),
+    ]
+}
+
+/// Checks the members of a shader's `PushConstant` block against the struct
+/// on the CPU side and returns the user fields after `scroll`.
+pub fn user_params(members: &[BlockMember]) -> Result<Vec<UserParam>, String> {
+    let boundaries = builtin_boundaries();
+    let user_offset = offset_of!(PushConstant, user);
+    let user_end = size_of::<PushConstant>();
+    let mut params = vec![];
+    for member in members {
+        let name = &member.name;
+        let start = member.offset as usize;
+        let end = start + member.size as usize;
+        if start < user_offset {
+            if member.size > 0 && !(boundaries.contains(&start) && boundaries.contains(&end)) {
+                return Err(format!(
+                    "`{name}` at byte {start} doesn't line up with the built-in push \
+                     constants, keep the fields of the default shaders as they are and \
+                     add your own after `scroll`"
+                ));
+            }
+            continue;
+        }
+        let Some(floats) = member.floats else {
+            return Err(format!(
+                "`{name}` has to be a float or a vector of floats to be a parameter"
+            ));
+        };
+        if end > user_end {
+            return Err(format!(
+                "`{name}` ends at byte {end}, only {} bytes of parameters fit after `scroll`",
+                user_end - user_offset
+            ));
+        }
+        params.push(UserParam {
+            name: name.clone(),
+            index: (start - user_offset) / size_of::<f32>(),
+            len: floats as usize,
+        });
+    }
+    Ok(params)
+}
+
+/// Parsed instructions, every one a slice starting with its opcode word.
+fn instructions(words: &[u32]) -> impl Iterator<Item = &[u32]> {
+    // Instructions follow the 5 word header
+    let mut rest = words.get(5..).unwrap_or_default();
+    std::iter::from_fn(move || {
+        let &first = rest.first()?;
+        let count = (first >> 16) as usize;
+        if count == 0 || count > rest.len() {
+            return None;
+        }
+        let (instruction, tail) = rest.split_at(count);
+        rest = tail;
+        Some(instruction)
+    })
+}
+
+fn opcode(instruction: &[u32]) -> u32 {
+    instruction[0] & 0xffff
+}
+
+fn string(words: &[u32]) -> String {
+    let bytes: Vec<u8> = words
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .take_while(|&byte| byte != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Workgroup size of a compute shader, `None` when it's not a literal
+/// `local_size_*` layout.
+pub fn local_size(words: &[u32]) -> Option<[u32; 3]> {
+    instructions(words).find_map(|instruction| match instruction {
+        &[_, _, EXECUTION_MODE_LOCAL_SIZE, x, y, z] if opcode(instruction) == OP_EXECUTION_MODE => {
+            Some([x, y, z])
+        }
+        _ => None,
+    })
+}
+
+/// Every resource the module declares in a descriptor set. Needs the debug
+/// names the shader compiler keeps.
+pub fn descriptor_bindings(words: &[u32]) -> Vec<DescriptorBinding> {
+    let mut names = HashMap::new();
+    let mut sets = HashMap::new();
+    let mut bindings = HashMap::new();
+    let mut buffer_blocks = Vec::new();
+    let mut constants = HashMap::new();
+    // Result id to the whole instruction of every type a descriptor can hold
+    let mut types = HashMap::new();
+    let mut pointers = HashMap::new();
+    let mut variables = vec![];
+    for instruction in instructions(words) {
+        let arg = |i: usize| instruction.get(i).copied().unwrap_or_default();
+        match opcode(instruction) {
+            OP_NAME => {
+                names.insert(arg(1), string(&instruction[2.min(instruction.len())..]));
+            }
+            OP_DECORATE => match arg(2) {
+                DECORATION_BINDING => {
+                    bindings.insert(arg(1), arg(3));
+                }
+                DECORATION_DESCRIPTOR_SET => {
+                    sets.insert(arg(1), arg(3));
+                }
+                DECORATION_BUFFER_BLOCK => buffer_blocks.push(arg(1)),
+                _ => {}
+            },
+            OP_CONSTANT => {
+                constants.insert(arg(2), arg(3));
+            }
+            OP_TYPE_IMAGE
+            | OP_TYPE_SAMPLER
+            | OP_TYPE_SAMPLED_IMAGE
+            | OP_TYPE_ARRAY
+            | OP_TYPE_RUNTIME_ARRAY
+            | OP_TYPE_STRUCT
+            | OP_TYPE_ACCELERATION_STRUCTURE => {
+                types.insert(arg(1), instruction);
+            }
+            OP_TYPE_POINTER => {
+                pointers.insert(arg(1), arg(3));
+            }
+            OP_VARIABLE => variables.push((arg(1), arg(2), arg(3))),
+            _ => {}
+        }
+    }
+
+    let mut declared = vec![];
+    for (ty, id, storage_class) in variables {
+        let Some(&binding) = bindings.get(&id) else {
+            continue;
+        };
+        let mut pointee = pointers.get(&ty).copied().unwrap_or_default();
+        let mut count = 1;
+        while let Some(&array) = types.get(&pointee) {
+            match opcode(array) {
+                OP_TYPE_ARRAY => {
+                    count *= constants.get(&array[3]).copied().unwrap_or_default();
+                }
+                OP_TYPE_RUNTIME_ARRAY => count = 0,
+                _ => break,
+            }
+            pointee = array[2];
+        }
+        let instruction = types.get(&pointee).copied().unwrap_or_default();
+        let arg = |i: usize| instruction.get(i).copied().unwrap_or_default();
+        use vk::DescriptorType as D;
+        let ty = match (
+            storage_class,
+            instruction.first().map(|_| opcode(instruction)),
+        ) {
+            (STORAGE_CLASS_STORAGE_BUFFER, _) => Some(D::STORAGE_BUFFER),
+            (STORAGE_CLASS_UNIFORM, _) if buffer_blocks.contains(&pointee) => {
+                Some(D::STORAGE_BUFFER)
+            }
+            (STORAGE_CLASS_UNIFORM, _) => Some(D::UNIFORM_BUFFER),
+            (STORAGE_CLASS_UNIFORM_CONSTANT, Some(op)) => match (op, arg(3), arg(7)) {
+                (OP_TYPE_IMAGE, DIM_BUFFER, 2) => Some(D::STORAGE_TEXEL_BUFFER),
+                (OP_TYPE_IMAGE, DIM_BUFFER, _) => Some(D::UNIFORM_TEXEL_BUFFER),
+                (OP_TYPE_IMAGE, _, 2) => Some(D::STORAGE_IMAGE),
+                (OP_TYPE_IMAGE, _, _) => Some(D::SAMPLED_IMAGE),
+                (OP_TYPE_SAMPLER, ..) => Some(D::SAMPLER),
+                (OP_TYPE_SAMPLED_IMAGE, ..) => Some(D::COMBINED_IMAGE_SAMPLER),
+                (OP_TYPE_ACCELERATION_STRUCTURE, ..) => Some(D::ACCELERATION_STRUCTURE_KHR),
+                _ => None,
+            },
+            _ => None,
+        };
+        let is_buffer = matches!(ty, Some(D::STORAGE_BUFFER | D::UNIFORM_BUFFER));
+        let named = if is_buffer { pointee } else { id };
+        declared.push(DescriptorBinding {
+            set: sets.get(&id).copied().unwrap_or(0),
+            binding,
+            ty,
+            count,
+            name: names.get(&named).cloned().unwrap_or_default(),
+        });
+    }
+    declared
+}
+
+/// Name of the push constant block and its members, `None` without one.
+/// Needs the debug names the shader compiler keeps.
+pub fn push_constant_block(words: &[u32]) -> Option<(String, Vec<BlockMember>)> {
+    let mut names = HashMap::new();
+    let mut member_names = HashMap::new();
+    let mut offsets = HashMap::new();
+    let mut types = HashMap::new();
+    let mut members = HashMap::new();
+    let mut pointers = HashMap::new();
+    let mut block_pointer = None;
+    for instruction in instructions(words) {
+        let op = opcode(instruction);
+        let arg = |i: usize| instruction.get(i).copied().unwrap_or_default();
+        match op {
+            OP_NAME => {
+                names.insert(arg(1), string(&instruction[2.min(instruction.len())..]));
+            }
+            OP_MEMBER_NAME => {
+                let name = string(&instruction[3.min(instruction.len())..]);
+                member_names.insert((arg(1), arg(2)), name);
+            }
+            OP_MEMBER_DECORATE if arg(3) == DECORATION_OFFSET => {
+                offsets.insert((arg(1), arg(2)), arg(4));
+            }
+            OP_TYPE_BOOL => {
+                types.insert(arg(1), Type::Other { size: 4 });
+            }
+            OP_TYPE_INT => {
+                types.insert(arg(1), Type::Other { size: arg(2) / 8 });
+            }
+            OP_TYPE_FLOAT => {
+                let ty = match arg(2) {
+                    32 => Type::Float,
+                    width => Type::Other { size: width / 8 },
+                };
+                types.insert(arg(1), ty);
+            }
+            OP_TYPE_VECTOR => {
+                let component = types.get(&arg(2)).copied();
+                let ty = match component {
+                    Some(Type::Float) => Type::Vector {
+                        float: true,
+                        size: 4 * arg(3),
+                    },
+                    Some(Type::Other { size }) => Type::Vector {
+                        float: false,
+                        size: size * arg(3),
+                    },
+                    _ => Type::Other { size: 0 },
+                };
+                types.insert(arg(1), ty);
+            }
+            OP_TYPE_STRUCT => {
+                types.insert(arg(1), Type::Struct);
+                members.insert(arg(1), instruction[2.min(instruction.len())..].to_vec());
+            }
+            OP_TYPE_POINTER => {
+                // Buffer references inside blocks are 64 bit addresses
+                types.insert(arg(1), Type::Other { size: 8 });
+                pointers.insert(arg(1), arg(3));
+            }
+            OP_VARIABLE if arg(3) == STORAGE_CLASS_PUSH_CONSTANT => block_pointer = Some(arg(1)),
+            _ => {}
+        }
+    }
+
+    let block = *pointers.get(&block_pointer?)?;
+    let member_types = members.get(&block)?;
+    let block_members = member_types
+        .iter()
+        .enumerate()
+        .map(|(i, ty)| {
+            let key = (block, i as u32);
+            let (size, floats) = match types.get(ty) {
+                Some(Type::Float) => (4, Some(1)),
+                Some(&Type::Vector { float: true, size }) => (size, Some(size / 4)),
+                Some(&Type::Vector { float: false, size } | &Type::Other { size }) => (size, None),
+                Some(Type::Struct) | None => (0, None),
+            };
+            BlockMember {
+                name: member_names.get(&key).cloned().unwrap_or_default(),
+                offset: offsets.get(&key).copied().unwrap_or_default(),
+                size,
+                floats,
+            }
+        })
+        .collect();
+    let name = names.get(&block).cloned().unwrap_or_default();
+    Some((name, block_members))
+}
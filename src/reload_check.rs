@@ -0,0 +1,250 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use anyhow::{bail, Result};
+use ash::vk;
+
+use crate::{
+    Device, Error, ManagedImage, PushConstant, RenderPipeline, StagedRender, COLOR_SUBRESOURCE_MASK,
+};
+
+/// Side of the square the candidates draw into.
+const SIZE: u32 = 4;
+/// How long a test draw may take, below the usual driver watchdogs.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+struct Target {
+    image: ManagedImage,
+    view: vk::ImageView,
+    device: Arc<Device>,
+}
+
+impl Target {
+    fn new(
+        device: &Arc<Device>,
+        format: vk::Format,
+        samples: vk::SampleCountFlags,
+        aspect: vk::ImageAspectFlags,
+    ) -> Result<Self> {
+        let usage = match aspect {
+            vk::ImageAspectFlags::DEPTH => vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+            _ => vk::ImageUsageFlags::COLOR_ATTACHMENT,
+        };
+        let info = vk::ImageCreateInfo::default()
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(format)
+            .extent(vk::Extent3D {
+                width: SIZE,
+                height: SIZE,
+                depth: 1,
+            })
+            .mip_levels(1)
+            .array_layers(1)
+            .samples(samples)
+            .tiling(vk::ImageTiling::OPTIMAL)
+            .usage(usage);
+        let image = ManagedImage::new(device, &info, gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS)?;
+        device.name_object(image.image, "Reload Check Target");
+        let view = unsafe {
+            device.create_image_view(
+                &vk::ImageViewCreateInfo::default()
+                    .view_type(vk::ImageViewType::TYPE_2D)
+                    .image(image.image)
+                    .format(format)
+                    .subresource_range(subresource_range(aspect)),
+                None,
+            )?
+        };
+        Ok(Self {
+            image,
+            view,
+            device: device.clone(),
+        })
+    }
+}
+
+impl Drop for Target {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_image_view(self.view, None) };
+    }
+}
+
+fn subresource_range(aspect: vk::ImageAspectFlags) -> vk::ImageSubresourceRange {
+    vk::ImageSubresourceRange {
+        aspect_mask: aspect,
+        ..COLOR_SUBRESOURCE_MASK
+    }
+}
+
+/// Draws rebuilt render pipelines once into a tiny offscreen target before
+/// they replace the live ones, so shaders hanging or losing the device are
+/// caught on reload rather than in the middle of the frame.
+pub struct ReloadCheck {
+    /// Created on first use, one per format and sample count.
+    targets: HashMap<(vk::Format, vk::SampleCountFlags), Target>,
+    depth_targets: HashMap<(vk::Format, vk::SampleCountFlags), Target>,
+    device: Arc<Device>,
+}
+
+impl ReloadCheck {
+    pub fn new(device: &Arc<Device>) -> Self {
+        Self {
+            targets: HashMap::new(),
+            depth_targets: HashMap::new(),
+            device: device.clone(),
+        }
+    }
+
+    /// Runs a test draw of `staged` and hands it back if it finished in time.
+    /// Failed candidates are destroyed, except the ones still running after
+    /// the timeout which have to be leaked. Those fail with a `TIMEOUT`
+    /// [`Error::Vulkan`] and leave the device to be restarted.
+    ///
+    /// Only pipelines taking [`PushConstant`] without vertex buffers are
+    /// drawn, others come back untested since their inputs are unknown here.
+    pub fn render(
+        &mut self,
+        queue: &vk::Queue,
+        pipeline: &RenderPipeline,
+        staged: StagedRender,
+        images_set: vk::DescriptorSet,
+        push_constant: &PushConstant,
+    ) -> Result<StagedRender> {
+        let range = pipeline.push_constant_ranges().first().copied();
+        let Some(range) = range.filter(|range| range.size as usize == size_of::<PushConstant>())
+        else {
+            return Ok(staged);
+        };
+        if pipeline.takes_vertex_buffers() {
+            return Ok(staged);
+        }
+        if let Err(err) = self.create_targets(pipeline) {
+            pipeline.discard(staged);
+            return Err(err);
+        }
+        let samples = pipeline.samples();
+        let color = &self.targets[&(pipeline.color_format(), samples)];
+        let depth = pipeline
+            .depth_format()
+            .map(|format| &self.depth_targets[&(format, samples)]);
+
+        let candidate = staged.pipeline;
+        let result = self.device.one_time_submit_timeout(
+            queue,
+            CHECK_TIMEOUT.as_nanos() as u64,
+            |device, cbuff| unsafe {
+                let mut barriers = vec![vk::ImageMemoryBarrier2::default()
+                    .subresource_range(COLOR_SUBRESOURCE_MASK)
+                    .dst_stage_mask(vk::PipelineStageFlags2::COLOR_ATTACHMENT_OUTPUT)
+                    .dst_access_mask(vk::AccessFlags2::COLOR_ATTACHMENT_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .image(color.image.image)];
+                if let Some(depth) = depth {
+                    barriers.push(
+                        vk::ImageMemoryBarrier2::default()
+                            .subresource_range(subresource_range(vk::ImageAspectFlags::DEPTH))
+                            .dst_stage_mask(vk::PipelineStageFlags2::EARLY_FRAGMENT_TESTS)
+                            .dst_access_mask(vk::AccessFlags2::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                            .old_layout(vk::ImageLayout::UNDEFINED)
+                            .new_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                            .image(depth.image.image),
+                    );
+                }
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default().image_memory_barriers(&barriers),
+                );
+
+                let color_attachments = [vk::RenderingAttachmentInfo::default()
+                    .image_view(color.view)
+                    .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::DONT_CARE)];
+                let depth_attachment = depth.map(|depth| {
+                    vk::RenderingAttachmentInfo::default()
+                        .image_view(depth.view)
+                        .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                        .load_op(vk::AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .clear_value(vk::ClearValue {
+                            depth_stencil: vk::ClearDepthStencilValue {
+                                depth: 1.0,
+                                stencil: 0,
+                            },
+                        })
+                });
+                let area = vk::Rect2D::from(vk::Extent2D {
+                    width: SIZE,
+                    height: SIZE,
+                });
+                let mut rendering_info = vk::RenderingInfo::default()
+                    .render_area(area)
+                    .layer_count(1)
+                    .color_attachments(&color_attachments);
+                if let Some(depth_attachment) = &depth_attachment {
+                    rendering_info = rendering_info.depth_attachment(depth_attachment);
+                }
+                device
+                    .dynamic_rendering
+                    .cmd_begin_rendering(cbuff, &rendering_info);
+                let viewport = vk::Viewport {
+                    x: 0.,
+                    y: SIZE as f32,
+                    width: SIZE as f32,
+                    height: -(SIZE as f32),
+                    min_depth: 0.0,
+                    max_depth: 1.0,
+                };
+                device.cmd_set_viewport(cbuff, 0, &[viewport]);
+                device.cmd_set_scissor(cbuff, 0, &[area]);
+                let bytes = std::slice::from_raw_parts(
+                    std::ptr::from_ref(push_constant).cast(),
+                    size_of::<PushConstant>(),
+                );
+                device.cmd_push_constants(cbuff, pipeline.layout, range.stage_flags, 0, bytes);
+                device.cmd_bind_descriptor_sets(
+                    cbuff,
+                    vk::PipelineBindPoint::GRAPHICS,
+                    pipeline.layout,
+                    0,
+                    &pipeline.descriptor_sets(images_set),
+                    &[],
+                );
+                device.cmd_bind_pipeline(cbuff, vk::PipelineBindPoint::GRAPHICS, candidate);
+                device.cmd_draw(cbuff, 3, 1, 0, 0);
+                device.dynamic_rendering.cmd_end_rendering(cbuff);
+            },
+        );
+        match result {
+            Ok(()) => Ok(staged),
+            // The draw may still be running, nothing it uses can be freed
+            Err(result @ vk::Result::TIMEOUT) => Err(Error::Vulkan {
+                context: "The test draw never finished, the shader likely doesn't terminate",
+                result,
+            }
+            .into()),
+            Err(err) => {
+                pipeline.discard(staged);
+                bail!("The test draw failed: {err}")
+            }
+        }
+    }
+
+    fn create_targets(&mut self, pipeline: &RenderPipeline) -> Result<()> {
+        let samples = pipeline.samples();
+        let key = (pipeline.color_format(), samples);
+        if !self.targets.contains_key(&key) {
+            let target = Target::new(&self.device, key.0, samples, vk::ImageAspectFlags::COLOR)?;
+            self.targets.insert(key, target);
+        }
+        if let Some(format) = pipeline.depth_format() {
+            let key = (format, samples);
+            if !self.depth_targets.contains_key(&key) {
+                let target =
+                    Target::new(&self.device, format, samples, vk::ImageAspectFlags::DEPTH)?;
+                self.depth_targets.insert(key, target);
+            }
+        }
+        Ok(())
+    }
+}
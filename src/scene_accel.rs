@@ -0,0 +1,258 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use ash::{khr, vk};
+
+use crate::{align_to, device::HostBuffer, Device};
+
+/// Bottom and top level acceleration structures of a triangle mesh, for ray
+/// queries in any shader through `pc.resources.scene`.
+pub struct SceneAccel {
+    blas: vk::AccelerationStructureKHR,
+    tlas: vk::AccelerationStructureKHR,
+    /// Storage of the structures, the build inputs are freed after it.
+    storage: Vec<HostBuffer>,
+    /// Device address of the top level structure.
+    pub address: u64,
+    device: Arc<Device>,
+}
+
+impl SceneAccel {
+    /// Builds one opaque instance of the triangles as they are. `None`
+    /// without ray query support.
+    pub fn new(
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        positions: &[[f32; 3]],
+        indices: &[u32],
+    ) -> Result<Option<Self>> {
+        let Some(loader) = &device.acceleration_structure else {
+            return Ok(None);
+        };
+        let mut accel = Self {
+            blas: vk::AccelerationStructureKHR::null(),
+            tlas: vk::AccelerationStructureKHR::null(),
+            storage: vec![],
+            address: 0,
+            device: device.clone(),
+        };
+
+        let input_usage = vk::BufferUsageFlags::ACCELERATION_STRUCTURE_BUILD_INPUT_READ_ONLY_KHR;
+        let vertices = upload(device, bytemuck::cast_slice(positions), input_usage)?;
+        let index_buffer = upload(device, bytemuck::cast_slice(indices), input_usage)?;
+        let triangles = vk::AccelerationStructureGeometryTrianglesDataKHR::default()
+            .vertex_format(vk::Format::R32G32B32_SFLOAT)
+            .vertex_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: vertices.address,
+            })
+            .vertex_stride(size_of::<[f32; 3]>() as _)
+            .max_vertex(positions.len().saturating_sub(1) as _)
+            .index_type(vk::IndexType::UINT32)
+            .index_data(vk::DeviceOrHostAddressConstKHR {
+                device_address: index_buffer.address,
+            });
+        let blas_geometry = vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::TRIANGLES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR { triangles })
+            .flags(vk::GeometryFlagsKHR::OPAQUE);
+        let blas_range = vk::AccelerationStructureBuildRangeInfoKHR::default()
+            .primitive_count((indices.len() / 3) as u32);
+        let blas_build = accel.create(
+            loader,
+            vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL,
+            &blas_geometry,
+            blas_range.primitive_count,
+        )?;
+        accel.blas = blas_build.structure;
+        let blas_address = unsafe {
+            loader.get_acceleration_structure_device_address(
+                &vk::AccelerationStructureDeviceAddressInfoKHR::default()
+                    .acceleration_structure(accel.blas),
+            )
+        };
+
+        let instance = vk::AccelerationStructureInstanceKHR {
+            transform: vk::TransformMatrixKHR {
+                matrix: [1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0.],
+            },
+            instance_custom_index_and_mask: vk::Packed24_8::new(0, 0xFF),
+            instance_shader_binding_table_record_offset_and_flags: vk::Packed24_8::new(
+                0,
+                vk::GeometryInstanceFlagsKHR::TRIANGLE_FACING_CULL_DISABLE.as_raw() as u8,
+            ),
+            acceleration_structure_reference: vk::AccelerationStructureReferenceKHR {
+                device_handle: blas_address,
+            },
+        };
+        // A plain C struct, only its union keeps bytemuck away
+        let instance_bytes = unsafe {
+            std::slice::from_raw_parts(
+                (&instance as *const vk::AccelerationStructureInstanceKHR).cast::<u8>(),
+                size_of::<vk::AccelerationStructureInstanceKHR>(),
+            )
+        };
+        let instances = upload(device, instance_bytes, input_usage)?;
+        let tlas_geometry = vk::AccelerationStructureGeometryKHR::default()
+            .geometry_type(vk::GeometryTypeKHR::INSTANCES)
+            .geometry(vk::AccelerationStructureGeometryDataKHR {
+                instances: vk::AccelerationStructureGeometryInstancesDataKHR::default().data(
+                    vk::DeviceOrHostAddressConstKHR {
+                        device_address: instances.address,
+                    },
+                ),
+            });
+        let tlas_range = vk::AccelerationStructureBuildRangeInfoKHR::default().primitive_count(1);
+        let tlas_build = accel.create(
+            loader,
+            vk::AccelerationStructureTypeKHR::TOP_LEVEL,
+            &tlas_geometry,
+            1,
+        )?;
+        accel.tlas = tlas_build.structure;
+
+        let blas_geometries = [blas_geometry];
+        let tlas_geometries = [tlas_geometry];
+        let blas_info = build_info(vk::AccelerationStructureTypeKHR::BOTTOM_LEVEL)
+            .geometries(&blas_geometries)
+            .dst_acceleration_structure(accel.blas)
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: blas_build.scratch_address,
+            });
+        let tlas_info = build_info(vk::AccelerationStructureTypeKHR::TOP_LEVEL)
+            .geometries(&tlas_geometries)
+            .dst_acceleration_structure(accel.tlas)
+            .scratch_data(vk::DeviceOrHostAddressKHR {
+                device_address: tlas_build.scratch_address,
+            });
+        device.one_time_submit(queue, |device, command_buffer| unsafe {
+            loader.cmd_build_acceleration_structures(
+                command_buffer,
+                &[blas_info],
+                &[&[blas_range]],
+            );
+            // The instance points at the bottom level structure
+            let barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_WRITE_KHR)
+                .dst_access_mask(vk::AccessFlags::ACCELERATION_STRUCTURE_READ_KHR);
+            device.cmd_pipeline_barrier(
+                command_buffer,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::PipelineStageFlags::ACCELERATION_STRUCTURE_BUILD_KHR,
+                vk::DependencyFlags::empty(),
+                &[barrier],
+                &[],
+                &[],
+            );
+            loader.cmd_build_acceleration_structures(
+                command_buffer,
+                &[tlas_info],
+                &[&[tlas_range]],
+            );
+        })?;
+
+        accel.address = unsafe {
+            loader.get_acceleration_structure_device_address(
+                &vk::AccelerationStructureDeviceAddressInfoKHR::default()
+                    .acceleration_structure(accel.tlas),
+            )
+        };
+        device.name_object(accel.blas, "Mesh BLAS");
+        device.name_object(accel.tlas, "Mesh TLAS");
+        Ok(Some(accel))
+    }
+
+    /// Creates a structure big enough for `primitive_count` primitives of
+    /// `geometry` and the scratch buffer to build it. The storage stays with
+    /// `self`, the scratch only for the build.
+    fn create(
+        &mut self,
+        loader: &khr::acceleration_structure::Device,
+        ty: vk::AccelerationStructureTypeKHR,
+        geometry: &vk::AccelerationStructureGeometryKHR,
+        primitive_count: u32,
+    ) -> Result<Build> {
+        let mut sizes = vk::AccelerationStructureBuildSizesInfoKHR::default();
+        unsafe {
+            loader.get_acceleration_structure_build_sizes(
+                vk::AccelerationStructureBuildTypeKHR::DEVICE,
+                &build_info(ty).geometries(std::slice::from_ref(geometry)),
+                &[primitive_count],
+                &mut sizes,
+            )
+        };
+
+        let storage = self.device.create_host_buffer(
+            sizes.acceleration_structure_size,
+            vk::BufferUsageFlags::ACCELERATION_STRUCTURE_STORAGE_KHR,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        let structure = unsafe {
+            loader.create_acceleration_structure(
+                &vk::AccelerationStructureCreateInfoKHR::default()
+                    .buffer(storage.buffer)
+                    .size(sizes.acceleration_structure_size)
+                    .ty(ty),
+                None,
+            )?
+        };
+        self.storage.push(storage);
+
+        let mut properties = vk::PhysicalDeviceAccelerationStructurePropertiesKHR::default();
+        let mut properties2 = vk::PhysicalDeviceProperties2::default().push_next(&mut properties);
+        unsafe {
+            self.device
+                .instance
+                .get_physical_device_properties2(self.device.physical_device, &mut properties2)
+        };
+        let alignment = properties.min_acceleration_structure_scratch_offset_alignment as u64;
+        let scratch = self.device.create_host_buffer(
+            sizes.build_scratch_size + alignment,
+            vk::BufferUsageFlags::STORAGE_BUFFER,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        Ok(Build {
+            structure,
+            scratch_address: align_to(scratch.address, alignment.max(1)),
+            _scratch: scratch,
+        })
+    }
+}
+
+impl Drop for SceneAccel {
+    fn drop(&mut self) {
+        let Some(loader) = &self.device.acceleration_structure else {
+            return;
+        };
+        for structure in [self.tlas, self.blas] {
+            if structure != vk::AccelerationStructureKHR::null() {
+                unsafe { loader.destroy_acceleration_structure(structure, None) };
+            }
+        }
+    }
+}
+
+/// A created structure waiting for its build.
+struct Build {
+    structure: vk::AccelerationStructureKHR,
+    scratch_address: u64,
+    _scratch: HostBuffer,
+}
+
+fn build_info<'a>(
+    ty: vk::AccelerationStructureTypeKHR,
+) -> vk::AccelerationStructureBuildGeometryInfoKHR<'a> {
+    vk::AccelerationStructureBuildGeometryInfoKHR::default()
+        .ty(ty)
+        .flags(vk::BuildAccelerationStructureFlagsKHR::PREFER_FAST_TRACE)
+        .mode(vk::BuildAccelerationStructureModeKHR::BUILD)
+}
+
+fn upload(device: &Arc<Device>, bytes: &[u8], usage: vk::BufferUsageFlags) -> Result<HostBuffer> {
+    let mut buffer = device.create_host_buffer(
+        bytes.len() as u64,
+        usage,
+        gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+    )?;
+    buffer[..bytes.len()].copy_from_slice(bytes);
+    Ok(buffer)
+}
@@ -0,0 +1,190 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use anyhow::{Context, Result};
+use ash::vk;
+use parking_lot::Mutex;
+
+use crate::Device;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const NVIDIA: u32 = 0x10de;
+const AMD: u32 = 0x1002;
+/// amdgpu starts pulling clocks this many degrees below a critical limit.
+const AMD_THROTTLE_MARGIN: f32 = 5.;
+/// HW slowdown, SW thermal and HW thermal slowdown in
+/// `clocks_throttle_reasons.active`.
+const NVIDIA_THERMAL_REASONS: u64 = 0x08 | 0x20 | 0x40;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GpuReading {
+    /// Degrees Celsius.
+    pub temperature: f32,
+    /// Watts, not every driver reports it.
+    pub power: Option<f32>,
+    pub throttled: bool,
+}
+
+impl std::fmt::Display for GpuReading {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:.0}°C", self.temperature)?;
+        if let Some(power) = self.power {
+            write!(f, " {power:.0} W")?;
+        }
+        if self.throttled {
+            write!(f, " throttled")?;
+        }
+        Ok(())
+    }
+}
+
+enum Source {
+    /// Polls `nvidia-smi`, which ships with the driver, for the GPU at this
+    /// PCI bus id.
+    Nvidia(String),
+    /// hwmon folder of the amdgpu card.
+    Amd(PathBuf),
+}
+
+impl Source {
+    fn read(&self) -> Result<GpuReading> {
+        match self {
+            Source::Nvidia(bus_id) => read_nvidia(bus_id),
+            Source::Amd(hwmon) => read_amdgpu(hwmon),
+        }
+    }
+}
+
+/// Temperature and power of the GPU polled on a thread, with a warning when
+/// it starts throttling.
+pub struct GpuSensors {
+    latest: Arc<Mutex<Option<GpuReading>>>,
+    _thread_handle: JoinHandle<()>,
+}
+
+impl GpuSensors {
+    /// Sensors of the GPU `device` runs on, found by its PCI address so
+    /// another card's aren't shown. `None` when the vendor has no supported
+    /// sensors or they can't be read.
+    pub fn new(device: &Device) -> Option<Self> {
+        let vendor_id = device.device_properties.vendor_id;
+        if ![NVIDIA, AMD].contains(&vendor_id) {
+            return None;
+        }
+        let Some(pci) = device.pci_bus_info else {
+            log::warn!("GPU sensors are unavailable: the driver doesn't report the PCI address");
+            return None;
+        };
+        let source = match vendor_id {
+            NVIDIA => Source::Nvidia(format!(
+                "{:08x}:{:02x}:{:02x}.{:x}",
+                pci.pci_domain, pci.pci_bus, pci.pci_device, pci.pci_function
+            )),
+            _ => Source::Amd(find_amdgpu_hwmon(&pci)?),
+        };
+        let first = source
+            .read()
+            .map_err(|err| log::warn!("GPU sensors are unavailable: {err}"))
+            .ok()?;
+        let latest = Arc::new(Mutex::new(Some(first)));
+        let thread_handle = std::thread::spawn({
+            let latest = latest.clone();
+            move || {
+                let mut throttled = first.throttled;
+                loop {
+                    std::thread::sleep(POLL_INTERVAL);
+                    let reading = match source.read() {
+                        Ok(reading) => reading,
+                        Err(err) => {
+                            log::warn!("Stopped reading GPU sensors: {err}");
+                            *latest.lock() = None;
+                            return;
+                        }
+                    };
+                    if reading.throttled && !throttled {
+                        log::warn!("GPU is throttling at {reading}, expect slower frames");
+                    } else if !reading.throttled && throttled {
+                        log::info!("GPU stopped throttling at {reading}");
+                    }
+                    throttled = reading.throttled;
+                    *latest.lock() = Some(reading);
+                }
+            }
+        });
+        Some(Self {
+            latest,
+            _thread_handle: thread_handle,
+        })
+    }
+
+    pub fn reading(&self) -> Option<GpuReading> {
+        *self.latest.lock()
+    }
+}
+
+fn read_nvidia(bus_id: &str) -> Result<GpuReading> {
+    let output = Command::new("nvidia-smi")
+        .args([
+            &format!("--id={bus_id}"),
+            "--query-gpu=temperature.gpu,power.draw,clocks_throttle_reasons.active",
+            "--format=csv,noheader,nounits",
+        ])
+        .output()
+        .context("Failed to run nvidia-smi")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut fields = stdout.lines().next().unwrap_or_default().split(',');
+    let mut field = || fields.next().map(str::trim).unwrap_or_default();
+    let temperature = field()
+        .parse()
+        .context("nvidia-smi reported no temperature")?;
+    // `[N/A]` on boards without a power sensor
+    let power = field().parse().ok();
+    let reasons = field().trim_start_matches("0x");
+    let reasons = u64::from_str_radix(reasons, 16).unwrap_or(0);
+    Ok(GpuReading {
+        temperature,
+        power,
+        throttled: reasons & NVIDIA_THERMAL_REASONS != 0,
+    })
+}
+
+fn read_amdgpu(hwmon: &Path) -> Result<GpuReading> {
+    let value = |name: &str| -> Option<f32> {
+        fs::read_to_string(hwmon.join(name))
+            .ok()?
+            .trim()
+            .parse()
+            .ok()
+    };
+    // Millidegrees of the edge, junction and memory sensors
+    let temperature = value("temp1_input").context("amdgpu reported no temperature")? / 1000.;
+    let throttled = (1..=3).any(|i| {
+        let input = value(&format!("temp{i}_input"));
+        let crit = value(&format!("temp{i}_crit"));
+        matches!((input, crit), (Some(input), Some(crit)) if input / 1000. >= crit / 1000. - AMD_THROTTLE_MARGIN)
+    });
+    // Microwatts, older kernels only have the average
+    let power = value("power1_input")
+        .or_else(|| value("power1_average"))
+        .map(|uw| uw / 1e6);
+    Ok(GpuReading {
+        temperature,
+        power,
+        throttled,
+    })
+}
+
+fn find_amdgpu_hwmon(pci: &vk::PhysicalDevicePCIBusInfoPropertiesEXT) -> Option<PathBuf> {
+    let device = PathBuf::from(format!(
+        "/sys/bus/pci/devices/{:04x}:{:02x}:{:02x}.{:x}",
+        pci.pci_domain, pci.pci_bus, pci.pci_device, pci.pci_function
+    ));
+    let entry = fs::read_dir(device.join("hwmon")).ok()?.next()?.ok()?;
+    Some(entry.path())
+}
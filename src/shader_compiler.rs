@@ -1,82 +1,348 @@
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::{Watcher, SHADER_FOLDER};
-use anyhow::{Context, Result};
-use shaderc::{CompilationArtifact, IncludeType, ShaderKind};
+use crate::{
+    bindings::check_binding,
+    reflect,
+    spirv_cache::{CachedInclude, SpirvCache},
+    Error, UserParam, Watcher,
+};
+use ahash::AHashSet;
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use shaderc::{IncludeType, ShaderKind};
+
+/// Compile environment from the `[compile]` table of the manifest.
+#[derive(Debug, Default, Clone, PartialEq, Hash, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CompileSettings {
+    /// Overrides the `#version` of every shader, e.g. `460`.
+    pub version: Option<u32>,
+    /// Enabled in every shader right after its `#version` line.
+    pub extensions: Vec<String>,
+    /// Target SPIR-V version like `"1.5"`, `"1.6"` by default.
+    pub spirv: Option<String>,
+    /// Folders searched by `#include` after the one of the including file.
+    pub include: Vec<PathBuf>,
+}
+
+impl CompileSettings {
+    /// Resolves the include folders of the manifest against its folder.
+    pub fn relative_to(mut self, dir: &Path) -> Self {
+        for path in &mut self.include {
+            *path = dir.join(&*path);
+        }
+        self
+    }
+
+    fn spirv_version(&self) -> Result<shaderc::SpirvVersion> {
+        use shaderc::SpirvVersion as V;
+        Ok(match self.spirv.as_deref() {
+            None | Some("1.6") => V::V1_6,
+            Some("1.5") => V::V1_5,
+            Some("1.4") => V::V1_4,
+            Some("1.3") => V::V1_3,
+            Some("1.2") => V::V1_2,
+            Some("1.1") => V::V1_1,
+            Some("1.0") => V::V1_0,
+            Some(version) => bail!("Unknown SPIR-V version `{version}`, expected 1.0 to 1.6"),
+        })
+    }
+
+    /// Puts the extensions after `#version` and restores the line numbers
+    /// for error messages.
+    fn inject_extensions<'a>(&self, source: &'a str) -> Cow<'a, str> {
+        if self.extensions.is_empty() {
+            return Cow::Borrowed(source);
+        }
+        let version_line = source
+            .lines()
+            .position(|line| line.trim_start().starts_with("#version"));
+        let split = version_line.map_or(0, |idx| idx + 1);
+
+        let mut lines: Vec<_> = source.lines().map(Cow::Borrowed).collect();
+        let mut directives: Vec<Cow<str>> = self
+            .extensions
+            .iter()
+            .map(|ext| format!("#extension {ext} : enable").into())
+            .collect();
+        directives.push(format!("#line {}", split + 1).into());
+        lines.splice(split..split, directives);
+        Cow::Owned(lines.join("\n"))
+    }
+}
 
 pub struct ShaderCompiler {
     compiler: shaderc::Compiler,
     options: shaderc::CompileOptions<'static>,
+    settings: CompileSettings,
+    /// Macros defined in every shader, like the texture indices.
+    defines: Vec<(String, String)>,
+    watcher: Watcher,
+    /// User push constant fields of every compiled shader.
+    user_params: Mutex<HashMap<PathBuf, Vec<UserParam>>>,
+    cache: SpirvCache,
+    /// Includes resolved by the compilation in progress.
+    includes: Arc<Mutex<Vec<CachedInclude>>>,
 }
 
 impl ShaderCompiler {
     pub fn new(watcher: &Watcher) -> Result<Self> {
-        let mut options =
-            shaderc::CompileOptions::new().context("Failed to create shader compiler options")?;
-        options.set_target_env(
-            shaderc::TargetEnv::Vulkan,
-            shaderc::EnvVersion::Vulkan1_3 as u32,
-        );
-        options.set_optimization_level(shaderc::OptimizationLevel::Performance);
-        options.set_target_spirv(shaderc::SpirvVersion::V1_6);
-        options.set_generate_debug_info();
-
-        let watcher_copy = watcher.clone();
-        options.set_include_callback(move |name, include_type, source_file, _depth| {
-            let path = match include_type {
-                IncludeType::Relative => Path::new(source_file).parent().unwrap().join(name),
-                IncludeType::Standard => Path::new(SHADER_FOLDER).join(name),
-            };
-            // TODO: recreate dependencies in case someone removes includes
-            match std::fs::read_to_string(&path) {
-                Ok(glsl_code) => {
-                    let include_path = path.canonicalize().unwrap();
-                    {
-                        let mut watcher = watcher_copy.watcher.lock();
-                        let _ = watcher
-                            .watcher()
-                            .watch(&include_path, notify::RecursiveMode::NonRecursive);
-                    }
-                    let source_path = Path::new(SHADER_FOLDER)
-                        .join(source_file)
-                        .canonicalize()
-                        .unwrap();
-                    {
-                        let mut mapping = watcher_copy.include_mapping.lock();
-                        let sources: Vec<_> = mapping[&source_path].iter().cloned().collect();
-                        for source in sources {
-                            mapping
-                                .entry(include_path.clone())
-                                .or_default()
-                                .insert(source);
-                        }
-                    }
-                    Ok(shaderc::ResolvedInclude {
-                        resolved_name: String::from(name),
-                        content: glsl_code,
-                    })
-                }
-                Err(err) => Err(format!(
-                    "Failed to resolve include to {} in {} (was looking for {:?}): {}",
-                    name, source_file, path, err
-                )),
-            }
-        });
-
+        let settings = CompileSettings::default();
+        let includes = Arc::default();
         Ok(Self {
             compiler: shaderc::Compiler::new().unwrap(),
-            options,
+            options: create_options(watcher, &settings, &[], &includes)?,
+            settings,
+            defines: vec![],
+            watcher: watcher.clone(),
+            user_params: Mutex::default(),
+            cache: SpirvCache::new(),
+            includes,
         })
     }
 
-    pub fn compile(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<CompilationArtifact> {
-        let source = std::fs::read_to_string(path.as_ref())?;
-        Ok(self.compiler.compile_into_spirv(
-            &source,
-            kind,
-            path.as_ref().file_name().and_then(|s| s.to_str()).unwrap(),
-            "main",
-            Some(&self.options),
-        )?)
+    /// Applies the project compile environment, takes effect for every
+    /// shader compiled afterwards.
+    pub fn set_settings(&mut self, settings: &CompileSettings) -> Result<()> {
+        if *settings == self.settings {
+            return Ok(());
+        }
+        self.options = create_options(&self.watcher, settings, &self.defines, &self.includes)?;
+        self.settings = settings.clone();
+        Ok(())
+    }
+
+    pub fn set_defines(&mut self, defines: &[(String, String)]) -> Result<()> {
+        if defines == self.defines {
+            return Ok(());
+        }
+        self.options = create_options(&self.watcher, &self.settings, defines, &self.includes)?;
+        self.defines = defines.to_vec();
+        Ok(())
+    }
+
+    /// Compiles `path` into SPIR-V words, or reads them from the cache when
+    /// neither the source, its includes nor the compile environment changed.
+    pub fn compile(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<Vec<u32>, Error> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|err| Error::Shader {
+            path: path.to_path_buf(),
+            message: format!("Failed to read {}: {err}", path.display()),
+        })?;
+        let source = self.settings.inject_extensions(&source);
+        let key = SpirvCache::key((path, kind as u32, &source, &self.settings, &self.defines));
+        let (words, included) = match self.cache.load(key) {
+            Some((words, includes)) => {
+                // The include callback didn't run, so watch them here
+                for include in &includes {
+                    track_include(&self.watcher, &include.path, &include.includer);
+                }
+                (
+                    words,
+                    includes.into_iter().map(|include| include.path).collect(),
+                )
+            }
+            None => {
+                self.includes.lock().clear();
+                let artifact = self
+                    .compiler
+                    .compile_into_spirv(
+                        &source,
+                        kind,
+                        &path.to_string_lossy(),
+                        "main",
+                        Some(&self.options),
+                    )
+                    .map_err(|err| Error::Shader {
+                        path: path.to_path_buf(),
+                        message: err.to_string(),
+                    })?;
+                let words = artifact.as_binary().to_vec();
+                let includes = std::mem::take(&mut *self.includes.lock());
+                let included = includes
+                    .iter()
+                    .map(|include| include.path.clone())
+                    .collect();
+                self.cache.store(key, &words, includes);
+                (words, included)
+            }
+        };
+        untrack_includes(&self.watcher, path, &included);
+        // Caught here with a hint instead of failing pipeline creation
+        for binding in reflect::descriptor_bindings(&words) {
+            check_binding(&binding).map_err(|message| Error::Shader {
+                path: path.to_path_buf(),
+                message,
+            })?;
+        }
+        // Other blocks belong to built-in passes with their own layouts
+        let block = reflect::push_constant_block(&words);
+        if let Some((_, members)) = block.filter(|(name, _)| name == "PushConstant") {
+            let params = reflect::user_params(&members).map_err(|message| Error::Shader {
+                path: path.to_path_buf(),
+                message,
+            })?;
+            self.user_params.lock().insert(path.to_path_buf(), params);
+        }
+        Ok(words)
+    }
+
+    /// Readable SPIR-V of `path`, compiled with the same environment as
+    /// [`Self::compile`] but never cached.
+    pub fn disassemble(&self, path: impl AsRef<Path>, kind: ShaderKind) -> Result<String, Error> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path).map_err(|err| Error::Shader {
+            path: path.to_path_buf(),
+            message: format!("Failed to read {}: {err}", path.display()),
+        })?;
+        let source = self.settings.inject_extensions(&source);
+        let artifact = self
+            .compiler
+            .compile_into_spirv_assembly(
+                &source,
+                kind,
+                &path.to_string_lossy(),
+                "main",
+                Some(&self.options),
+            )
+            .map_err(|err| Error::Shader {
+                path: path.to_path_buf(),
+                message: err.to_string(),
+            });
+        self.includes.lock().clear();
+        Ok(artifact?.as_text())
+    }
+
+    /// Extra push constant fields declared by the compiled shaders, sorted by
+    /// name.
+    pub fn user_params(&self) -> Vec<UserParam> {
+        let mut params: Vec<_> = self
+            .user_params
+            .lock()
+            .values()
+            .flatten()
+            .cloned()
+            .collect();
+        params.sort_by(|a, b| a.name.cmp(&b.name));
+        params.dedup_by(|a, b| a.name == b.name);
+        params
+    }
+}
+
+fn create_options(
+    watcher: &Watcher,
+    settings: &CompileSettings,
+    defines: &[(String, String)],
+    includes: &Arc<Mutex<Vec<CachedInclude>>>,
+) -> Result<shaderc::CompileOptions<'static>> {
+    let mut options =
+        shaderc::CompileOptions::new().context("Failed to create shader compiler options")?;
+    options.set_target_env(
+        shaderc::TargetEnv::Vulkan,
+        shaderc::EnvVersion::Vulkan1_3 as u32,
+    );
+    options.set_optimization_level(shaderc::OptimizationLevel::Performance);
+    options.set_target_spirv(settings.spirv_version()?);
+    if let Some(version) = settings.version {
+        options.set_forced_version_profile(version, shaderc::GlslProfile::None);
+    }
+    options.set_generate_debug_info();
+    for (name, value) in defines {
+        options.add_macro_definition(name, Some(value));
+    }
+
+    let watcher_copy = watcher.clone();
+    let include_dirs = settings.include.clone();
+    let includes = includes.clone();
+    options.set_include_callback(move |name, include_type, source_file, _depth| {
+        // Both kinds resolve next to the including file first, so a project
+        // folder stays self-contained wherever it lives.
+        let local = match include_type {
+            IncludeType::Relative | IncludeType::Standard => {
+                Path::new(source_file).parent().unwrap().join(name)
+            }
+        };
+        let path = std::iter::once(local.clone())
+            .chain(include_dirs.iter().map(|dir| dir.join(name)))
+            .find(|path| path.is_file())
+            .unwrap_or(local);
+        match std::fs::read_to_string(&path) {
+            Ok(glsl_code) => {
+                let include_path = path.canonicalize().unwrap();
+                let source_path = Path::new(source_file).canonicalize().unwrap();
+                track_include(&watcher_copy, &include_path, &source_path);
+                includes.lock().push(CachedInclude::new(
+                    include_path.clone(),
+                    source_path,
+                    &glsl_code,
+                ));
+                Ok(shaderc::ResolvedInclude {
+                    resolved_name: include_path.to_string_lossy().into_owned(),
+                    content: glsl_code,
+                })
+            }
+            Err(err) => Err(format!(
+                "Failed to resolve include to {} in {} (was looking for {:?} and in the include folders): {}",
+                name, source_file, path, err
+            )),
+        }
+    });
+
+    Ok(options)
+}
+
+/// Watches `include_path` and points it at the shaders `source_path` is
+/// part of, so editing it reloads them.
+fn track_include(watcher: &Watcher, include_path: &Path, source_path: &Path) {
+    {
+        let mut watcher = watcher.watcher.lock();
+        let _ = watcher
+            .watcher()
+            .watch(include_path, notify::RecursiveMode::NonRecursive);
+    }
+    let mut mapping = watcher.include_mapping.lock();
+    let sources: Vec<_> = mapping
+        .get(source_path)
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect();
+    for source in sources {
+        mapping
+            .entry(include_path.to_path_buf())
+            .or_default()
+            .insert(source);
+    }
+}
+
+/// Unlinks `source_path` from the files it no longer includes, directly or
+/// through another include, and stops watching the ones nothing includes.
+fn untrack_includes(watcher: &Watcher, source_path: &Path, included: &AHashSet<PathBuf>) {
+    let Ok(source_path) = source_path.canonicalize() else {
+        return;
+    };
+    let mut stale = vec![];
+    {
+        let mut mapping = watcher.include_mapping.lock();
+        mapping.retain(|path, sources| {
+            if *path == source_path || included.contains(path) {
+                return true;
+            }
+            sources.retain(|source| source.path != source_path);
+            if sources.is_empty() {
+                stale.push(path.clone());
+            }
+            !sources.is_empty()
+        });
+    }
+    let mut watcher = watcher.watcher.lock();
+    for path in stale {
+        let _ = watcher.watcher().unwatch(&path);
     }
 }
@@ -0,0 +1,226 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+/// Push constant fields with a Shadertoy counterpart, set from the uniforms
+/// before the pilka `main` runs. The others stay zero.
+const UNIFORMS: &[(&str, &str)] = &[
+    ("time", "iTime"),
+    ("resolution", "iResolution.xy"),
+    ("mouse", "(iMouse.xy / iResolution.xy - 0.5) * 2."),
+    ("mouse_pressed", "iMouse.z > 0."),
+    ("frame", "uint(iFrame)"),
+    ("time_delta", "iTimeDelta"),
+    ("picked_color", "vec4(0., 0., 0., 1.)"),
+    ("mouse_buttons", "iMouse.z > 0. ? 1u : 0u"),
+];
+
+/// Types a push constant field can keep, buffer references have no
+/// Shadertoy equivalent.
+const PLAIN_TYPES: &[&str] = &[
+    "float", "int", "uint", "bool", "vec2", "vec3", "vec4", "ivec2", "ivec3", "ivec4", "uvec2",
+    "uvec3", "uvec4", "bvec2", "bvec3", "bvec4", "mat2", "mat3", "mat4",
+];
+
+/// Stand-ins of what the header declares, `Tex` reads the channels.
+const SHIM: &str = "vec2 in_uv;
+vec4 out_color;
+
+// pilka samples textures from the top left, Shadertoy from the bottom left
+vec4 Tex(uint id, vec2 uv) {
+    uv.y = 1. - uv.y;
+    switch (id) {
+    case 0u: return texture(iChannel0, uv);
+    case 1u: return texture(iChannel1, uv);
+    case 2u: return texture(iChannel2, uv);
+    case 3u: return texture(iChannel3, uv);
+    }
+    return vec4(0.);
+}
+vec4 Tex(uint id) {
+    return Tex(id, in_uv);
+}
+";
+
+/// Rewrites the fragment shader at `path` into a Shadertoy image pass:
+/// includes are inlined, the bindings and the push constant block become
+/// plain globals and `mainImage` fills them from the `i` uniforms. What has
+/// no counterpart is listed in a comment on top and logged.
+pub fn to_shadertoy(path: &Path, include: &[PathBuf]) -> Result<String> {
+    let mut converter = Converter {
+        include,
+        inlined: HashSet::new(),
+        push_constant: None,
+    };
+    let mut body = converter.source(path)?;
+    let Some((instance, fields)) = converter.push_constant else {
+        bail!("{} has no push constant block", path.display());
+    };
+    let Some(main) = body.find("void main(") else {
+        bail!("{} has no `main`", path.display());
+    };
+    body.replace_range(main..main + "void main(".len(), "void pilka_main(");
+    // The dropped declarations leave their blank lines behind
+    while body.contains("\n\n\n") {
+        body = body.replace("\n\n\n", "\n\n");
+    }
+
+    let mut warnings = vec![];
+    for (ty, name) in &fields {
+        let used = body.contains(&format!("{instance}.{name}"));
+        if used && !PLAIN_TYPES.contains(&ty.as_str()) {
+            warnings.push(format!("`{instance}.{name}` has no Shadertoy counterpart"));
+        }
+    }
+    if body.contains("StreamTex(") {
+        warnings.push("`StreamTex` has no Shadertoy counterpart".into());
+    }
+    for warning in &warnings {
+        log::warn!("{warning}");
+    }
+
+    let mut out = format!(
+        "// Converted from {} by pilka.\n\
+         // iChannel0-3 stand for textures 0-3: PREV_TEX, GENERIC_TEX1, GENERIC_TEX2, DITHER_TEX.\n",
+        path.display()
+    );
+    for warning in &warnings {
+        out += &format!("// TODO: {warning}\n");
+    }
+    out += "\n";
+    out += SHIM;
+    out += &body;
+    out += "\n\nvoid mainImage(out vec4 fragColor, in vec2 fragCoord) {\n";
+    out += "    in_uv = fragCoord / iResolution.xy;\n";
+    for (name, value) in UNIFORMS {
+        if fields
+            .iter()
+            .any(|(ty, field)| field == name && PLAIN_TYPES.contains(&ty.as_str()))
+        {
+            out += &format!("    {instance}.{name} = {value};\n");
+        }
+    }
+    out += "    pilka_main();\n    fragColor = out_color;\n}\n";
+    Ok(out)
+}
+
+struct Converter<'a> {
+    include: &'a [PathBuf],
+    /// Every include is inlined once, like behind an include guard.
+    inlined: HashSet<PathBuf>,
+    /// Instance name and `(type, name)` fields of the push constant block.
+    push_constant: Option<(String, Vec<(String, String)>)>,
+}
+
+impl Converter<'_> {
+    fn source(&mut self, path: &Path) -> Result<String> {
+        let source = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut out = String::new();
+        let mut rest = source.as_str();
+        while !rest.is_empty() {
+            let line_end = rest.find('\n').map_or(rest.len(), |i| i + 1);
+            let line = rest[..line_end].trim();
+            let skip = if line.starts_with("#version") || line.starts_with("#extension") {
+                line_end
+            } else if let Some(name) = include_name(line) {
+                let resolved = self.resolve(path, name)?;
+                if self.inlined.insert(resolved.clone()) {
+                    out += &self.source(&resolved)?;
+                    out += "\n";
+                }
+                line_end
+            } else if line.starts_with("layout(") || line.starts_with("layout (") {
+                let end = statement_end(rest);
+                if rest[..end].contains("push_constant") {
+                    out += &self.push_constant_struct(&rest[..end]);
+                }
+                end
+            } else if line.starts_with("vec4 Tex(") || line.starts_with("vec4 StreamTex(") {
+                block_end(rest)
+            } else {
+                out += &rest[..line_end];
+                line_end
+            };
+            rest = &rest[skip..];
+        }
+        Ok(out)
+    }
+
+    /// Next to the including file first, then in the include folders, the
+    /// same order the compiler uses.
+    fn resolve(&self, includer: &Path, name: &str) -> Result<PathBuf> {
+        let local = includer.parent().unwrap_or(Path::new(".")).join(name);
+        std::iter::once(local)
+            .chain(self.include.iter().map(|dir| dir.join(name)))
+            .find(|path| path.is_file())
+            .with_context(|| format!("Failed to resolve include {name} in {}", includer.display()))
+    }
+
+    fn push_constant_struct(&mut self, block: &str) -> String {
+        let (Some(open), Some(close)) = (block.find('{'), block.rfind('}')) else {
+            return String::new();
+        };
+        let instance = block[close + 1..].trim_end_matches(';').trim().to_string();
+        let fields: Vec<(String, String)> = block[open + 1..close]
+            .lines()
+            .map(|line| line.split_once("//").map_or(line, |(code, _)| code))
+            .collect::<String>()
+            .split(';')
+            .filter_map(|field| {
+                let mut words = field.split_whitespace();
+                let ty = words.next()?;
+                let name = words.last()?;
+                Some((ty.to_string(), name.to_string()))
+            })
+            .collect();
+
+        let mut out = "struct PushConstant {\n".to_string();
+        for (ty, name) in &fields {
+            if PLAIN_TYPES.contains(&ty.as_str()) {
+                out += &format!("    {ty} {name};\n");
+            }
+        }
+        out += &format!("}};\nPushConstant {instance};\n");
+        self.push_constant = Some((instance, fields));
+        out
+    }
+}
+
+fn include_name(line: &str) -> Option<&str> {
+    let name = line.strip_prefix("#include")?.trim();
+    name.strip_prefix('<')
+        .and_then(|name| name.strip_suffix('>'))
+        .or_else(|| name.strip_prefix('"')?.strip_suffix('"'))
+}
+
+/// Byte after the `;` that ends the declaration `text` starts with.
+fn statement_end(text: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            ';' if depth == 0 => return i + 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
+
+/// Byte after the `}` that closes the function `text` starts with.
+fn block_end(text: &str) -> usize {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => return i + 1,
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    text.len()
+}
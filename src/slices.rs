@@ -0,0 +1,195 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::{bail, Result};
+use ash::vk;
+use either::Either;
+use serde::Deserialize;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, Device, FragmentOutputDesc,
+    FragmentShaderDesc, PipelineArena, RenderHandle, TextureArena, VertexInputDesc,
+    VertexShaderDesc, POST_FOLDER,
+};
+
+/// A region of the frame sent to one projector.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SliceDesc {
+    /// `x, y, width, height` of the frame in uv space.
+    pub source: [f32; 4],
+    /// `x, y, width, height` of the window in uv space.
+    pub output: [f32; 4],
+    /// Grid of `columns, rows` correction points.
+    pub mesh_size: [u32; 2],
+    /// Row-major positions of the grid points inside `output`, an empty
+    /// mesh keeps the rect undistorted.
+    pub mesh: Vec<[f32; 2]>,
+    /// Edge blend widths `left, right, top, bottom` as a fraction of the slice.
+    pub blend: [f32; 4],
+    /// Projector gamma the blend ramp compensates for.
+    pub gamma: f32,
+}
+
+impl Default for SliceDesc {
+    fn default() -> Self {
+        Self {
+            source: [0., 0., 1., 1.],
+            output: [0., 0., 1., 1.],
+            mesh_size: [2, 2],
+            mesh: vec![],
+            blend: [0.; 4],
+            gamma: 2.2,
+        }
+    }
+}
+
+impl SliceDesc {
+    fn points(&self) -> Result<Vec<[f32; 2]>> {
+        let [columns, rows] = self.mesh_size;
+        if columns < 2 || rows < 2 {
+            bail!("Slice mesh needs at least 2x2 points, got {columns}x{rows}");
+        }
+        if self.mesh.is_empty() {
+            let point = |i: u32| {
+                let (x, y) = (i % columns, i / columns);
+                [
+                    x as f32 / (columns - 1) as f32,
+                    y as f32 / (rows - 1) as f32,
+                ]
+            };
+            return Ok((0..columns * rows).map(point).collect());
+        }
+        if self.mesh.len() != (columns * rows) as usize {
+            bail!(
+                "Slice mesh has {} points, `mesh_size` {columns}x{rows} expects {}",
+                self.mesh.len(),
+                columns * rows
+            );
+        }
+        Ok(self.mesh.clone())
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct SlicePushConstant {
+    source: [f32; 4],
+    output: [f32; 4],
+    blend: [f32; 4],
+    mesh: u64,
+    src: u32,
+    columns: u32,
+    rows: u32,
+    gamma: f32,
+}
+
+struct Slice {
+    desc: SliceDesc,
+    mesh: HostBuffer,
+}
+
+/// Splits the finished frame into warped and edge blended regions of the
+/// window, one per projector, in place of the plain final copy.
+pub struct Slices {
+    slices: Vec<Slice>,
+    pipeline: RenderHandle,
+}
+
+impl Slices {
+    pub fn new(
+        descs: &[SliceDesc],
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let mut slices = vec![];
+        for (i, desc) in descs.iter().enumerate() {
+            let points = desc.points()?;
+            let mut mesh = device.create_host_buffer(
+                size_of_val(points.as_slice()) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_alloc::UsageFlags::UPLOAD,
+            )?;
+            device.name_object(mesh.buffer, &format!("Slice Mesh {i}"));
+            let bytes = points.iter().flatten().flat_map(|v| v.to_ne_bytes());
+            for (dst, src) in mesh.iter_mut().zip(bytes) {
+                *dst = src;
+            }
+            slices.push(Slice {
+                desc: desc.clone(),
+                mesh,
+            });
+        }
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<SlicePushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT);
+        let pipeline = pipeline_arena.create_render_pipeline(
+            &VertexInputDesc::default(),
+            &VertexShaderDesc {
+                shader_path: dir.join("slice.vert"),
+                // Warped meshes are free to fold over
+                cull_mode: vk::CullModeFlags::NONE,
+                ..Default::default()
+            },
+            &FragmentShaderDesc {
+                shader_path: dir.join("slice.frag"),
+            },
+            &FragmentOutputDesc {
+                surface_format,
+                ..Default::default()
+            },
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+
+        Ok(Self { slices, pipeline })
+    }
+
+    pub fn destroy(self, pipeline_arena: &mut PipelineArena) {
+        pipeline_arena.remove_pipeline(Either::Left(self.pipeline));
+    }
+
+    /// Draws every slice of texture `src` into `view`, the rest stays black.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        view: &vk::ImageView,
+        src: usize,
+    ) {
+        let pipeline = pipeline_arena.get_pipeline(self.pipeline);
+        frame.begin_rendering(view, [0., 0., 0., 1.]);
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.images_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        for slice in &self.slices {
+            let [columns, rows] = slice.desc.mesh_size;
+            let push_constant = SlicePushConstant {
+                source: slice.desc.source,
+                output: slice.desc.output,
+                blend: slice.desc.blend,
+                mesh: slice.mesh.address,
+                src: src as u32,
+                columns,
+                rows,
+                gamma: slice.desc.gamma,
+            };
+            frame.push_constant(
+                pipeline.layout,
+                vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT,
+                &[push_constant],
+            );
+            frame.draw((columns - 1) * (rows - 1) * 6, 0, 1, 0);
+        }
+        frame.end_rendering();
+    }
+}
@@ -0,0 +1,109 @@
+use std::{
+    hash::{DefaultHasher, Hash, Hasher},
+    path::PathBuf,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A file pulled in by `#include` while compiling, with the hash of the
+/// contents it had.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedInclude {
+    pub path: PathBuf,
+    /// The file with the `#include`, the shader itself or another include.
+    pub includer: PathBuf,
+    hash: u64,
+}
+
+impl CachedInclude {
+    pub fn new(path: PathBuf, includer: PathBuf, contents: &str) -> Self {
+        Self {
+            path,
+            includer,
+            hash: hash_of(contents),
+        }
+    }
+
+    fn is_current(&self) -> bool {
+        std::fs::read_to_string(&self.path).is_ok_and(|contents| hash_of(&contents) == self.hash)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CacheEntry {
+    includes: Vec<CachedInclude>,
+}
+
+/// Compiled SPIR-V kept in the temporary directory across runs, so a warm
+/// start only reads what it compiled last time. Entries are keyed by the
+/// source and everything the compiler was given, the includes are checked
+/// against their hashes on every hit.
+pub struct SpirvCache {
+    dir: Option<PathBuf>,
+}
+
+impl SpirvCache {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join("pilka-spirv");
+        let dir = std::fs::create_dir_all(&dir)
+            .map_err(|err| log::warn!("SPIR-V cache disabled: {err}"))
+            .ok()
+            .map(|_| dir);
+        Self { dir }
+    }
+
+    pub fn key(parts: impl Hash) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        env!("CARGO_PKG_VERSION").hash(&mut hasher);
+        parts.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn paths(&self, key: u64) -> Option<(PathBuf, PathBuf)> {
+        let dir = self.dir.as_deref()?;
+        let name = |extension: &str| dir.join(format!("{key:016x}.{extension}"));
+        Some((name("spv"), name("toml")))
+    }
+
+    /// The SPIR-V words and includes of `key`, `None` when it wasn't
+    /// compiled yet or an include changed since.
+    pub fn load(&self, key: u64) -> Option<(Vec<u32>, Vec<CachedInclude>)> {
+        let (spirv_path, entry_path) = self.paths(key)?;
+        let entry: CacheEntry = toml::from_str(&std::fs::read_to_string(entry_path).ok()?).ok()?;
+        if !entry.includes.iter().all(CachedInclude::is_current) {
+            return None;
+        }
+        let bytes = std::fs::read(spirv_path).ok()?;
+        if bytes.is_empty() || bytes.len() % 4 != 0 {
+            return None;
+        }
+        let words = bytes
+            .chunks_exact(4)
+            .map(|word| u32::from_ne_bytes(word.try_into().unwrap()))
+            .collect();
+        Some((words, entry.includes))
+    }
+
+    /// Failing to write only costs the next start a compile.
+    pub fn store(&self, key: u64, words: &[u32], includes: Vec<CachedInclude>) {
+        let Some((spirv_path, entry_path)) = self.paths(key) else {
+            return;
+        };
+        let bytes: Vec<u8> = words.iter().copied().flat_map(u32::to_ne_bytes).collect();
+        let entry = CacheEntry { includes };
+        // The entry goes last, a half written cache is never read
+        let res = std::fs::write(&spirv_path, bytes)
+            .map_err(anyhow::Error::from)
+            .and_then(|_| Ok(toml::to_string(&entry)?))
+            .and_then(|text| Ok(std::fs::write(&entry_path, text)?));
+        if let Err(err) = res {
+            log::debug!("Failed to cache {}: {err}", spirv_path.display());
+        }
+    }
+}
+
+fn hash_of(contents: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
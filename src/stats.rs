@@ -0,0 +1,254 @@
+use std::{
+    collections::VecDeque,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Result;
+use ash::vk;
+
+use crate::{
+    default_shaders, device::HostBuffer, swapchain::FrameGuard, BlendMode, Device,
+    FragmentOutputDesc, FragmentShaderDesc, PipelineArena, RenderHandle, TextureArena,
+    VertexInputDesc, VertexShaderDesc, POST_FOLDER,
+};
+
+/// Frames kept for the rolling statistics and the graph.
+const WINDOW: usize = 240;
+
+/// Rolling frame times measured between redraws.
+pub struct FrameStats {
+    samples: VecDeque<f32>,
+    last_frame: Option<Instant>,
+}
+
+impl FrameStats {
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::with_capacity(WINDOW),
+            last_frame: None,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_frame.replace(now) {
+            if self.samples.len() == WINDOW {
+                self.samples.pop_front();
+            }
+            self.samples
+                .push_back(now.duration_since(last).as_secs_f32() * 1000.);
+        }
+    }
+
+    /// Forgets the gap of a pause or a blocking reload.
+    pub fn reset(&mut self) {
+        self.last_frame = None;
+    }
+
+    pub fn average_ms(&self) -> f32 {
+        self.samples.iter().sum::<f32>() / self.samples.len().max(1) as f32
+    }
+
+    pub fn fps(&self) -> f32 {
+        1000. / self.average_ms()
+    }
+
+    /// Frame time under which `p` percent of the window falls.
+    pub fn percentile_ms(&self, p: f32) -> f32 {
+        let mut sorted: Vec<_> = self.samples.iter().copied().collect();
+        sorted.sort_by(f32::total_cmp);
+        let idx = ((sorted.len() as f32 * p / 100.).ceil() as usize).saturating_sub(1);
+        sorted.get(idx).copied().unwrap_or_default()
+    }
+
+    /// Frame times from oldest to newest in milliseconds.
+    pub fn samples(&self) -> impl Iterator<Item = f32> + '_ {
+        self.samples.iter().copied()
+    }
+
+    pub fn summary(&self) -> String {
+        format!(
+            "{:.1} fps, {:.2} ms (p95 {:.2}, p99 {:.2}, max {:.2})",
+            self.fps(),
+            self.average_ms(),
+            self.percentile_ms(95.),
+            self.percentile_ms(99.),
+            self.percentile_ms(100.),
+        )
+    }
+}
+
+impl std::fmt::Display for FrameStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "fps:\t\t{:.2}\nframe time:\t{:.2} ms\np50, p95, p99:\t{:.2}, {:.2}, {:.2} ms\n",
+            self.fps(),
+            self.average_ms(),
+            self.percentile_ms(50.),
+            self.percentile_ms(95.),
+            self.percentile_ms(99.),
+        )
+    }
+}
+
+/// Throttles how often the window title is rewritten.
+pub struct TitleTimer {
+    last: Instant,
+}
+
+impl TitleTimer {
+    const PERIOD: Duration = Duration::from_millis(500);
+
+    pub fn new() -> Self {
+        Self {
+            last: Instant::now(),
+        }
+    }
+
+    pub fn ready(&mut self) -> bool {
+        let ready = self.last.elapsed() >= Self::PERIOD;
+        if ready {
+            self.last = Instant::now();
+        }
+        ready
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+struct GraphPushConstant {
+    resolution: [f32; 2],
+    budget_ms: f32,
+    count: u32,
+    samples: u64,
+}
+
+/// Frame time bar graph in the corner of the window, drawn after the
+/// exported copy of the frame.
+pub struct StatsGraph {
+    pub enabled: bool,
+    /// Frame time drawn at half the graph height.
+    pub budget_ms: f32,
+    buffer: HostBuffer,
+    pipeline: RenderHandle,
+}
+
+impl StatsGraph {
+    pub fn new(
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        pipeline_arena: &mut PipelineArena,
+        texture_arena: &TextureArena,
+        surface_format: vk::Format,
+    ) -> Result<Self> {
+        let buffer = device.create_host_buffer(
+            (WINDOW * size_of::<f32>()) as u64,
+            vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+            gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+        )?;
+        device.name_object(buffer.buffer, "Frame Time Samples");
+
+        let dir = shader_dir.join(POST_FOLDER);
+        default_shaders::create_post_shaders(&dir)?;
+        let push_constant_range = vk::PushConstantRange::default()
+            .size(size_of::<GraphPushConstant>() as _)
+            .stage_flags(vk::ShaderStageFlags::FRAGMENT);
+        let pipeline = pipeline_arena.create_render_pipeline(
+            &VertexInputDesc::default(),
+            &VertexShaderDesc {
+                shader_path: dir.join("post.vert"),
+                ..Default::default()
+            },
+            &FragmentShaderDesc {
+                shader_path: dir.join("stats.frag"),
+            },
+            &FragmentOutputDesc {
+                surface_format,
+                blend: BlendMode::Alpha,
+                ..Default::default()
+            },
+            &[push_constant_range],
+            &[texture_arena.images_set_layout],
+        )?;
+
+        Ok(Self {
+            enabled: false,
+            budget_ms: 1000. / 60.,
+            buffer,
+            pipeline,
+        })
+    }
+
+    pub fn toggle(&mut self) {
+        self.enabled = !self.enabled;
+    }
+
+    /// Uploads the samples and draws the graph over whatever is in `view`.
+    pub fn draw(
+        &self,
+        frame: &mut FrameGuard,
+        device: &Device,
+        pipeline_arena: &PipelineArena,
+        texture_arena: &TextureArena,
+        stats: &FrameStats,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+    ) {
+        if !self.enabled {
+            return;
+        }
+        let bytes: Vec<u8> = stats.samples().flat_map(f32::to_ne_bytes).collect();
+        if bytes.is_empty() {
+            return;
+        }
+        let cbuff = *frame.command_buffer();
+        let memory_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .src_access_mask(vk::AccessFlags2::SHADER_READ)
+            .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE);
+        let upload_barrier = vk::MemoryBarrier2::default()
+            .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+            .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+            .dst_stage_mask(vk::PipelineStageFlags2::FRAGMENT_SHADER)
+            .dst_access_mask(vk::AccessFlags2::SHADER_READ);
+        unsafe {
+            device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&memory_barrier)),
+            );
+            device.cmd_update_buffer(cbuff, self.buffer.buffer, 0, &bytes);
+            device.cmd_pipeline_barrier2(
+                cbuff,
+                &vk::DependencyInfo::default()
+                    .memory_barriers(std::slice::from_ref(&upload_barrier)),
+            );
+        }
+
+        let pipeline = pipeline_arena.get_pipeline(self.pipeline);
+        let push_constant = GraphPushConstant {
+            resolution: [extent.width as f32, extent.height as f32],
+            budget_ms: self.budget_ms,
+            count: (bytes.len() / size_of::<f32>()) as u32,
+            samples: self.buffer.address,
+        };
+        frame.resume_rendering(view);
+        frame.push_constant(
+            pipeline.layout,
+            vk::ShaderStageFlags::FRAGMENT,
+            &[push_constant],
+        );
+        frame.bind_descriptor_sets(
+            vk::PipelineBindPoint::GRAPHICS,
+            pipeline.layout,
+            &[texture_arena.images_set],
+        );
+        frame.bind_pipeline(vk::PipelineBindPoint::GRAPHICS, &pipeline.pipeline);
+        frame.draw(3, 0, 1, 0);
+        frame.end_rendering();
+    }
+}
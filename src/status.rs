@@ -0,0 +1,90 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result};
+
+use crate::{osc::encode_bundle, OscArg, OscMessage};
+
+/// Time between two reports.
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a report tells about the running instance.
+pub struct Status<'a> {
+    pub project: &'a str,
+    pub paused: bool,
+    pub recording: bool,
+    pub frame: u32,
+    /// The last failed shader reload, until a reload succeeds.
+    pub error: Option<&'a str>,
+}
+
+/// Sends the state of pilka as an OSC bundle every second, so a monitoring
+/// dashboard notices when an installation stops rendering.
+pub struct StatusPublisher {
+    socket: UdpSocket,
+    target: SocketAddr,
+    started: Instant,
+    last: Instant,
+    /// Frames presented since the last report.
+    frames: u32,
+}
+
+impl StatusPublisher {
+    pub fn new(target: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(("0.0.0.0", 0)).context("Failed to bind status socket")?;
+        socket.set_broadcast(true)?;
+        Ok(Self {
+            socket,
+            target,
+            started: Instant::now(),
+            last: Instant::now(),
+            frames: 0,
+        })
+    }
+
+    pub fn frame_presented(&mut self) {
+        self.frames += 1;
+    }
+
+    pub fn due(&self) -> bool {
+        self.last.elapsed() >= INTERVAL
+    }
+
+    pub fn next_report(&self) -> Instant {
+        self.last + INTERVAL
+    }
+
+    /// The fps are the frames presented since the last report, so a
+    /// minimized or stuck window reports `0`.
+    pub fn publish(&mut self, status: &Status) {
+        let elapsed = self.last.elapsed();
+        let fps = self.frames as f32 / elapsed.as_secs_f32();
+        self.frames = 0;
+        self.last = Instant::now();
+
+        let message = |address: &str, arg| OscMessage {
+            address: format!("/pilka/status/{address}"),
+            args: vec![arg],
+        };
+        let messages = [
+            message("fps", OscArg::Float(fps)),
+            message("frame", OscArg::Int(status.frame as i32)),
+            message("paused", OscArg::Bool(status.paused)),
+            message("recording", OscArg::Bool(status.recording)),
+            message("project", OscArg::Str(status.project.to_string())),
+            message(
+                "error",
+                OscArg::Str(status.error.unwrap_or_default().to_string()),
+            ),
+            message(
+                "uptime",
+                OscArg::Float(self.started.elapsed().as_secs_f32()),
+            ),
+        ];
+        if let Err(err) = self.socket.send_to(&encode_bundle(&messages), self.target) {
+            log::warn!("Failed to send status to {}: {err}", self.target);
+        }
+    }
+}
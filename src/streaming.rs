@@ -0,0 +1,694 @@
+use std::{
+    ffi::OsStr,
+    fs::File,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use crossbeam_channel::{Receiver, TryRecvError};
+use serde::Deserialize;
+
+use crate::{device::HostBuffer, overlay::expand_to_rgba, Device, TextureArena};
+
+const MAGIC: u32 = u32::from_le_bytes(*b"PLKT");
+/// `magic, width, height, tile_size, overview width, overview height` in
+/// front of the tiles of a cache file.
+const FILE_HEADER_SIZE: u64 = 24;
+/// `feedback, size, tile_size, tiles_x, atlas, atlas_columns, overview` in
+/// front of the page table.
+const HEADER_SIZE: usize = 36;
+const OVERVIEW_OFFSET: usize = 32;
+/// `Stream::overview` while the image is still being tiled.
+const NO_TEXTURE: u32 = u32::MAX;
+/// Longest side of the overview drawn where tiles aren't resident.
+const OVERVIEW_SIZE: u32 = 2048;
+/// Tiles uploaded per frame over all streams.
+const MAX_UPLOADS: usize = 4;
+const UPDATE_CHUNK: usize = 65536;
+
+/// A PNG too large for VRAM, sampled in shaders with `StreamTex(i, uv)` in
+/// manifest order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StreamDesc {
+    /// Relative to the shader folder.
+    pub path: PathBuf,
+    /// Side of a tile in pixels.
+    #[serde(default = "default_tile_size")]
+    pub tile_size: u32,
+    /// Tiles resident at once, the atlas holding them is allocated up front.
+    #[serde(default = "default_cache")]
+    pub cache: u32,
+}
+
+fn default_tile_size() -> u32 {
+    256
+}
+
+fn default_cache() -> u32 {
+    256
+}
+
+/// Box filtered copy of the whole image.
+struct Overview {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+/// Tiles of a converted image, read on demand from the cache file.
+struct TileFile {
+    file: File,
+    tile_bytes: u64,
+}
+
+impl TileFile {
+    fn read(&mut self, tile: u32, dst: &mut [u8]) -> std::io::Result<()> {
+        let offset = FILE_HEADER_SIZE + tile as u64 * self.tile_bytes;
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(dst)
+    }
+}
+
+struct Slot {
+    tile: Option<u32>,
+    last_used: u64,
+}
+
+struct Stream {
+    desc: StreamDesc,
+    /// Page table behind the header, updated in the frame command buffer.
+    buffer: HostBuffer,
+    /// Non-zero for every tile a shader asked for since the last upload.
+    feedback: HostBuffer,
+    atlas: usize,
+    atlas_columns: u32,
+    overview: usize,
+    slots: Vec<Slot>,
+    /// Cache slot + 1 of every tile, `0` when it isn't resident.
+    pages: Vec<u32>,
+    tiles: Option<TileFile>,
+    loading: Option<Receiver<Result<(PathBuf, Overview)>>>,
+}
+
+/// Huge images split into tiles on a background thread, the ones shaders
+/// sample are streamed into a fixed size atlas.
+#[derive(Default)]
+pub struct Streams {
+    streams: Vec<Stream>,
+    list: Option<HostBuffer>,
+    staging: Option<HostBuffer>,
+    /// Texture arena slots reused across folder switches, the arena never shrinks.
+    texture_slots: Vec<usize>,
+    /// Counts uploads, the age of cache slots.
+    clock: u64,
+}
+
+impl Streams {
+    /// Replaces the streams with the ones of `descs`. Images without a tile
+    /// cache from an earlier run are converted in the background.
+    pub fn configure(
+        &mut self,
+        descs: &[StreamDesc],
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<()> {
+        self.streams.clear();
+        self.list = None;
+        self.staging = None;
+
+        let max_size = device.device_properties.limits.max_image_dimension2_d;
+        let mut staging_size = 0;
+        for (i, desc) in descs.iter().enumerate() {
+            let path = shader_dir.join(&desc.path);
+            if path.extension().and_then(OsStr::to_str) != Some("png") {
+                bail!("Stream {} is not a .png file", path.display());
+            }
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("Failed to find stream {}", path.display()))?;
+            if desc.tile_size == 0 || desc.cache == 0 {
+                bail!(
+                    "Stream {} needs a non-zero tile_size and cache",
+                    path.display()
+                );
+            }
+            let (width, height) = read_size(&path)?;
+            let tile = desc.tile_size;
+            let tile_count = width.div_ceil(tile) * height.div_ceil(tile);
+
+            let atlas_columns = (desc.cache as f32).sqrt().ceil() as u32;
+            let atlas_rows = desc.cache.div_ceil(atlas_columns);
+            if atlas_columns.max(atlas_rows) * tile > max_size {
+                bail!(
+                    "Tile cache of {} doesn't fit into a {max_size}px texture, lower `cache` or `tile_size`",
+                    path.display()
+                );
+            }
+            let atlas_info = image_info(atlas_columns * tile, atlas_rows * tile);
+            let zeros = vec![0; (atlas_columns * atlas_rows * tile * tile * 4) as usize];
+            let atlas = self.texture(2 * i, atlas_info, &zeros, device, queue, texture_arena)?;
+            let overview = self.texture(
+                2 * i + 1,
+                image_info(1, 1),
+                &[0; 4],
+                device,
+                queue,
+                texture_arena,
+            )?;
+            device.name_object(
+                texture_arena.images[atlas].image,
+                &format!("Stream Atlas: {}", path.display()),
+            );
+
+            let mut feedback = device.create_host_buffer(
+                tile_count as u64 * 4,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_alloc::UsageFlags::DOWNLOAD,
+            )?;
+            device.name_object(
+                feedback.buffer,
+                &format!("Stream Feedback: {}", path.display()),
+            );
+            feedback.fill(0);
+
+            let mut buffer = device.create_host_buffer(
+                (HEADER_SIZE + tile_count as usize * 4) as u64,
+                vk::BufferUsageFlags::STORAGE_BUFFER | vk::BufferUsageFlags::TRANSFER_DST,
+                gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            )?;
+            device.name_object(buffer.buffer, &format!("Stream: {}", path.display()));
+            buffer.fill(0);
+            let header = [
+                width,
+                height,
+                tile,
+                width.div_ceil(tile),
+                atlas as u32,
+                atlas_columns,
+                NO_TEXTURE,
+            ];
+            buffer[0..8].copy_from_slice(&feedback.address.to_ne_bytes());
+            for (dst, value) in buffer[8..HEADER_SIZE].chunks_exact_mut(4).zip(header) {
+                dst.copy_from_slice(&value.to_ne_bytes());
+            }
+
+            let (tx, rx) = crossbeam_channel::bounded(1);
+            std::thread::spawn(move || {
+                let _ = tx.send(load_or_convert(&path, tile));
+            });
+
+            staging_size = staging_size.max(tile as u64 * tile as u64 * 4);
+            self.streams.push(Stream {
+                desc: desc.clone(),
+                buffer,
+                feedback,
+                atlas,
+                atlas_columns,
+                overview,
+                slots: (0..desc.cache)
+                    .map(|_| Slot {
+                        tile: None,
+                        last_used: 0,
+                    })
+                    .collect(),
+                pages: vec![0; tile_count as usize],
+                tiles: None,
+                loading: Some(rx),
+            });
+        }
+
+        if !self.streams.is_empty() {
+            let staging = device.create_host_buffer(
+                staging_size * MAX_UPLOADS as u64,
+                vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST,
+                gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
+            )?;
+            device.name_object(staging.buffer, "Stream Staging");
+            self.staging = Some(staging);
+
+            let mut list = device.create_host_buffer(
+                self.streams.len() as u64 * 8,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_alloc::UsageFlags::UPLOAD,
+            )?;
+            device.name_object(list.buffer, "Stream List");
+            for (dst, stream) in list.chunks_exact_mut(8).zip(&self.streams) {
+                dst.copy_from_slice(&stream.buffer.address.to_ne_bytes());
+            }
+            self.list = Some(list);
+        }
+        Ok(())
+    }
+
+    /// Address for `Resources::streams`, `0` when no stream is declared.
+    pub fn address(&self) -> u64 {
+        self.list.as_ref().map_or(0, |list| list.address)
+    }
+
+    /// Picks up finished conversions and records the upload of the tiles
+    /// shaders asked for into `cbuff`. Slots are recycled least recently
+    /// used first.
+    pub fn upload(
+        &mut self,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+        cbuff: vk::CommandBuffer,
+    ) {
+        for stream in &mut self.streams {
+            let Some(loading) = &stream.loading else {
+                continue;
+            };
+            let result = match loading.try_recv() {
+                Ok(result) => result,
+                Err(TryRecvError::Empty) => continue,
+                Err(TryRecvError::Disconnected) => Err(anyhow::anyhow!("Tiling thread died")),
+            };
+            stream.loading = None;
+            if let Err(err) = result.and_then(|(cache, overview)| {
+                stream.finish(cache, overview, device, queue, texture_arena)
+            }) {
+                log::error!("Failed to stream {}: {err}", stream.desc.path.display());
+            }
+        }
+
+        let Some(staging) = &self.staging else {
+            return;
+        };
+        self.clock += 1;
+        let slot_size = staging.size as usize / MAX_UPLOADS;
+        let mut staged = vec![];
+        let mut page_writes = vec![];
+        let mut copies: Vec<(vk::Image, Vec<vk::BufferImageCopy>)> = vec![];
+        for stream in &mut self.streams {
+            let Some(tiles) = &mut stream.tiles else {
+                continue;
+            };
+            let requested: Vec<u32> = stream
+                .feedback
+                .chunks_exact(4)
+                .enumerate()
+                .filter(|(_, flag)| *flag != [0; 4])
+                .map(|(tile, _)| tile as u32)
+                .collect();
+            stream.feedback.fill(0);
+
+            let mut missing = vec![];
+            for tile in requested {
+                match stream.pages[tile as usize] {
+                    0 => missing.push(tile),
+                    page => stream.slots[page as usize - 1].last_used = self.clock,
+                }
+            }
+
+            let tile_size = stream.desc.tile_size;
+            let tile_bytes = (tile_size * tile_size * 4) as usize;
+            let mut regions = vec![];
+            for tile in missing {
+                if staged.len() == MAX_UPLOADS {
+                    break;
+                }
+                let clock = self.clock;
+                let Some((slot_idx, slot)) = stream
+                    .slots
+                    .iter_mut()
+                    .enumerate()
+                    .filter(|(_, slot)| slot.last_used < clock)
+                    .min_by_key(|(_, slot)| slot.last_used)
+                else {
+                    break;
+                };
+                let mut data = vec![0; tile_bytes];
+                if let Err(err) = tiles.read(tile, &mut data) {
+                    log::error!(
+                        "Failed to read tile {tile} of {}: {err}",
+                        stream.desc.path.display()
+                    );
+                    break;
+                }
+                if let Some(old) = slot.tile.replace(tile) {
+                    stream.pages[old as usize] = 0;
+                    page_writes.push((stream.buffer.buffer, old, 0));
+                }
+                slot.last_used = clock;
+                stream.pages[tile as usize] = slot_idx as u32 + 1;
+                page_writes.push((stream.buffer.buffer, tile, slot_idx as u32 + 1));
+
+                let column = slot_idx as u32 % stream.atlas_columns;
+                let row = slot_idx as u32 / stream.atlas_columns;
+                regions.push(
+                    vk::BufferImageCopy::default()
+                        .buffer_offset((staged.len() * slot_size) as u64)
+                        .image_subresource(vk::ImageSubresourceLayers {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            mip_level: 0,
+                            base_array_layer: 0,
+                            layer_count: 1,
+                        })
+                        .image_offset(vk::Offset3D {
+                            x: (column * tile_size) as i32,
+                            y: (row * tile_size) as i32,
+                            z: 0,
+                        })
+                        .image_extent(vk::Extent3D {
+                            width: tile_size,
+                            height: tile_size,
+                            depth: 1,
+                        }),
+                );
+                staged.push(data);
+            }
+            if !regions.is_empty() {
+                copies.push((texture_arena.images[stream.atlas].image, regions));
+            }
+        }
+        if staged.is_empty() {
+            return;
+        }
+
+        let barrier = |src_stage, src_access, dst_stage, dst_access| {
+            let memory_barrier = vk::MemoryBarrier2::default()
+                .src_stage_mask(src_stage)
+                .src_access_mask(src_access)
+                .dst_stage_mask(dst_stage)
+                .dst_access_mask(dst_access);
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .memory_barriers(std::slice::from_ref(&memory_barrier)),
+                )
+            };
+        };
+
+        barrier(
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::SHADER_READ | vk::AccessFlags2::TRANSFER_READ,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+        );
+        for (i, data) in staged.iter().enumerate() {
+            for (j, chunk) in data.chunks(UPDATE_CHUNK).enumerate() {
+                let offset = (i * slot_size + j * UPDATE_CHUNK) as u64;
+                unsafe { device.cmd_update_buffer(cbuff, staging.buffer, offset, chunk) };
+            }
+        }
+        for (buffer, tile, page) in page_writes {
+            let offset = (HEADER_SIZE + tile as usize * 4) as u64;
+            unsafe { device.cmd_update_buffer(cbuff, buffer, offset, &page.to_ne_bytes()) };
+        }
+        barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_READ,
+        );
+
+        for (image, regions) in copies {
+            let mut image_barrier = vk::ImageMemoryBarrier2::default()
+                .subresource_range(crate::COLOR_SUBRESOURCE_MASK)
+                .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .src_access_mask(vk::AccessFlags2::SHADER_READ)
+                .dst_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .old_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .image(image);
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .image_memory_barriers(std::slice::from_ref(&image_barrier)),
+                );
+                device.cmd_copy_buffer_to_image(
+                    cbuff,
+                    staging.buffer,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &regions,
+                );
+            }
+            image_barrier = image_barrier
+                .src_stage_mask(vk::PipelineStageFlags2::TRANSFER)
+                .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                .dst_access_mask(vk::AccessFlags2::SHADER_READ)
+                .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+            unsafe {
+                device.cmd_pipeline_barrier2(
+                    cbuff,
+                    &vk::DependencyInfo::default()
+                        .image_memory_barriers(std::slice::from_ref(&image_barrier)),
+                )
+            };
+        }
+
+        barrier(
+            vk::PipelineStageFlags2::TRANSFER,
+            vk::AccessFlags2::TRANSFER_WRITE,
+            vk::PipelineStageFlags2::ALL_COMMANDS,
+            vk::AccessFlags2::SHADER_READ,
+        );
+    }
+
+    fn texture(
+        &mut self,
+        n: usize,
+        info: vk::ImageCreateInfo<'static>,
+        data: &[u8],
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<usize> {
+        if let Some(&slot) = self.texture_slots.get(n) {
+            texture_arena.replace_image(device, queue, slot, info, data)?;
+            return Ok(slot);
+        }
+        let slot = texture_arena.push_image(device, queue, info, data)? as usize;
+        self.texture_slots.push(slot);
+        Ok(slot)
+    }
+}
+
+impl Stream {
+    /// Uploads the overview and starts serving tiles from `cache`.
+    fn finish(
+        &mut self,
+        cache: PathBuf,
+        overview: Overview,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<()> {
+        let tile_size = self.desc.tile_size as u64;
+        self.tiles = Some(TileFile {
+            file: File::open(cache)?,
+            tile_bytes: tile_size * tile_size * 4,
+        });
+        // The placeholder may be bound by frames in flight
+        unsafe { device.device_wait_idle()? };
+        let info = image_info(overview.width, overview.height);
+        texture_arena.replace_image(device, queue, self.overview, info, &overview.pixels)?;
+        device.name_object(
+            texture_arena.images[self.overview].image,
+            &format!("Stream Overview: {}", self.desc.path.display()),
+        );
+        self.buffer[OVERVIEW_OFFSET..OVERVIEW_OFFSET + 4]
+            .copy_from_slice(&(self.overview as u32).to_ne_bytes());
+        Ok(())
+    }
+}
+
+fn image_info(width: u32, height: u32) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(vk::Format::R8G8B8A8_SRGB)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .mip_levels(1)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+}
+
+fn png_reader(path: &Path) -> Result<png::Reader<BufReader<File>>> {
+    let limits = png::Limits { bytes: usize::MAX };
+    let mut decoder = png::Decoder::new_with_limits(BufReader::new(File::open(path)?), limits);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    Ok(decoder.read_info()?)
+}
+
+fn read_size(path: &Path) -> Result<(u32, u32)> {
+    let reader =
+        png_reader(path).with_context(|| format!("Failed to read stream {}", path.display()))?;
+    Ok(reader.info().size())
+}
+
+/// Cache files live in the temporary directory, keyed by the image and the
+/// tile size so an edited image is converted again.
+fn cache_path(source: &Path, tile_size: u32) -> Result<PathBuf> {
+    let metadata = std::fs::metadata(source)?;
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    metadata.len().hash(&mut hasher);
+    metadata.modified()?.hash(&mut hasher);
+    tile_size.hash(&mut hasher);
+    let dir = std::env::temp_dir().join("pilka-tiles");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{:016x}.tiles", hasher.finish())))
+}
+
+fn load_or_convert(source: &Path, tile_size: u32) -> Result<(PathBuf, Overview)> {
+    let cache = cache_path(source, tile_size)?;
+    if let Ok(overview) = read_cache(&cache, tile_size) {
+        return Ok((cache, overview));
+    }
+    println!("Tiling {}", source.display());
+    let overview = convert(source, &cache, tile_size)?;
+    println!("Tiled {}", source.display());
+    Ok((cache, overview))
+}
+
+fn read_cache(cache: &Path, tile_size: u32) -> Result<Overview> {
+    let mut file = BufReader::new(File::open(cache)?);
+    let mut header = [0u8; FILE_HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    let header: Vec<u32> = header
+        .chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .collect();
+    let [magic, width, height, tile, overview_width, overview_height] = header[..] else {
+        unreachable!()
+    };
+    if magic != MAGIC || tile != tile_size {
+        bail!("Stale tile cache {}", cache.display());
+    }
+    let tile_count = width.div_ceil(tile) as u64 * height.div_ceil(tile) as u64;
+    let tile_bytes = tile as u64 * tile as u64 * 4;
+    file.seek(SeekFrom::Start(FILE_HEADER_SIZE + tile_count * tile_bytes))?;
+    let mut pixels = vec![0; (overview_width * overview_height * 4) as usize];
+    file.read_exact(&mut pixels)?;
+    Ok(Overview {
+        width: overview_width,
+        height: overview_height,
+        pixels,
+    })
+}
+
+/// Decodes `source` row by row into tiles, keeping only one row of tiles in
+/// memory. Tiles at the right and bottom edges are padded with zeros.
+fn convert(source: &Path, cache: &Path, tile_size: u32) -> Result<Overview> {
+    let mut reader = png_reader(source)?;
+    if reader.info().interlaced {
+        bail!("Interlaced PNGs can't be streamed, save it without interlacing");
+    }
+    let (width, height) = reader.info().size();
+    let color_type = reader.output_color_type().0;
+    let tile = tile_size as usize;
+    let tiles_x = width.div_ceil(tile_size) as usize;
+    let strip_stride = tiles_x * tile * 4;
+    let mut strip = vec![0u8; strip_stride * tile];
+    let mut overview = OverviewBuilder::new(width, height);
+
+    let partial = cache.with_extension("partial");
+    let mut out = BufWriter::new(File::create(&partial)?);
+    let header = [
+        MAGIC,
+        width,
+        height,
+        tile_size,
+        overview.width,
+        overview.height,
+    ];
+    for value in header {
+        out.write_all(&value.to_le_bytes())?;
+    }
+    for y in 0..height {
+        let row = reader.next_row()?.context("Image ended early")?;
+        let rgba = expand_to_rgba(color_type, row.data())?;
+        overview.push_row(&rgba, y + 1 == height);
+        let local = y as usize % tile;
+        strip[local * strip_stride..][..rgba.len()].copy_from_slice(&rgba);
+        if local + 1 == tile || y + 1 == height {
+            for column in 0..tiles_x {
+                for row in strip.chunks_exact(strip_stride) {
+                    out.write_all(&row[column * tile * 4..][..tile * 4])?;
+                }
+            }
+            strip.fill(0);
+        }
+    }
+    out.write_all(&overview.pixels)?;
+    out.into_inner()?.sync_all()?;
+    std::fs::rename(&partial, cache)?;
+
+    Ok(Overview {
+        width: overview.width,
+        height: overview.height,
+        pixels: overview.pixels,
+    })
+}
+
+/// Averages blocks of `factor` by `factor` pixels as rows come in.
+struct OverviewBuilder {
+    factor: u32,
+    source_width: u32,
+    width: u32,
+    height: u32,
+    sums: Vec<u32>,
+    rows: u32,
+    pixels: Vec<u8>,
+}
+
+impl OverviewBuilder {
+    fn new(source_width: u32, source_height: u32) -> Self {
+        let factor = source_width
+            .max(source_height)
+            .div_ceil(OVERVIEW_SIZE)
+            .max(1);
+        let width = source_width.div_ceil(factor);
+        let height = source_height.div_ceil(factor);
+        Self {
+            factor,
+            source_width,
+            width,
+            height,
+            sums: vec![0; width as usize * 4],
+            rows: 0,
+            pixels: Vec::with_capacity((width * height * 4) as usize),
+        }
+    }
+
+    fn push_row(&mut self, rgba: &[u8], last: bool) {
+        let factor = self.factor as usize;
+        for (x, pixel) in rgba.chunks_exact(4).enumerate() {
+            let sum = &mut self.sums[x / factor * 4..][..4];
+            for (sum, &channel) in sum.iter_mut().zip(pixel) {
+                *sum += channel as u32;
+            }
+        }
+        self.rows += 1;
+        if self.rows < self.factor && !last {
+            return;
+        }
+        for (x, sum) in self.sums.chunks_exact(4).enumerate() {
+            let columns = self.factor.min(self.source_width - x as u32 * self.factor);
+            let count = columns * self.rows;
+            self.pixels.extend(sum.iter().map(|&s| (s / count) as u8));
+        }
+        self.sums.fill(0);
+        self.rows = 0;
+    }
+}
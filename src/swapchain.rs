@@ -6,7 +6,178 @@ use ash::{
     vk::{self, CompositeAlphaFlagsKHR},
 };
 
-use crate::{device::Device, surface::Surface, ImageDimensions};
+use serde::Deserialize;
+
+use crate::{device::Device, is_srgb, surface::Surface, ImageDimensions};
+
+/// Encoding of the swapchain images. With `unorm` the shaders write
+/// display values, i.e. they apply the gamma themselves, `float` is what
+/// scRGB needs.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SurfaceEncoding {
+    #[default]
+    Srgb,
+    Unorm,
+    Float,
+}
+
+/// Color spaces of `VK_EXT_swapchain_colorspace`, only the ones the surface
+/// reports can be used.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorSpace {
+    #[default]
+    SrgbNonlinear,
+    ExtendedSrgbLinear,
+    ExtendedSrgbNonlinear,
+    DisplayP3Nonlinear,
+    DisplayP3Linear,
+    DciP3Nonlinear,
+    Bt709Nonlinear,
+    Bt2020Linear,
+    Hdr10St2084,
+    Hdr10Hlg,
+    AdobeRgbNonlinear,
+    PassThrough,
+}
+
+/// Macro names of the color spaces, `COLOR_SPACE` is defined as one of them.
+const COLOR_SPACE_MACROS: [(ColorSpace, &str); 12] = [
+    (ColorSpace::SrgbNonlinear, "COLOR_SPACE_SRGB_NONLINEAR"),
+    (
+        ColorSpace::ExtendedSrgbLinear,
+        "COLOR_SPACE_EXTENDED_SRGB_LINEAR",
+    ),
+    (
+        ColorSpace::ExtendedSrgbNonlinear,
+        "COLOR_SPACE_EXTENDED_SRGB_NONLINEAR",
+    ),
+    (
+        ColorSpace::DisplayP3Nonlinear,
+        "COLOR_SPACE_DISPLAY_P3_NONLINEAR",
+    ),
+    (ColorSpace::DisplayP3Linear, "COLOR_SPACE_DISPLAY_P3_LINEAR"),
+    (ColorSpace::DciP3Nonlinear, "COLOR_SPACE_DCI_P3_NONLINEAR"),
+    (ColorSpace::Bt709Nonlinear, "COLOR_SPACE_BT709_NONLINEAR"),
+    (ColorSpace::Bt2020Linear, "COLOR_SPACE_BT2020_LINEAR"),
+    (ColorSpace::Hdr10St2084, "COLOR_SPACE_HDR10_ST2084"),
+    (ColorSpace::Hdr10Hlg, "COLOR_SPACE_HDR10_HLG"),
+    (
+        ColorSpace::AdobeRgbNonlinear,
+        "COLOR_SPACE_ADOBE_RGB_NONLINEAR",
+    ),
+    (ColorSpace::PassThrough, "COLOR_SPACE_PASS_THROUGH"),
+];
+
+/// Macros telling shaders what the surface expects: `COLOR_SPACE` with one
+/// of the `COLOR_SPACE_*` values, `SURFACE_SRGB` when the hardware encodes
+/// the output and `SURFACE_HDR` for the HDR color spaces.
+pub fn surface_defines(format: vk::SurfaceFormatKHR) -> Vec<(String, String)> {
+    let mut defines: Vec<_> = COLOR_SPACE_MACROS
+        .iter()
+        .enumerate()
+        .map(|(i, (_, name))| (name.to_string(), i.to_string()))
+        .collect();
+    let current = COLOR_SPACE_MACROS
+        .iter()
+        .find(|(color_space, _)| vk::ColorSpaceKHR::from(*color_space) == format.color_space)
+        .map_or("-1", |(_, name)| name);
+    defines.push(("COLOR_SPACE".into(), current.into()));
+    defines.push((
+        "SURFACE_SRGB".into(),
+        (is_srgb(format.format) as u32).to_string(),
+    ));
+    defines.push(("SURFACE_HDR".into(), (is_hdr(format) as u32).to_string()));
+    defines
+}
+
+/// Whether `format` is presented with more range than sRGB.
+pub fn is_hdr(format: vk::SurfaceFormatKHR) -> bool {
+    matches!(
+        format.color_space,
+        vk::ColorSpaceKHR::EXTENDED_SRGB_LINEAR_EXT
+            | vk::ColorSpaceKHR::EXTENDED_SRGB_NONLINEAR_EXT
+            | vk::ColorSpaceKHR::BT2020_LINEAR_EXT
+            | vk::ColorSpaceKHR::HDR10_ST2084_EXT
+            | vk::ColorSpaceKHR::HDR10_HLG_EXT
+    )
+}
+
+impl From<ColorSpace> for vk::ColorSpaceKHR {
+    fn from(color_space: ColorSpace) -> Self {
+        match color_space {
+            ColorSpace::SrgbNonlinear => Self::SRGB_NONLINEAR,
+            ColorSpace::ExtendedSrgbLinear => Self::EXTENDED_SRGB_LINEAR_EXT,
+            ColorSpace::ExtendedSrgbNonlinear => Self::EXTENDED_SRGB_NONLINEAR_EXT,
+            ColorSpace::DisplayP3Nonlinear => Self::DISPLAY_P3_NONLINEAR_EXT,
+            ColorSpace::DisplayP3Linear => Self::DISPLAY_P3_LINEAR_EXT,
+            ColorSpace::DciP3Nonlinear => Self::DCI_P3_NONLINEAR_EXT,
+            ColorSpace::Bt709Nonlinear => Self::BT709_NONLINEAR_EXT,
+            ColorSpace::Bt2020Linear => Self::BT2020_LINEAR_EXT,
+            ColorSpace::Hdr10St2084 => Self::HDR10_ST2084_EXT,
+            ColorSpace::Hdr10Hlg => Self::HDR10_HLG_EXT,
+            ColorSpace::AdobeRgbNonlinear => Self::ADOBERGB_NONLINEAR_EXT,
+            ColorSpace::PassThrough => Self::PASS_THROUGH_EXT,
+        }
+    }
+}
+
+/// The `[swapchain]` table of the manifest, read once on launch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SwapchainSettings {
+    pub format: SurfaceEncoding,
+    pub color_space: ColorSpace,
+}
+
+impl SwapchainSettings {
+    /// The surface format matching the settings. Falls back to any format of
+    /// the color space and then to an sRGB one, with a warning listing what
+    /// the surface offers.
+    fn pick(&self, formats: &[vk::SurfaceFormatKHR]) -> vk::SurfaceFormatKHR {
+        let color_space = vk::ColorSpaceKHR::from(self.color_space);
+        let encoded = |format: &&vk::SurfaceFormatKHR| match self.format {
+            SurfaceEncoding::Srgb => is_srgb(format.format),
+            SurfaceEncoding::Unorm => matches!(
+                format.format,
+                vk::Format::B8G8R8A8_UNORM
+                    | vk::Format::R8G8B8A8_UNORM
+                    | vk::Format::A2B10G10R10_UNORM_PACK32
+                    | vk::Format::A2R10G10B10_UNORM_PACK32
+            ),
+            SurfaceEncoding::Float => format.format == vk::Format::R16G16B16A16_SFLOAT,
+        };
+        let mut candidates = formats
+            .iter()
+            .filter(|format| format.color_space == color_space);
+        if let Some(format) = candidates.clone().find(encoded) {
+            return *format;
+        }
+        let available = formats
+            .iter()
+            .map(|format| format!("{:?} {:?}", format.format, format.color_space))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let fallback = candidates
+            .next()
+            .or_else(|| {
+                formats.iter().find(|format| {
+                    format.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+                        && is_srgb(format.format)
+                })
+            })
+            .unwrap_or(&formats[0]);
+        log::warn!(
+            "No {:?} {:?} surface format, using {:?} {:?}. Available: {available}",
+            self.format,
+            color_space,
+            fallback.format,
+            fallback.color_space
+        );
+        *fallback
+    }
+}
 
 pub struct Frame {
     command_buffer: vk::CommandBuffer,
@@ -64,6 +235,21 @@ pub struct FrameGuard {
     device: Arc<Device>,
 }
 
+/// Attachments the scene rendering has next to its color view.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SceneAttachments {
+    /// Multisampled target drawn into and resolved into the color view.
+    pub msaa: Option<vk::ImageView>,
+    /// Depth buffer cleared to the far plane.
+    pub depth: Option<vk::ImageView>,
+}
+
+impl SceneAttachments {
+    pub fn is_empty(&self) -> bool {
+        self.msaa.is_none() && self.depth.is_none()
+    }
+}
+
 pub struct Swapchain {
     pub images: Vec<vk::Image>,
     pub views: Vec<vk::ImageView>,
@@ -73,11 +259,31 @@ pub struct Swapchain {
     pub format: vk::SurfaceFormatKHR,
     pub extent: vk::Extent2D,
     pub image_dimensions: ImageDimensions,
+    composite_alpha: CompositeAlphaFlagsKHR,
     inner: vk::SwapchainKHR,
     loader: khr::swapchain::Device,
     device: Arc<Device>,
 }
 
+/// How the compositor blends a transparent window, opaque when it can't.
+fn pick_composite_alpha(
+    supported: CompositeAlphaFlagsKHR,
+    transparent: bool,
+) -> CompositeAlphaFlagsKHR {
+    let blended = [
+        CompositeAlphaFlagsKHR::PRE_MULTIPLIED,
+        CompositeAlphaFlagsKHR::POST_MULTIPLIED,
+        CompositeAlphaFlagsKHR::INHERIT,
+    ];
+    let picked = blended
+        .into_iter()
+        .find(|&mode| transparent && supported.contains(mode));
+    if transparent && picked.is_none() {
+        log::warn!("The surface doesn't support transparency, presenting opaque");
+    }
+    picked.unwrap_or(CompositeAlphaFlagsKHR::OPAQUE)
+}
+
 impl Swapchain {
     const SUBRANGE: vk::ImageSubresourceRange = vk::ImageSubresourceRange {
         aspect_mask: vk::ImageAspectFlags::COLOR,
@@ -91,6 +297,10 @@ impl Swapchain {
         self.format.format
     }
 
+    pub fn surface_format(&self) -> vk::SurfaceFormatKHR {
+        self.format
+    }
+
     pub fn extent(&self) -> vk::Extent2D {
         self.extent
     }
@@ -99,19 +309,12 @@ impl Swapchain {
         device: &Arc<Device>,
         surface: &Surface,
         swapchain_loader: khr::swapchain::Device,
+        settings: &SwapchainSettings,
+        transparent: bool,
     ) -> VkResult<Self> {
         let info = surface.info(device);
         let capabilities = info.capabilities;
-        let format = info
-            .formats
-            .iter()
-            .find(|format| {
-                matches!(
-                    format.format,
-                    vk::Format::B8G8R8A8_SRGB | vk::Format::R8G8B8A8_SRGB
-                )
-            })
-            .unwrap_or(&info.formats[0]);
+        let format = &settings.pick(&info.formats);
 
         let image_count = capabilities
             .max_image_count
@@ -125,9 +328,11 @@ impl Swapchain {
         extent.width = extent.width.min(capabilities.max_image_extent.width);
         extent.height = extent.height.min(capabilities.max_image_extent.height);
 
+        let composite_alpha =
+            pick_composite_alpha(capabilities.supported_composite_alpha, transparent);
         assert!(capabilities
             .supported_composite_alpha
-            .contains(CompositeAlphaFlagsKHR::OPAQUE));
+            .contains(composite_alpha));
         let swapchain_create_info = vk::SwapchainCreateInfoKHR::default()
             .surface(**surface)
             .image_format(format.format)
@@ -139,7 +344,7 @@ impl Swapchain {
             .queue_family_indices(&queue_family_index)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
+            .composite_alpha(composite_alpha)
             .present_mode(vk::PresentModeKHR::FIFO)
             .clipped(true);
         let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None)? };
@@ -174,6 +379,7 @@ impl Swapchain {
             image_dimensions,
             format: *format,
             extent,
+            composite_alpha,
             inner: swapchain,
             loader: swapchain_loader,
             device: device.clone(),
@@ -214,7 +420,7 @@ impl Swapchain {
             .queue_family_indices(&queue_family_index)
             .image_sharing_mode(vk::SharingMode::EXCLUSIVE)
             .pre_transform(vk::SurfaceTransformFlagsKHR::IDENTITY)
-            .composite_alpha(CompositeAlphaFlagsKHR::OPAQUE)
+            .composite_alpha(self.composite_alpha)
             .present_mode(vk::PresentModeKHR::FIFO)
             .clipped(true);
         self.inner = unsafe { self.loader.create_swapchain(&swapchain_create_info, None)? };
@@ -376,39 +582,137 @@ impl FrameGuard {
         &self.frame.command_buffer
     }
 
+    pub fn extent(&self) -> vk::Extent2D {
+        self.extent
+    }
+
     pub fn begin_rendering(&mut self, view: &vk::ImageView, color: [f32; 4]) {
+        let extent = self.extent;
+        self.begin_rendering_with(
+            view,
+            SceneAttachments::default(),
+            extent,
+            vk::AttachmentLoadOp::CLEAR,
+            color,
+        );
+    }
+
+    /// Starts rendering into an offscreen target that isn't swapchain sized.
+    pub fn begin_rendering_extent(
+        &mut self,
+        view: &vk::ImageView,
+        extent: vk::Extent2D,
+        color: [f32; 4],
+    ) {
+        self.begin_rendering_with(
+            view,
+            SceneAttachments::default(),
+            extent,
+            vk::AttachmentLoadOp::CLEAR,
+            color,
+        );
+    }
+
+    /// Starts rendering on top of what is already in `view`.
+    pub fn resume_rendering(&mut self, view: &vk::ImageView) {
+        self.resume_rendering_extent(view, self.extent);
+    }
+
+    pub fn resume_rendering_extent(&mut self, view: &vk::ImageView, extent: vk::Extent2D) {
+        self.begin_rendering_with(
+            view,
+            SceneAttachments::default(),
+            extent,
+            vk::AttachmentLoadOp::LOAD,
+            [0.; 4],
+        );
+    }
+
+    /// Like [`Self::begin_rendering_extent`] with the extra attachments of
+    /// the scene.
+    pub fn begin_rendering_scene(
+        &mut self,
+        view: &vk::ImageView,
+        attachments: SceneAttachments,
+        extent: vk::Extent2D,
+        color: [f32; 4],
+    ) {
+        self.begin_rendering_with(
+            view,
+            attachments,
+            extent,
+            vk::AttachmentLoadOp::CLEAR,
+            color,
+        );
+    }
+
+    fn begin_rendering_with(
+        &mut self,
+        view: &vk::ImageView,
+        attachments: SceneAttachments,
+        extent: vk::Extent2D,
+        load_op: vk::AttachmentLoadOp,
+        color: [f32; 4],
+    ) {
         let clear_color = vk::ClearValue {
             color: vk::ClearColorValue { float32: color },
         };
-        let color_attachments = [vk::RenderingAttachmentInfo::default()
-            .image_view(*view)
+        let color_attachment = match attachments.msaa {
+            Some(msaa_view) => vk::RenderingAttachmentInfo::default()
+                .image_view(msaa_view)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(*view)
+                .resolve_image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE),
+            None => vk::RenderingAttachmentInfo::default()
+                .image_view(*view)
+                .resolve_image_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                .store_op(vk::AttachmentStoreOp::STORE),
+        };
+        let color_attachments = [color_attachment
             .image_layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
-            .resolve_image_layout(vk::ImageLayout::PRESENT_SRC_KHR)
-            .load_op(vk::AttachmentLoadOp::CLEAR)
-            .store_op(vk::AttachmentStoreOp::STORE)
+            .load_op(load_op)
             .clear_value(clear_color)];
-        let rendering_info = vk::RenderingInfo::default()
-            .render_area(self.extent.into())
+        let depth_attachment = attachments.depth.map(|depth_view| {
+            vk::RenderingAttachmentInfo::default()
+                .image_view(depth_view)
+                .image_layout(vk::ImageLayout::DEPTH_ATTACHMENT_OPTIMAL)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .clear_value(vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    },
+                })
+        });
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(extent.into())
             .layer_count(1)
             .color_attachments(&color_attachments);
+        if let Some(depth_attachment) = &depth_attachment {
+            rendering_info = rendering_info.depth_attachment(depth_attachment);
+        }
         unsafe {
             self.device
                 .dynamic_rendering
                 .cmd_begin_rendering(self.frame.command_buffer, &rendering_info)
         };
+        self.set_area(extent.into());
+    }
+
+    /// Restricts drawing to `area`, keeping the viewport flipped.
+    pub fn set_area(&self, area: vk::Rect2D) {
         let viewport = vk::Viewport {
-            x: 0.0,
-            y: self.extent.height as f32,
-            width: self.extent.width as f32,
-            height: -(self.extent.height as f32),
+            x: area.offset.x as f32,
+            y: (area.offset.y + area.extent.height as i32) as f32,
+            width: area.extent.width as f32,
+            height: -(area.extent.height as f32),
             min_depth: 0.0,
             max_depth: 1.0,
         };
         self.set_viewports(&[viewport]);
-        self.set_scissors(&[vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: self.extent,
-        }]);
+        self.set_scissors(&[area]);
     }
 
     pub fn draw(
@@ -0,0 +1,147 @@
+use std::{
+    net::{SocketAddr, UdpSocket},
+    str::FromStr,
+    sync::Arc,
+    thread::JoinHandle,
+    time::Instant,
+};
+
+use anyhow::{bail, Context, Result};
+use parking_lot::Mutex;
+
+const MAGIC: &[u8; 4] = b"PLKS";
+const PACKET_SIZE: usize = 4 + 8 + 4 + 1;
+
+/// Clock and transport shared by the master with every follower.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SyncState {
+    pub time: f64,
+    pub frame: u32,
+    pub paused: bool,
+}
+
+impl SyncState {
+    fn encode(&self) -> [u8; PACKET_SIZE] {
+        let mut packet = [0; PACKET_SIZE];
+        packet[..4].copy_from_slice(MAGIC);
+        packet[4..12].copy_from_slice(&self.time.to_le_bytes());
+        packet[12..16].copy_from_slice(&self.frame.to_le_bytes());
+        packet[16] = self.paused as u8;
+        packet
+    }
+
+    fn decode(packet: &[u8]) -> Result<Self> {
+        if packet.len() != PACKET_SIZE || !packet.starts_with(MAGIC) {
+            bail!("Not a pilka sync packet");
+        }
+        Ok(Self {
+            time: f64::from_le_bytes(packet[4..12].try_into().unwrap()),
+            frame: u32::from_le_bytes(packet[12..16].try_into().unwrap()),
+            paused: packet[16] != 0,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    /// Sends the clock to this address every frame, a broadcast address
+    /// reaches the whole LAN.
+    Master(SocketAddr),
+    /// Takes the clock from packets arriving on this port.
+    Follow(u16),
+}
+
+impl FromStr for SyncMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s.split_once(':') {
+            Some(("follow", port)) => SyncMode::Follow(port.parse().context("Invalid sync port")?),
+            Some(("master", addr)) => {
+                SyncMode::Master(addr.parse().context("Invalid sync address")?)
+            }
+            _ => bail!("Expected `master:ip:port` or `follow:port`, got `{s}`"),
+        })
+    }
+}
+
+pub enum ClockSync {
+    Master {
+        socket: UdpSocket,
+        target: SocketAddr,
+    },
+    Follower {
+        port: u16,
+        latest: Arc<Mutex<Option<(SyncState, Instant)>>>,
+        _thread_handle: JoinHandle<()>,
+    },
+}
+
+impl ClockSync {
+    pub fn new(mode: SyncMode) -> Result<Self> {
+        match mode {
+            SyncMode::Master(target) => {
+                let socket = UdpSocket::bind(("0.0.0.0", 0))?;
+                socket.set_broadcast(true)?;
+                Ok(Self::Master { socket, target })
+            }
+            SyncMode::Follow(port) => {
+                let socket = UdpSocket::bind(("0.0.0.0", port))
+                    .with_context(|| format!("Failed to bind sync socket on port {port}"))?;
+                let latest = Arc::new(Mutex::new(None));
+                let thread_latest = latest.clone();
+                let thread_handle = std::thread::spawn(move || {
+                    let mut buf = [0u8; 64];
+                    loop {
+                        let len = match socket.recv(&mut buf) {
+                            Ok(len) => len,
+                            Err(err) => {
+                                log::error!("Sync socket error: {err}");
+                                return;
+                            }
+                        };
+                        match SyncState::decode(&buf[..len]) {
+                            Ok(state) => *thread_latest.lock() = Some((state, Instant::now())),
+                            Err(err) => log::warn!("{err}"),
+                        }
+                    }
+                });
+                Ok(Self::Follower {
+                    port,
+                    latest,
+                    _thread_handle: thread_handle,
+                })
+            }
+        }
+    }
+
+    /// Broadcasts the local clock, followers ignore it.
+    pub fn publish(&self, state: SyncState) {
+        if let Self::Master { socket, target } = self {
+            if let Err(err) = socket.send_to(&state.encode(), target) {
+                log::warn!("Failed to send sync packet: {err}");
+            }
+        }
+    }
+
+    /// Latest master clock, advanced by the time since it arrived.
+    pub fn state(&self) -> Option<SyncState> {
+        let Self::Follower { latest, .. } = self else {
+            return None;
+        };
+        let (mut state, received) = (*latest.lock())?;
+        if !state.paused {
+            state.time += received.elapsed().as_secs_f64();
+        }
+        Some(state)
+    }
+}
+
+impl std::fmt::Display for ClockSync {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Master { target, .. } => write!(f, "Sending clock sync to {target}"),
+            Self::Follower { port, .. } => write!(f, "Following clock sync on port {port}"),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{device::HostBuffer, Device, TextureArena, Watcher};
+
+/// Size of `uint rows; uint columns; uint texture;` in front of the values.
+const HEADER_SIZE: usize = 12;
+/// `Table::texture` of tables without a texture.
+const NO_TEXTURE: u32 = u32::MAX;
+
+/// A CSV or JSON file of numbers, read in shaders through
+/// `pc.resources.tables.tables[i]` in manifest order.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TableDesc {
+    /// Relative to the shader folder.
+    pub path: PathBuf,
+    /// Also upload the cells as an `R32_SFLOAT` texture, one texel per cell.
+    #[serde(default)]
+    pub texture: bool,
+}
+
+/// Numbers of a table in row-major order, short rows are padded with NaN.
+struct TableData {
+    rows: u32,
+    columns: u32,
+    values: Vec<f32>,
+}
+
+impl TableData {
+    fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let rows = match path.extension().and_then(OsStr::to_str) {
+            Some("json") => parse_json(&text)?,
+            _ => parse_csv(&text),
+        };
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        if columns == 0 {
+            bail!("Table has no values");
+        }
+        let mut values = Vec::with_capacity(rows.len() * columns);
+        for row in &rows {
+            values.extend_from_slice(row);
+            values.resize(values.len() + columns - row.len(), f32::NAN);
+        }
+        Ok(Self {
+            rows: rows.len() as u32,
+            columns: columns as u32,
+            values,
+        })
+    }
+}
+
+struct Table {
+    desc: TableDesc,
+    path: PathBuf,
+    buffer: HostBuffer,
+    texture: Option<usize>,
+}
+
+/// Tables loaded from the shader folder, each buffer laid out as
+/// `uint rows; uint columns; uint texture; float values[]`.
+#[derive(Default)]
+pub struct Tables {
+    tables: Vec<Table>,
+    list: Option<HostBuffer>,
+    /// Texture arena slots reused across folder switches, the arena never shrinks.
+    texture_slots: Vec<usize>,
+}
+
+impl Tables {
+    /// Replaces the tables with the ones of `descs` and watches their files.
+    pub fn configure(
+        &mut self,
+        descs: &[TableDesc],
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+        watcher: &mut Watcher,
+    ) -> Result<()> {
+        for table in self.tables.drain(..) {
+            let _ = watcher.unwatch_file(&table.path);
+        }
+        self.list = None;
+
+        let mut textures = 0;
+        for desc in descs {
+            let path = shader_dir.join(&desc.path);
+            if !matches!(
+                path.extension().and_then(OsStr::to_str),
+                Some("csv" | "json")
+            ) {
+                bail!(
+                    "Table {} is neither a .csv nor a .json file",
+                    path.display()
+                );
+            }
+            let path = path
+                .canonicalize()
+                .with_context(|| format!("Failed to find table {}", path.display()))?;
+            let texture = match desc.texture {
+                true => {
+                    textures += 1;
+                    Some(self.texture_slot(textures - 1, device, queue, texture_arena)?)
+                }
+                false => None,
+            };
+            let buffer = load_table(&path, texture, device, queue, texture_arena)?;
+            watcher.watch_file(&path)?;
+            self.tables.push(Table {
+                desc: desc.clone(),
+                path,
+                buffer,
+                texture,
+            });
+        }
+
+        if !self.tables.is_empty() {
+            let list = device.create_host_buffer(
+                self.tables.len() as u64 * 8,
+                vk::BufferUsageFlags::STORAGE_BUFFER,
+                gpu_alloc::UsageFlags::UPLOAD,
+            )?;
+            device.name_object(list.buffer, "Table List");
+            self.list = Some(list);
+            self.write_list();
+        }
+        Ok(())
+    }
+
+    /// Address for `Resources::tables`, `0` when no table is declared.
+    pub fn address(&self) -> u64 {
+        self.list.as_ref().map_or(0, |list| list.address)
+    }
+
+    /// Reads every table loaded from `path` again. The GPU has to be idle,
+    /// the old buffers and textures are freed.
+    pub fn reload(
+        &mut self,
+        path: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<()> {
+        for table in self.tables.iter_mut().filter(|table| table.path == path) {
+            table.buffer = load_table(path, table.texture, device, queue, texture_arena)?;
+            println!("Reloaded table {}", table.desc.path.display());
+        }
+        self.write_list();
+        Ok(())
+    }
+
+    fn texture_slot(
+        &mut self,
+        n: usize,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<usize> {
+        if let Some(&slot) = self.texture_slots.get(n) {
+            return Ok(slot);
+        }
+        let slot = texture_arena.push_image(device, queue, image_info(1, 1), &[0; 4])? as usize;
+        self.texture_slots.push(slot);
+        Ok(slot)
+    }
+
+    fn write_list(&mut self) {
+        let Some(list) = &mut self.list else { return };
+        for (dst, table) in list.chunks_exact_mut(8).zip(&self.tables) {
+            dst.copy_from_slice(&table.buffer.address.to_ne_bytes());
+        }
+    }
+}
+
+fn image_info(width: u32, height: u32) -> vk::ImageCreateInfo<'static> {
+    vk::ImageCreateInfo::default()
+        .extent(vk::Extent3D {
+            width,
+            height,
+            depth: 1,
+        })
+        .image_type(vk::ImageType::TYPE_2D)
+        .format(vk::Format::R32_SFLOAT)
+        .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+        .samples(vk::SampleCountFlags::TYPE_1)
+        .mip_levels(1)
+        .array_layers(1)
+        .tiling(vk::ImageTiling::OPTIMAL)
+}
+
+/// Uploads the table at `path` into a new buffer and into texture slot `texture`.
+fn load_table(
+    path: &Path,
+    texture: Option<usize>,
+    device: &Arc<Device>,
+    queue: &vk::Queue,
+    texture_arena: &mut TextureArena,
+) -> Result<HostBuffer> {
+    let data = TableData::load(path)
+        .with_context(|| format!("Failed to load table {}", path.display()))?;
+    let values: Vec<u8> = data.values.iter().flat_map(|v| v.to_ne_bytes()).collect();
+
+    if let Some(slot) = texture {
+        let info = image_info(data.columns, data.rows);
+        texture_arena.replace_image(device, queue, slot, info, &values)?;
+        device.name_object(
+            texture_arena.images[slot].image,
+            &format!("Table: {}", path.display()),
+        );
+    }
+
+    let mut buffer = device.create_host_buffer(
+        (HEADER_SIZE + values.len()) as u64,
+        vk::BufferUsageFlags::STORAGE_BUFFER,
+        gpu_alloc::UsageFlags::UPLOAD,
+    )?;
+    device.name_object(buffer.buffer, &format!("Table: {}", path.display()));
+    let texture = texture.map_or(NO_TEXTURE, |slot| slot as u32);
+    buffer[0..4].copy_from_slice(&data.rows.to_ne_bytes());
+    buffer[4..8].copy_from_slice(&data.columns.to_ne_bytes());
+    buffer[8..12].copy_from_slice(&texture.to_ne_bytes());
+    buffer[HEADER_SIZE..HEADER_SIZE + values.len()].copy_from_slice(&values);
+    Ok(buffer)
+}
+
+/// One row per line, cells separated by commas, semicolons or tabs. A first
+/// line without any number is taken for a header and skipped, other cells
+/// that aren't numbers become NaN.
+fn parse_csv(text: &str) -> Vec<Vec<f32>> {
+    let mut rows = vec![];
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let row: Vec<f32> = line
+            .split([',', ';', '\t'])
+            .map(|cell| cell.trim().trim_matches('"').parse().unwrap_or(f32::NAN))
+            .collect();
+        if rows.is_empty() && row.iter().all(|v| v.is_nan()) {
+            continue;
+        }
+        rows.push(row);
+    }
+    rows
+}
+
+/// Every array or object inside the top-level one is a row with its values in
+/// order, nested deeper ones are flattened into it. Numbers directly in the
+/// top-level array make a single row. Object keys are skipped, strings and
+/// `null` become NaN, booleans `0` or `1`.
+fn parse_json(text: &str) -> Result<Vec<Vec<f32>>> {
+    let bytes = text.as_bytes();
+    let mut rows = vec![];
+    let mut row = vec![];
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        let value = match bytes[i] {
+            b'[' | b'{' => {
+                depth += 1;
+                i += 1;
+                continue;
+            }
+            b']' | b'}' => {
+                depth = depth
+                    .checked_sub(1)
+                    .context("Unbalanced brackets in JSON")?;
+                if depth <= 1 && !row.is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                i += 1;
+                continue;
+            }
+            b'"' => {
+                i += 1;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                i += 1;
+                let rest = text.get(i..).unwrap_or_default().trim_start();
+                if rest.starts_with(':') {
+                    continue;
+                }
+                f32::NAN
+            }
+            b'-' | b'0'..=b'9' => {
+                let start = i;
+                while i < bytes.len()
+                    && matches!(bytes[i], b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')
+                {
+                    i += 1;
+                }
+                let number = &text[start..i];
+                row.push(
+                    number
+                        .parse()
+                        .with_context(|| format!("Invalid number `{number}` in JSON"))?,
+                );
+                continue;
+            }
+            _ if text[i..].starts_with("true") => {
+                i += 4;
+                1.
+            }
+            _ if text[i..].starts_with("false") => {
+                i += 5;
+                0.
+            }
+            _ if text[i..].starts_with("null") => {
+                i += 4;
+                f32::NAN
+            }
+            b',' | b':' => {
+                i += 1;
+                continue;
+            }
+            c if c.is_ascii_whitespace() => {
+                i += 1;
+                continue;
+            }
+            c => bail!("Unexpected `{}` at byte {i} of JSON", c as char),
+        };
+        row.push(value);
+    }
+    if depth != 0 {
+        bail!("Unbalanced brackets in JSON");
+    }
+    if !row.is_empty() {
+        rows.push(row);
+    }
+    Ok(rows)
+}
@@ -1,13 +1,14 @@
 use std::{mem::ManuallyDrop, sync::Arc};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use ash::{
     prelude::VkResult,
     vk::{self, DeviceMemory},
 };
 use gpu_alloc::{MemoryBlock, UsageFlags};
+use serde::Deserialize;
 
-use crate::{Device, ImageDimensions, COLOR_SUBRESOURCE_MASK};
+use crate::{Device, ImageDimensions, MemoryKind, COLOR_SUBRESOURCE_MASK};
 
 pub const LINEAR_SAMPLER_IDX: usize = 0;
 pub const NEAREST_SAMPLER_IDX: usize = 1;
@@ -18,9 +19,18 @@ pub const GENERIC_IMAGE2_IDX: usize = 2;
 pub const DITHER_IMAGE_IDX: usize = 3;
 pub const NOISE_IMAGE_IDX: usize = 4;
 pub const BLUE_IMAGE_IDX: usize = 5;
+/// Ring of the last frames, bound as a `texture2DArray`.
+pub const HISTORY_IMAGE_IDX: usize = 6;
 
 pub const SCREENSIZED_IMAGE_INDICES: [usize; 3] =
     [PREV_FRAME_IMAGE_IDX, GENERIC_IMAGE1_IDX, GENERIC_IMAGE2_IDX];
+/// Images carrying state between frames, zeroed by `clear_images`.
+pub const FEEDBACK_IMAGE_INDICES: [usize; 4] = [
+    PREV_FRAME_IMAGE_IDX,
+    GENERIC_IMAGE1_IDX,
+    GENERIC_IMAGE2_IDX,
+    HISTORY_IMAGE_IDX,
+];
 
 pub struct Image {
     pub image: vk::Image,
@@ -36,7 +46,7 @@ impl Image {
     ) -> Result<Self> {
         let image = unsafe { device.create_image(info, None)? };
         let memory_reqs = unsafe { device.get_image_memory_requirements(image) };
-        let memory = device.alloc_memory(memory_reqs, usage)?;
+        let memory = device.alloc_memory(memory_reqs, usage, MemoryKind::Textures)?;
         unsafe { device.bind_image_memory(image, *memory.memory(), memory.offset()) }?;
         let image_dimensions = ImageDimensions::new(
             info.extent.width as _,
@@ -59,47 +69,186 @@ impl Image {
     }
 }
 
+/// Layered images get an array view so shaders can bind them as `texture2DArray`.
+fn create_view(
+    device: &Device,
+    image: &vk::Image,
+    info: &vk::ImageCreateInfo,
+) -> VkResult<vk::ImageView> {
+    match info.array_layers {
+        1 => device.create_2d_view(image, info.format),
+        layers => device.create_2d_array_view(image, info.format, layers),
+    }
+}
+
+/// Image descriptors allocated up front, the set is reallocated twice as
+/// large whenever an image doesn't fit.
 const IMAGES_COUNT: u32 = 2048;
 const SAMPLER_COUNT: u32 = 8;
 
+/// Filtering of the built-in samplers, from the `[sampler]` table of the
+/// manifest.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SamplerSettings {
+    /// Maximum anisotropy of the linear sampler, `1` disables it. Clamped to
+    /// the device limit.
+    pub anisotropy: f32,
+    /// Added to the mip level both samplers compute.
+    pub lod_bias: f32,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            anisotropy: 1.,
+            lod_bias: 0.,
+        }
+    }
+}
+
+fn create_sampler(
+    device: &Device,
+    filter: vk::Filter,
+    settings: SamplerSettings,
+) -> Result<vk::Sampler> {
+    let limits = &device.device_properties.limits;
+    let anisotropy = settings.anisotropy.min(limits.max_sampler_anisotropy);
+    let anisotropic = filter == vk::Filter::LINEAR
+        && anisotropy > 1.
+        && device.enabled_features.sampler_anisotropy == vk::TRUE;
+    let lod_bias = settings
+        .lod_bias
+        .clamp(-limits.max_sampler_lod_bias, limits.max_sampler_lod_bias);
+    let info = vk::SamplerCreateInfo::default()
+        .min_filter(filter)
+        .mag_filter(filter)
+        .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
+        .address_mode_u(vk::SamplerAddressMode::MIRRORED_REPEAT)
+        .address_mode_v(vk::SamplerAddressMode::MIRRORED_REPEAT)
+        .address_mode_w(vk::SamplerAddressMode::MIRRORED_REPEAT)
+        .anisotropy_enable(anisotropic)
+        .max_anisotropy(if anisotropic { anisotropy } else { 1. })
+        .mip_lod_bias(lod_bias)
+        .max_lod(vk::LOD_CLAMP_NONE);
+    Ok(unsafe { device.create_sampler(&info, None)? })
+}
+
+fn write_sampler(device: &Device, set: vk::DescriptorSet, idx: usize, sampler: vk::Sampler) {
+    let image_info = vk::DescriptorImageInfo::default().sampler(sampler);
+    let write = vk::WriteDescriptorSet::default()
+        .descriptor_type(vk::DescriptorType::SAMPLER)
+        .dst_set(set)
+        .dst_binding(0)
+        .image_info(std::slice::from_ref(&image_info))
+        .dst_array_element(idx as _);
+    unsafe { device.update_descriptor_sets(&[write], &[]) };
+}
+
+/// Pool with room for a single set of `images` image descriptors.
+fn allocate_images_set(
+    device: &Device,
+    layout: vk::DescriptorSetLayout,
+    images: u32,
+) -> Result<(vk::DescriptorPool, vk::DescriptorSet)> {
+    let pool_sizes = [
+        vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::SAMPLED_IMAGE)
+            .descriptor_count(images),
+        vk::DescriptorPoolSize::default()
+            .ty(vk::DescriptorType::SAMPLER)
+            .descriptor_count(SAMPLER_COUNT),
+    ];
+    let descriptor_pool = unsafe {
+        device.create_descriptor_pool(
+            &vk::DescriptorPoolCreateInfo::default()
+                .flags(
+                    vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
+                        | vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
+                )
+                .pool_sizes(&pool_sizes)
+                .max_sets(1),
+            None,
+        )?
+    };
+
+    let counts = [images];
+    let mut variable_info =
+        vk::DescriptorSetVariableDescriptorCountAllocateInfo::default().descriptor_counts(&counts);
+    let allocate_info = vk::DescriptorSetAllocateInfo::default()
+        .descriptor_pool(descriptor_pool)
+        .set_layouts(std::slice::from_ref(&layout))
+        .push_next(&mut variable_info);
+    match unsafe { device.allocate_descriptor_sets(&allocate_info) } {
+        Ok(sets) => Ok((descriptor_pool, sets[0])),
+        Err(err) => {
+            unsafe { device.destroy_descriptor_pool(descriptor_pool, None) };
+            Err(err.into())
+        }
+    }
+}
+
 pub struct TextureArena {
     pub images: Vec<Image>,
     pub image_infos: Vec<vk::ImageCreateInfo<'static>>,
     pub views: Vec<vk::ImageView>,
     pub samplers: [vk::Sampler; SAMPLER_COUNT as usize],
+    sampler_settings: SamplerSettings,
+    /// Replaced images and samplers set 1 descriptors of older pipelines may
+    /// still point at, destroyed by [`Self::release_retired`].
+    retired_images: Vec<(Image, vk::ImageView)>,
+    retired_samplers: Vec<vk::Sampler>,
     descriptor_pool: vk::DescriptorPool,
+    /// Image descriptors `images_set` was allocated with.
+    capacity: u32,
     pub images_set: vk::DescriptorSet,
     pub images_set_layout: vk::DescriptorSetLayout,
     device: Arc<Device>,
 }
 
 impl TextureArena {
+    /// Recreates the built-in samplers, the old ones are retired. The GPU has
+    /// to be idle.
+    pub fn set_sampler_settings(&mut self, settings: SamplerSettings) -> Result<()> {
+        if settings == self.sampler_settings {
+            return Ok(());
+        }
+        for (idx, filter) in [
+            (LINEAR_SAMPLER_IDX, vk::Filter::LINEAR),
+            (NEAREST_SAMPLER_IDX, vk::Filter::NEAREST),
+        ] {
+            let sampler = create_sampler(&self.device, filter, settings)?;
+            write_sampler(&self.device, self.images_set, idx, sampler);
+            let old = std::mem::replace(&mut self.samplers[idx], sampler);
+            self.retired_samplers.push(old);
+        }
+        self.sampler_settings = settings;
+        Ok(())
+    }
+
+    /// Destroys what was retired, once no pipeline built before is left.
+    pub fn release_retired(&mut self) {
+        for (mut image, view) in self.retired_images.drain(..) {
+            image.desctroy(&self.device);
+            unsafe { self.device.destroy_image_view(view, None) };
+        }
+        for sampler in self.retired_samplers.drain(..) {
+            unsafe { self.device.destroy_sampler(sampler, None) };
+        }
+    }
+
     pub fn image_count(&self) -> usize {
         self.images.len()
     }
 
-    pub fn new(device: &Arc<Device>, queue: &vk::Queue, extent: vk::Extent2D) -> Result<Self> {
-        let pool_sizes = [
-            vk::DescriptorPoolSize::default()
-                .ty(vk::DescriptorType::SAMPLED_IMAGE)
-                .descriptor_count(IMAGES_COUNT),
-            vk::DescriptorPoolSize::default()
-                .ty(vk::DescriptorType::SAMPLER)
-                .descriptor_count(SAMPLER_COUNT),
-        ];
-        let descriptor_pool = unsafe {
-            device.create_descriptor_pool(
-                &vk::DescriptorPoolCreateInfo::default()
-                    .flags(
-                        vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND
-                            | vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET,
-                    )
-                    .pool_sizes(&pool_sizes)
-                    .max_sets(1),
-                None,
-            )?
-        };
-
+    /// `copy_format` is the format of the images the frame is copied into,
+    /// it should match the encoding of the surface.
+    pub fn new(
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        extent: vk::Extent2D,
+        copy_format: vk::Format,
+    ) -> Result<Self> {
         let binding_flags = vk::DescriptorBindingFlags::PARTIALLY_BOUND
             | vk::DescriptorBindingFlags::UPDATE_AFTER_BIND
             | vk::DescriptorBindingFlags::UPDATE_UNUSED_WHILE_PENDING;
@@ -138,13 +287,13 @@ impl TextureArena {
             )?
         };
 
-        let mut variable_info = vk::DescriptorSetVariableDescriptorCountAllocateInfo::default()
-            .descriptor_counts(&[IMAGES_COUNT]);
-        let allocate_info = vk::DescriptorSetAllocateInfo::default()
-            .descriptor_pool(descriptor_pool)
-            .set_layouts(std::slice::from_ref(&images_set_layout))
-            .push_next(&mut variable_info);
-        let images_set = unsafe { device.allocate_descriptor_sets(&allocate_info)? }[0];
+        let capacity = IMAGES_COUNT.min(
+            device
+                .descriptor_indexing_props
+                .max_descriptor_set_update_after_bind_sampled_images,
+        );
+        let (descriptor_pool, images_set) =
+            allocate_images_set(device, images_set_layout, capacity)?;
 
         let image_infos: [_; 3] = std::array::from_fn(|_| {
             vk::ImageCreateInfo::default()
@@ -154,7 +303,7 @@ impl TextureArena {
                     depth: 1,
                 })
                 .image_type(vk::ImageType::TYPE_2D)
-                .format(vk::Format::R8G8B8A8_SRGB)
+                .format(copy_format)
                 .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
                 .samples(vk::SampleCountFlags::TYPE_1)
                 .mip_levels(1)
@@ -187,44 +336,30 @@ impl TextureArena {
         }
 
         let mut samplers = [vk::Sampler::null(); SAMPLER_COUNT as usize];
-        let mut sampler_create_info = vk::SamplerCreateInfo::default()
-            .min_filter(vk::Filter::LINEAR)
-            .mag_filter(vk::Filter::LINEAR)
-            .mipmap_mode(vk::SamplerMipmapMode::NEAREST)
-            .address_mode_u(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .address_mode_v(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .address_mode_w(vk::SamplerAddressMode::MIRRORED_REPEAT)
-            .max_lod(vk::LOD_CLAMP_NONE);
-        let sampler = unsafe { device.create_sampler(&sampler_create_info, None)? };
-        let descriptor_image_info = vk::DescriptorImageInfo::default().sampler(sampler);
-        let mut desc_write = vk::WriteDescriptorSet::default()
-            .descriptor_type(vk::DescriptorType::SAMPLER)
-            .dst_set(images_set)
-            .dst_binding(0)
-            .image_info(std::slice::from_ref(&descriptor_image_info))
-            .dst_array_element(0);
-        unsafe { device.update_descriptor_sets(&[desc_write], &[]) };
-        samplers[0] = sampler;
-
-        sampler_create_info = sampler_create_info
-            .mag_filter(vk::Filter::NEAREST)
-            .min_filter(vk::Filter::NEAREST);
-        let sampler = unsafe { device.create_sampler(&sampler_create_info, None)? };
-        let descriptor_image_info = vk::DescriptorImageInfo::default().sampler(sampler);
-        desc_write = desc_write.image_info(std::slice::from_ref(&descriptor_image_info));
-        unsafe { device.update_descriptor_sets(&[desc_write], &[]) };
-        samplers[1] = sampler;
+        let sampler_settings = SamplerSettings::default();
+        for (idx, filter) in [
+            (LINEAR_SAMPLER_IDX, vk::Filter::LINEAR),
+            (NEAREST_SAMPLER_IDX, vk::Filter::NEAREST),
+        ] {
+            samplers[idx] = create_sampler(device, filter, sampler_settings)?;
+            write_sampler(device, images_set, idx, samplers[idx]);
+        }
 
         let mut texture_arena = Self {
             images,
             image_infos: image_infos.to_vec(),
             views,
             samplers,
+            sampler_settings,
+            retired_images: vec![],
+            retired_samplers: vec![],
             descriptor_pool,
+            capacity,
             images_set,
             images_set_layout,
             device: device.clone(),
         };
+        texture_arena.clear_images(queue, &SCREENSIZED_IMAGE_INDICES)?;
 
         texture_arena.device.name_object(
             texture_arena.images[PREV_FRAME_IMAGE_IDX].image,
@@ -286,17 +421,41 @@ impl TextureArena {
             .device
             .name_object(texture_arena.views[BLUE_IMAGE_IDX], "Blue Noise Image View");
 
+        // Placeholder until a project asks for history with `set_history_len`
+        let info = vk::ImageCreateInfo::default()
+            .extent(vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            })
+            .image_type(vk::ImageType::TYPE_2D)
+            .format(copy_format)
+            .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+            .samples(vk::SampleCountFlags::TYPE_1)
+            .mip_levels(1)
+            .array_layers(2)
+            .tiling(vk::ImageTiling::OPTIMAL);
+        texture_arena.push_render_target(info)?;
+        texture_arena.clear_images(queue, &[HISTORY_IMAGE_IDX])?;
+        texture_arena.device.name_object(
+            texture_arena.images[HISTORY_IMAGE_IDX].image,
+            "History Image",
+        );
+        texture_arena
+            .device
+            .name_object(texture_arena.views[HISTORY_IMAGE_IDX], "History Image View");
+
         Ok(texture_arena)
     }
 
-    pub fn push_image(
-        &mut self,
+    /// Creates an image filled with `data` and left ready to be sampled.
+    fn upload_image(
         device: &Arc<Device>,
         queue: &vk::Queue,
-        info: vk::ImageCreateInfo,
+        info: &vk::ImageCreateInfo<'static>,
         data: &[u8],
-    ) -> Result<u32> {
-        let image = { Image::new(device, &info, UsageFlags::FAST_DEVICE_ACCESS)? };
+    ) -> Result<Image> {
+        let image = { Image::new(device, info, UsageFlags::FAST_DEVICE_ACCESS)? };
         let mut staging = device.create_host_buffer(
             image.memory.size(),
             vk::BufferUsageFlags::TRANSFER_SRC,
@@ -335,8 +494,20 @@ impl TextureArena {
             device.cmd_pipeline_barrier2(cbuff, &dependency_info);
         })?;
 
+        Ok(image)
+    }
+
+    pub fn push_image(
+        &mut self,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        info: vk::ImageCreateInfo<'static>,
+        data: &[u8],
+    ) -> Result<u32> {
+        let image = Self::upload_image(device, queue, &info, data)?;
         let view = self.device.create_2d_view(&image.image, info.format)?;
         let idx = self.images.len() as u32;
+        self.reserve(idx as usize)?;
 
         let image_info = vk::DescriptorImageInfo::default()
             .image_view(view)
@@ -350,19 +521,166 @@ impl TextureArena {
         unsafe { device.update_descriptor_sets(&[write], &[]) };
 
         self.images.push(image);
+        self.image_infos.push(info);
+        self.views.push(view);
+
+        Ok(idx)
+    }
+
+    /// Makes room for the descriptor of image `idx`, moving every descriptor
+    /// into a set twice as large if needed. Waits for the GPU to go idle since
+    /// frames in flight still use the old set.
+    fn reserve(&mut self, idx: usize) -> Result<()> {
+        if idx < self.capacity as usize {
+            return Ok(());
+        }
+        let limit = self
+            .device
+            .descriptor_indexing_props
+            .max_descriptor_set_update_after_bind_sampled_images;
+        if idx >= limit as usize {
+            bail!("Texture arena is full, the device can't bind more than {limit} images");
+        }
+        let capacity = (self.capacity * 2).max(idx as u32 + 1).min(limit);
+        log::info!("Growing texture arena to {capacity} images");
+
+        let (descriptor_pool, images_set) =
+            allocate_images_set(&self.device, self.images_set_layout, capacity)?;
+        let sampler_infos: Vec<_> = self
+            .samplers
+            .iter()
+            .take_while(|sampler| **sampler != vk::Sampler::null())
+            .map(|&sampler| vk::DescriptorImageInfo::default().sampler(sampler))
+            .collect();
+        let image_infos: Vec<_> = self
+            .views
+            .iter()
+            .map(|&view| {
+                vk::DescriptorImageInfo::default()
+                    .image_view(view)
+                    .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            })
+            .collect();
+        let writes = [
+            vk::WriteDescriptorSet::default()
+                .dst_set(images_set)
+                .descriptor_type(vk::DescriptorType::SAMPLER)
+                .dst_binding(0)
+                .image_info(&sampler_infos),
+            vk::WriteDescriptorSet::default()
+                .dst_set(images_set)
+                .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+                .dst_binding(1)
+                .image_info(&image_infos),
+        ];
+        unsafe {
+            self.device.device_wait_idle()?;
+            self.device.update_descriptor_sets(&writes, &[]);
+            self.device
+                .destroy_descriptor_pool(self.descriptor_pool, None);
+        }
+        self.descriptor_pool = descriptor_pool;
+        self.images_set = images_set;
+        self.capacity = capacity;
+        Ok(())
+    }
+
+    /// Swaps the image at `idx` for a new one with different contents or size.
+    /// The old image must no longer be in use by the GPU.
+    pub fn replace_image(
+        &mut self,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        idx: usize,
+        info: vk::ImageCreateInfo<'static>,
+        data: &[u8],
+    ) -> Result<()> {
+        let (mut image, view) = self.swap_image(device, queue, idx, info, data)?;
+        image.desctroy(&self.device);
+        unsafe { self.device.destroy_image_view(view, None) };
+        Ok(())
+    }
+
+    /// Like [`Self::replace_image`], but the old image is only retired, for
+    /// the set 1 descriptors of pipelines built before.
+    pub fn retire_image(
+        &mut self,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        idx: usize,
+        info: vk::ImageCreateInfo<'static>,
+        data: &[u8],
+    ) -> Result<()> {
+        let old = self.swap_image(device, queue, idx, info, data)?;
+        self.retired_images.push(old);
+        Ok(())
+    }
+
+    /// Puts a new image into slot `idx` and returns the old one with its view.
+    fn swap_image(
+        &mut self,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        idx: usize,
+        info: vk::ImageCreateInfo<'static>,
+        data: &[u8],
+    ) -> Result<(Image, vk::ImageView)> {
+        let image = Self::upload_image(device, queue, &info, data)?;
+        let view = self.device.create_2d_view(&image.image, info.format)?;
+
+        let image_info = vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.images_set)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .dst_binding(1)
+            .image_info(std::slice::from_ref(&image_info))
+            .dst_array_element(idx as _);
+        unsafe { device.update_descriptor_sets(&[write], &[]) };
+
+        let old_image = std::mem::replace(&mut self.images[idx], image);
+        let old_view = std::mem::replace(&mut self.views[idx], view);
+        self.image_infos[idx] = info;
+
+        Ok((old_image, old_view))
+    }
+
+    /// Adds an image without initial contents, meant to be rendered into.
+    pub fn push_render_target(&mut self, info: vk::ImageCreateInfo<'static>) -> Result<usize> {
+        let image = Image::new(&self.device, &info, UsageFlags::FAST_DEVICE_ACCESS)?;
+        let view = create_view(&self.device, &image.image, &info)?;
+        let idx = self.images.len();
+        self.reserve(idx)?;
+
+        let image_info = vk::DescriptorImageInfo::default()
+            .image_view(view)
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.images_set)
+            .descriptor_type(vk::DescriptorType::SAMPLED_IMAGE)
+            .dst_binding(1)
+            .image_info(std::slice::from_ref(&image_info))
+            .dst_array_element(idx as _);
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+
+        self.images.push(image);
+        self.image_infos.push(info);
         self.views.push(view);
 
         Ok(idx)
     }
 
-    pub fn update_images(&mut self, indices: &[usize]) -> Result<()> {
+    /// Recreates the images from their `image_infos`, the new ones start
+    /// zeroed and ready to be sampled.
+    pub fn update_images(&mut self, queue: &vk::Queue, indices: &[usize]) -> Result<()> {
         for (i, info) in indices.iter().map(|&i| (i, &self.image_infos[i])) {
             let image = Image::new(
                 &self.device,
                 info,
                 gpu_alloc::UsageFlags::FAST_DEVICE_ACCESS,
             )?;
-            let view = self.device.create_2d_view(&image.image, info.format)?;
+            let view = create_view(&self.device, &image.image, info)?;
 
             let image_info = vk::DescriptorImageInfo::default()
                 .image_view(view)
@@ -381,12 +699,79 @@ impl TextureArena {
             self.views[i] = view;
         }
 
+        self.clear_images(queue, indices)
+    }
+}
+
+impl TextureArena {
+    /// Reallocates the history ring with `len` frames, `0` shrinks it back
+    /// to a placeholder.
+    pub fn set_history_len(
+        &mut self,
+        queue: &vk::Queue,
+        len: u32,
+        extent: vk::Extent2D,
+    ) -> Result<()> {
+        let info = &mut self.image_infos[HISTORY_IMAGE_IDX];
+        info.array_layers = len.max(2);
+        info.extent = match len {
+            0 => vk::Extent3D {
+                width: 1,
+                height: 1,
+                depth: 1,
+            },
+            _ => vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+        };
+        self.update_images(queue, &[HISTORY_IMAGE_IDX])
+    }
+
+    /// Zeroes the images without touching anything else, e.g. to restart a
+    /// diverged feedback simulation.
+    pub fn clear_images(&self, queue: &vk::Queue, indices: &[usize]) -> Result<()> {
+        self.device.one_time_submit(queue, |device, cbuff| unsafe {
+            for &i in indices {
+                let image = self.images[i].image;
+                let mut image_barrier = vk::ImageMemoryBarrier2::default()
+                    .subresource_range(COLOR_SUBRESOURCE_MASK)
+                    .src_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                    .dst_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .old_layout(vk::ImageLayout::UNDEFINED)
+                    .new_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .image(image);
+                let dependency_info = vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&image_barrier));
+                device.cmd_pipeline_barrier2(cbuff, &dependency_info);
+                device.cmd_clear_color_image(
+                    cbuff,
+                    image,
+                    vk::ImageLayout::TRANSFER_DST_OPTIMAL,
+                    &vk::ClearColorValue { float32: [0.; 4] },
+                    &[COLOR_SUBRESOURCE_MASK],
+                );
+                image_barrier = image_barrier
+                    .src_stage_mask(vk::PipelineStageFlags2::CLEAR)
+                    .src_access_mask(vk::AccessFlags2::TRANSFER_WRITE)
+                    .dst_stage_mask(vk::PipelineStageFlags2::ALL_COMMANDS)
+                    .dst_access_mask(vk::AccessFlags2::MEMORY_READ)
+                    .old_layout(vk::ImageLayout::TRANSFER_DST_OPTIMAL)
+                    .new_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+                let dependency_info = vk::DependencyInfo::default()
+                    .image_memory_barriers(std::slice::from_ref(&image_barrier));
+                device.cmd_pipeline_barrier2(cbuff, &dependency_info);
+            }
+        })?;
         Ok(())
     }
 }
 
 impl Drop for TextureArena {
     fn drop(&mut self) {
+        self.release_retired();
         unsafe {
             self.images.iter_mut().for_each(|image| {
                 image.desctroy(&self.device);
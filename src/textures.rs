@@ -0,0 +1,100 @@
+use std::{path::Path, path::PathBuf, sync::Arc};
+
+use anyhow::{bail, Context, Result};
+use ash::vk;
+use serde::Deserialize;
+
+use crate::{overlay::load_png, Device, TextureArena};
+
+/// A PNG loaded into the texture arena, shaders get its index as `<NAME>_TEX`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureDesc {
+    pub name: String,
+    /// Relative to the shader folder.
+    pub path: PathBuf,
+}
+
+#[derive(Default)]
+pub struct Textures {
+    /// Texture arena slots reused across reloads, the arena never shrinks.
+    texture_slots: Vec<usize>,
+    /// Name and slot of the textures of the manifest.
+    loaded: Vec<(String, usize)>,
+    defines: Vec<(String, String)>,
+}
+
+impl Textures {
+    /// Loads the textures of `descs`, replacing the previous ones. The GPU
+    /// has to be idle.
+    pub fn configure(
+        &mut self,
+        descs: &[TextureDesc],
+        shader_dir: &Path,
+        device: &Arc<Device>,
+        queue: &vk::Queue,
+        texture_arena: &mut TextureArena,
+    ) -> Result<()> {
+        self.defines.clear();
+        self.loaded.clear();
+        for (i, desc) in descs.iter().enumerate() {
+            let valid_name = desc
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && desc.name.starts_with(|c: char| c.is_ascii_alphabetic());
+            if !valid_name {
+                bail!("Texture name `{}` has to be a GLSL identifier", desc.name);
+            }
+            let path = shader_dir.join(&desc.path);
+            let (width, height, pixels) = load_png(&path)
+                .with_context(|| format!("Failed to load texture {}", path.display()))?;
+            let info = vk::ImageCreateInfo::default()
+                .extent(vk::Extent3D {
+                    width,
+                    height,
+                    depth: 1,
+                })
+                .image_type(vk::ImageType::TYPE_2D)
+                .format(vk::Format::R8G8B8A8_SRGB)
+                .usage(vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST)
+                .samples(vk::SampleCountFlags::TYPE_1)
+                .mip_levels(1)
+                .array_layers(1)
+                .tiling(vk::ImageTiling::OPTIMAL);
+            let slot = match self.texture_slots.get(i) {
+                Some(&slot) => {
+                    texture_arena.retire_image(device, queue, slot, info, &pixels)?;
+                    slot
+                }
+                None => {
+                    let slot = texture_arena.push_image(device, queue, info, &pixels)? as usize;
+                    self.texture_slots.push(slot);
+                    slot
+                }
+            };
+            device.name_object(
+                texture_arena.images[slot].image,
+                &format!("Texture: {}", desc.name),
+            );
+            self.loaded.push((desc.name.clone(), slot));
+            self.defines.push((
+                format!("{}_TEX", desc.name.to_uppercase()),
+                slot.to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Name and arena index of every texture.
+    pub fn slots(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.loaded
+            .iter()
+            .map(|(name, slot)| (name.as_str(), *slot))
+    }
+
+    /// Macros with the arena index of every texture.
+    pub fn defines(&self) -> &[(String, String)] {
+        &self.defines
+    }
+}
@@ -0,0 +1,209 @@
+use std::{fmt, fs::File, io::Write, str::FromStr, time::Instant};
+
+use anyhow::bail;
+use ash::vk;
+use parking_lot::Mutex;
+
+pub const VALIDATION_LOG_FILE: &str = "pilka-validation.log";
+
+/// Distinct messages kept for the listing, later ones only go to the file.
+const MAX_ENTRIES: usize = 256;
+
+/// Lowest severity of the debug messages that are kept.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ValidationLevel {
+    Verbose,
+    Info,
+    #[default]
+    Warning,
+    Error,
+}
+
+impl FromStr for ValidationLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "verbose" => Self::Verbose,
+            "info" => Self::Info,
+            "warning" => Self::Warning,
+            "error" => Self::Error,
+            _ => bail!("Unknown validation level `{s}`, expected error, warning, info or verbose"),
+        })
+    }
+}
+
+impl fmt::Display for ValidationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Verbose => "verbose",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Error => "error",
+        };
+        f.write_str(name)
+    }
+}
+
+impl ValidationLevel {
+    fn from_severity(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> Self {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as S;
+        match severity {
+            S::ERROR => Self::Error,
+            S::WARNING => Self::Warning,
+            S::INFO => Self::Info,
+            _ => Self::Verbose,
+        }
+    }
+
+    /// The messenger severities from this level up.
+    pub fn severities(self) -> vk::DebugUtilsMessageSeverityFlagsEXT {
+        use vk::DebugUtilsMessageSeverityFlagsEXT as S;
+        [
+            (Self::Verbose, S::VERBOSE),
+            (Self::Info, S::INFO),
+            (Self::Warning, S::WARNING),
+            (Self::Error, S::ERROR),
+        ]
+        .into_iter()
+        .filter(|&(level, _)| level >= self)
+        .fold(S::empty(), |flags, (_, severity)| flags | severity)
+    }
+}
+
+/// What the instance reports through its debug messenger.
+#[derive(Debug, Clone, Copy)]
+pub struct ValidationSettings {
+    /// Enable the Khronos validation layer, always on in debug builds.
+    pub layers: bool,
+    pub level: ValidationLevel,
+}
+
+impl Default for ValidationSettings {
+    fn default() -> Self {
+        Self {
+            layers: cfg!(debug_assertions),
+            level: ValidationLevel::default(),
+        }
+    }
+}
+
+/// One distinct message, repeats are counted instead of printed.
+struct ValidationEntry {
+    level: ValidationLevel,
+    message: String,
+    count: u32,
+    id: String,
+}
+
+#[derive(Default)]
+struct LogState {
+    entries: Vec<ValidationEntry>,
+    /// Messages past `MAX_ENTRIES` that only made it into the file.
+    overflow: u32,
+    file: Option<File>,
+    file_failed: bool,
+}
+
+/// Messages of the debug messenger. The first of each kind is logged, every
+/// one is appended to [`VALIDATION_LOG_FILE`] in the working directory and
+/// the distinct ones are kept with their counts for `Action::Validation`
+/// and the HUD.
+pub struct ValidationLog {
+    started: Instant,
+    state: Mutex<LogState>,
+}
+
+impl Default for ValidationLog {
+    fn default() -> Self {
+        Self {
+            started: Instant::now(),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl ValidationLog {
+    pub(crate) fn push(
+        &self,
+        severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+        id: &str,
+        message: &str,
+    ) {
+        let level = ValidationLevel::from_severity(severity);
+        let mut state = self.state.lock();
+        state.write(self.started, level, message);
+
+        // Messages differ in the handles they mention, so the VUID decides
+        let id = if id.is_empty() { message } else { id };
+        if let Some(entry) = state.entries.iter_mut().find(|entry| entry.id == id) {
+            entry.count += 1;
+            return;
+        }
+        if state.entries.len() >= MAX_ENTRIES {
+            state.overflow += 1;
+            return;
+        }
+        match level {
+            ValidationLevel::Error => log::error!("{message}"),
+            ValidationLevel::Warning => log::warn!("{message}"),
+            _ => log::info!("{message}"),
+        }
+        state.entries.push(ValidationEntry {
+            level,
+            message: message.to_string(),
+            count: 1,
+            id: id.to_string(),
+        });
+    }
+
+    /// Distinct errors and warnings so far.
+    pub fn counts(&self) -> (usize, usize) {
+        let state = self.state.lock();
+        let count = |level| {
+            state
+                .entries
+                .iter()
+                .filter(|entry| entry.level == level)
+                .count()
+        };
+        (
+            count(ValidationLevel::Error),
+            count(ValidationLevel::Warning),
+        )
+    }
+
+    /// Prints every distinct message with its count.
+    pub fn print(&self) {
+        let state = self.state.lock();
+        if state.entries.is_empty() {
+            println!("No validation messages");
+            return;
+        }
+        for entry in &state.entries {
+            println!("[{}] x{}: {}", entry.level, entry.count, entry.message);
+        }
+        if state.overflow > 0 {
+            println!("{} more in {VALIDATION_LOG_FILE}", state.overflow);
+        }
+    }
+}
+
+impl LogState {
+    /// The file is created with the first message, a clean run leaves none.
+    fn write(&mut self, started: Instant, level: ValidationLevel, message: &str) {
+        if self.file.is_none() && !self.file_failed {
+            match File::create(VALIDATION_LOG_FILE) {
+                Ok(file) => self.file = Some(file),
+                Err(err) => {
+                    log::warn!("Failed to create {VALIDATION_LOG_FILE}: {err}");
+                    self.file_failed = true;
+                }
+            }
+        }
+        if let Some(file) = &mut self.file {
+            let secs = started.elapsed().as_secs_f32();
+            let _ = writeln!(file, "{secs:.3} [{level}] {message}");
+        }
+    }
+}
@@ -10,7 +10,7 @@ use std::{
     time::Duration,
 };
 
-use crate::{ShaderSource, UserEvent};
+use crate::{ShaderSource, UserEvent, MANIFEST_FILE};
 
 use parking_lot::Mutex;
 
@@ -33,6 +33,19 @@ impl Watcher {
         })
     }
 
+    /// Watches nothing anyone hears about, for rendering without an event loop.
+    pub fn detached() -> Result<Self> {
+        let watcher = notify_debouncer_mini::new_debouncer(
+            Duration::from_millis(350),
+            |_: DebounceEventResult| {},
+        )?;
+
+        Ok(Self {
+            watcher: Arc::new(Mutex::new(watcher)),
+            include_mapping: Arc::new(Mutex::new(AHashMap::new())),
+        })
+    }
+
     pub fn unwatch_file(&mut self, path: impl AsRef<Path>) -> Result<()> {
         let mut watcher = self.watcher.lock();
         watcher.watcher().unwatch(path.as_ref())?;
@@ -51,22 +64,44 @@ impl Watcher {
 fn watch_callback(proxy: EventLoopProxy<UserEvent>) -> impl FnMut(DebounceEventResult) {
     move |event| match event {
         Ok(events) => {
-            if let Some(path) = events
+            // Files copied in together arrive in one batch, each gets its event
+            let mut seen = AHashSet::new();
+            for path in events
                 .into_iter()
                 .filter(|e| e.kind == DebouncedEventKind::Any)
                 .map(|event| event.path)
-                .next()
+                .filter(|path| seen.insert(path.clone()))
             {
-                if path.extension() == Some(OsStr::new("glsl"))
+                if path.file_name() == Some(OsStr::new(MANIFEST_FILE)) {
+                    let _ = proxy
+                        .send_event(UserEvent::Manifest)
+                        .map_err(|err| log::error!("Event Loop has been dropped: {err}"));
+                } else if path.extension() == Some(OsStr::new("glsl"))
                     || path.extension() == Some(OsStr::new("frag"))
                     || path.extension() == Some(OsStr::new("vert"))
                     || path.extension() == Some(OsStr::new("comp"))
                 {
+                    // A removed file can only be a pass going away
+                    let event = match path.canonicalize() {
+                        Ok(path) => UserEvent::Glsl { path },
+                        Err(_) => UserEvent::Passes,
+                    };
                     let _ = proxy
-                        .send_event(UserEvent::Glsl {
-                            path: path.canonicalize().unwrap(),
-                        })
+                        .send_event(event)
                         .map_err(|err| log::error!("Event Loop has been dropped: {err}"));
+                } else if path.is_dir() {
+                    let _ = proxy
+                        .send_event(UserEvent::Passes)
+                        .map_err(|err| log::error!("Event Loop has been dropped: {err}"));
+                } else if path.extension() == Some(OsStr::new("csv"))
+                    || path.extension() == Some(OsStr::new("json"))
+                {
+                    // Gone again by now, the next write brings it back
+                    if let Ok(path) = path.canonicalize() {
+                        let _ = proxy
+                            .send_event(UserEvent::Table { path })
+                            .map_err(|err| log::error!("Event Loop has been dropped: {err}"));
+                    }
                 }
             }
         }
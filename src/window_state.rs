@@ -0,0 +1,123 @@
+use std::path::Path;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use winit::{
+    dpi::{PhysicalPosition, PhysicalSize},
+    monitor::MonitorHandle,
+    window::{Window, WindowAttributes, WindowLevel},
+};
+
+pub const WINDOW_STATE_FILE: &str = "pilka-window.toml";
+
+/// Window flags for using the shader as a screen overlay, e.g. during
+/// streams. Set by the `[window]` table or the command line, read on launch.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OverlayFlags {
+    pub always_on_top: bool,
+    pub borderless: bool,
+    /// Clicks and scrolls go to the windows below.
+    pub click_through: bool,
+    /// The alpha the frame ends with is the opacity of the window.
+    pub transparent: bool,
+}
+
+impl OverlayFlags {
+    pub const ALL: Self = Self {
+        always_on_top: true,
+        borderless: true,
+        click_through: true,
+        transparent: true,
+    };
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            always_on_top: self.always_on_top || other.always_on_top,
+            borderless: self.borderless || other.borderless,
+            click_through: self.click_through || other.click_through,
+            transparent: self.transparent || other.transparent,
+        }
+    }
+
+    pub fn apply(self, mut attributes: WindowAttributes) -> WindowAttributes {
+        if self.always_on_top {
+            attributes = attributes.with_window_level(WindowLevel::AlwaysOnTop);
+        }
+        if self.borderless {
+            attributes = attributes.with_decorations(false);
+        }
+        attributes.with_transparent(self.transparent)
+    }
+}
+
+/// Window geometry of a project saved next to its shaders on exit and
+/// restored on the next launch.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WindowState {
+    /// Monitor the window was on, `position` is relative to its origin.
+    monitor: Option<String>,
+    /// Outer position in physical pixels.
+    position: [i32; 2],
+    /// Inner size in physical pixels.
+    size: [u32; 2],
+    maximized: bool,
+}
+
+impl WindowState {
+    pub fn load(shader_dir: &Path) -> Option<Self> {
+        let path = shader_dir.join(WINDOW_STATE_FILE);
+        let text = std::fs::read_to_string(&path).ok()?;
+        toml::from_str(&text)
+            .map_err(|err| log::warn!("Ignoring {}: {err}", path.display()))
+            .ok()
+    }
+
+    /// Geometry of `window`, `None` while it is minimized or fullscreen and
+    /// doesn't have one worth keeping.
+    pub fn capture(window: &Window) -> Option<Self> {
+        if window.is_minimized() == Some(true) || window.fullscreen().is_some() {
+            return None;
+        }
+        let outer = window.outer_position().ok()?;
+        let monitor = window.current_monitor();
+        let origin = monitor
+            .as_ref()
+            .map_or(PhysicalPosition::new(0, 0), MonitorHandle::position);
+        let size = window.inner_size();
+        Some(Self {
+            monitor: monitor.and_then(|monitor| monitor.name()),
+            position: [outer.x - origin.x, outer.y - origin.y],
+            size: [size.width, size.height],
+            maximized: window.is_maximized(),
+        })
+    }
+
+    pub fn save(&self, shader_dir: &Path) -> Result<()> {
+        let text = toml::to_string(self)?;
+        std::fs::write(shader_dir.join(WINDOW_STATE_FILE), text)?;
+        Ok(())
+    }
+
+    /// Places the window back on its monitor. The position is dropped when
+    /// that monitor isn't connected anymore, so the window can't end up off
+    /// screen.
+    pub fn apply(
+        &self,
+        attributes: WindowAttributes,
+        mut monitors: impl Iterator<Item = MonitorHandle>,
+    ) -> WindowAttributes {
+        let [width, height] = self.size;
+        let mut attributes = attributes
+            .with_inner_size(PhysicalSize::new(width, height))
+            .with_maximized(self.maximized);
+        let monitor = monitors.find(|monitor| monitor.name() == self.monitor);
+        if let Some(monitor) = monitor {
+            let origin = monitor.position();
+            let [x, y] = self.position;
+            attributes =
+                attributes.with_position(PhysicalPosition::new(origin.x + x, origin.y + y));
+        }
+        attributes
+    }
+}